@@ -242,6 +242,7 @@ fn main() {
         .file("tectonic/stringpool.c")
         .file("tectonic/synctex.c")
         .file("tectonic/texmfmp.c")
+        .file("tectonic/xdvisvg.c")
         .file("tectonic/xetex0.c")
         .file("tectonic/XeTeX_ext.c")
         .file("tectonic/xetexini.c")
@@ -2,991 +2,1052 @@
 // Copyright 2016-2017 the Tectonic Project
 // Licensed under the MIT License.
 
-extern crate aho_corasick;
 extern crate clap;
+extern crate libc;
 #[macro_use] extern crate tectonic;
 extern crate termcolor;
 
-use aho_corasick::{Automaton, AcAutomaton};
-use clap::{Arg, ArgMatches, App};
-use std::collections::{HashMap, HashSet};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use std::cmp;
+use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::io::Write;
-use std::os::unix::ffi::OsStrExt;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process;
-
-use tectonic::config::PersistentConfig;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tectonic::config::{BuildProfile, PersistentConfig};
+use tectonic::driver::{is_plain_relative_path, CommandHooks};
 use tectonic::digest::DigestData;
-use tectonic::engines::IoEventBackend;
-use tectonic::errors::{ErrorKind, Result, ResultExt};
-use tectonic::io::{FilesystemIo, FilesystemPrimaryInputIo, GenuineStdoutIo, InputOrigin,
-                   IoProvider, IoStack, MemoryIo, OpenResult};
+use tectonic::errors::{Error, ErrorKind, Result, ResultExt};
+use tectonic::io::{check_bundle_digest, open_file_url_bundle, open_local_bundle, IoProvider};
 use tectonic::io::itarbundle::{HttpITarIoFactory, ITarBundle};
-use tectonic::io::stdstreams::BufferedPrimaryIo;
-use tectonic::io::zipbundle::ZipBundle;
+use tectonic::io::s3bundle::S3Bundle;
+use tectonic::manifest::{DocumentInfo, ProjectManifest, MANIFEST_FILE_NAME};
+use tectonic::preview::PreviewServer;
 use tectonic::status::{ChatterLevel, StatusBackend};
-use tectonic::status::termcolor::TermcolorStatusBackend;
-use tectonic::{BibtexEngine, TexEngine, TexResult, XdvipdfmxEngine};
-
-
-/// The CliIoSetup struct encapsulates, well, the input/output setup used by
-/// the Tectonic engines in this CLI session.
-///
-/// The IoStack struct must necessarily erase types (i.e., turn I/O layers
-/// into IoProvider trait objects) while it lives. But, between invocations of
-/// various engines, we want to look at our individual typed I/O providers and
-/// interrogate them (i.e., see what files were created in the memory layer.
-/// The CliIoSetup struct helps us maintain detailed knowledge of types while
-/// creating an IoStack when needed. In principle we could reuse the same
-/// IoStack for each processing step, but the borrow checker doesn't let us
-/// poke at (e.g.) io.mem while the IoStack exists, since the IoStack keeps a
-/// mutable borrow of it.
-
-struct CliIoSetup {
-    primary_input: Box<IoProvider>,
-    bundle: Option<Box<IoProvider>>,
-    mem: MemoryIo,
-    filesystem: FilesystemIo,
-    genuine_stdout: Option<GenuineStdoutIo>,
-    format_primary: Option<BufferedPrimaryIo>,
-}
+use tectonic::status::filter::FilteringStatusBackend;
+use tectonic::status::json::JsonStatusBackend;
+use tectonic::status::logfile::LogFileStatusBackend;
+use tectonic::status::termcolor::{ColorMode, TermcolorStatusBackend};
+use tectonic::{CancellationToken, InteractionMode, OutputFormat, PassSetting, PdfEncryption, PdfMetadata,
+               ProcessingSession, SessionBuilder, SvgFontMode, XdvipdfmxEngine, XdvipngEngine, XdvisvgEngine};
+
+
+/// Set up the bundles of support files given by the command-line arguments,
+/// or else the default ones.
+fn bundles(args: &ArgMatches, config: &PersistentConfig, pinned_digest: Option<&DigestData>,
+           status: &mut StatusBackend) -> Result<Vec<Box<IoProvider>>> {
+    if let Some(info) = args.value_of("bundle").and_then(|name| config.named_bundle(name)) {
+        let mut info = info.clone();
+
+        if let Some(d) = pinned_digest {
+            info.pin_digest(d);
+        }
 
-impl CliIoSetup {
-    fn as_stack<'a> (&'a mut self) -> IoStack<'a> {
-        let mut providers: Vec<&mut IoProvider> = Vec::new();
+        return config.io_providers(&[info], args.is_present("only_cached"), status);
+    }
 
-        if let Some(ref mut p) = self.genuine_stdout {
-            providers.push(p);
+    let explicit_bundle: Option<Box<IoProvider>> = if let Some(p) = args.value_of("bundle") {
+        Some(ctry!(open_local_bundle(Path::new(&p)); "error opening bundle"))
+    } else if let Some(u) = args.value_of("web_bundle") {
+        if u.starts_with("file://") {
+            Some(ctry!(open_file_url_bundle(&u); "error opening bundle"))
+        } else if u.starts_with("s3://") {
+            let mut s3 = ctry!(S3Bundle::new(&u, None, None); "error opening bundle");
+            s3.proxy(config.proxy())?;
+            s3.network(config.network())?;
+            Some(Box::new(s3))
+        } else {
+            let mut tb = ITarBundle::<HttpITarIoFactory>::new(&u);
+            tb.proxy(config.proxy());
+            tb.network(config.network());
+            Some(Box::new(tb))
         }
+    } else {
+        None
+    };
 
-        providers.push(&mut *self.primary_input);
-        providers.push(&mut self.mem);
-        providers.push(&mut self.filesystem);
+    match explicit_bundle {
+        Some(mut b) => {
+            if let Some(d) = pinned_digest {
+                check_bundle_digest(&mut *b, d, status)?;
+            }
+
+            Ok(vec![config.with_format_cache(b)?])
+        },
+        None => config.default_io_providers(args.is_present("only_cached"), status),
+    }
+}
 
-        if let Some(ref mut b) = self.bundle {
-            providers.push(&mut **b);
-        }
 
-        IoStack::new(providers)
+/// Look up the profile named `name` in the project manifest, if there is
+/// one, then in the persistent configuration.
+fn find_profile(name: &str, manifest: Option<&ProjectManifest>, config: &PersistentConfig) -> Result<BuildProfile> {
+    if let Some(p) = manifest.and_then(|m| m.profile(name)) {
+        return Ok(p.clone());
     }
 
-    fn as_stack_for_format<'a> (&'a mut self, kickstart: &str) -> IoStack<'a> {
-        let mut providers: Vec<&mut IoProvider> = Vec::new();
+    match config.profile(name) {
+        Some(p) => Ok(p),
+        None => Err(ErrorKind::Msg(format!("no profile named \"{}\" is defined", name)).into()),
+    }
+}
 
-        if let Some(ref mut p) = self.genuine_stdout {
-            providers.push(p);
-        }
 
+/// Get the name of the kind of output to generate, as given by `--outfmt` or
+/// else the configuration.
+fn output_format_name<'a>(args: &'a ArgMatches, config: &'a PersistentConfig) -> &'a str {
+    match args.value_of("outfmt") {
+        Some(name) => name,
+        None => match config.build_defaults().outfmt {
+            Some(ref name) => name,
+            None => "pdf",
+        },
+    }
+}
 
-        self.format_primary = Some(BufferedPrimaryIo::from_text(kickstart));
-        providers.push(self.format_primary.as_mut().unwrap());
-        providers.push(&mut self.mem);
 
-        if let Some(ref mut b) = self.bundle {
-            providers.push(&mut **b);
-        }
+/// Set up a processing session for the input `tex_path` according to the
+/// command-line arguments and the persistent configuration.
+fn session_builder(args: &ArgMatches, tex_path: &OsStr, config: &PersistentConfig,
+                   status: &mut StatusBackend) -> Result<SessionBuilder> {
+    let mut sb = SessionBuilder::new();
+    sb.format_name(args.value_of("format").unwrap());
+
+    // The configured defaults and the profile go first so that the other
+    // options can override them.
+
+    config.build_defaults().configure_session(&mut sb)?;
+
+    let profile = match args.value_of("profile") {
+        Some(name) => find_profile(name, None, config)?,
+        None => BuildProfile::default(),
+    };
+
+    profile.configure_session(&mut sb);
 
-        IoStack::new(providers)
+    sb.output_format(OutputFormat::from_str(output_format_name(args, config))?);
+
+    sb.pass(match args.value_of("pass").unwrap() {
+        "default" => PassSetting::Default,
+        "bibtex_first" => PassSetting::BibtexFirst,
+        "tex" => PassSetting::Tex,
+        _ => unreachable!()
+    });
+
+    if let Some(mode) = args.value_of("interaction") {
+        sb.interaction(InteractionMode::from_str(mode)?);
     }
-}
 
-/// The CliIoBuilder provides a convenient builder interface for specifying
-/// the I/O setup.
+    if let Some(s) = args.value_of("reruns") {
+        sb.reruns(usize::from_str_radix(s, 10)?);
+    }
 
-struct CliIoBuilder {
-    primary_input_path: Option<PathBuf>,
-    filesystem_root: PathBuf,
-    use_stdin: bool,
-    bundle: Option<Box<IoProvider>>,
-    use_genuine_stdout: bool,
-    hidden_input_paths: HashSet<PathBuf>,
-}
+    if let Some(s) = args.value_of("max_seconds") {
+        sb.max_seconds(ctry!(s.parse::<u64>(); "invalid time limit \"{}\"", s));
+    }
 
-impl Default for CliIoBuilder {
-    fn default() -> Self {
-        CliIoBuilder {
-            primary_input_path: None,
-            filesystem_root: PathBuf::new(),
-            use_stdin: false,
-            bundle: None,
-            use_genuine_stdout: false,
-            hidden_input_paths: HashSet::new(),
-        }
+    if let Some(s) = args.value_of("max_pages") {
+        sb.max_pages(ctry!(s.parse::<u64>(); "invalid page limit \"{}\"", s));
     }
-}
 
-impl CliIoBuilder {
-    fn primary_input_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
-        if self.use_stdin {
-            panic!("cannot use both stdin and primary_input_path");
-        }
+    if let Some(s) = args.value_of("max_passes") {
+        sb.max_passes(ctry!(s.parse::<usize>(); "invalid limit on TeX passes \"{}\"", s));
+    }
 
-        self.primary_input_path = Some(path.as_ref().to_owned());
-        self
+    sb.untrusted(args.is_present("untrusted"));
+
+    sb.dry_run(args.is_present("dry_run"))
+      .biber_command(args.value_of_os("biber").unwrap());
+
+    if args.is_present("keep_intermediates") {
+        sb.keep_intermediates(true);
     }
 
-    fn primary_input_stdin(&mut self) -> &mut Self {
-        if self.primary_input_path.is_some() {
-            panic!("cannot use both primary_input_path and stdin");
-        }
+    if args.is_present("keep_logs") {
+        sb.keep_logs(true);
+    }
 
-        self.use_stdin = true;
-        self
+    if args.is_present("synctex") {
+        sb.synctex(true);
     }
 
-    fn filesystem_root<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
-        self.filesystem_root = path.as_ref().to_owned();
-        self
+    if args.is_present("precompiled_preamble") {
+        sb.precompiled_preamble(true);
     }
 
-    fn bundle<T: 'static + IoProvider>(&mut self, bundle: T) -> &mut Self {
-        self.bundle = Some(Box::new(bundle));
-        self
+    if args.is_present("draft") {
+        sb.draft(true);
     }
 
-    fn boxed_bundle(&mut self, bundle: Box<IoProvider>) -> &mut Self {
-        self.bundle = Some(bundle);
-        self
+    match args.value_of_os("outdir") {
+        Some(d) if d == "-" => { sb.outputs_in_memory(true); },
+        Some(d) => { sb.output_dir(d); },
+        None => {},
     }
 
-    fn use_genuine_stdout(&mut self, setting: bool) -> &mut Self {
-        self.use_genuine_stdout = setting;
-        self
+    if let Some(d) = args.value_of_os("auxdir") {
+        sb.aux_dir(d);
     }
 
-    fn hide_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
-        self.hidden_input_paths.insert(path.as_ref().to_owned());
-        self
+    if let Some(names) = args.values_of("includeonly") {
+        let names: Vec<&str> = names.collect();
+        sb.include_only(&names);
     }
 
-    fn create(self) -> Result<CliIoSetup> {
-        let pio: Box<IoProvider> = if self.use_stdin {
-            Box::new(ctry!(BufferedPrimaryIo::from_stdin(); "error reading standard input"))
-        } else if let Some(pip) = self.primary_input_path {
-            Box::new(FilesystemPrimaryInputIo::new(&pip))
-        } else {
-            panic!("no primary input mechanism specified");
-        };
+    if let Some(p) = args.value_of_os("makefile_rules") {
+        sb.makefile_rules(p);
+    }
 
-        Ok(CliIoSetup {
-            primary_input: pio,
-            mem: MemoryIo::new(true),
-            filesystem: FilesystemIo::new(&self.filesystem_root, false, true, self.hidden_input_paths),
-            bundle: self.bundle,
-            genuine_stdout: if self.use_genuine_stdout {
-                Some(GenuineStdoutIo::new())
-            } else {
-                None
-            },
-            format_primary: None,
-        })
+    sb.dep_file(args.is_present("dep_file"));
+
+    if let Some(p) = args.value_of_os("audit_io") {
+        sb.audit_io(p);
     }
-}
 
+    // xdvipdfmx settings
 
-/// Different patterns with which files may have been accessed by the
-/// underlying engines. Once a file is marked as ReadThenWritten or
-/// WrittenThenRead, its pattern does not evolve further.
-#[derive(Clone,Copy,Debug,Eq,PartialEq)]
-enum AccessPattern {
-    /// This file is only ever read.
-    Read,
-
-    /// This file is only ever written. This suggests that it is
-    /// a final output of the processing session.
-    Written,
-
-    /// This file is read, then written. We call this a "circular" access
-    /// pattern. Multiple passes of an engine will result in outputs that
-    /// change if this file's contents change, or if the file did not exist at
-    /// the time of the first pass.
-    ReadThenWritten,
-
-    /// This file is written, then read. We call this a "temporary" access
-    /// pattern. This file is likely a temporary buffer that is not of
-    /// interest to the user.
-    WrittenThenRead,
-}
+    let mut xdvipdfmx = XdvipdfmxEngine::new();
+    let mut xdvisvg = XdvisvgEngine::new();
+    let mut xdvipng = XdvipngEngine::new();
+    profile.configure_xdvipdfmx(&mut xdvipdfmx);
 
+    if let Some(s) = args.value_of("paper") {
+        xdvipdfmx.paper_spec(s);
+        xdvisvg.paper_spec(s);
+        xdvipng.paper_spec(s);
+    }
 
-/// A summary of the I/O that happened on a file. We record its access
-/// pattern; where it came from, if it was used as an input; the cryptographic
-/// digest of the file when it was last read; and the cryptographic digest of
-/// the file as it was last written.
-#[derive(Clone,Debug,Eq,PartialEq)]
-struct FileSummary {
-    access_pattern: AccessPattern,
-    input_origin: InputOrigin,
-    read_digest: Option<DigestData>,
-    write_digest: Option<DigestData>,
-    got_written_to_disk: bool,
-}
+    if let Some(s) = args.value_of("png_dpi") {
+        xdvipng.dpi(ctry!(s.parse::<u32>(); "invalid image resolution \"{}\"", s));
+    }
 
-impl FileSummary {
-    fn new(access_pattern: AccessPattern, input_origin: InputOrigin) -> FileSummary {
-        FileSummary {
-            access_pattern: access_pattern,
-            input_origin: input_origin,
-            read_digest: None,
-            write_digest: None,
-            got_written_to_disk: false,
-        }
+    if args.value_of("svg_fonts") == Some("woff") {
+        xdvisvg.font_mode(SvgFontMode::Woff);
     }
-}
 
+    if let Some(s) = args.value_of("pdf_compression") {
+        xdvipdfmx.compression_level(ctry!(s.parse::<u8>(); "invalid PDF compression level \"{}\"", s));
+    }
 
-/// The CliIoEvents type implements the IoEventBackend. The CLI uses it to
-/// figure out when to rerun the TeX engine; to figure out which files should
-/// be written to disk; and to emit Makefile rules.
-struct CliIoEvents(HashMap<OsString, FileSummary>);
+    if let Some(s) = args.value_of("pdf_version") {
+        let minor = if s.starts_with("1.") { &s[2..] } else { s };
+        xdvipdfmx.pdf_version(ctry!(minor.parse::<u8>(); "invalid PDF version \"{}\"", s));
+    }
 
-impl CliIoEvents {
-    fn new() -> CliIoEvents { CliIoEvents(HashMap::new()) }
-}
+    if args.is_present("no_png_predictors") {
+        xdvipdfmx.png_predictors(false);
+    }
 
-impl IoEventBackend for CliIoEvents {
-    fn output_opened(&mut self, name: &OsStr) {
-        if let Some(summ) = self.0.get_mut(name) {
-            summ.access_pattern = match summ.access_pattern {
-                AccessPattern::Read => AccessPattern::ReadThenWritten,
-                c => c, // identity mapping makes sense for remaining options
-            };
-            return;
-        }
+    if args.is_present("pdfa") {
+        xdvipdfmx.pdfa(true);
+    }
 
-        self.0.insert(name.to_os_string(), FileSummary::new(AccessPattern::Written, InputOrigin::NotInput));
+    if args.is_present("reproducible") {
+        xdvipdfmx.reproducible(true);
     }
 
-    fn stdout_opened(&mut self) {
-        // Life is easier if we track stdout in the same way that we do other
-        // output files.
+    if args.is_present("linearize") {
+        xdvipdfmx.linearize(true);
+    }
 
-        if let Some(summ) = self.0.get_mut(OsStr::new("")) {
-            summ.access_pattern = match summ.access_pattern {
-                AccessPattern::Read => AccessPattern::ReadThenWritten,
-                c => c, // identity mapping makes sense for remaining options
-            };
-            return;
-        }
+    let xmp = match args.value_of_os("xmp") {
+        Some(path) => {
+            let mut data = Vec::new();
+            let mut f = ctry!(File::open(path); "couldn't open XMP metadata file \"{}\"", path.to_string_lossy());
+            ctry!(f.read_to_end(&mut data); "couldn't read XMP metadata file \"{}\"", path.to_string_lossy());
+            Some(data)
+        },
+        None => None,
+    };
 
-        self.0.insert(OsString::from(""), FileSummary::new(AccessPattern::Written, InputOrigin::NotInput));
+    xdvipdfmx.metadata(PdfMetadata {
+        title: args.value_of("title").map(|s| s.to_owned()),
+        author: args.value_of("author").map(|s| s.to_owned()),
+        subject: args.value_of("subject").map(|s| s.to_owned()),
+        keywords: args.value_of("keywords").map(|s| s.to_owned()),
+        xmp: xmp,
+    });
+
+    if let Some(owner) = args.value_of("owner_password") {
+        xdvipdfmx.encryption(Some(PdfEncryption {
+            owner_password: owner.to_owned(),
+            user_password: args.value_of("user_password").unwrap_or("").to_owned(),
+            no_print: args.is_present("no_print"),
+            no_copy: args.is_present("no_copy"),
+        }));
     }
 
-    fn output_closed(&mut self, name: OsString, digest: DigestData) {
-        let mut summ = self.0.get_mut(&name).expect("closing file that wasn't opened?");
-        summ.write_digest = Some(digest);
+    sb.xdvipdfmx_engine(xdvipdfmx)
+      .attach_sources(args.is_present("attach_sources"))
+      .xdvisvg_engine(xdvisvg)
+      .xdvipng_engine(xdvipng);
+
+    // Input and path setup
+
+    if tex_path == "-" {
+        let jobname = args.value_of("jobname").unwrap_or("texput");
+        sb.primary_input_stdin()
+          .tex_input_name(&format!("{}.tex", jobname));
+        tt_note!(status, "reading from standard input; outputs will appear under the base name \"{}\"", jobname);
+    } else if args.is_present("jobname") {
+        return Err(ErrorKind::Msg("--jobname can only be used when reading from standard input".to_owned()).into());
+    } else {
+        sb.primary_input_path(Path::new(tex_path));
     }
 
-    fn input_not_available(&mut self, name: &OsStr) {
-        // For the purposes of file access pattern tracking, an attempt to
-        // open a nonexistent file counts as a read of a zero-size file. I
-        // don't see how such a file could have previously been written, but
-        // let's use the full update logic just in case.
+    // Set up the rest of I/O.
 
-        if let Some(summ) = self.0.get_mut(name) {
-            summ.access_pattern = match summ.access_pattern {
-                AccessPattern::Written => AccessPattern::WrittenThenRead,
-                c => c, // identity mapping makes sense for remaining options
-            };
-            return;
+    sb.print_stdout(args.is_present("print_stdout"));
+    sb.case_insensitive(args.is_present("case_insensitive"));
+
+    if let Some(items) = args.values_of_os("hide") {
+        for v in items {
+            sb.hide_path(v);
         }
+    }
 
-        // Unlike other cases, here we need to fill in the read_digest. `None`
-        // is not an appropriate value since, if the file is written and then
-        // read again later, the `None` will be overwritten; but what matters
-        // is the contents of the file the very first time it was read.
-        let mut fs = FileSummary::new(AccessPattern::Read, InputOrigin::NotInput);
-        fs.read_digest = Some(DigestData::of_nothing());
-        self.0.insert(name.to_os_string(), fs);
+    if let Some(spec) = env::var_os("TEXINPUTS") {
+        sb.search_path_spec(&spec);
     }
 
-    fn input_opened(&mut self, name: &OsStr, origin: InputOrigin) {
-        if let Some(summ) = self.0.get_mut(name) {
-            summ.access_pattern = match summ.access_pattern {
-                AccessPattern::Written => AccessPattern::WrittenThenRead,
-                c => c, // identity mapping makes sense for remaining options
-            };
-            return;
-        }
+    for dir in config.search_path() {
+        sb.search_dir(dir);
+    }
 
-        self.0.insert(name.to_os_string(), FileSummary::new(AccessPattern::Read, origin));
+    let pinned_digest = match args.value_of("bundle_digest") {
+        Some(d) => Some(ctry!(DigestData::from_str(d); "invalid bundle digest \"{}\"", d)),
+        None => None,
+    };
+
+    if pinned_digest.is_some() && !args.is_present("bundle") && !args.is_present("web_bundle") {
+        return Err(ErrorKind::Msg("--bundle-digest can only be used with --bundle or --web-bundle; \
+                                   pin default bundles in the configuration file".to_owned()).into());
     }
 
-    //fn primary_input_opened(&mut self, _origin: InputOrigin) {}
+    let bundles = bundles(args, config, pinned_digest.as_ref(), status).chain_err(|| ErrorKind::BundleUnavailable)?;
 
-    fn input_closed(&mut self, name: OsString, digest: Option<DigestData>) {
-        let mut summ = self.0.get_mut(&name).expect("closing file that wasn't opened?");
+    for b in bundles {
+        sb.boxed_bundle(b);
+    }
 
-        // It's what was in the file the *first* time that it was read that
-        // matters, so don't replace the read digest if it's already got one.
+    for (name, b) in config.hint_io_providers(status) {
+        sb.hint_bundle(&name, b);
+    }
+
+    if args.is_present("before_build") || args.is_present("after_build") {
+        let mut hooks = CommandHooks::new();
 
-        if summ.read_digest.is_none() {
-            summ.read_digest = digest;
+        if let Some(commands) = args.values_of("before_build") {
+            for c in commands {
+                hooks.before(c);
+            }
         }
+
+        if let Some(commands) = args.values_of("after_build") {
+            for c in commands {
+                hooks.after(c);
+            }
+        }
+
+        sb.hooks(Box::new(hooks));
     }
-}
 
+    if args.is_present("skip_unchanged") {
+        // Any change to the arguments could change the outputs.
+        let settings: Vec<String> = env::args_os().skip(1).map(|a| a.to_string_lossy().into_owned()).collect();
+        sb.skip_unchanged(config.build_state_dir()?, &settings.join("\0"));
+    }
 
-/// The ProcessingSession struct runs the whole show when we're actually
-/// processing a file. It merges the command-line arguments and the persistent
-/// configuration to figure out what exactly we're going to do.
+    // The font cache just saves time, so we can do without it.
 
-#[derive(Clone,Copy,Debug,Eq,PartialEq)]
-enum OutputFormat {
-    Aux,
-    Xdv,
-    Pdf,
-    Format,
-}
+    match config.font_cache_dir() {
+        Ok(d) => { sb.font_cache_dir(d); },
+        Err(e) => { tt_warning!(status, "cannot use the font cache"; e); },
+    }
 
-#[derive(Clone,Copy,Debug,Eq,PartialEq)]
-enum PassSetting {
-    Tex,
-    Default,
-    BibtexFirst,
+    Ok(sb)
 }
 
-struct ProcessingSession {
-    io: CliIoSetup,
-    events: CliIoEvents,
-
-    /// If our primary input is an actual file on disk, this is its path.
-    primary_input_path: Option<PathBuf>,
-
-    /// This is the name of the input that we tell TeX. It is the basename of
-    /// the UTF8-ified version of `primary_input_path`; or something anodyne
-    /// if the latter is None. (Name, "texput.tex").
-    primary_input_tex_path: String,
-
-    /// This is the virtual "CWD" that our filesystem accesses use. It is the
-    /// dirname of `primary_input_path`, or an empty path (i.e., corresponding
-    /// to the CWD if `primary_input_path` is None.
-    fs_root: PathBuf,
-
-    /// This is the name of the format file to use. TeX has to open it by name
-    /// internally, so it has to be String compatible.
-    format_path: String,
-
-    /// These are the paths of the various output files as TeX knows them --
-    /// just `primary_input_tex_path` with the extension changed. We store
-    /// them as OsStrings since that's what the main crate currently uses for
-    /// TeX paths, even though I've since realized that it should really just
-    /// use String.
-    tex_aux_path: OsString,
-    tex_xdv_path: OsString,
-    tex_pdf_path: OsString,
-
-    /// If we're writing out Makefile rules, this is where they go. The TeX
-    /// engine doesn't know about this path at all.
-    makefile_output_path: Option<PathBuf>,
-
-    pass: PassSetting,
-    output_format: OutputFormat,
-    tex_rerun_specification: Option<usize>,
-    keep_intermediates: bool,
-    keep_logs: bool,
-    noted_tex_warnings: bool,
-    synctex_enabled: bool,
-}
 
 
-const DEFAULT_MAX_TEX_PASSES: usize = 6;
 
-impl ProcessingSession {
-    fn new(args: &ArgMatches, config: &PersistentConfig,
-           status: &mut TermcolorStatusBackend) -> Result<ProcessingSession> {
-        let format_path = args.value_of("format").unwrap();
-        let tex_path = args.value_of_os("INPUT").unwrap();
+/// The address of the flag behind the cancellation token of the session,
+/// once there is one. A signal handler can't do much more than poke at an
+/// atomic, so this is how it gets at the token.
+static SIGINT_FLAG: AtomicUsize = ATOMIC_USIZE_INIT;
 
-        let output_format = match args.value_of("outfmt").unwrap() {
-            "aux" => OutputFormat::Aux,
-            "xdv" => OutputFormat::Xdv,
-            "pdf" => OutputFormat::Pdf,
-            "format" => OutputFormat::Format,
-            _ => unreachable!()
-        };
+extern "C" fn handle_sigint(_signum: libc::c_int) {
+    let flag = SIGINT_FLAG.load(Ordering::SeqCst) as *const AtomicBool;
 
-        let pass = match args.value_of("pass").unwrap() {
-            "default" => PassSetting::Default,
-            "bibtex_first" => PassSetting::BibtexFirst,
-            "tex" => PassSetting::Tex,
-            _ => unreachable!()
-        };
+    if !flag.is_null() {
+        unsafe { (*flag).store(true, Ordering::SeqCst); }
+    }
 
-        let reruns = match args.value_of("reruns") {
-            Some(s) => Some(usize::from_str_radix(s, 10)?),
-            None => None,
-        };
+    // If cancelling doesn't do the trick, a second Control-C should.
+    unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL); }
+}
 
-        let makefile_output_path = args.value_of_os("makefile_rules").map(|s| s.into());
+/// Make Control-C cancel the processing rather than killing us outright, so
+/// that we can clean up properly.
+fn cancel_on_sigint(token: &CancellationToken) {
+    SIGINT_FLAG.store(token.leak_flag() as *const AtomicBool as usize, Ordering::SeqCst);
+    unsafe { libc::signal(libc::SIGINT, handle_sigint as libc::sighandler_t); }
+}
 
-        // Input and path setup
 
-        let mut io_builder = CliIoBuilder::default();
-        let primary_input_path;
-        let fs_root;
-        let tex_input_stem;
+fn inner(matches: ArgMatches, mut config: PersistentConfig, status: &mut StatusBackend) -> Result<i32> {
+    if let Some(s) = matches.value_of("max_cache_size") {
+        config.set_max_cache_size_mb(Some(ctry!(s.parse::<u64>(); "invalid maximum cache size \"{}\"", s)));
+    }
 
-        if tex_path == "-" {
-            io_builder.primary_input_stdin();
+    if let Some(dir) = matches.value_of_os("cache_dir") {
+        config.set_cache_dir(dir);
+    }
 
-            primary_input_path = None;
-            fs_root = Path::new("");
-            tex_input_stem = OsStr::new("texput.tex");
-            tt_note!(status, "reading from standard input; outputs will appear under the base name \"texput\"");
-        } else {
-            let tex_path = Path::new(tex_path);
-            io_builder.primary_input_path(&tex_path);
+    if let Some(completions_args) = matches.subcommand_matches("completions") {
+        return print_completions(completions_args, &config);
+    }
 
-            primary_input_path = Some(tex_path.to_owned());
+    if let Some(config_args) = matches.subcommand_matches("config") {
+        return config_command(config_args, &config, status);
+    }
 
-            tex_input_stem = match tex_path.file_name() {
-                Some(fname) => fname,
-                None => { return Err(ErrorKind::Msg(format!("can't figure out a basename for input path \"{}\"",
-                                                            tex_path.to_string_lossy())).into()); },
-            };
+    if let Some(build_args) = matches.subcommand_matches("build") {
+        return build_project(&matches, build_args, &config, status);
+    }
 
-            if let Some(par) = tex_path.parent() {
-                fs_root = par;
-                io_builder.filesystem_root(par);
-            } else {
-                return Err(ErrorKind::Msg(format!("can't figure out a parent directory for input path \"{}\"",
-                                                  tex_path.to_string_lossy())).into());
-            }
-        }
+    if let Some(serve_args) = matches.subcommand_matches("serve") {
+        return serve(&matches, serve_args, &config, status);
+    }
 
-        let mut aux_path = Path::new(tex_input_stem).to_owned();
-        aux_path.set_extension("aux");
-        let mut xdv_path = aux_path.clone();
-        xdv_path.set_extension("xdv");
-        let mut pdf_path = aux_path.clone();
-        pdf_path.set_extension("pdf");
+    let inputs: Vec<&OsStr> = matches.values_of_os("INPUT").unwrap().collect();
 
-        // Set up the rest of I/O.
+    // When we're one of the jobs started by `process_in_parallel`, we only
+    // handle the input that we've been assigned.
 
-        io_builder.use_genuine_stdout(args.is_present("print_stdout"));
+    let inputs = match matches.value_of("only_input") {
+        Some(s) => {
+            let i = ctry!(s.parse::<usize>(); "invalid input index \"{}\"", s);
 
-        if let Some(items) = args.values_of_os("hide") {
-            for v in items {
-                io_builder.hide_path(v);
+            match inputs.get(i) {
+                Some(&input) => vec![input],
+                None => { return Err(ErrorKind::Msg(format!("input index {} out of range", i)).into()); },
             }
-        }
+        },
+        None => inputs,
+    };
 
-        if let Some(p) = args.value_of("bundle") {
-            let zb = ctry!(ZipBundle::<File>::open(Path::new(&p)); "error opening bundle");
-            io_builder.bundle(zb);
-        } else if let Some(u) = args.value_of("web_bundle") {
-            let tb = ITarBundle::<HttpITarIoFactory>::new(&u);
-            io_builder.bundle(tb);
-        } else {
-            io_builder.boxed_bundle(config.default_io_provider(status)?);
+    let to_stdout = matches.value_of_os("outdir") == Some(OsStr::new("-"));
+
+    if to_stdout {
+        // These would either write to standard output too, or produce more
+        // than one thing to write there.
+
+        if inputs.len() > 1 {
+            return Err(ErrorKind::Msg("only one input file can be processed with \"-o -\"".to_owned()).into());
         }
 
-        let io = io_builder.create()?;
-
-        // Ready to roll.
-
-        Ok(ProcessingSession {
-            io: io,
-            events: CliIoEvents::new(),
-            pass: pass,
-            primary_input_path: primary_input_path,
-            primary_input_tex_path: tex_input_stem.to_string_lossy().into_owned(),
-            fs_root: fs_root.to_owned(),
-            format_path: format_path.to_owned(),
-            tex_aux_path: aux_path.into_os_string(),
-            tex_xdv_path: xdv_path.into_os_string(),
-            tex_pdf_path: pdf_path.into_os_string(),
-            output_format: output_format,
-            makefile_output_path: makefile_output_path,
-            tex_rerun_specification: reruns,
-            keep_intermediates: args.is_present("keep_intermediates"),
-            keep_logs: args.is_present("keep_logs"),
-            noted_tex_warnings: false,
-            synctex_enabled: args.is_present("synctex"),
-        })
-    }
-
-
-    /// Assess whether we need to rerun an engine. This is the case if there
-    /// was a file that the engine read and then rewrote, and the rewritten
-    /// version is different than the version that it read in.
-    fn rerun_needed(&mut self, status: &mut TermcolorStatusBackend) -> Option<String> {
-        // TODO: we should probably wire up diagnostics since I expect this
-        // stuff could get finicky and we're going to want to be able to
-        // figure out why rerun detection is breaking.
-
-        for (name, info) in &self.events.0 {
-            if info.access_pattern == AccessPattern::ReadThenWritten {
-                let file_changed = match (&info.read_digest, &info.write_digest) {
-                    (&Some(ref d1), &Some(ref d2)) => d1 != d2,
-                    (&None, &Some(_)) => true,
-                    (_, _) => {
-                        // Other cases shouldn't happen.
-                        tt_warning!(status, "internal consistency problem when checking if {} changed",
-                                    name.to_string_lossy());
-                        true
-                    }
-                };
-
-                if file_changed {
-                    return Some(name.to_string_lossy().into_owned());
-                }
+        for &(name, option) in &[("watch", "--watch"), ("dry_run", "--dry-run"), ("print_stdout", "--print")] {
+            if matches.is_present(name) {
+                return Err(ErrorKind::Msg(format!("{} can't be used with \"-o -\"", option)).into());
             }
         }
 
-        return None;
-    }
-
-    #[allow(dead_code)]
-    fn _dump_access_info(&self, status: &mut TermcolorStatusBackend) {
-        for (name, info) in &self.events.0 {
-            if info.access_pattern != AccessPattern::Read {
-                use std::string::ToString;
-                let r = match info.read_digest {
-                    Some(ref d) => d.to_string(),
-                    None => "-".into()
-                };
-                let w = match info.write_digest {
-                    Some(ref d) => d.to_string(),
-                    None => "-".into()
-                };
-                tt_note!(status, "ACCESS: {} {:?} {:?} {:?}",
-                         name.to_string_lossy(),
-                         info.access_pattern, r, w);
-            }
+        if matches.is_present("report") && !matches.is_present("report_file") {
+            return Err(ErrorKind::Msg("--report needs --report-file when used with \"-o -\"".to_owned()).into());
         }
     }
 
-    fn run(&mut self, status: &mut TermcolorStatusBackend) -> Result<i32> {
-        // Do we need to generate the format file?
+    if inputs.len() > 1 {
+        return process_several(&matches, &inputs, &config, status);
+    }
 
-        let generate_format = if self.output_format == OutputFormat::Format {
-            false
-        } else {
-            let fmt_result = {
-                let mut stack = self.io.as_stack();
-                stack.input_open_format(OsStr::new(&self.format_path), status)
-            };
+    if matches.is_present("watch") {
+        return watch(&matches, inputs[0], &config, None, status);
+    }
 
-            match fmt_result {
-                OpenResult::Ok(_) => false,
-                OpenResult::NotAvailable => true,
-                OpenResult::Err(e) => {
-                    return Err(e).chain_err(|| format!("could not open format file {}", self.format_path));
-                },
-            }
-        };
+    let mut sess = session_builder(&matches, inputs[0], &config, status)?.build()?;
+    cancel_on_sigint(sess.cancellation_token());
+    let result = sess.run(status);
+    write_report(&matches, &sess, &result, status);
 
-        if generate_format {
-            tt_note!(status, "generating format \"{}\"", self.format_path);
-            self.make_format_pass(status)?;
-        }
+    if matches.is_present("dry_run") && result.is_ok() {
+        print_inputs_used(&sess)?;
+    }
 
-        // Do the meat of the work.
+    if to_stdout && result.is_ok() {
+        print_output(&mut sess, output_format_name(&matches, &config))?;
+    }
 
-        let result = match self.pass {
-            PassSetting::Tex => self.tex_pass(None, status),
-            PassSetting::Default => self.default_pass(false, status),
-            PassSetting::BibtexFirst => self.default_pass(true, status),
-        };
+    result
+}
 
-        if let Err(e) = result {
-            self.write_files(None, status, true)?;
-            return Err(e);
-        };
 
-        // Write output files and the first line of our Makefile output.
+/// Write the main output of the session, which has been kept in memory, to
+/// standard output.
+fn print_output(sess: &mut ProcessingSession, outfmt: &str) -> Result<()> {
+    let ext = match outfmt {
+        "format" => ".fmt".to_owned(),
+        "svg" | "png" => {
+            return Err(ErrorKind::Msg(format!("{} output has a file for each page, so it can't be written to \
+                                               standard output", outfmt)).into());
+        },
+        _ => format!(".{}", outfmt),
+    };
 
-        let mut mf_dest_maybe = match self.makefile_output_path {
-            Some(ref p) => Some(File::create(p)?),
-            None => None
-        };
+    let data = match sess.take_output_files().into_iter().find(|&(ref name, _)| name.ends_with(&ext)) {
+        Some((_, data)) => data,
+        None => { return Err(ErrorKind::Msg("no output was produced".to_owned()).into()); },
+    };
+
+    let stdout = io::stdout();
+    let mut lock = stdout.lock();
+    ctry!(lock.write_all(&data); "couldn't write the output to standard output");
+    ctry!(lock.flush(); "couldn't write the output to standard output");
+    Ok(())
+}
+
+
+/// Print the files that a dry run found that the document needs, one per
+/// line, with the name of the provider that each came from after a tab.
+fn print_inputs_used(sess: &ProcessingSession) -> Result<()> {
+    let stdout = io::stdout();
+    let mut lock = stdout.lock();
+
+    for (name, provider) in sess.inputs_used() {
+        writeln!(lock, "{}\t{}", name, provider.as_ref().map(|s| s.as_str()).unwrap_or("-"))?;
+    }
 
-        let n_skipped_intermediates = self.write_files(mf_dest_maybe.as_mut(), status, false)?;
+    Ok(())
+}
+
+
+/// Write the machine-readable build report, if one was asked for. It goes to
+/// standard output unless a file was specified.
+fn write_report(matches: &ArgMatches, sess: &ProcessingSession, result: &Result<i32>,
+                status: &mut StatusBackend) {
+    if !matches.is_present("report") {
+        return;
+    }
+
+    let report = sess.report(result);
+
+    let write_result = match matches.value_of_os("report_file") {
+        Some(path) => File::create(path)
+            .map_err(|e| e.into())
+            .and_then(|mut f| report.write_json(&mut f)),
+        None => {
+            let stdout = io::stdout();
+            let mut lock = stdout.lock();
+            report.write_json(&mut lock).and_then(|_| writeln!(lock, "").map_err(|e| e.into()))
+        },
+    };
+
+    if let Err(e) = write_result {
+        tt_warning!(status, "failed to write the build report"; e);
+    }
+}
+
+
+/// Process several documents, one after the other, or `--jobs` at a time.
+fn process_several(matches: &ArgMatches, inputs: &[&OsStr], config: &PersistentConfig,
+                   status: &mut StatusBackend) -> Result<i32> {
+    // These options name a single file, or only make sense for one document.
 
-        if n_skipped_intermediates > 0 {
-            status.note_highlighted("Skipped writing ", &format!("{}", n_skipped_intermediates),
-                                    " intermediate files (use --keep-intermediates to keep them)");
+    for &(name, option) in &[("watch", "--watch"), ("dry_run", "--dry-run"),
+                             ("makefile_rules", "--makefile-rules"), ("audit_io", "--audit-io"),
+                             ("report", "--report")] {
+        if matches.is_present(name) {
+            return Err(ErrorKind::Msg(format!("{} can only be used with a single input file", option)).into());
         }
+    }
+
+    if inputs.iter().any(|&i| i == "-") {
+        return Err(ErrorKind::Msg("standard input can't be processed along with other input files".to_owned()).into());
+    }
 
-        // Finish Makefile rules, maybe.
+    let n_parallel = parse_jobs(matches.value_of("jobs"))?;
 
-        if let Some(ref mut mf_dest) = mf_dest_maybe {
-            ctry!(write!(mf_dest, ": "); "couldn't write to Makefile-rules file");
+    if n_parallel > 1 {
+        // The jobs are told which input to process by its position.
+        let args: Vec<OsString> = env::args_os().skip(1).collect();
+        let jobs = inputs.iter().enumerate().map(|(i, input)| {
+            let mut job_args = vec![OsString::from("--only-input"), OsString::from(i.to_string())];
 
-            if let Some(ref pip) = self.primary_input_path {
-                ctry!(mf_dest.write_all(pip.as_os_str().as_bytes()); "couldn't write to Makefile-rules file");
+            if matches.is_present("log_file") {
+                job_args.push(OsString::from("--append-log"));
             }
 
-            for (name, info) in &self.events.0 {
-                if info.input_origin != InputOrigin::Filesystem {
-                    continue;
-                }
+            job_args.extend(args.iter().cloned());
+            (input.to_string_lossy().into_owned(), job_args)
+        }).collect();
 
-                if info.got_written_to_disk {
-                    // If the file originally came from the filesystem, and it
-                    // was written as well as read, and we actually wrote it
-                    // to disk, there's a circular dependency that's
-                    // inappropriate to express in a Makefile. If it was
-                    // "written" by the engine but we didn't actually write
-                    // those modifications to disk, we're OK. If there's a
-                    // two-stage compilation involving the .aux file, the
-                    // latter case is what arises unless --keep-intermediates
-                    // is specified.
-                    tt_warning!(status, "omitting circular Makefile dependency for {}", name.to_string_lossy());
-                    continue;
-                }
+        return process_in_parallel(jobs, n_parallel, status);
+    }
+
+    let mut n_failed = 0;
+
+    for &input in inputs {
+        let mut sess = session_builder(matches, input, config, status)?.build()?;
+        cancel_on_sigint(sess.cancellation_token());
 
-                ctry!(write!(mf_dest, " \\\n  {}", name.to_string_lossy()); "couldn't write to Makefile-rules file");
+        if let Err(e) = sess.run(status) {
+            if let ErrorKind::Cancelled = *e.kind() {
+                return Err(e);
             }
 
-            ctry!(writeln!(mf_dest, ""); "couldn't write to Makefile-rules file");
+            status.report_error(&e);
+            n_failed += 1;
         }
+    }
 
-        // All done.
-
-        Ok(0)
+    if n_failed > 0 {
+        return Err(ErrorKind::Msg(format!("{} of {} documents could not be processed", n_failed, inputs.len())).into());
     }
 
+    Ok(0)
+}
 
-    fn write_files(&mut self, mut mf_dest_maybe: Option<&mut File>, status: &mut
-                   TermcolorStatusBackend, only_logs: bool) -> Result<u32> {
-        let mut n_skipped_intermediates = 0;
-        for (name, contents) in &*self.io.mem.files.borrow() {
-            if name == self.io.mem.stdout_key() {
-                continue;
-            }
+/// Process the documents of the project whose manifest is in the current
+/// directory or one of its parents: all of them, or the ones named on the
+/// command line.
+fn build_project(matches: &ArgMatches, build_args: &ArgMatches, config: &PersistentConfig,
+                 status: &mut StatusBackend) -> Result<i32> {
+    let cwd = env::current_dir()?;
+
+    let manifest = match ProjectManifest::find(&cwd)? {
+        Some(m) => m,
+        None => {
+            return Err(ErrorKind::Msg(format!("no project manifest \"{}\" in \"{}\" or its parent directories",
+                                              MANIFEST_FILE_NAME, cwd.display())).into());
+        },
+    };
+
+    let docs: Vec<&DocumentInfo> = match build_args.values_of("DOCUMENT") {
+        Some(names) => {
+            let mut docs = Vec::new();
 
-            let sname = name.to_string_lossy();
-            let mut summ = self.events.0.get_mut(name).unwrap();
-
-            if !only_logs && (self.output_format == OutputFormat::Aux) {
-                // In this mode we're only writing the .aux file. I initially
-                // wanted to be clever-ish and output all auxiliary-type
-                // files, but doing so ended up causing non-obvious problems
-                // for my use case, which involves using Ninja to manage
-                // dependencies.
-                if !sname.ends_with(".aux") {
-                    continue;
+            for name in names {
+                match manifest.document(name) {
+                    Some(d) => docs.push(d),
+                    None => {
+                        return Err(ErrorKind::Msg(format!("the project has no document named \"{}\"", name)).into());
+                    },
                 }
-            } else if summ.access_pattern != AccessPattern::Written && !self.keep_intermediates {
-                n_skipped_intermediates += 1;
-                continue;
             }
 
-            let is_logfile = sname.ends_with(".log") || sname.ends_with(".blg");
+            docs
+        },
+        None => manifest.documents().iter().collect(),
+    };
+
+    // The manifest comes with the documents, so if they're untrusted, so is
+    // it: it mustn't be able to run commands or read files from elsewhere.
 
-            if is_logfile && !self.keep_logs {
-                continue;
+    let untrusted = matches.is_present("untrusted");
+
+    if untrusted {
+        for doc in &docs {
+            if !doc.before_build.is_empty() || !doc.after_build.is_empty() {
+                return Err(ErrorKind::Msg(format!("document \"{}\" has build hooks, which can't be run for \
+                                                   untrusted documents", doc.name)).into());
             }
 
-            if !is_logfile && only_logs {
-                continue;
+            if let Some(dir) = doc.search_path.iter().find(|d| !is_plain_relative_path(Path::new(d))) {
+                return Err(ErrorKind::Msg(format!("document \"{}\" searches \"{}\", but untrusted documents \
+                                                   can only search inside the project", doc.name, dir)).into());
             }
+        }
+    }
+
+    let only_cached = matches.is_present("only_cached");
+    let n_parallel = parse_jobs(build_args.value_of("jobs").or(matches.value_of("jobs")))?;
+    let profile_name = build_args.value_of("profile").or(matches.value_of("profile"));
 
-            if contents.len() == 0 {
-                status.note_highlighted("Not writing ", &sname, ": it would be empty.");
-                continue;
+    let profile = match profile_name {
+        Some(name) => find_profile(name, Some(&manifest), config)?,
+        None => BuildProfile::default(),
+    };
+
+    if n_parallel > 1 && docs.len() > 1 {
+        let jobs = docs.iter().map(|d| {
+            let mut job_args = vec![OsString::from("--chatter"),
+                                    OsString::from(matches.value_of("chatter_level").unwrap()),
+                                    OsString::from("--color"),
+                                    OsString::from(matches.value_of("color").unwrap()),
+                                    OsString::from("--error-format"),
+                                    OsString::from(matches.value_of("error_format").unwrap())];
+
+            if matches.is_present("quiet") {
+                job_args.push(OsString::from("--quiet"));
             }
 
-            let mut real_path = self.fs_root.clone();
-            real_path.push(name);
+            for _ in 0..matches.occurrences_of("verbose") {
+                job_args.push(OsString::from("--verbose"));
+            }
 
-            status.note_highlighted("Writing ", &real_path.to_string_lossy(), &format!(" ({} bytes)", contents.len()));
+            if only_cached {
+                job_args.push(OsString::from("--only-cached"));
+            }
 
-            let mut f = File::create(&real_path)?;
-            f.write_all(contents)?;
-            summ.got_written_to_disk = true;
+            if untrusted {
+                job_args.push(OsString::from("--untrusted"));
+            }
 
-            if let Some(ref mut mf_dest) = mf_dest_maybe {
-                // Maybe it'd be better to have this just be a warning? But if
-                // the program is supposed to write the file, you don't want
-                // it exiting with error code zero if it couldn't do that
-                // successfully.
-                //
-                // Not quite sure why, but I can't pull out the target path
-                // here. I think 'self' is borrow inside the loop?
-                ctry!(write!(mf_dest, "{} ", real_path.to_string_lossy()); "couldn't write to Makefile-rules file");
+            if let Some(dir) = matches.value_of_os("cache_dir") {
+                job_args.push(OsString::from("--cache-dir"));
+                job_args.push(dir.to_owned());
             }
-        }
-        Ok(n_skipped_intermediates)
-    }
 
-    /// The "default" pass really runs a bunch of sub-passes. It is a "Do What
-    /// I Mean" operation.
-    fn default_pass(&mut self, bibtex_first: bool, status: &mut TermcolorStatusBackend) -> Result<i32> {
-        // If `bibtex_first` is true, we start by running bibtex, and run
-        // proceed with the standard rerun logic. Otherwise, we run TeX,
-        // auto-detect whether we need to run bibtex, possibly run it, and
-        // then go ahead.
+            if let Some(path) = matches.value_of_os("log_file") {
+                job_args.push(OsString::from("--log-file"));
+                job_args.push(path.to_owned());
+                job_args.push(OsString::from("--append-log"));
+            }
 
-        let mut rerun_result = if bibtex_first {
-            self.bibtex_pass(status)?;
-            Some(String::new())
-        } else {
-            self.tex_pass(None, status)?;
-            //
-
-            let use_bibtex = {
-                if let Some(auxdata) = self.io.mem.files.borrow().get(&self.tex_aux_path) {
-                    let cite_aut = AcAutomaton::new(vec!["\\bibdata"]);
-                    cite_aut.find(auxdata).next().is_some()
-                } else {
-                    false
-                }
-            };
+            if let Some(name) = profile_name {
+                job_args.push(OsString::from("--profile"));
+                job_args.push(OsString::from(name));
+            }
 
-            if use_bibtex {
-                self.bibtex_pass(status)?;
-                Some(String::new())
-            } else {
-                self.rerun_needed(status)
+            if let Some(kinds) = matches.values_of("hide_warnings") {
+                for kind in kinds {
+                    job_args.push(OsString::from("--hide-warnings"));
+                    job_args.push(OsString::from(kind));
+                }
             }
-        };
 
-        // Now we enter the main rerun loop.
+            job_args.push(OsString::from("build"));
+            job_args.push(OsString::from(d.name.as_str()));
+            (d.name.clone(), job_args)
+        }).collect();
 
-        let (pass_count, reruns_fixed) = match self.tex_rerun_specification {
-            Some(n) => (n, true),
-            None => (DEFAULT_MAX_TEX_PASSES, false),
-        };
+        return process_in_parallel(jobs, n_parallel, status);
+    }
+
+    let mut n_sessions = 0;
+    let mut n_failed = 0;
+
+    for doc in docs {
+        for output in &doc.outputs {
+            let mut sb = manifest.session_builder(doc, output)?;
+            profile.configure_session(&mut sb);
+            sb.untrusted(untrusted);
 
-        for i in 0..pass_count {
-            let rerun_explanation = if reruns_fixed {
-                "I was told to".to_owned()
+            let mut xdvipdfmx = XdvipdfmxEngine::new();
+            profile.configure_xdvipdfmx(&mut xdvipdfmx);
+            sb.xdvipdfmx_engine(xdvipdfmx);
+
+            let bundles = if manifest.bundles().is_empty() {
+                config.default_io_providers(only_cached, status)
             } else {
-                match rerun_result {
-                    Some(ref s) => {
-                        if s == "" {
-                            "bibtex was run".to_owned()
-                        } else {
-                            format!("\"{}\" changed", s)
-                        }
-                    },
-                    None => {
-                        break;
-                    }
-                }
+                config.io_providers(manifest.bundles(), only_cached, status)
             };
+            let bundles = bundles.chain_err(|| ErrorKind::BundleUnavailable)?;
 
-            // We're restarting the engine afresh, so clear the read inputs.
-            // We do *not* clear the entire HashMap since we want to remember,
-            // e.g., that bibtex wrote out the .bbl file, since that way we
-            // can later know that it's OK to delete. I am not super confident
-            // that the access_pattern data can just be left as-is when we do
-            // this, but, uh, so far it seems to work.
-            for summ in self.events.0.values_mut() {
-                summ.read_digest = None;
+            for b in bundles {
+                sb.boxed_bundle(b);
             }
 
-            self.tex_pass(Some(&rerun_explanation), status)?;
+            for (name, b) in config.hint_io_providers(status) {
+                sb.hint_bundle(&name, b);
+            }
 
-            if !reruns_fixed {
-                rerun_result = self.rerun_needed(status);
+            if let Some(spec) = env::var_os("TEXINPUTS") {
+                sb.search_path_spec(&spec);
+            }
 
-                if rerun_result.is_some() && i == DEFAULT_MAX_TEX_PASSES - 1 {
-                    tt_warning!(status, "TeX rerun seems needed, but stopping at {} passes", DEFAULT_MAX_TEX_PASSES);
-                    break;
-                }
+            for dir in config.search_path() {
+                sb.search_dir(dir);
             }
-        }
 
-        // And finally, xdvipdfmx. Maybe.
+            if let Ok(d) = config.font_cache_dir() {
+                sb.font_cache_dir(d);
+            }
 
-        if let OutputFormat::Pdf = self.output_format {
-            self.xdvipdfmx_pass(status)?;
-        }
+            status.note_highlighted("Building ", &doc.name, &format!(" ({}) ...", output));
+            n_sessions += 1;
 
-        Ok(0)
-    }
+            let mut sess = sb.build()?;
+            cancel_on_sigint(sess.cancellation_token());
 
+            if let Err(e) = sess.run(status) {
+                if let ErrorKind::Cancelled = *e.kind() {
+                    return Err(e);
+                }
 
-    /// Use the TeX engine to generate a format file.
-    fn make_format_pass(&mut self, status: &mut TermcolorStatusBackend) -> Result<i32> {
-        if self.io.bundle.is_none() {
-            return Err(ErrorKind::Msg("cannot create formats without using a bundle".to_owned()).into())
+                status.report_error(&e);
+                n_failed += 1;
+            }
         }
+    }
 
-        // PathBuf.file_stem() doesn't do what we want since it only strips
-        // one extension. As of 1.17, the compiler needs a type annotation for
-        // some reason, which is why we use the `r` variable.
-        let r: Result<&str> = self.format_path.splitn(2, ".").next().ok_or_else(
-            || ErrorKind::Msg(format!("incomprehensible format file name \"{}\"", self.format_path)).into()
-        );
-        let stem = r?;
-
-        let result = {
-            let mut stack = self.io.as_stack_for_format(&format!("\\input tectonic-format-{}.tex", stem));
-            TexEngine::new()
-                    .halt_on_error_mode(true)
-                    .initex_mode(true)
-                    .process(&mut stack, &mut self.events, status, "UNUSED.fmt.gz", "texput")
-        };
+    if n_failed > 0 {
+        return Err(ErrorKind::Msg(format!("{} of {} outputs could not be built", n_failed, n_sessions)).into());
+    }
 
-        match result {
-            Ok(TexResult::Spotless) => {},
-            Ok(TexResult::Warnings) => {
-                tt_warning!(status, "warnings were issued by the TeX engine; use --print and/or --keep-logs for details.");
-            },
-            Ok(TexResult::Errors) => {
-                tt_error!(status, "errors were issued by the TeX engine; use --print and/or --keep-logs for details.");
-                return Err(ErrorKind::Msg("unhandled TeX engine error".to_owned()).into());
-            },
-            Err(e) => {
-                if let Some(output) = self.io.mem.files.borrow().get(self.io.mem.stdout_key()) {
-                    tt_error!(status, "something bad happened inside TeX; its output follows:\n");
-                    tt_error_styled!(status, "===============================================================================");
-                    status.dump_to_stderr(&output);
-                    tt_error_styled!(status, "===============================================================================");
-                    tt_error_styled!(status, "");
-                }
+    Ok(0)
+}
 
-                return Err(e);
-            }
-        }
+/// Parse the value of a `--jobs` option.
+fn parse_jobs(value: Option<&str>) -> Result<usize> {
+    match value {
+        Some(s) => Ok(ctry!(s.parse::<usize>(); "invalid number of jobs \"{}\"", s)),
+        None => Ok(1),
+    }
+}
 
-        // Now we can write the format file to its special location. In
-        // principle we could stream the format file directly to the staging
-        // area as we ran the TeX engine, but we don't bother.
+/// Process several documents at once by running a copy of this program for
+/// each of them, up to `n_parallel` at a time. Each job is a name to report
+/// it by and the arguments to give its copy. The engines keep their state
+/// in global variables, so each process can only run one document at a
+/// time. The copies share the bundle and format caches on disk, which are
+/// safe to use from several processes at once. We collect the output of
+/// each copy and print it when the copy finishes, so that the messages about
+/// different documents don't get mixed together.
+fn process_in_parallel(jobs: Vec<(String, Vec<OsString>)>, n_parallel: usize,
+                       status: &mut StatusBackend) -> Result<i32> {
+    let exe = ctry!(env::current_exe(); "couldn't find this program to run copies of it");
+    let n_jobs = jobs.len();
+    let jobs = Arc::new(jobs);
+    let next_job = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..cmp::min(n_parallel, n_jobs) {
+        let exe = exe.clone();
+        let jobs = jobs.clone();
+        let next_job = next_job.clone();
+        let tx = tx.clone();
+
+        thread::spawn(move || {
+            loop {
+                let i = next_job.fetch_add(1, Ordering::SeqCst);
+
+                if i >= n_jobs {
+                    break;
+                }
 
-        let bundle = &mut *self.io.bundle.as_mut().unwrap();
+                let output = process::Command::new(&exe).args(&jobs[i].1).output();
 
-        for (name, contents) in &*self.io.mem.files.borrow() {
-            if name == self.io.mem.stdout_key() {
-                continue;
+                if tx.send((i, output)).is_err() {
+                    break;
+                }
             }
+        });
+    }
 
-            let sname = name.to_string_lossy();
+    drop(tx);
+    let mut n_failed = 0;
 
-            if !sname.ends_with(".fmt.gz") {
-                continue;
-            }
+    for (i, output) in rx {
+        let name = &jobs[i].0;
 
-            // Note that we intentionally pass 'stem', not 'name'.
-            ctry!(bundle.write_format(stem, contents, status); "cannot write format file {}", sname);
+        match output {
+            Ok(o) => {
+                status.note_highlighted("Finished ", name, if o.status.success() { "" } else { " (failed)" });
+                let stdout = io::stdout();
+                let stderr = io::stderr();
+                stdout.lock().write_all(&o.stdout)?;
+                stderr.lock().write_all(&o.stderr)?;
+
+                if !o.status.success() {
+                    n_failed += 1;
+                }
+            },
+            Err(e) => {
+                tt_error!(status, "couldn't start processing \"{}\"", name; e.into());
+                n_failed += 1;
+            },
         }
+    }
 
-        // All done. Clear the memory layer since this was a special preparatory step.
-        self.io.mem.files.borrow_mut().clear();
+    if n_failed > 0 {
+        return Err(ErrorKind::Msg(format!("{} of {} documents could not be processed", n_failed, n_jobs)).into());
+    }
 
-        Ok(0)
+    Ok(0)
+}
+
+
+/// How often we look at the watched files to see if they've changed.
+const WATCH_POLL_INTERVAL: u64 = 500; // milliseconds
+
+/// Get the modification times of the files in `paths`, with `None` for ones
+/// that can't be looked at (e.g., because they've been deleted).
+fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter().map(|p| p.metadata().and_then(|md| md.modified()).ok()).collect()
+}
+
+/// Process the document over and over: every time that one of the files
+/// that it read changes, process it again. The set of files comes from the
+/// I/O layer, so it includes everything that TeX and friends looked at on
+/// the filesystem, and it's updated after each run. This only ends when
+/// something goes wrong outside of the processing itself, or the user hits
+/// Control-C. If there's a preview `server`, it's given the outputs of each
+/// successful run.
+fn watch(matches: &ArgMatches, tex_path: &OsStr, config: &PersistentConfig, server: Option<&PreviewServer>,
+         status: &mut StatusBackend) -> Result<i32> {
+    if tex_path == "-" {
+        return Err(ErrorKind::Msg("can't watch for changes when reading from standard input".to_owned()).into());
     }
 
+    let interval = Duration::from_millis(WATCH_POLL_INTERVAL);
 
-    /// Run one pass of the TeX engine.
-    fn tex_pass(&mut self, rerun_explanation: Option<&str>, status: &mut TermcolorStatusBackend) -> Result<i32> {
-        let result = {
-            let mut stack = self.io.as_stack();
-            if let Some(s) = rerun_explanation {
-                status.note_highlighted("Rerunning ", "TeX", &format!(" because {} ...", s));
-            } else {
-                status.note_highlighted("Running ", "TeX", " ...");
-            }
+    loop {
+        let mut sess = session_builder(matches, tex_path, config, status)?.build()?;
+        cancel_on_sigint(sess.cancellation_token());
 
-            TexEngine::new()
-                    .halt_on_error_mode(true)
-                    .initex_mode(self.output_format == OutputFormat::Format)
-                    .synctex(self.synctex_enabled)
-                    .process(&mut stack, &mut self.events, status, &self.format_path, &self.primary_input_tex_path)
-        };
+        let result = sess.run(status);
+        write_report(matches, &sess, &result, status);
 
         match result {
-            Ok(TexResult::Spotless) => {},
-            Ok(TexResult::Warnings) => {
-                if !self.noted_tex_warnings {
-                    tt_note!(status, "warnings were issued by the TeX engine; use --print and/or --keep-logs for details.");
-                    self.noted_tex_warnings = true;
-                }
-            },
-            Ok(TexResult::Errors) => {
-                if !self.noted_tex_warnings {
-                    // Weakness: if a first pass produces warnings and a
-                    // second pass produces ignored errors, we won't say so.
-                    tt_warning!(status, "errors were issued by the TeX engine, but were ignored; \
-                                         use --print and/or --keep-logs for details.");
-                    self.noted_tex_warnings = true;
+            Ok(_) => {
+                if let Some(s) = server {
+                    s.update(sess.output_paths());
                 }
             },
             Err(e) => {
-                if let Some(output) = self.io.mem.files.borrow().get(self.io.mem.stdout_key()) {
-                    tt_error!(status, "something bad happened inside TeX; its output follows:\n");
-                    tt_error_styled!(status, "===============================================================================");
-                    status.dump_to_stderr(&output);
-                    tt_error_styled!(status, "===============================================================================");
-                    tt_error_styled!(status, "");
+                if let ErrorKind::Cancelled = *e.kind() {
+                    return Err(e);
                 }
 
-                return Err(e);
-            }
+                status.report_error(&e);
+            },
         }
 
-        Ok(0)
-    }
+        // While we're waiting, Control-C should just take us out.
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL); }
 
+        let paths = sess.filesystem_inputs();
+        tt_note!(status, "watching {} files for changes; press Control-C to stop", paths.len());
 
-    fn bibtex_pass(&mut self, status: &mut TermcolorStatusBackend) -> Result<i32> {
-        let result = {
-            let mut stack = self.io.as_stack();
-            let mut engine = BibtexEngine::new ();
-            status.note_highlighted("Running ", "BibTeX", " ...");
-            engine.process(&mut stack, &mut self.events, status,
-                           &self.tex_aux_path.to_str().unwrap())
-        };
+        let mut times = modification_times(&paths);
 
-        match result {
-            Ok(TexResult::Spotless) => {},
-            Ok(TexResult::Warnings) => {
-                tt_note!(status, "warnings were issued by BibTeX; use --print and/or --keep-logs for details.");
-            },
-            Ok(TexResult::Errors) => {
-                tt_warning!(status, "errors were issued by BibTeX, but were ignored; \
-                                          use --print and/or --keep-logs for details.");
-            },
-            Err(e) => {
-                if let Some(output) = self.io.mem.files.borrow().get(self.io.mem.stdout_key()) {
-                    tt_error!(status, "something bad happened inside BibTeX; its output follows:\n");
-                    tt_error_styled!(status, "===============================================================================");
-                    status.dump_to_stderr(&output);
-                    tt_error_styled!(status, "===============================================================================");
-                    tt_error_styled!(status, "");
-                }
+        loop {
+            thread::sleep(interval);
 
-                return Err(e);
+            if modification_times(&paths) != times {
+                break;
             }
         }
 
-        Ok(0)
-    }
+        // Editors often save files in several steps, and several files may
+        // be changing at once, so wait until things have settled down.
+
+        loop {
+            thread::sleep(interval);
+            let new_times = modification_times(&paths);
 
+            if new_times == times {
+                break;
+            }
 
-    fn xdvipdfmx_pass(&mut self, status: &mut TermcolorStatusBackend) -> Result<i32> {
-        {
-            let mut stack = self.io.as_stack();
-            let mut engine = XdvipdfmxEngine::new ();
-            status.note_highlighted("Running ", "xdvipdfmx", " ...");
-            engine.process(&mut stack, &mut self.events, status,
-                           &self.tex_xdv_path.to_str().unwrap(), &self.tex_pdf_path.to_str().unwrap())?;
+            times = new_times;
         }
 
-        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
-        Ok(0)
+        tt_note!(status, "changes detected; processing again");
     }
 }
 
 
-fn inner(matches: ArgMatches, config: PersistentConfig, status: &mut TermcolorStatusBackend) -> Result<i32> {
-    let mut sess = ProcessingSession::new(&matches, &config, status)?;
-    sess.run(status)
+/// Process the document as in watch mode, and show the results in a web
+/// browser that reloads them every time the document is processed.
+fn serve(matches: &ArgMatches, serve_args: &ArgMatches, config: &PersistentConfig,
+         status: &mut StatusBackend) -> Result<i32> {
+    let tex_path = serve_args.value_of_os("INPUT").unwrap();
+    let port = serve_args.value_of("port").unwrap();
+    let port = ctry!(port.parse::<u16>(); "invalid port number \"{}\"", port);
+
+    let server = ctry!(PreviewServer::start(("127.0.0.1", port)); "couldn't start the preview server");
+    status.note_highlighted("Serving a preview at ", &format!("http://{}/", server.addr()), "");
+
+    watch(matches, tex_path, config, Some(&server), status)
 }
 
 
-fn main() {
-    let matches = App::new("Tectonic")
+/// Print a completion script for the shell named in `args`. The names of
+/// the profiles in the configuration, and in the manifest of the project
+/// that we're in, if any, are baked in, so that `--profile` can be
+/// completed.
+fn print_completions(args: &ArgMatches, config: &PersistentConfig) -> Result<i32> {
+    let shell = match args.value_of("SHELL").unwrap().parse::<Shell>() {
+        Ok(s) => s,
+        Err(e) => { return Err(ErrorKind::Msg(e).into()); },
+    };
+
+    let mut names: Vec<String> = config.profile_names();
+
+    if let Ok(Some(manifest)) = ProjectManifest::find(env::current_dir()?) {
+        names.extend(manifest.profile_names().iter().map(|s| s.to_string()));
+    }
+
+    names.sort();
+    names.dedup();
+
+    let names: Vec<&str> = names.iter().map(|s| s.as_str()).collect();
+    make_app(Some(&names[..])).gen_completions_to("tectonic", shell, &mut io::stdout());
+    Ok(0)
+}
+
+
+/// Show or change the configuration.
+fn config_command(args: &ArgMatches, config: &PersistentConfig, status: &mut StatusBackend) -> Result<i32> {
+    match args.subcommand() {
+        ("show", _) => {
+            print!("{}", config.to_toml()?);
+        },
+        ("path", _) => {
+            println!("{}", PersistentConfig::user_config_path(false)?.display());
+        },
+        ("set", Some(set_args)) => {
+            PersistentConfig::set_user_setting(set_args.value_of("KEY").unwrap(),
+                                               set_args.value_of("VALUE").unwrap())?;
+        },
+        ("pin", _) => {
+            let path = config.pin_default_bundles(status)?;
+            tt_note!(status, "recorded the bundle digests in {}", path.display());
+        },
+        _ => unreachable!(),
+    }
+
+    Ok(0)
+}
+
+
+/// Define the command-line interface. If `profiles` is given, only those
+/// names are accepted for `--profile`; this is for generating shell
+/// completions that know about the configured profiles.
+fn make_app<'a>(profiles: Option<&[&'a str]>) -> App<'a, 'a> {
+    let profile = Arg::with_name("profile")
+        .long("profile")
+        .value_name("NAME")
+        .help("Use the processing options of this profile, such as \"draft\" or \"final\". Profiles are \
+               defined in the configuration file or the project manifest; other options override them.");
+
+    let profile = match profiles {
+        Some(names) => profile.possible_values(names),
+        None => profile,
+    };
+
+    App::new("Tectonic")
         .version("0.1.6-dev")
         .about("Process a (La)TeX document.")
+        .after_help("EXIT STATUS:
+    0      Success
+    1      Failure for some other reason, including bad arguments
+    2      The document couldn't be processed because of errors in it
+    3      The bundle of support files couldn't be used, e.g. because the network is down
+    4      A file couldn't be read or written
+    5      A limit set by --max-seconds, --max-pages, or --max-passes was exceeded
+    101    Tectonic crashed; please report this as a bug
+    130    Processing was interrupted")
         .arg(Arg::with_name("format")
              .long("format")
              .value_name("PATH")
@@ -995,8 +1056,10 @@ fn main() {
         .arg(Arg::with_name("bundle")
              .long("bundle")
              .short("b")
-             .value_name("PATH")
-             .help("Use this Zip-format bundle file to find resource files instead of the default.")
+             .value_name("BUNDLE")
+             .help("Use the bundle with this name in the configuration file, or else this bundle file (Zip \
+                    format, or Zstandard format if named *.zst) or unpacked bundle directory, to find resource \
+                    files instead of the default.")
              .takes_value(true))
         .arg(Arg::with_name("web_bundle")
              .long("web-bundle")
@@ -1004,22 +1067,205 @@ fn main() {
              .value_name("URL")
              .help("Use this URL find resource files instead of the default.")
              .takes_value(true))
+        .arg(Arg::with_name("bundle_digest")
+             .long("bundle-digest")
+             .value_name("DIGEST")
+             .help("Refuse to use the bundle given with --bundle or --web-bundle unless its SHA256 \
+                    digest is <DIGEST>.")
+             .takes_value(true))
+        .arg(Arg::with_name("only_cached")
+             .long("only-cached")
+             .short("C")
+             .help("Use only resource files cached locally; never touch the network.")
+             .conflicts_with("web_bundle"))
+        .arg(Arg::with_name("max_cache_size")
+             .long("max-cache-size")
+             .value_name("MB")
+             .help("Limit the local cache of resource files to this many megabytes, evicting the \
+                    least recently used files as needed."))
+        .arg(Arg::with_name("cache_dir")
+             .long("cache-dir")
+             .value_name("DIR")
+             .help("Keep the local cache of resource files, formats, and fonts in <DIR> rather than in the \
+                    usual place."))
+        .arg(Arg::with_name("max_seconds")
+             .long("max-seconds")
+             .value_name("SECS")
+             .help("Abort if processing takes longer than this many seconds."))
+        .arg(Arg::with_name("max_pages")
+             .long("max-pages")
+             .value_name("COUNT")
+             .help("Abort if the TeX engine produces more than this many pages."))
+        .arg(Arg::with_name("max_passes")
+             .long("max-passes")
+             .value_name("COUNT")
+             .help("Abort if the document needs more than this many passes of the TeX engine."))
+        .arg(Arg::with_name("untrusted")
+             .long("untrusted")
+             .help("Process the document as if it might be malicious: only read the usual kinds of TeX \
+                    inputs from the directory of the input and below, ignore TEXINPUTS and the configured \
+                    search path, don't run external programs like biber, don't write files outside the \
+                    output directory, ignore the project's configuration file, and limit the amount of \
+                    I/O, and the processing time, pages, and TeX passes unless other limits are given. \
+                    TeX's shell escape is never available in any case."))
         .arg(Arg::with_name("outfmt")
              .long("outfmt")
              .value_name("FORMAT")
-             .help("The kind of output to generate.")
-             .possible_values(&["pdf", "xdv", "aux", "format"])
-             .default_value("pdf"))
+             .help("The kind of output to generate. \"xdv\" stops after the TeX engine and saves its \
+                    extended-DVI output without converting it to PDF. \"svg\" and \"png\" write one file per page, \
+                    while \"txt\" writes the document's text to a single file.")
+             .possible_values(&["pdf", "svg", "png", "txt", "xdv", "aux", "format"]))
+        .arg(Arg::with_name("paper")
+             .long("paper")
+             .value_name("SPEC")
+             .help("The default paper size of PDF, SVG, and PNG output, as a name such as \"a4\" or a \
+                    width and height such as \"20cm,30cm\"."))
+        .arg(Arg::with_name("svg_fonts")
+             .long("svg-fonts")
+             .value_name("MODE")
+             .help("How SVG output draws text: \"paths\" turns every glyph into a path, while \"woff\" \
+                    refers to WOFF versions of the document's OpenType and TrueType fonts.")
+             .possible_values(&["paths", "woff"])
+             .default_value("paths"))
+        .arg(Arg::with_name("png_dpi")
+             .long("png-dpi")
+             .value_name("DPI")
+             .help("The resolution of PNG output, in pixels per inch. The default is 150."))
+        .arg(Arg::with_name("pdf_compression")
+             .long("pdf-compression")
+             .value_name("LEVEL")
+             .help("The compression level of PDF output, from 0 (none) to 9 (best)."))
+        .arg(Arg::with_name("pdf_version")
+             .long("pdf-version")
+             .value_name("VERSION")
+             .help("The version of PDF to generate, from 1.3 to 1.7."))
+        .arg(Arg::with_name("no_png_predictors")
+             .long("no-png-predictors")
+             .help("Don't use PNG predictors when compressing images in PDF output."))
+        .arg(Arg::with_name("reproducible")
+             .long("reproducible")
+             .help("Generate PDF output that doesn't depend on the time of the run, so that identical inputs \
+                    give byte-identical PDFs."))
+        .arg(Arg::with_name("linearize")
+             .long("linearize")
+             .help("Generate linearized (\"fast web view\") PDF output, whose first page can be displayed \
+                    before the whole file has been downloaded."))
+        .arg(Arg::with_name("attach_sources")
+             .long("attach-sources")
+             .help("Attach the document's sources to the PDF output: the main input file and every other \
+                    file that it read from the filesystem, such as bibliographies and figures."))
+        .arg(Arg::with_name("title")
+             .long("title")
+             .value_name("TEXT")
+             .help("The title to record in the PDF output's metadata, overriding the document's."))
+        .arg(Arg::with_name("author")
+             .long("author")
+             .value_name("TEXT")
+             .help("The author to record in the PDF output's metadata, overriding the document's."))
+        .arg(Arg::with_name("subject")
+             .long("subject")
+             .value_name("TEXT")
+             .help("The subject to record in the PDF output's metadata, overriding the document's."))
+        .arg(Arg::with_name("keywords")
+             .long("keywords")
+             .value_name("TEXT")
+             .help("The keywords to record in the PDF output's metadata, overriding the document's."))
+        .arg(Arg::with_name("xmp")
+             .long("xmp")
+             .value_name("PATH")
+             .help("An XMP packet to store as the PDF output's metadata stream."))
+        .arg(Arg::with_name("owner_password")
+             .long("owner-password")
+             .value_name("PASSWORD")
+             .help("Encrypt the PDF output with AES-256, using this password to grant full access to it."))
+        .arg(Arg::with_name("user_password")
+             .long("user-password")
+             .value_name("PASSWORD")
+             .requires("owner_password")
+             .help("The password needed to open the encrypted PDF output. By default, anyone can open it."))
+        .arg(Arg::with_name("no_print")
+             .long("no-print")
+             .requires("owner_password")
+             .help("Forbid printing the encrypted PDF output."))
+        .arg(Arg::with_name("no_copy")
+             .long("no-copy")
+             .requires("owner_password")
+             .help("Forbid copying text and graphics out of the encrypted PDF output."))
+        .arg(Arg::with_name("pdfa")
+             .long("pdfa")
+             .help("Generate archival PDF/A-1b output. Fails if the document uses transparency or fonts \
+                    that can't be embedded."))
         .arg(Arg::with_name("makefile_rules")
              .long("makefile-rules")
              .value_name("PATH")
              .help("Write Makefile-format rules expressing the dependencies of this run to <PATH>."))
+        .arg(Arg::with_name("dep_file")
+             .long("dep-file")
+             .help("Write a Make/Ninja dependency file listing the outputs and every input file that \
+                    was read from disk, named like the outputs but with the extension \".d\"."))
+        .arg(Arg::with_name("audit_io")
+             .long("audit-io")
+             .value_name("PATH")
+             .help("Write a JSON record of every file that the engines opened to <PATH>."))
+        .arg(Arg::with_name("dry_run")
+             .long("dry-run")
+             .conflicts_with("watch")
+             .help("Process the document without writing any files, then print the names of all of the \
+                    files that it needs, with where each one came from. This fills the bundle cache too."))
+        .arg(Arg::with_name("before_build")
+             .long("before-build")
+             .value_name("COMMAND")
+             .multiple(true)
+             .number_of_values(1)
+             .help("Run this shell command before processing. Can be given more than once."))
+        .arg(Arg::with_name("after_build")
+             .long("after-build")
+             .value_name("COMMAND")
+             .multiple(true)
+             .number_of_values(1)
+             .help("Run this shell command after processing, even if it failed. The path of a JSON report \
+                    on the run is in $TECTONIC_BUILD_REPORT, and $TECTONIC_BUILD_SUCCESS is 1 or 0. Can be \
+                    given more than once."))
+        .arg(Arg::with_name("skip_unchanged")
+             .long("skip-unchanged")
+             .help("Don't process the document if none of the files that it read from disk the last time \
+                    have changed, and the outputs from then are still there."))
+        .arg(Arg::with_name("report")
+             .long("report")
+             .value_name("FORMAT")
+             .possible_values(&["json"])
+             .help("Report the outcome of the run, including the outputs, the engines that were run, \
+                    errors and warnings, and the files used, in a machine-readable format. The report \
+                    is printed on standard output, and other messages are kept to a minimum, unless \
+                    --report-file is given."))
+        .arg(Arg::with_name("report_file")
+             .long("report-file")
+             .value_name("PATH")
+             .requires("report")
+             .help("Write the --report output to <PATH>."))
+        .arg(Arg::with_name("log_file")
+             .long("log-file")
+             .value_name("PATH")
+             .help("Also write every message, and the whole TeX log, to <PATH>, with the time of each."))
+        .arg(Arg::with_name("append_log")
+             .long("append-log")
+             .requires("log_file")
+             .hidden(true))
         .arg(Arg::with_name("pass")
              .long("pass")
              .value_name("PASS")
              .help("Which engines to run.")
              .possible_values(&["default", "tex", "bibtex_first"])
              .default_value("default"))
+        .arg(Arg::with_name("biber")
+             .long("biber")
+             .value_name("PROGRAM")
+             .help("The external program used to process bibliographies of documents that use biblatex.")
+             .default_value("biber"))
+        .arg(profile)
+        .arg(Arg::with_name("draft")
+             .long("draft")
+             .help("Draw the outlines of images rather than including them, to save time."))
         .arg(Arg::with_name("reruns")
              .long("reruns")
              .short("r")
@@ -1035,12 +1281,55 @@ fn main() {
         .arg(Arg::with_name("synctex")
              .long("synctex")
              .help("Generate SyncTeX data."))
+        .arg(Arg::with_name("interaction")
+             .long("interaction")
+             .value_name("MODE")
+             .help("The TeX interaction mode. In \"errorstopmode\", processing stops at the first error; \
+                    the other modes keep going. \"batchmode\" also keeps the engine quiet.")
+             .possible_values(&["batchmode", "nonstopmode", "scrollmode", "errorstopmode"]))
+        .arg(Arg::with_name("precompiled_preamble")
+             .long("precompiled-preamble")
+             .help("Save the engine state after the document preamble as a format file, and reuse it \
+                    while the preamble is unchanged."))
+        .arg(Arg::with_name("outdir")
+             .long("outdir")
+             .short("o")
+             .value_name("DIR")
+             .help("Write the output files to <DIR> rather than next to the input file. If <DIR> is \"-\", \
+                    write the main output to standard output instead, and print messages on standard \
+                    error."))
+        .arg(Arg::with_name("auxdir")
+             .long("auxdir")
+             .value_name("DIR")
+             .help("Write the intermediate files and logs that are kept (see --keep-intermediates and \
+                    --keep-logs) to <DIR> rather than with the output files."))
+        .arg(Arg::with_name("includeonly")
+             .long("includeonly")
+             .value_name("NAMES")
+             .use_delimiter(true)
+             .help("Only typeset these comma-separated files out of those pulled in with \\include, \
+                    as with \\includeonly. The .aux files are kept, so that references to the other \
+                    parts use the page numbers from the last time that they were typeset."))
         .arg(Arg::with_name("hide")
              .long("hide")
              .value_name("PATH")
              .multiple(true)
              .number_of_values(1)
              .help("Tell the engine that no file at <PATH> exists, if it tries to read it."))
+        .arg(Arg::with_name("hide_warnings")
+             .long("hide-warnings")
+             .value_name("KIND")
+             .multiple(true)
+             .number_of_values(1)
+             .possible_values(&["warning", "badbox", "missing-character", "font-substitution"])
+             .help("Don't show warnings of this kind. The number that were hidden is still reported."))
+        .arg(Arg::with_name("case_insensitive")
+             .long("case-insensitive")
+             .help("If an input file can't be found, look for one whose name differs only in case."))
+        .arg(Arg::with_name("watch")
+             .long("watch")
+             .help("Keep running, and process the document again whenever one of the files that it \
+                    uses changes."))
         .arg(Arg::with_name("print_stdout")
              .long("print")
              .short("p")
@@ -1049,26 +1338,187 @@ fn main() {
              .long("chatter")
              .short("c")
              .value_name("LEVEL")
-             .help("How much chatter to print when running.")
-             .possible_values(&["default", "minimal"])
+             .help("How to print messages when running. With \"json\", every message is printed as a JSON \
+                    object on a line of its own, for other programs to read. \"minimal\" is the same as --quiet.")
+             .possible_values(&["default", "minimal", "json"])
              .default_value("default"))
+        .arg(Arg::with_name("error_format")
+             .long("error-format")
+             .value_name("FORMAT")
+             .help("How to print the error that stops the run, if there is one. With \"json\", it's printed \
+                    on standard error as a JSON object on a line of its own, giving its kind, its code, and \
+                    its causes.")
+             .possible_values(&["default", "json"])
+             .default_value("default"))
+        .arg(Arg::with_name("color")
+             .long("color")
+             .value_name("WHEN")
+             .help("When to color the messages. With \"auto\", they're colored if they're going to a terminal \
+                    and the NO_COLOR environment variable isn't set.")
+             .possible_values(&["auto", "always", "never"])
+             .default_value("auto"))
+        .arg(Arg::with_name("quiet")
+             .long("quiet")
+             .short("q")
+             .conflicts_with("verbose")
+             .help("Only print warnings and errors."))
+        .arg(Arg::with_name("verbose")
+             .long("verbose")
+             .short("v")
+             .multiple(true)
+             .help("Print more details about the processing: once for how long each step takes, twice for \
+                    where each file is found as well."))
+        .arg(Arg::with_name("jobs")
+             .long("jobs")
+             .short("j")
+             .value_name("COUNT")
+             .help("When processing several files, process up to <COUNT> of them at once."))
+        .arg(Arg::with_name("only_input")
+             .long("only-input")
+             .value_name("INDEX")
+             .hidden(true))
+        .arg(Arg::with_name("jobname")
+             .long("jobname")
+             .value_name("NAME")
+             .help("When reading the document from standard input, name the outputs after <NAME> rather than \
+                    \"texput\"."))
         .arg(Arg::with_name("INPUT")
-             .help("The file to process.")
+             .help("The file or files to process. Use \"-\" to read a document from standard input; the files \
+                    that it uses are then looked for in the current directory.")
              .required(true)
+             .multiple(true)
              .index(1))
-        .get_matches ();
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("build")
+                    .about("Process the documents of the project described by the nearest Tectonic.toml file.")
+                    .arg(Arg::with_name("jobs")
+                         .long("jobs")
+                         .short("j")
+                         .value_name("COUNT")
+                         .help("Process up to <COUNT> documents at once."))
+                    .arg(Arg::with_name("profile")
+                         .long("profile")
+                         .value_name("NAME")
+                         .help("Use the processing options of this profile."))
+                    .arg(Arg::with_name("DOCUMENT")
+                         .help("The names of the documents to process. By default, all of them are processed.")
+                         .multiple(true)
+                         .index(1)))
+        .subcommand(SubCommand::with_name("serve")
+                    .about("Process a document whenever it changes, and preview the results in a web browser.")
+                    .arg(Arg::with_name("port")
+                         .long("port")
+                         .short("p")
+                         .value_name("PORT")
+                         .help("Listen for browsers on this port of the local machine. If 0, pick any free port.")
+                         .default_value("8000"))
+                    .arg(Arg::with_name("INPUT")
+                         .help("The file to process.")
+                         .required(true)
+                         .index(1)))
+        .subcommand(SubCommand::with_name("config")
+                    .about("Show or change the configuration.")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("show")
+                                .about("Print the settings in effect here, after combining the configuration \
+                                        file, any project configuration file, and the environment."))
+                    .subcommand(SubCommand::with_name("path")
+                                .about("Print the path of the configuration file."))
+                    .subcommand(SubCommand::with_name("set")
+                                .about("Change a setting in the configuration file, such as \"default_bundle\" \
+                                        or \"defaults.outfmt\".")
+                                .arg(Arg::with_name("KEY")
+                                     .help("The name of the setting, with dots to reach into tables.")
+                                     .required(true)
+                                     .index(1))
+                                .arg(Arg::with_name("VALUE")
+                                     .help("The new value, in TOML syntax; bare words are taken as strings.")
+                                     .required(true)
+                                     .index(2)))
+                    .subcommand(SubCommand::with_name("pin")
+                                .about("Record the digests of the default bundles in the configuration file, so \
+                                        that newer versions of them are refused rather than silently used.")))
+        .subcommand(SubCommand::with_name("completions")
+                    .about("Print a script that teaches your shell to complete the arguments of this program. \
+                            It knows about the profiles defined when it's generated.")
+                    .arg(Arg::with_name("SHELL")
+                         .help("The shell to generate the script for.")
+                         .required(true)
+                         .possible_values(&Shell::variants())
+                         .index(1)))
+}
 
-    let chatter = match matches.value_of("chatter_level").unwrap() {
-        "default" => ChatterLevel::Normal,
-        "minimal" => ChatterLevel::Minimal,
-        _ => unreachable!()
+
+/// Get the directory to start looking for a project configuration file in:
+/// that of the first document given, or else the current directory.
+fn project_config_dir(matches: &ArgMatches) -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+
+    let input = match matches.subcommand() {
+        ("serve", Some(args)) => args.value_of_os("INPUT"),
+        ("", _) => matches.values_of_os("INPUT").and_then(|mut inputs| inputs.next()),
+        _ => None,
+    };
+
+    match input.and_then(|i| Path::new(i).parent()) {
+        Some(dir) if input != Some(OsStr::new("-")) => cwd.join(dir),
+        _ => cwd,
+    }
+}
+
+
+fn main() {
+    let matches = make_app(None).get_matches();
+
+    // Notes go to standard output, so they'd get mixed in with the report,
+    // the list of files, or the output if any of those go there too.
+    let stdout_taken = (matches.is_present("report") && !matches.is_present("report_file")) ||
+        matches.is_present("dry_run") || matches.subcommand_matches("completions").is_some() ||
+        matches.subcommand_matches("config").is_some() ||
+        matches.value_of_os("outdir") == Some(OsStr::new("-"));
+
+    let chatter_name = matches.value_of("chatter_level").unwrap();
+
+    let verbosity = if matches.is_present("quiet") || chatter_name == "minimal" {
+        ChatterLevel::Minimal
+    } else {
+        match matches.occurrences_of("verbose") {
+            0 => ChatterLevel::Normal,
+            1 => ChatterLevel::Verbose,
+            _ => ChatterLevel::Debug,
+        }
+    };
+
+    let chatter = match chatter_name {
+        // The JSON messages can be told apart from everything else, so they
+        // just move over to standard error.
+        "json" => verbosity,
+        _ if matches.is_present("report") && !matches.is_present("report_file") => ChatterLevel::Minimal,
+        _ if matches.is_present("dry_run") => ChatterLevel::Minimal,
+        _ if matches.subcommand_matches("completions").is_some() => ChatterLevel::Minimal,
+        _ if matches.subcommand_matches("config").is_some() => ChatterLevel::Minimal,
+        _ => verbosity,
     };
 
     // I want the CLI program to take as little configuration as possible, but
     // we do need to at least provide a mechanism for storing the default
     // bundle.
 
-    let config = match PersistentConfig::open(false) {
+    // Settings that belong to a project can be kept with it, in a file that
+    // adds to the user's configuration, and the environment can override
+    // both. See `PersistentConfig::load()` for the details. An untrusted
+    // document's project configuration came along with it, so it's ignored:
+    // otherwise it could loosen the limits or point us at other files.
+
+    let json_errors = matches.value_of("error_format") == Some("json");
+    let project_dir = if matches.is_present("untrusted") {
+        None
+    } else {
+        Some(project_config_dir(&matches))
+    };
+    let config = PersistentConfig::load(project_dir);
+
+    let config = match config {
         Ok(c) => c,
         Err(ref e) => {
             // Uhoh, we couldn't get the configuration. Our main
@@ -1076,7 +1526,7 @@ fn main() {
             // have yet. If we can't even load the config we might really be
             // in trouble, so it seems safest to keep things simple anyway and
             // just use bare stderr without colorization.
-            e.dump_uncolorized();
+            dump_error(e, json_errors);
             process::exit(1);
         }
     };
@@ -1086,7 +1536,48 @@ fn main() {
     // something I'd be relatively OK with since it'd only affect the progam
     // UI, not the processing results).
 
-    let mut status = TermcolorStatusBackend::new(chatter);
+    let mut status: Box<StatusBackend> = if chatter_name == "json" {
+        if stdout_taken {
+            Box::new(JsonStatusBackend::new(chatter, io::stderr()))
+        } else {
+            Box::new(JsonStatusBackend::new(chatter, io::stdout()))
+        }
+    } else {
+        let color = match matches.value_of("color").unwrap() {
+            "always" => ColorMode::Always,
+            "never" => ColorMode::Never,
+            _ => ColorMode::Auto,
+        };
+
+        let mut status = TermcolorStatusBackend::with_color(chatter, color);
+
+        if matches.value_of_os("outdir") == Some(OsStr::new("-")) {
+            status.notes_to_stderr();
+        }
+
+        Box::new(status)
+    };
+
+    // Copies of this program that process documents in parallel add to the
+    // log file that we've started.
+
+    if let Some(path) = matches.value_of_os("log_file") {
+        status = match LogFileStatusBackend::open(path, matches.is_present("append_log"), status) {
+            Ok(s) => Box::new(s),
+            Err(ref e) => {
+                dump_error(e, json_errors);
+                process::exit(exit_code(e));
+            },
+        };
+    }
+
+    let mut filter = config.warning_filter().clone();
+
+    if let Some(kinds) = matches.values_of("hide_warnings") {
+        filter.hide.extend(kinds.map(|k| k.to_owned()));
+    }
+
+    let mut status = FilteringStatusBackend::new(status, filter);
 
     // For now ...
 
@@ -1096,12 +1587,66 @@ fn main() {
     // function ... all so that we can print out the word "error:" in red.
     // This code parallels various bits of the `error_chain` crate.
 
-    process::exit(match inner(matches, config, &mut status) {
+    let result = inner(matches, config, &mut status);
+    status.report_hidden();
+
+    process::exit(match result {
         Ok(ret) => ret,
 
         Err(ref e) => {
-            status.bare_error(e);
-            1
+            if json_errors {
+                dump_error(e, true);
+            } else {
+                status.report_error(e);
+            }
+
+            exit_code(e)
         }
     })
 }
+
+
+/// Print an error when there's no status backend to report it, or when the
+/// user has asked for it to be printed as JSON.
+fn dump_error(e: &Error, json: bool) {
+    if json {
+        e.write_json(&mut io::stderr()).expect("write to stderr failed");
+    } else {
+        e.dump_uncolorized();
+    }
+}
+
+
+// The exit codes for the different kinds of failure, so that scripts can
+// tell a broken document from a broken network. These are listed in the
+// --help output, so keep that up to date. If we panic, the Rust runtime
+// exits with code 101.
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_DOCUMENT_ERROR: i32 = 2;
+const EXIT_BUNDLE_ERROR: i32 = 3;
+const EXIT_IO_ERROR: i32 = 4;
+const EXIT_LIMIT_EXCEEDED: i32 = 5;
+
+// By convention, being stopped by SIGINT gives exit code 130.
+const EXIT_CANCELLED: i32 = 130;
+
+/// Most errors are wrapped in messages that say what we were doing, so we go
+/// by the most specific kind of error in the chain.
+fn exit_code(e: &Error) -> i32 {
+    let kind = match e.specific_kind() {
+        Some(kind) => kind,
+        None if e.io_cause().is_some() => return EXIT_IO_ERROR,
+        None => return EXIT_FAILURE,
+    };
+
+    match *kind {
+        ErrorKind::EngineFailed(..) => EXIT_DOCUMENT_ERROR,
+        ErrorKind::BundleUnavailable | ErrorKind::CacheCorrupted(..) | ErrorKind::Hyper(..) |
+        ErrorKind::NetworkDisabled(..) | ErrorKind::PinnedDigestMismatch(..) => EXIT_BUNDLE_ERROR,
+        ErrorKind::Io(..) | ErrorKind::PathForbidden(..) => EXIT_IO_ERROR,
+        ErrorKind::ResourceLimitExceeded(..) => EXIT_LIMIT_EXCEEDED,
+        ErrorKind::Cancelled => EXIT_CANCELLED,
+        _ => EXIT_FAILURE,
+    }
+}
@@ -8,18 +8,37 @@
 /// running the command-line client. So we begrudgingly have a *little*
 /// configuration.
 
+use std::collections::HashMap;
+use std::env;
 use std::io::{Read, Write};
 use std::io::ErrorKind as IoErrorKind;
-use std::fs::File;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use std::str::{self, FromStr};
 
 use app_dirs::{app_dir, app_root, get_app_root, sanitized, AppDataType};
+use hyper::Url;
+use serde::Deserialize;
 use toml;
 
-use errors::{ErrorKind, Result};
-use io::IoProvider;
+use config_schema::{self, CONFIG_FIELDS};
+
+use digest::{self, Digest, DigestData};
+use driver::{is_plain_relative_path, SessionBuilder};
+use engines::tex::InteractionMode;
+use engines::xdvipdfmx::XdvipdfmxEngine;
+use errors::{ErrorKind, Result, ResultExt};
+use io::{check_bundle_digest, get_bundle_digest, open_file_url_bundle, open_local_bundle, IoProvider};
+use io::format_cache::FormatCache;
+use io::gitbundle::GitBundle;
+use io::http::NetworkSettings;
 use io::itarbundle::{HttpITarIoFactory, ITarBundle};
 use io::local_cache::LocalCache;
+use io::offline::OfflineGuardIo;
+use io::s3bundle::S3Bundle;
 use status::StatusBackend;
+use status::filter::WarningFilter;
 
 
 const DEFAULT_CONFIG: &'static str = r#"[[default_bundles]]
@@ -27,31 +46,471 @@ url = "https://purl.org/net/pkgwpub/tectonic-default"
 "#;
 
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct PersistentConfig {
+    /// If a file in the local bundle cache turns out to be corrupted, fetch
+    /// it again rather than erroring out.
+    #[serde(default)]
+    redownload_corrupt_cache_files: bool,
+
+    /// The maximum size of the local cache of bundle files, in megabytes.
+    /// If unset, the cache can grow without limit.
+    #[serde(default)]
+    max_cache_size_mb: Option<u64>,
+
+    /// A proxy to use for all network requests, e.g.
+    /// `http://proxy.example.com:3128`. If unset, the standard `HTTP_PROXY`
+    /// and `HTTPS_PROXY` environment variables are consulted. Hosts listed in
+    /// `NO_PROXY` are always contacted directly.
+    #[serde(default)]
+    proxy: Option<String>,
+
+    /// Extra directories to search for input files, after the document's
+    /// own directory but before the bundles. As with kpathsea, directories
+    /// ending in `//` are searched recursively.
+    #[serde(default)]
+    search_path: Vec<String>,
+
+    /// Where to keep the cache of bundle files, formats, fonts, and build
+    /// state, rather than in the usual place for the system (such as
+    /// `~/.cache/Tectonic` on Linux). Shared build machines might want it on
+    /// a fast scratch volume. This should be an absolute path.
+    #[serde(default)]
+    cache_dir: Option<PathBuf>,
+
+    // The tables come last, since TOML requires them to follow the plain
+    // values when the configuration is written out.
+
+    /// The bundles to search for resource files, in order of precedence.
+    /// A small bundle of local customizations can be listed before the
+    /// standard bundle to override or supplement its files.
     default_bundles: Vec<BundleInfo>,
+
+    /// Named sets of processing options, selected with `--profile`.
+    #[serde(default)]
+    profiles: HashMap<String, BuildProfile>,
+
+    /// Bundles that can be selected by name, with `-b NAME`, rather than by
+    /// giving their full URL, e.g.:
+    ///
+    /// ```toml
+    /// [bundles.stable]
+    /// url = "https://example.com/tlextras-2017.tar"
+    /// ```
+    #[serde(default)]
+    bundles: HashMap<String, BundleInfo>,
+
+    /// Warnings that the command-line program shouldn't show.
+    #[serde(default)]
+    warnings: WarningFilter,
+
+    /// Options that the command-line program uses unless told otherwise.
+    #[serde(default)]
+    defaults: BuildDefaults,
+
+    /// Timeouts and retries for downloading bundles.
+    #[serde(default)]
+    network: NetworkSettings,
 }
 
+/// The name of the file holding a project's configuration, which is looked
+/// for in the directory of the document and its parents. It can contain any
+/// of the settings of the user's configuration file, and the ones that it
+/// contains replace the user's, except that profiles and named bundles
+/// are replaced one by one. Relative paths in it are relative to the file. This makes it
+/// possible to commit settings like the choice of bundle to version control
+/// along with the document.
+pub const PROJECT_CONFIG_FILE_NAME: &'static str = ".tectonic.toml";
+
+
+/// The settings in a project configuration file, all of which are optional.
 #[derive(Deserialize)]
+struct ProjectConfig {
+    #[serde(default)]
+    default_bundles: Option<Vec<BundleInfo>>,
+
+    #[serde(default)]
+    redownload_corrupt_cache_files: Option<bool>,
+
+    #[serde(default)]
+    max_cache_size_mb: Option<u64>,
+
+    #[serde(default)]
+    proxy: Option<String>,
+
+    #[serde(default)]
+    search_path: Option<Vec<String>>,
+
+    #[serde(default)]
+    cache_dir: Option<PathBuf>,
+
+    #[serde(default)]
+    profiles: HashMap<String, BuildProfile>,
+
+    #[serde(default)]
+    bundles: HashMap<String, BundleInfo>,
+
+    #[serde(default)]
+    warnings: Option<WarningFilter>,
+
+    #[serde(default)]
+    defaults: Option<BuildDefaults>,
+
+    #[serde(default)]
+    network: Option<NetworkSettings>,
+}
+
+
+#[derive(Clone,Deserialize,Serialize)]
 pub struct BundleInfo {
+    /// Either an `http://`, `https://`, `s3://`, or `file://` URL, or the
+    /// path to a bundle on the local filesystem. Git repositories are
+    /// specified by prefixing their URL or path with `git+`, e.g.
+    /// `git+https://example.com/texfiles.git`.
     url: String,
+
+    /// Pin the bundle to the version with this SHA256 digest (the contents of
+    /// its `SHA256SUM` file). If the bundle is ever updated, we will refuse
+    /// to use the new version rather than silently upgrading, so that
+    /// documents continue to build with exactly the same support files.
+    /// Combine this with the URL of a dated bundle snapshot, rather than one
+    /// that always points at the latest bundle, to keep building offline
+    /// once the cache is cleared. `tectonic config pin` records the digests
+    /// of the versions of the default bundles that are in use.
+    #[serde(default)]
+    digest: Option<String>,
+
+    /// For git bundles, the commit to read files from.
+    #[serde(default)]
+    git_commit: Option<String>,
+
+    /// For `http://` and `https://` bundles, alternative URLs providing the
+    /// same bundle, to be tried in order if the main URL is unavailable.
+    #[serde(default)]
+    mirrors: Vec<String>,
+
+    /// For `s3://` bundles, the base URL of an S3-compatible service to use
+    /// instead of Amazon's.
+    #[serde(default)]
+    s3_endpoint: Option<String>,
+
+    /// For `s3://` bundles, the region in which the bucket lives.
+    #[serde(default)]
+    s3_region: Option<String>,
+}
+
+
+/// A named set of processing options, so that switching between quick
+/// drafts and polished final versions is a matter of saying `--profile
+/// draft` or `--profile final`. Profiles are defined in the configuration
+/// file or in a project manifest, like:
+///
+/// ```toml
+/// [profiles.draft]
+/// reruns = 0
+/// draft = true
+/// ```
+///
+/// Settings that a profile leaves out keep their defaults, and options
+/// given on the command line are applied on top of the profile.
+#[derive(Clone,Debug,Default,Deserialize,Serialize)]
+pub struct BuildProfile {
+    /// Rerun TeX exactly this many times, rather than deciding
+    /// automatically.
+    #[serde(default)]
+    pub reruns: Option<usize>,
+
+    /// Draw the outlines of images rather than loading them. See
+    /// `SessionBuilder::draft`.
+    #[serde(default)]
+    pub draft: bool,
+
+    #[serde(default)]
+    pub synctex: bool,
+
+    #[serde(default)]
+    pub precompiled_preamble: bool,
+
+    /// Generate archival PDF/A-1b output.
+    #[serde(default)]
+    pub pdfa: bool,
+
+    /// The compression level of the PDF output, from 0 to 9.
+    #[serde(default)]
+    pub pdf_compression: Option<u8>,
+}
+
+
+impl BuildProfile {
+    /// Get one of the profiles that are available without being defined:
+    /// "draft", which runs TeX once, skips images, reuses the preamble, and
+    /// doesn't bother compressing the PDF; and "final", which runs TeX as
+    /// many times as needed and creates PDF/A output. Defining a profile
+    /// with the same name replaces these.
+    pub fn builtin(name: &str) -> Option<BuildProfile> {
+        match name {
+            "draft" => Some(BuildProfile {
+                reruns: Some(0),
+                draft: true,
+                precompiled_preamble: true,
+                pdf_compression: Some(0),
+                ..BuildProfile::default()
+            }),
+            "final" => Some(BuildProfile {
+                pdfa: true,
+                ..BuildProfile::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Apply the settings of the profile that belong to the session.
+    pub fn configure_session(&self, sb: &mut SessionBuilder) {
+        if let Some(n) = self.reruns {
+            sb.reruns(n);
+        }
+
+        if self.draft {
+            sb.draft(true);
+        }
+
+        if self.synctex {
+            sb.synctex(true);
+        }
+
+        if self.precompiled_preamble {
+            sb.precompiled_preamble(true);
+        }
+    }
+
+    /// Apply the settings of the profile that belong to the PDF engine.
+    pub fn configure_xdvipdfmx(&self, engine: &mut XdvipdfmxEngine) {
+        if self.pdfa {
+            engine.pdfa(true);
+        }
+
+        if let Some(level) = self.pdf_compression {
+            engine.compression_level(level);
+        }
+    }
+}
+
+
+/// Processing options to use when the command line doesn't give them, so
+/// that they don't have to be typed every time. These go in the `[defaults]`
+/// section of the configuration file:
+///
+/// ```toml
+/// [defaults]
+/// outfmt = "xdv"
+/// keep_logs = true
+/// max_passes = 8
+/// ```
+///
+/// Profiles and command-line options are applied on top of these. They
+/// don't affect `tectonic build`, which takes its options from the project
+/// manifest.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BuildDefaults {
+    /// The kind of output to generate, by one of the names accepted by
+    /// `--outfmt`, such as "pdf".
+    #[serde(default)]
+    pub outfmt: Option<String>,
+
+    #[serde(default)]
+    pub keep_logs: bool,
+
+    #[serde(default)]
+    pub keep_intermediates: bool,
+
+    #[serde(default)]
+    pub synctex: bool,
+
+    /// The TeX interaction mode, by one of the names accepted by
+    /// `--interaction`, such as "nonstopmode".
+    #[serde(default)]
+    pub interaction: Option<String>,
+
+    /// Rerun TeX exactly this many times, rather than deciding
+    /// automatically.
+    #[serde(default)]
+    pub reruns: Option<usize>,
+
+    /// Give up if the document needs more than this many TeX passes.
+    #[serde(default)]
+    pub max_passes: Option<usize>,
+}
+
+
+impl BuildDefaults {
+    /// Apply the defaults to a session. The output format is left to the
+    /// caller, which needs to know it anyway.
+    pub fn configure_session(&self, sb: &mut SessionBuilder) -> Result<()> {
+        sb.keep_logs(self.keep_logs)
+          .keep_intermediates(self.keep_intermediates)
+          .synctex(self.synctex);
+
+        if let Some(ref mode) = self.interaction {
+            sb.interaction(ctry!(InteractionMode::from_str(mode); "invalid interaction mode in the configuration"));
+        }
+
+        if let Some(n) = self.reruns {
+            sb.reruns(n);
+        }
+
+        if let Some(n) = self.max_passes {
+            sb.max_passes(n);
+        }
+
+        Ok(())
+    }
+}
+
+
+/// The environment variables that override settings of the configuration
+/// files. Settings that are tables, like profiles, can't be given this way.
+///
+/// - `TECTONIC_BUNDLE_URL`: use just this bundle as the default bundle.
+/// - `TECTONIC_CACHE_DIR`: keep the cache in this directory.
+/// - `TECTONIC_MAX_CACHE_SIZE_MB`: the `max_cache_size_mb` setting.
+/// - `TECTONIC_PROXY`: the `proxy` setting.
+/// - `TECTONIC_REDOWNLOAD_CORRUPT_CACHE_FILES`: the
+///   `redownload_corrupt_cache_files` setting, as "true" or "false" (or "1"
+///   or "0").
+/// - `TECTONIC_SEARCH_PATH`: the `search_path` setting, as a list of
+///   directories separated like those in `PATH`.
+/// - `TECTONIC_HIDE_WARNINGS`: the `hide` setting of the `warnings` table,
+///   as a comma-separated list.
+pub const ENV_VARS: &'static [&'static str] = &[
+    "TECTONIC_BUNDLE_URL",
+    "TECTONIC_CACHE_DIR",
+    "TECTONIC_MAX_CACHE_SIZE_MB",
+    "TECTONIC_PROXY",
+    "TECTONIC_REDOWNLOAD_CORRUPT_CACHE_FILES",
+    "TECTONIC_SEARCH_PATH",
+    "TECTONIC_HIDE_WARNINGS",
+];
+
+
+/// Parse the configuration file at `path`, whose contents are `buf`. The
+/// settings are checked first, so that mistakes in them are reported along
+/// with where they are, rather than being ignored or given a vague error.
+fn parse_config_file<T: Deserialize>(path: &Path, buf: &[u8]) -> Result<T> {
+    let text = ctry!(str::from_utf8(buf); "configuration file \"{}\" isn't valid UTF-8", path.display());
+    let root: toml::value::Table = ctry!(toml::from_str(text); "couldn't parse configuration file \"{}\"",
+                                         path.display());
+    let problems = config_schema::check(text, &root, CONFIG_FIELDS);
+
+    if !problems.is_empty() {
+        let problems = problems.iter().map(|p| p.to_string()).collect();
+        return Err(ErrorKind::InvalidConfig(path.display().to_string(), problems).into());
+    }
+
+    Ok(ctry!(toml::from_str(text); "couldn't load configuration file \"{}\"", path.display()))
+}
+
+
+/// Read the user's configuration file as a plain TOML table, for editing.
+/// If there's no file yet, the default configuration is used.
+fn read_user_settings() -> Result<(PathBuf, toml::value::Table)> {
+    let path = PersistentConfig::user_config_path(true)?;
+
+    let root = match File::open(&path) {
+        Ok(mut f) => {
+            let mut buf = Vec::new();
+            f.read_to_end(&mut buf)?;
+            ctry!(toml::from_slice(&buf); "couldn't parse configuration file \"{}\"", path.display())
+        },
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => toml::from_str(DEFAULT_CONFIG)?,
+        Err(e) => { return Err(e.into()); },
+    };
+
+    Ok((path, root))
+}
+
+
+/// Save the edited configuration, after making sure that it still makes
+/// sense.
+fn write_user_settings(path: &Path, root: &toml::value::Table) -> Result<()> {
+    let text = ctry!(toml::to_string(root); "couldn't write out the configuration");
+    let _: PersistentConfig = ctry!(parse_config_file(path, text.as_bytes());
+                                    "the changed configuration would be invalid");
+
+    let mut f = ctry!(File::create(path); "couldn't create configuration file \"{}\"", path.display());
+    ctry!(f.write_all(text.as_bytes()); "couldn't write configuration file \"{}\"", path.display());
+    Ok(())
+}
+
+
+/// Interpret the value of a setting given on the command line: as TOML,
+/// such as `true`, `5`, or `["a", "b"]`, or else as a bare string.
+fn parse_setting_value(text: &str) -> toml::Value {
+    let doc = format!("value = {}", text);
+
+    match toml::from_str::<toml::value::Table>(&doc) {
+        Ok(mut t) => t.remove("value").unwrap_or_else(|| toml::Value::String(text.to_owned())),
+        Err(_) => toml::Value::String(text.to_owned()),
+    }
+}
+
+
+/// Read an environment variable that's meant to be text.
+fn env_string(name: &str) -> Result<Option<String>> {
+    match env::var_os(name) {
+        None => Ok(None),
+        Some(v) => match v.into_string() {
+            Ok(s) => Ok(Some(s)),
+            Err(_) => Err(ErrorKind::Msg(format!("the value of {} isn't valid Unicode", name)).into()),
+        },
+    }
 }
 
 
 impl PersistentConfig {
-    pub fn open(auto_create_config_file: bool) -> Result<PersistentConfig> {
-        let mut cfg_path = if auto_create_config_file {
+    /// Load the configuration that applies to a run of the program. Each
+    /// setting is resolved in this order, with later sources overriding
+    /// earlier ones:
+    ///
+    /// 1. the built-in defaults;
+    /// 2. the user's configuration file (see `open()`);
+    /// 3. the project configuration file found by looking in `project_dir`
+    ///    and its parents, if a directory is given (see
+    ///    `merge_project_config()`);
+    /// 4. the environment variables listed in `ENV_VARS`.
+    ///
+    /// Command-line options are applied by the caller, on top of all of
+    /// these.
+    pub fn load<P: AsRef<Path>>(project_dir: Option<P>) -> Result<PersistentConfig> {
+        let mut config = PersistentConfig::open(false)?;
+
+        if let Some(dir) = project_dir {
+            config.merge_project_config(dir)?;
+        }
+
+        config.merge_env()?;
+        Ok(config)
+    }
+
+    /// Get the path of the user's configuration file, which might not exist.
+    /// If `create_dir` is true, the directory that holds it is created.
+    pub fn user_config_path(create_dir: bool) -> Result<PathBuf> {
+        let mut cfg_path = if create_dir {
             app_root(AppDataType::UserConfig, &::APP_INFO)?
         } else {
             get_app_root(AppDataType::UserConfig, &::APP_INFO)?
         };
         cfg_path.push("config.toml");
+        Ok(cfg_path)
+    }
+
+    pub fn open(auto_create_config_file: bool) -> Result<PersistentConfig> {
+        let cfg_path = PersistentConfig::user_config_path(auto_create_config_file)?;
 
         let config = match File::open(&cfg_path) {
             Ok(mut f) => {
                 let mut buf = Vec::<u8>::new();
                 f.read_to_end(&mut buf)?;
-                toml::from_slice(&buf)?
+                parse_config_file(&cfg_path, &buf)?
             },
             Err(e) => {
                 if e.kind() == IoErrorKind::NotFound {
@@ -71,27 +530,554 @@ impl PersistentConfig {
         Ok(config)
     }
 
-    fn make_cached_url_provider(&self, url: &str, status: &mut StatusBackend) -> Result<LocalCache<ITarBundle<HttpITarIoFactory>>> {
-        let itb = ITarBundle::<HttpITarIoFactory>::new(url);
+    /// Change one setting in the user's configuration file, creating the
+    /// file if needed, and return the file's path. `key` is the name of the
+    /// setting, with dots to reach into tables, as in `defaults.outfmt`;
+    /// `default_bundle` is a shorthand for making the bundle at the URL
+    /// `value` the only default bundle. The value is read as TOML if it can
+    /// be, and as a string otherwise. Comments in the file are lost.
+    pub fn set_user_setting(key: &str, value: &str) -> Result<PathBuf> {
+        let (path, mut root) = read_user_settings()?;
+
+        let (key, value) = if key == "default_bundle" {
+            let mut bundle = toml::value::Table::new();
+            bundle.insert("url".to_owned(), toml::Value::String(value.to_owned()));
+            ("default_bundles", toml::Value::Array(vec![toml::Value::Table(bundle)]))
+        } else {
+            (key, parse_setting_value(value))
+        };
+
+        let mut pieces: Vec<&str> = key.split('.').collect();
+        let last = pieces.pop().unwrap();
+
+        let top = match pieces.first() {
+            Some(p) => *p,
+            None => last,
+        };
+
+        if !CONFIG_FIELDS.iter().any(|f| f.name == top) {
+            return Err(ErrorKind::Msg(format!("there is no setting named \"{}\"", key)).into());
+        }
+
+        {
+            let mut table = &mut root;
 
-        let mut url2digest_path = app_dir(AppDataType::UserCache, &::APP_INFO, "urls")?;
-        url2digest_path.push(sanitized(url));
+            for piece in pieces {
+                let current = table;
+                let entry = current.entry(piece.to_owned())
+                    .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
 
-        LocalCache::<ITarBundle<HttpITarIoFactory>>::new(
-            itb,
-            &url2digest_path,
-            &app_dir(AppDataType::UserCache, &::APP_INFO, "manifests")?,
-            &app_dir(AppDataType::UserCache, &::APP_INFO, "formats")?,
-            &app_dir(AppDataType::UserCache, &::APP_INFO, "files")?,
-            status
-        )
+                table = match *entry {
+                    toml::Value::Table(ref mut t) => t,
+                    _ => { return Err(ErrorKind::Msg(format!("setting \"{}\" is not a table", piece)).into()); },
+                };
+            }
+
+            table.insert(last.to_owned(), value);
+        }
+
+        write_user_settings(&path, &root).chain_err(|| format!("couldn't set \"{}\"", key))?;
+        Ok(path)
     }
 
-    pub fn default_io_provider(&self, status: &mut StatusBackend) -> Result<Box<IoProvider>> {
-        if self.default_bundles.len() != 1 {
-            return Err(ErrorKind::Msg("exactly one default_bundle item must be specified (for now)".to_owned()).into());
+    /// Pin each of the default bundles in the user's configuration file to
+    /// the version that we're using now, by recording its digest in the
+    /// file. From then on, we refuse to use any other version of the bundle
+    /// rather than silently switching to it if it changes upstream. Returns
+    /// the path of the file.
+    pub fn pin_default_bundles(&self, status: &mut StatusBackend) -> Result<PathBuf> {
+        let (path, mut root) = read_user_settings()?;
+
+        match root.get_mut("default_bundles") {
+            Some(&mut toml::Value::Array(ref mut bundles)) => {
+                for entry in bundles {
+                    let table = match *entry {
+                        toml::Value::Table(ref mut t) => t,
+                        _ => {
+                            return Err(ErrorKind::Msg("default_bundles must be a list of tables".to_owned()).into());
+                        },
+                    };
+
+                    let text = ctry!(toml::to_string(&*table); "couldn't write out the configuration");
+                    let info: BundleInfo = ctry!(toml::from_str(&text); "invalid bundle in default_bundles");
+                    let mut bundle = ctry!(self.make_bundle_provider(&info, false, status);
+                                           "error opening bundle \"{}\"", info.url);
+                    let digest = ctry!(get_bundle_digest(&mut *bundle, status);
+                                       "couldn't get the digest of bundle \"{}\"", info.url);
+
+                    tt_note!(status, "pinning bundle {} to digest {}", info.url, digest);
+                    table.insert("digest".to_owned(), toml::Value::String(digest.to_string()));
+                }
+            },
+            _ => {
+                return Err(ErrorKind::Msg("the configuration file has no default_bundles to pin".to_owned()).into());
+            },
         }
 
-        Ok(Box::new(self.make_cached_url_provider(&self.default_bundles[0].url, status)?))
+        write_user_settings(&path, &root)?;
+        Ok(path)
+    }
+
+    /// Write out the settings in the format of the configuration file.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(ctry!(toml::to_string(self); "couldn't write out the configuration"))
+    }
+
+    /// Look for a project configuration file in `dir` and its parents, and
+    /// apply the settings of the nearest one on top of ours. Returns the
+    /// path of the file, if there was one.
+    pub fn merge_project_config<P: AsRef<Path>>(&mut self, dir: P) -> Result<Option<PathBuf>> {
+        let mut dir = Some(dir.as_ref());
+
+        while let Some(d) = dir {
+            let path = d.join(PROJECT_CONFIG_FILE_NAME);
+
+            if path.is_file() {
+                self.merge_project_config_file(&path)?;
+                return Ok(Some(path));
+            }
+
+            dir = d.parent();
+        }
+
+        Ok(None)
+    }
+
+    /// Apply the settings of the project configuration file at `path` on
+    /// top of ours.
+    pub fn merge_project_config_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut buf = Vec::new();
+        let mut f = ctry!(File::open(path); "couldn't open project configuration \"{}\"", path.display());
+        ctry!(f.read_to_end(&mut buf); "couldn't read project configuration \"{}\"", path.display());
+        let project: ProjectConfig = parse_config_file(path, &buf)?;
+
+        let dir = match path.parent() {
+            Some(p) => p.to_owned(),
+            None => PathBuf::new(),
+        };
+
+        if let Some(mut bundles) = project.default_bundles {
+            for b in &mut bundles {
+                b.make_relative_to(&dir);
+            }
+
+            self.default_bundles = bundles;
+        }
+
+        if let Some(b) = project.redownload_corrupt_cache_files {
+            self.redownload_corrupt_cache_files = b;
+        }
+
+        if project.max_cache_size_mb.is_some() {
+            self.max_cache_size_mb = project.max_cache_size_mb;
+        }
+
+        if project.proxy.is_some() {
+            self.proxy = project.proxy;
+        }
+
+        if let Some(search_path) = project.search_path {
+            // The search path is trusted, so a project can only add
+            // directories that are inside it.
+            if let Some(p) = search_path.iter().find(|p| !is_plain_relative_path(Path::new(p))) {
+                return Err(ErrorKind::Msg(format!("the search path in project configuration \"{}\" can only \
+                                                   name directories inside the project, not \"{}\"",
+                                                  path.display(), p)).into());
+            }
+
+            self.search_path = search_path.iter().map(|p| dir.join(p).to_string_lossy().into_owned()).collect();
+        }
+
+        if let Some(cache_dir) = project.cache_dir {
+            self.cache_dir = Some(dir.join(cache_dir));
+        }
+
+        self.profiles.extend(project.profiles);
+
+        for (name, mut info) in project.bundles {
+            info.make_relative_to(&dir);
+            self.bundles.insert(name, info);
+        }
+
+        if let Some(warnings) = project.warnings {
+            self.warnings = warnings;
+        }
+
+        if let Some(defaults) = project.defaults {
+            self.defaults = defaults;
+        }
+
+        if let Some(network) = project.network {
+            self.network = network;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the settings given by the environment variables in `ENV_VARS`
+    /// on top of ours.
+    pub fn merge_env(&mut self) -> Result<()> {
+        if let Some(url) = env_string("TECTONIC_BUNDLE_URL")? {
+            self.default_bundles = vec![BundleInfo::from_url(url)];
+        }
+
+        if let Some(dir) = env::var_os("TECTONIC_CACHE_DIR") {
+            self.cache_dir = Some(PathBuf::from(dir));
+        }
+
+        if let Some(text) = env_string("TECTONIC_MAX_CACHE_SIZE_MB")? {
+            self.max_cache_size_mb = Some(ctry!(text.parse::<u64>(); "invalid TECTONIC_MAX_CACHE_SIZE_MB \"{}\"",
+                                                text));
+        }
+
+        if let Some(proxy) = env_string("TECTONIC_PROXY")? {
+            self.proxy = Some(proxy);
+        }
+
+        if let Some(text) = env_string("TECTONIC_REDOWNLOAD_CORRUPT_CACHE_FILES")? {
+            self.redownload_corrupt_cache_files = match &text[..] {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => {
+                    return Err(ErrorKind::Msg(format!("invalid TECTONIC_REDOWNLOAD_CORRUPT_CACHE_FILES \"{}\"; \
+                                                       it should be \"true\" or \"false\"", text)).into());
+                },
+            };
+        }
+
+        if let Some(paths) = env::var_os("TECTONIC_SEARCH_PATH") {
+            self.search_path = env::split_paths(&paths).map(|p| p.to_string_lossy().into_owned()).collect();
+        }
+
+        if let Some(text) = env_string("TECTONIC_HIDE_WARNINGS")? {
+            self.warnings.hide = text.split(',').map(|k| k.trim().to_owned()).filter(|k| !k.is_empty()).collect();
+        }
+
+        Ok(())
+    }
+
+    /// Get a directory of the cache, creating it if needed.
+    fn cache_dir(&self, name: &str) -> Result<PathBuf> {
+        match self.cache_dir {
+            Some(ref root) => {
+                let dir = root.join(name);
+                ctry!(fs::create_dir_all(&dir); "couldn't create cache directory \"{}\"", dir.display());
+                Ok(dir)
+            },
+            None => Ok(app_dir(AppDataType::UserCache, &::APP_INFO, name)?),
+        }
+    }
+
+    /// Get the timeouts and retry policy for network requests.
+    pub fn network(&self) -> &NetworkSettings {
+        &self.network
+    }
+
+    /// Get the explicitly configured network proxy, if any.
+    pub fn proxy(&self) -> Option<&str> {
+        self.proxy.as_ref().map(|s| s.as_str())
+    }
+
+    /// Get the configured list of extra directories to search for inputs.
+    pub fn search_path(&self) -> &[String] {
+        &self.search_path
+    }
+
+    /// Get the names of all of the profiles, configured and built in, in
+    /// sorted order.
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        names.push("draft".to_owned());
+        names.push("final".to_owned());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Look up the profile named `name`, whether it's configured or built
+    /// in.
+    pub fn profile(&self, name: &str) -> Option<BuildProfile> {
+        match self.profiles.get(name) {
+            Some(p) => Some(p.clone()),
+            None => BuildProfile::builtin(name),
+        }
+    }
+
+    /// Get the options to use when the command line doesn't give them.
+    pub fn build_defaults(&self) -> &BuildDefaults {
+        &self.defaults
+    }
+
+    /// Get the configured rules for hiding warnings.
+    pub fn warning_filter(&self) -> &WarningFilter {
+        &self.warnings
+    }
+
+    /// Look up the bundle that the configuration gives the name `name`.
+    pub fn named_bundle(&self, name: &str) -> Option<&BundleInfo> {
+        self.bundles.get(name)
+    }
+
+    /// Override the configured maximum size of the local cache, e.g. from a
+    /// command-line option.
+    pub fn set_max_cache_size_mb(&mut self, mb: Option<u64>) -> &mut Self {
+        self.max_cache_size_mb = mb;
+        self
+    }
+
+    /// Override the configured location of the cache, e.g. from a
+    /// command-line option.
+    pub fn set_cache_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.cache_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Several bundles can be cached at once. The per-bundle state (which
+    /// version of the bundle we last saw, and prefetch hints) is kept in files
+    /// named by `bundle_cache_key()`. Manifests and formats are named by the
+    /// bundle's digest, and the files themselves by their own digests, so
+    /// those can all be shared safely.
+    fn make_cached_provider<B: IoProvider>(&self, info: &BundleInfo, backend: B, status: &mut StatusBackend) -> Result<LocalCache<B>> {
+        let urls = self.cache_dir("urls")?;
+        let url2digest_path = urls.join(bundle_cache_key(&info.url));
+
+        // Earlier versions named the digest file by the sanitized URL alone.
+        // Pick up where they left off, but leave the old file for them.
+        let legacy_path = urls.join(sanitized(&info.url));
+
+        if !url2digest_path.exists() && legacy_path.is_file() {
+            if let Err(e) = fs::copy(&legacy_path, &url2digest_path) {
+                tt_warning!(status, "couldn't migrate the cached digest of bundle {}", info.url; e.into());
+            }
+        }
+
+        let manifests = self.cache_dir("manifests")?;
+        let formats = self.cache_dir("formats")?;
+        let files = self.cache_dir("files")?;
+
+        let mut cache = match info.pinned_digest()? {
+            Some(d) => LocalCache::<B>::new_pinned(backend, &d, &url2digest_path, &manifests, &formats,
+                                                   &files, status)?,
+            None => LocalCache::<B>::new(backend, &url2digest_path, &manifests, &formats, &files, status)?,
+        };
+
+        cache.redownload_corrupt(self.redownload_corrupt_cache_files);
+        cache.max_size(self.max_cache_size_mb.map(|mb| mb * 1024 * 1024));
+        Ok(cache)
+    }
+
+    fn make_cached_url_provider(&self, info: &BundleInfo, status: &mut StatusBackend) -> Result<LocalCache<ITarBundle<HttpITarIoFactory>>> {
+        let mut itb = ITarBundle::<HttpITarIoFactory>::new(&info.url);
+
+        let mut hints_path = self.cache_dir("prefetch")?;
+        hints_path.push(format!("{}.txt", bundle_cache_key(&info.url)));
+        itb.prefetch_hints_path(&hints_path);
+        itb.proxy(self.proxy());
+        itb.network(&self.network);
+        itb.mirrors(&info.mirrors);
+
+        self.make_cached_provider(info, itb, status)
+    }
+
+    fn make_cached_s3_provider(&self, info: &BundleInfo, status: &mut StatusBackend) -> Result<LocalCache<S3Bundle>> {
+        let mut s3b = S3Bundle::new(&info.url,
+                                    info.s3_endpoint.as_ref().map(|s| s.as_str()),
+                                    info.s3_region.as_ref().map(|s| s.as_str()))?;
+        s3b.proxy(self.proxy())?;
+        s3b.network(&self.network)?;
+        self.make_cached_provider(info, s3b, status)
+    }
+
+    fn make_git_provider(&self, info: &BundleInfo, only_cached: bool,
+                         status: &mut StatusBackend) -> Result<GitBundle> {
+        let repo = &info.url[4..];
+
+        let commit = match info.git_commit {
+            Some(ref c) => c,
+            None => return Err(ErrorKind::Msg(format!("git bundle \"{}\" must specify a git_commit", info.url)).into()),
+        };
+
+        if repo.starts_with("file://") {
+            let path = match Url::parse(repo).ok().and_then(|u| u.to_file_path().ok()) {
+                Some(p) => p,
+                None => return Err(ErrorKind::Msg(format!("cannot understand bundle URL \"{}\"", info.url)).into()),
+            };
+            GitBundle::open(&path, commit)
+        } else if !repo.contains("://") && !repo.contains('@') {
+            GitBundle::open(Path::new(repo), commit)
+        } else {
+            let clones = self.cache_dir("git")?;
+            let mut clone_dir = clones.join(bundle_cache_key(repo));
+            let legacy_dir = clones.join(sanitized(repo));
+
+            if !clone_dir.exists() && legacy_dir.is_dir() {
+                clone_dir = legacy_dir;
+            }
+
+            if only_cached && !clone_dir.exists() {
+                return Err(ErrorKind::NetworkDisabled(commit.to_owned(), repo.to_owned()).into());
+            }
+
+            if only_cached {
+                GitBundle::open(&clone_dir, commit)
+            } else {
+                GitBundle::open_remote(repo, &clone_dir, commit, status)
+            }
+        }
+    }
+
+    /// Wrap a bundle that isn't kept in the local cache so that the formats
+    /// generated from it are still saved there.
+    pub fn with_format_cache(&self, bundle: Box<IoProvider>) -> Result<Box<IoProvider>> {
+        let formats = self.cache_dir("formats")?;
+        Ok(Box::new(FormatCache::new(bundle, &formats)))
+    }
+
+    /// Get the directory where the engine saves information about the
+    /// system fonts, creating it if needed.
+    pub fn font_cache_dir(&self) -> Result<PathBuf> {
+        self.cache_dir("fonts")
+    }
+
+    /// Get the directory where we remember what each document's last
+    /// successful run did, for skipping runs whose inputs haven't changed.
+    pub fn build_state_dir(&self) -> Result<PathBuf> {
+        self.cache_dir("builds")
+    }
+
+    fn make_bundle_provider(&self, info: &BundleInfo, only_cached: bool,
+                            status: &mut StatusBackend) -> Result<Box<IoProvider>> {
+        let is_remote = info.url.starts_with("s3://") || info.url.starts_with("http://")
+            || info.url.starts_with("https://");
+
+        if !is_remote {
+            // These bundles aren't cached, so any pin has to be checked
+            // directly.
+            let mut bundle: Box<IoProvider> = if info.url.starts_with("git+") {
+                Box::new(self.make_git_provider(info, only_cached, status)?)
+            } else if info.url.starts_with("file://") {
+                open_file_url_bundle(&info.url)?
+            } else {
+                open_local_bundle(Path::new(&info.url))?
+            };
+
+            if let Some(d) = info.pinned_digest()? {
+                check_bundle_digest(&mut *bundle, &d, status)?;
+            }
+
+            self.with_format_cache(bundle)
+        } else if only_cached {
+            Ok(Box::new(self.make_cached_provider(info, OfflineGuardIo::new(&info.url), status)?))
+        } else if info.url.starts_with("s3://") {
+            Ok(Box::new(self.make_cached_s3_provider(info, status)?))
+        } else {
+            Ok(Box::new(self.make_cached_url_provider(info, status)?))
+        }
+    }
+
+    /// Create the IoProviders for the default bundles, in order of
+    /// precedence. If `only_cached` is true, only files that are already in
+    /// the local cache can be used; anything that would require a network
+    /// fetch results in an error.
+    pub fn default_io_providers(&self, only_cached: bool, status: &mut StatusBackend) -> Result<Vec<Box<IoProvider>>> {
+        if self.default_bundles.len() == 0 {
+            return Err(ErrorKind::Msg("at least one default_bundle item must be specified".to_owned()).into());
+        }
+
+        self.io_providers(&self.default_bundles, only_cached, status)
+    }
+
+    /// Create the IoProviders for `bundles`, such as those listed in a
+    /// project manifest, in order of precedence. They're cached and
+    /// configured just like the default bundles.
+    pub fn io_providers(&self, bundles: &[BundleInfo], only_cached: bool,
+                        status: &mut StatusBackend) -> Result<Vec<Box<IoProvider>>> {
+        let mut providers = Vec::with_capacity(bundles.len());
+
+        for info in bundles {
+            providers.push(ctry!(self.make_bundle_provider(info, only_cached, status);
+                                 "error opening bundle \"{}\"", info.url));
+        }
+
+        Ok(providers)
+    }
+
+    /// Create IoProviders for the named bundles, for suggesting where to
+    /// find packages that a document's bundles don't have (see
+    /// `SessionBuilder::hint_bundle()`). Nothing is fetched over the
+    /// network, so only the files of remote bundles that are already in the
+    /// local cache are known, and bundles that can't be opened are left out.
+    pub fn hint_io_providers(&self, status: &mut StatusBackend) -> Vec<(String, Box<IoProvider>)> {
+        let mut names: Vec<&String> = self.bundles.keys().collect();
+        names.sort();
+
+        names.into_iter().filter_map(|name| {
+            self.make_bundle_provider(&self.bundles[name], true, status).ok().map(|b| (name.clone(), b))
+        }).collect()
+    }
+}
+
+
+/// Get a name for files that hold per-bundle state in the cache. Sanitizing
+/// URLs for use as file names can map different URLs to the same name, so
+/// we append part of the URL's digest.
+fn bundle_cache_key(url: &str) -> String {
+    let mut dc = digest::create();
+    dc.input(url.as_bytes());
+    let d = DigestData::from(dc).to_string();
+    format!("{}-{}", sanitized(url), &d[..16])
+}
+
+
+impl BundleInfo {
+    fn from_url(url: String) -> BundleInfo {
+        BundleInfo {
+            url: url,
+            digest: None,
+            git_commit: None,
+            mirrors: Vec::new(),
+            s3_endpoint: None,
+            s3_region: None,
+        }
+    }
+
+    /// Require the bundle to have the digest `digest`, overriding any that
+    /// was configured.
+    pub fn pin_digest(&mut self, digest: &DigestData) -> &mut Self {
+        self.digest = Some(digest.to_string());
+        self
+    }
+
+    /// Get the URL or path of the bundle.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// If the bundle is a plain path on the local filesystem, take it to be
+    /// relative to `dir`. Paths in files like project manifests are
+    /// relative to the file, not the current directory.
+    pub fn make_relative_to(&mut self, dir: &Path) {
+        let (prefix, path) = if self.url.starts_with("git+") {
+            ("git+", &self.url[4..])
+        } else {
+            ("", &self.url[..])
+        };
+
+        // Same test as in `make_git_provider`.
+        if path.contains("://") || (prefix == "git+" && path.contains('@')) {
+            return;
+        }
+
+        let url = format!("{}{}", prefix, dir.join(path).to_string_lossy());
+        self.url = url;
+    }
+
+    fn pinned_digest(&self) -> Result<Option<DigestData>> {
+        match self.digest {
+            Some(ref text) => Ok(Some(ctry!(DigestData::from_str(text); "invalid digest \"{}\" for bundle \"{}\"",
+                                            text, self.url))),
+            None => Ok(None),
+        }
     }
 }
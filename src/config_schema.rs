@@ -0,0 +1,394 @@
+// src/config_schema.rs -- checking configuration files before using them
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Checking of configuration files against the settings that we know about.
+//! Serde ignores keys that it doesn't know, so a misspelled setting would
+//! silently do nothing, and its complaints about values of the wrong type
+//! don't say which setting is wrong or where it is. `check()` walks the
+//! parsed TOML before serde sees it and describes each problem, with the
+//! line that it's on and, where we can guess, what was meant.
+//!
+//! The lists of fields here have to be kept in step with the structs in
+//! `config` that the files are deserialized into.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+
+use toml::Value;
+use toml::value::Table;
+
+
+/// What a setting is allowed to be.
+pub enum Kind {
+    Bool,
+    /// A whole number that isn't negative.
+    Integer,
+    /// Any number.
+    Float,
+    Str,
+    /// A string that has to be one of these.
+    OneOf(&'static [&'static str]),
+    StrList,
+    /// A table with these fields.
+    Table(&'static [Field]),
+    /// A list of tables with these fields.
+    TableList(&'static [Field]),
+    /// A table of tables with these fields, under names chosen by the user,
+    /// like the profiles.
+    NamedTables(&'static [Field]),
+}
+
+impl Kind {
+    fn describe(&self) -> &'static str {
+        match *self {
+            Kind::Bool => "true or false",
+            Kind::Integer => "a whole number",
+            Kind::Float => "a number",
+            Kind::Str | Kind::OneOf(_) => "a string",
+            Kind::StrList => "a list of strings",
+            Kind::Table(_) => "a table",
+            Kind::TableList(_) => "a list of tables",
+            Kind::NamedTables(_) => "a table of tables",
+        }
+    }
+}
+
+
+pub struct Field {
+    pub name: &'static str,
+    pub kind: Kind,
+}
+
+
+const BUNDLE_FIELDS: &'static [Field] = &[
+    Field { name: "url", kind: Kind::Str },
+    Field { name: "digest", kind: Kind::Str },
+    Field { name: "git_commit", kind: Kind::Str },
+    Field { name: "mirrors", kind: Kind::StrList },
+    Field { name: "s3_endpoint", kind: Kind::Str },
+    Field { name: "s3_region", kind: Kind::Str },
+];
+
+const PROFILE_FIELDS: &'static [Field] = &[
+    Field { name: "reruns", kind: Kind::Integer },
+    Field { name: "draft", kind: Kind::Bool },
+    Field { name: "synctex", kind: Kind::Bool },
+    Field { name: "precompiled_preamble", kind: Kind::Bool },
+    Field { name: "pdfa", kind: Kind::Bool },
+    Field { name: "pdf_compression", kind: Kind::Integer },
+];
+
+const WARNINGS_FIELDS: &'static [Field] = &[
+    Field { name: "hide", kind: Kind::StrList },
+    Field { name: "min_overfull_pt", kind: Kind::Float },
+    Field { name: "hide_matching", kind: Kind::StrList },
+];
+
+const DEFAULTS_FIELDS: &'static [Field] = &[
+    Field { name: "outfmt", kind: Kind::OneOf(&["pdf", "svg", "png", "txt", "xdv", "aux", "format"]) },
+    Field { name: "keep_logs", kind: Kind::Bool },
+    Field { name: "keep_intermediates", kind: Kind::Bool },
+    Field { name: "synctex", kind: Kind::Bool },
+    Field { name: "interaction", kind: Kind::OneOf(&["batchmode", "nonstopmode", "scrollmode", "errorstopmode"]) },
+    Field { name: "reruns", kind: Kind::Integer },
+    Field { name: "max_passes", kind: Kind::Integer },
+];
+
+const NETWORK_FIELDS: &'static [Field] = &[
+    Field { name: "connect_timeout_secs", kind: Kind::Integer },
+    Field { name: "read_timeout_secs", kind: Kind::Integer },
+    Field { name: "max_attempts", kind: Kind::Integer },
+    Field { name: "retry_delay_ms", kind: Kind::Integer },
+];
+
+/// The settings of the user's configuration file and of project
+/// configuration files.
+pub const CONFIG_FIELDS: &'static [Field] = &[
+    Field { name: "redownload_corrupt_cache_files", kind: Kind::Bool },
+    Field { name: "max_cache_size_mb", kind: Kind::Integer },
+    Field { name: "proxy", kind: Kind::Str },
+    Field { name: "search_path", kind: Kind::StrList },
+    Field { name: "cache_dir", kind: Kind::Str },
+    Field { name: "default_bundles", kind: Kind::TableList(BUNDLE_FIELDS) },
+    Field { name: "profiles", kind: Kind::NamedTables(PROFILE_FIELDS) },
+    Field { name: "bundles", kind: Kind::NamedTables(BUNDLE_FIELDS) },
+    Field { name: "warnings", kind: Kind::Table(WARNINGS_FIELDS) },
+    Field { name: "defaults", kind: Kind::Table(DEFAULTS_FIELDS) },
+    Field { name: "network", kind: Kind::Table(NETWORK_FIELDS) },
+];
+
+/// Settings that people might write but that aren't supported, by their
+/// dotted paths, with what to do instead.
+const DEPRECATED: &'static [(&'static str, &'static str)] = &[
+    ("default_bundle", "list the bundle in a [[default_bundles]] table with a `url` setting instead, or run \
+                        `tectonic config set default_bundle URL`"),
+];
+
+
+/// Something wrong with a configuration file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Problem {
+    /// The line of the file that the problem is on, counting from 1, if we
+    /// could find it.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(n) => write!(f, "line {}: {}", n, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+
+/// Check the parsed contents `root` of a configuration file against the
+/// settings `fields`. `text` is the text of the file, for finding the lines
+/// that problems are on.
+pub fn check(text: &str, root: &Table, fields: &[Field]) -> Vec<Problem> {
+    let mut checker = Checker {
+        lines: key_lines(text),
+        path: Vec::new(),
+        problems: Vec::new(),
+    };
+
+    checker.check_table(root, fields);
+    checker.problems
+}
+
+
+struct Checker {
+    lines: HashMap<String, usize>,
+
+    /// Where we are in the file, as a list of keys, with list indices
+    /// written like "[0]".
+    path: Vec<String>,
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    fn display_path(&self) -> String {
+        self.path.join(".").replace(".[", "[")
+    }
+
+    fn report(&mut self, message: String) {
+        // If the setting itself can't be found, say where the table that
+        // it's in is.
+        let mut line = None;
+
+        for n in (1..self.path.len() + 1).rev() {
+            if let Some(&l) = self.lines.get(&self.path[..n].join(".")) {
+                line = Some(l);
+                break;
+            }
+        }
+
+        self.problems.push(Problem { line: line, message: message });
+    }
+
+    fn check_table(&mut self, table: &Table, fields: &[Field]) {
+        for (key, value) in table {
+            self.path.push(key.clone());
+
+            match fields.iter().find(|f| f.name == &key[..]) {
+                Some(f) => self.check_value(value, &f.kind),
+                None => {
+                    let path = self.display_path();
+
+                    let message = match DEPRECATED.iter().find(|&&(p, _)| p == path) {
+                        Some(&(_, advice)) => format!("\"{}\" is not supported; {}", path, advice),
+                        None => match suggest(key, fields.iter().map(|f| f.name)) {
+                            Some(s) => format!("unknown setting \"{}\"; did you mean \"{}\"?", path, s),
+                            None => format!("unknown setting \"{}\"", path),
+                        },
+                    };
+
+                    self.report(message);
+                },
+            }
+
+            self.path.pop();
+        }
+    }
+
+    fn check_value(&mut self, value: &Value, kind: &Kind) {
+        match (kind, value) {
+            (&Kind::Bool, &Value::Boolean(_)) => {},
+            (&Kind::Float, &Value::Float(_)) => {},
+            (&Kind::Float, &Value::Integer(_)) => {},
+            (&Kind::Str, &Value::String(_)) => {},
+
+            (&Kind::Integer, &Value::Integer(n)) => {
+                if n < 0 {
+                    let message = format!("\"{}\" can't be negative", self.display_path());
+                    self.report(message);
+                }
+            },
+
+            (&Kind::OneOf(choices), &Value::String(ref s)) => {
+                if !choices.iter().any(|c| *c == &s[..]) {
+                    let message = match suggest(s, choices.iter().cloned()) {
+                        Some(c) => format!("\"{}\" can't be \"{}\"; did you mean \"{}\"?", self.display_path(), s, c),
+                        None => format!("\"{}\" can't be \"{}\"; it should be one of \"{}\"", self.display_path(), s,
+                                        choices.join("\", \"")),
+                    };
+                    self.report(message);
+                }
+            },
+
+            (&Kind::StrList, &Value::Array(ref items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.path.push(format!("[{}]", i));
+                    self.check_value(item, &Kind::Str);
+                    self.path.pop();
+                }
+            },
+
+            (&Kind::Table(fields), &Value::Table(ref t)) => self.check_table(t, fields),
+
+            (&Kind::TableList(fields), &Value::Array(ref items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.path.push(format!("[{}]", i));
+                    self.check_value(item, &Kind::Table(fields));
+                    self.path.pop();
+                }
+            },
+
+            (&Kind::NamedTables(fields), &Value::Table(ref t)) => {
+                for (name, item) in t {
+                    self.path.push(name.clone());
+                    self.check_value(item, &Kind::Table(fields));
+                    self.path.pop();
+                }
+            },
+
+            _ => {
+                let message = format!("\"{}\" should be {}, not {}", self.display_path(), kind.describe(),
+                                      describe_value(value));
+                self.report(message);
+            },
+        }
+    }
+}
+
+
+fn describe_value(value: &Value) -> &'static str {
+    match *value {
+        Value::String(_) => "a string",
+        Value::Integer(_) => "a whole number",
+        Value::Float(_) => "a number",
+        Value::Boolean(_) => "true or false",
+        Value::Datetime(_) => "a date",
+        Value::Array(_) => "a list",
+        Value::Table(_) => "a table",
+    }
+}
+
+
+/// Find the line that each key of a TOML file is on, by its dotted path.
+/// This understands the kinds of lines that configuration files have, not
+/// the whole of TOML; keys that it misses are reported without a line.
+fn key_lines(text: &str) -> HashMap<String, usize> {
+    let mut lines = HashMap::new();
+    let mut table: Vec<String> = Vec::new();
+    let mut list_lengths: HashMap<String, usize> = HashMap::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.starts_with("[[") {
+            let name = split_key(line.trim_left_matches('[').split(']').next().unwrap_or("")).join(".");
+            let index = list_lengths.entry(name.clone()).or_insert(0);
+            table = vec![name, format!("[{}]", index)];
+            *index += 1;
+        } else if line.starts_with('[') {
+            table = split_key(line.trim_left_matches('[').split(']').next().unwrap_or(""));
+        } else if let Some(eq) = line.find('=') {
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let mut path = table.clone();
+            path.extend(split_key(&line[..eq]));
+            lines.entry(path.join(".")).or_insert(i + 1);
+            continue;
+        } else {
+            continue;
+        }
+
+        lines.entry(table.join(".")).or_insert(i + 1);
+    }
+
+    lines
+}
+
+
+fn split_key(key: &str) -> Vec<String> {
+    key.split('.').map(|k| k.trim().trim_matches('"').trim_matches('\'').to_owned()).collect()
+}
+
+
+/// Pick the one of `candidates` that `word` was most likely meant to be, if
+/// any are close enough.
+pub fn suggest<'a, I: Iterator<Item = &'a str>>(word: &str, candidates: I) -> Option<&'a str> {
+    let limit = cmp::max(2, word.len() / 4);
+
+    candidates.map(|c| (edit_distance(word, c), c))
+        .filter(|&(d, _)| d <= limit)
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, c)| c)
+}
+
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == cb { 0 } else { 1 };
+            cur.push(cmp::min(substitute, cmp::min(prev[j + 1] + 1, cur[j] + 1)));
+        }
+
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    fn problems(text: &str) -> Vec<String> {
+        let root: Table = toml::from_str(text).unwrap();
+        check(text, &root, CONFIG_FIELDS).iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn checking() {
+        assert_eq!(problems("[[default_bundles]]\nurl = \"https://example.com/b.tar\"\n"), Vec::<String>::new());
+
+        assert_eq!(problems("max_cache_size = 100\n"),
+                   vec!["line 1: unknown setting \"max_cache_size\"; did you mean \"max_cache_size_mb\"?"]);
+
+        assert_eq!(problems("proxy = 3128\n\n[defaults]\noutfmt = \"pfd\"\n"),
+                   vec!["line 4: \"defaults.outfmt\" can't be \"pfd\"; did you mean \"pdf\"?",
+                        "line 1: \"proxy\" should be a string, not a whole number"]);
+
+        assert_eq!(problems("[[default_bundles]]\nurl = \"a\"\n[[default_bundles]]\nulr = \"b\"\n"),
+                   vec!["line 4: unknown setting \"default_bundles[1].ulr\"; did you mean \"url\"?"]);
+
+        assert_eq!(problems("[profiles.draft]\nreruns = -1\n"),
+                   vec!["line 2: \"profiles.draft.reruns\" can't be negative"]);
+    }
+}
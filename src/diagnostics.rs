@@ -0,0 +1,822 @@
+// src/diagnostics.rs -- structured information from the TeX log
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! The TeX engine reports errors, warnings, and so on by writing them to its
+//! log file, in a format meant for humans. This module parses that stream
+//! into `Diagnostic` records so that programs driving the engine don't all
+//! have to do it themselves. The `TexEngine` runs its log through a
+//! `LogParser` as it goes; the results are available from
+//! `TexEngine::diagnostics()`.
+//!
+//! The log format isn't really designed to be parsed, so this is a
+//! best-effort affair. In particular, TeX reports which file it's reading
+//! only by printing parentheses around the file's output, so a stray
+//! parenthesis can confuse us about where a message came from.
+//!
+//! Some problems are reported to us directly by the engine, rather than by
+//! way of the log. Those diagnostics are also passed to the
+//! `StatusBackend::report_diagnostic()` of the engine's status backend as
+//! they happen. The errors found in the log are passed along the same way by
+//! the `ProcessingSession`, once the pass that produced them is over.
+//!
+//! Along with each TeX error, the log shows where TeX was when it happened:
+//! the macros that it was expanding, and how far it had got through the
+//! line of input. That's kept in the error's `ErrorContext`, to which the
+//! `ProcessingSession` adds the lines of the input file around the error,
+//! so that programs can show the problem without sending people to the log.
+
+use std::{char, fmt, mem};
+use std::collections::BTreeSet;
+
+
+/// The number of characters at which TeX wraps the lines of its log.
+const MAX_PRINT_LINE: usize = 79;
+
+/// How many lines to look at for the location of an error before giving up.
+const MAX_ERROR_CONTEXT_LINES: usize = 20;
+
+/// How many continuation lines a warning can have.
+const MAX_WARNING_LINES: usize = 10;
+
+/// How many lines of the input file to keep on each side of the line where
+/// an error happened.
+const SOURCE_CONTEXT_LINES: u32 = 2;
+
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum DiagnosticKind {
+    /// A TeX error, such as "Undefined control sequence".
+    Error,
+
+    /// A warning issued by LaTeX, a class, or a package.
+    Warning,
+
+    /// An overfull or underfull box.
+    BadBox,
+
+    /// The engine started reading an input file.
+    FileOpened,
+
+    /// The current font has no glyph for a character, so the character was
+    /// left out. The code point is a Unicode scalar value, or a character
+    /// code for fonts that aren't Unicode-based.
+    MissingCharacter { font: String, codepoint: u32 },
+
+    /// A bold or italic version of a font was requested, but the font family
+    /// doesn't have one, so the named face was used instead.
+    FontSubstitution { requested: String, substitute: String },
+}
+
+
+impl DiagnosticKind {
+    /// A short name for the kind of diagnostic, for use in machine-readable
+    /// output.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DiagnosticKind::Error => "error",
+            DiagnosticKind::Warning => "warning",
+            DiagnosticKind::BadBox => "badbox",
+            DiagnosticKind::FileOpened => "file-opened",
+            DiagnosticKind::MissingCharacter { .. } => "missing-character",
+            DiagnosticKind::FontSubstitution { .. } => "font-substitution",
+        }
+    }
+}
+
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+
+    /// The text of the message, with TeX's line breaks removed. For
+    /// `FileOpened` records, this is the name of the file.
+    pub message: String,
+
+    /// The input file that the engine was reading when the message was
+    /// issued, if known.
+    pub file: Option<String>,
+
+    /// The line of the input file that the message refers to, if known.
+    pub line: Option<u32>,
+
+    /// For errors, what TeX said about where it was when the error
+    /// happened.
+    pub context: Option<ErrorContext>,
+}
+
+
+/// Where TeX was when an error happened. TeX shows this as pairs of lines,
+/// the first ending at the point where the error was noticed and the second
+/// giving what comes after it, like this:
+///
+/// ```text
+/// \mymacro ->\foo
+///                 bar
+/// l.7 \mymacro
+///             {baz}
+/// ```
+#[derive(Clone,Debug,Default,Eq,PartialEq,Serialize)]
+pub struct ErrorContext {
+    /// The macro expansions and other lists of tokens that TeX was reading,
+    /// from the innermost out. They don't include the input line.
+    pub expansions: Vec<ContextLevel>,
+
+    /// The input line, split at the point where the error was noticed.
+    pub input: Option<ContextLevel>,
+
+    /// Some lines of the input file around the one where the error
+    /// happened, if it could be read again.
+    pub source: Vec<SourceLine>,
+
+    /// A suggestion for fixing the error, such as another name for a file
+    /// that couldn't be found.
+    pub hint: Option<String>,
+}
+
+
+/// One of the pairs of lines in an `ErrorContext`. `read` starts with TeX's
+/// label for the level, such as "\mymacro ->", "<argument>", or "l.7".
+#[derive(Clone,Debug,Default,Eq,PartialEq,Serialize)]
+pub struct ContextLevel {
+    pub read: String,
+    pub unread: String,
+}
+
+
+/// A line of an input file in an `ErrorContext`.
+#[derive(Clone,Debug,Eq,PartialEq,Serialize)]
+pub struct SourceLine {
+    pub line: u32,
+    pub text: String,
+}
+
+
+impl ErrorContext {
+    /// Add the line of input that TeX shows at the end of the context, or
+    /// the second half of the last pair of lines.
+    fn add_line(&mut self, line: &str) {
+        if line.starts_with(' ') {
+            let text = line.trim();
+
+            // TeX puts a lone "..." in where it's left out some levels.
+            if text == "..." {
+                return;
+            }
+
+            if let Some(level) = self.expansions.last_mut() {
+                if level.unread.is_empty() {
+                    level.unread = text.to_owned();
+                }
+            }
+        } else if line.starts_with('<') || line.starts_with('\\') || line.starts_with("...") {
+            self.expansions.push(ContextLevel {
+                read: line.trim_right().to_owned(),
+                unread: String::new(),
+            });
+        }
+    }
+
+    /// Take the lines of `text` that surround line `line`, counting from 1.
+    pub fn set_source(&mut self, text: &str, line: u32) {
+        let first = line.saturating_sub(SOURCE_CONTEXT_LINES).max(1);
+        let last = line.saturating_add(SOURCE_CONTEXT_LINES);
+
+        self.source = text.lines().enumerate()
+            .map(|(i, t)| (i as u32 + 1, t))
+            .skip_while(|&(n, _)| n < first)
+            .take_while(|&(n, _)| n <= last)
+            .map(|(n, t)| SourceLine { line: n, text: t.trim_right().to_owned() })
+            .collect();
+    }
+}
+
+
+impl Diagnostic {
+    /// Create a diagnostic for a character that isn't in a font.
+    pub fn missing_character(font: &str, codepoint: u32) -> Diagnostic {
+        let message = match char::from_u32(codepoint) {
+            Some(c) if !c.is_control() => format!("there is no character U+{:04X} ({}) in font {}",
+                                                  codepoint, c, font),
+            _ => format!("there is no character U+{:04X} in font {}", codepoint, font),
+        };
+
+        Diagnostic {
+            kind: DiagnosticKind::MissingCharacter { font: font.to_owned(), codepoint: codepoint },
+            message: message,
+            file: None,
+            line: None,
+            context: None,
+        }
+    }
+
+    /// Create a diagnostic for a font that was replaced by another face.
+    pub fn font_substitution(requested: &str, substitute: &str) -> Diagnostic {
+        Diagnostic {
+            kind: DiagnosticKind::FontSubstitution {
+                requested: requested.to_owned(),
+                substitute: substitute.to_owned(),
+            },
+            message: format!("font \"{}\" is not available; using \"{}\" instead", requested, substitute),
+            file: None,
+            line: None,
+            context: None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (&Some(ref file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+            (&Some(ref file), None) => write!(f, "{}: {}", file, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+
+/// The problems that people most often have to go looking for in the log,
+/// gathered from a set of diagnostics, with each one listed once, in
+/// sorted order.
+#[derive(Clone,Debug,Default,Eq,PartialEq,Serialize)]
+pub struct ProblemSummary {
+    /// The labels of references that LaTeX couldn't resolve.
+    pub undefined_references: Vec<String>,
+
+    /// The keys of citations that LaTeX couldn't resolve.
+    pub undefined_citations: Vec<String>,
+
+    /// The files that TeX or LaTeX couldn't find.
+    pub missing_files: Vec<String>,
+
+    /// The overfull boxes, described with their locations.
+    pub overfull_boxes: Vec<String>,
+}
+
+
+impl ProblemSummary {
+    pub fn from_diagnostics(diagnostics: &[Diagnostic]) -> ProblemSummary {
+        let mut references = BTreeSet::new();
+        let mut citations = BTreeSet::new();
+        let mut files = BTreeSet::new();
+        let mut boxes = BTreeSet::new();
+
+        for d in diagnostics {
+            match d.kind {
+                DiagnosticKind::Warning if d.message.contains(" undefined") => {
+                    if let Some(label) = quoted_after(&d.message, "Reference `") {
+                        references.insert(label.to_owned());
+                    } else if let Some(key) = quoted_after(&d.message, "Citation `") {
+                        citations.insert(key.to_owned());
+                    }
+                },
+                DiagnosticKind::Error => {
+                    if let Some(name) = missing_file_name(&d.message) {
+                        files.insert(name.to_owned());
+                    }
+                },
+                DiagnosticKind::BadBox if d.message.starts_with("Overfull ") => {
+                    boxes.insert(d.to_string());
+                },
+                _ => {},
+            }
+        }
+
+        ProblemSummary {
+            undefined_references: references.into_iter().collect(),
+            undefined_citations: citations.into_iter().collect(),
+            missing_files: files.into_iter().collect(),
+            overfull_boxes: boxes.into_iter().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undefined_references.is_empty() && self.undefined_citations.is_empty() &&
+            self.missing_files.is_empty() && self.overfull_boxes.is_empty()
+    }
+}
+
+
+/// What we're in the middle of parsing.
+enum State {
+    Normal,
+
+    /// We've seen an error and are looking for the line where it happened,
+    /// which TeX prints as "l.123", followed by the rest of that line of
+    /// input. After that comes help text, which continues until a blank
+    /// line.
+    Error(Diagnostic, usize),
+    ErrorInput(Diagnostic),
+    ErrorHelp,
+
+    /// We've seen the start of a warning, which continues until a blank
+    /// line.
+    Warning(Diagnostic, usize),
+
+    /// We've seen a bad box, which is followed by a description of its
+    /// contents that continues until a blank line.
+    BadBox,
+}
+
+
+/// An incremental parser of the TeX log.
+pub struct LogParser {
+    /// The bytes of the current physical line that we've seen so far.
+    partial: Vec<u8>,
+
+    /// The logical line that we're assembling from wrapped physical lines.
+    logical: Option<String>,
+
+    /// The files that TeX is reading. Parentheses that don't seem to belong
+    /// to a file name are recorded as None so that they balance properly.
+    files: Vec<Option<String>>,
+
+    state: State,
+    diagnostics: Vec<Diagnostic>,
+}
+
+
+impl LogParser {
+    pub fn new() -> LogParser {
+        LogParser {
+            partial: Vec::new(),
+            logical: None,
+            files: Vec::new(),
+            state: State::Normal,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Process some more of the log.
+    pub fn feed(&mut self, data: &[u8]) {
+        for &b in data {
+            if b == b'\n' {
+                let line = String::from_utf8_lossy(&self.partial).into_owned();
+                self.partial.clear();
+                self.physical_line(line);
+            } else if b != b'\r' {
+                self.partial.push(b);
+            }
+        }
+    }
+
+    /// Add a diagnostic that didn't come from the log itself.
+    pub fn record(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Finish parsing and get the results.
+    pub fn finish(mut self) -> Vec<Diagnostic> {
+        if !self.partial.is_empty() {
+            let line = String::from_utf8_lossy(&self.partial).into_owned();
+            self.physical_line(line);
+        }
+
+        if let Some(line) = self.logical.take() {
+            self.logical_line(&line);
+        }
+
+        self.flush_pending();
+        self.diagnostics
+    }
+
+    fn physical_line(&mut self, line: String) {
+        // TeX wraps lines that are too long. That means that a line that's
+        // exactly as long as the limit is probably continued on the next one.
+
+        let wrapped = line.chars().count() == MAX_PRINT_LINE;
+
+        let logical = match self.logical.take() {
+            Some(mut prev) => {
+                prev.push_str(&line);
+                prev
+            },
+            None => line,
+        };
+
+        if wrapped {
+            self.logical = Some(logical);
+        } else {
+            self.logical_line(&logical);
+        }
+    }
+
+    fn current_file(&self) -> Option<String> {
+        self.files.iter().rev().filter_map(|f| f.clone()).next()
+    }
+
+    fn new_diagnostic(&self, kind: DiagnosticKind, message: &str) -> Diagnostic {
+        Diagnostic {
+            kind: kind,
+            message: message.to_owned(),
+            file: self.current_file(),
+            line: None,
+            context: None,
+        }
+    }
+
+    /// Emit any diagnostic that we're still assembling.
+    fn flush_pending(&mut self) {
+        match mem::replace(&mut self.state, State::Normal) {
+            State::Error(d, _) | State::ErrorInput(d) | State::Warning(d, _) => self.diagnostics.push(d),
+            _ => {},
+        }
+    }
+
+    fn logical_line(&mut self, line: &str) {
+        let state = mem::replace(&mut self.state, State::Normal);
+
+        self.state = match state {
+            State::Normal => State::Normal,
+
+            State::Error(mut d, n) => {
+                if let Some(lineno) = parse_error_line(line) {
+                    d.line = Some(lineno);
+
+                    if let Some(ref mut context) = d.context {
+                        context.input = Some(ContextLevel {
+                            read: line.trim_right().to_owned(),
+                            unread: String::new(),
+                        });
+                    }
+
+                    State::ErrorInput(d)
+                } else if line.starts_with("! ") || n >= MAX_ERROR_CONTEXT_LINES {
+                    self.diagnostics.push(d);
+                    State::Normal
+                } else {
+                    if let Some(ref mut context) = d.context {
+                        context.add_line(line);
+                    }
+
+                    State::Error(d, n + 1)
+                }
+            },
+
+            State::ErrorInput(mut d) => {
+                let rest_of_input = line.starts_with(' ') && !line.trim().is_empty();
+
+                if rest_of_input {
+                    if let Some(input) = d.context.as_mut().and_then(|c| c.input.as_mut()) {
+                        input.unread = line.trim().to_owned();
+                    }
+                }
+
+                self.diagnostics.push(d);
+
+                if rest_of_input {
+                    State::ErrorHelp
+                } else {
+                    after_error_help(line)
+                }
+            },
+
+            State::ErrorHelp => after_error_help(line),
+
+            State::Warning(mut d, n) => {
+                if line.is_empty() || line.starts_with("! ") || n >= MAX_WARNING_LINES {
+                    self.diagnostics.push(d);
+                    State::Normal
+                } else {
+                    let text = strip_continuation_prefix(line);
+                    d.message.push(' ');
+                    d.message.push_str(text);
+
+                    if d.line.is_none() {
+                        d.line = parse_input_line(text);
+                    }
+
+                    State::Warning(d, n + 1)
+                }
+            },
+
+            State::BadBox => {
+                if line.is_empty() {
+                    State::Normal
+                } else {
+                    State::BadBox
+                }
+            },
+        };
+
+        if let State::Normal = self.state {
+            self.start_line(line);
+        }
+    }
+
+    /// Handle a line that isn't part of a multi-line message that we already
+    /// know about.
+    fn start_line(&mut self, line: &str) {
+        if line.starts_with("! ") {
+            let mut d = self.new_diagnostic(DiagnosticKind::Error, line[2..].trim());
+            d.context = Some(ErrorContext::default());
+            self.state = State::Error(d, 0);
+        } else if is_warning(line) {
+            let mut d = self.new_diagnostic(DiagnosticKind::Warning, line.trim());
+            d.line = parse_input_line(line);
+            self.state = State::Warning(d, 0);
+        } else if line.starts_with("Overfull \\") || line.starts_with("Underfull \\") {
+            let mut d = self.new_diagnostic(DiagnosticKind::BadBox, line.trim());
+            d.line = parse_bad_box_line(line);
+            self.diagnostics.push(d);
+            self.state = State::BadBox;
+        } else {
+            self.scan_parens(line);
+        }
+    }
+
+    /// Keep track of the files that TeX opens and closes. It reports them
+    /// as "(name" when it starts reading a file and ")" when it's done.
+    fn scan_parens(&mut self, line: &str) {
+        let mut rest = line;
+
+        while let Some(i) = rest.find(|c: char| c == '(' || c == ')') {
+            if &rest[i..i+1] == ")" {
+                self.files.pop();
+                rest = &rest[i+1..];
+                continue;
+            }
+
+            rest = &rest[i+1..];
+            let end = rest.find(|c: char| c.is_whitespace() || c == '(' || c == ')').unwrap_or(rest.len());
+            let name = &rest[..end];
+
+            if looks_like_file_name(name) {
+                let d = Diagnostic {
+                    kind: DiagnosticKind::FileOpened,
+                    message: name.to_owned(),
+                    file: Some(name.to_owned()),
+                    line: None,
+                    context: None,
+                };
+                self.diagnostics.push(d);
+                self.files.push(Some(name.to_owned()));
+            } else {
+                self.files.push(None);
+            }
+
+            rest = &rest[end..];
+        }
+    }
+}
+
+
+/// Get the name of the file that an error is about, if it's one of the
+/// errors that TeX and LaTeX give when they can't find a file.
+pub fn missing_file_name(message: &str) -> Option<&str> {
+    quoted_after(message, "File `").or_else(|| quoted_after(message, "I can't find file `"))
+}
+
+
+/// The state after a line of the help text that follows an error, which
+/// ends at a blank line or the next error.
+fn after_error_help(line: &str) -> State {
+    if line.is_empty() || line.starts_with("! ") {
+        State::Normal
+    } else {
+        State::ErrorHelp
+    }
+}
+
+
+/// Parse a whole log at once.
+pub fn parse_log(data: &[u8]) -> Vec<Diagnostic> {
+    let mut p = LogParser::new();
+    p.feed(data);
+    p.finish()
+}
+
+
+fn is_warning(line: &str) -> bool {
+    if line.starts_with("LaTeX Warning:") || line.starts_with("LaTeX Font Warning:") {
+        return true;
+    }
+
+    // "Package foo Warning:" and "Class foo Warning:".
+    for prefix in &["Package ", "Class "] {
+        if line.starts_with(*prefix) {
+            let mut words = line[prefix.len()..].splitn(3, ' ');
+            words.next();
+            return words.next() == Some("Warning:");
+        }
+    }
+
+    false
+}
+
+
+/// Continuation lines of package warnings start with the package name in
+/// parentheses, padded with spaces.
+fn strip_continuation_prefix(line: &str) -> &str {
+    if line.starts_with('(') {
+        if let Some(i) = line.find(')') {
+            return line[i+1..].trim();
+        }
+    }
+
+    line.trim()
+}
+
+
+/// Parse the "l.123" that TeX uses to show where an error happened.
+fn parse_error_line(line: &str) -> Option<u32> {
+    if !line.starts_with("l.") {
+        return None;
+    }
+
+    let digits: String = line[2..].chars().take_while(|c| c.is_digit(10)).collect();
+    digits.parse().ok()
+}
+
+
+/// Parse the "on input line 123" that LaTeX appends to its warnings.
+fn parse_input_line(text: &str) -> Option<u32> {
+    const MARKER: &'static str = "on input line ";
+
+    text.rfind(MARKER).and_then(|i| {
+        let digits: String = text[i + MARKER.len()..].chars().take_while(|c| c.is_digit(10)).collect();
+        digits.parse().ok()
+    })
+}
+
+
+/// Parse the "in paragraph at lines 12--14" or "detected at line 12" of a
+/// bad box message. We give the first line.
+fn parse_bad_box_line(text: &str) -> Option<u32> {
+    for marker in &[" at lines ", " at line "] {
+        if let Some(i) = text.rfind(*marker) {
+            let digits: String = text[i + marker.len()..].chars().take_while(|c| c.is_digit(10)).collect();
+            return digits.parse().ok();
+        }
+    }
+
+    None
+}
+
+
+/// Get the text quoted TeX-style, `like this', right after `prefix`.
+fn quoted_after<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    text.find(prefix).and_then(|i| {
+        let rest = &text[i + prefix.len()..];
+        rest.find('\'').map(|j| &rest[..j])
+    })
+}
+
+
+/// Guess whether the text following an opening parenthesis is the name of
+/// a file.
+fn looks_like_file_name(name: &str) -> bool {
+    if name.is_empty() || name.starts_with(|c: char| c.is_digit(10)) {
+        return false;
+    }
+
+    name.contains('/') || name.rfind('.').map(|i| i > 0 && i < name.len() - 1).unwrap_or(false)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &'static str = "\
+This is XeTeX, Version 3.14159265-2.6-0.99996 (Tectonic) (preloaded format=latex 2017.6.16)
+entering extended mode
+(doc.tex
+LaTeX2e <2017-04-15>
+(article.cls
+Document Class: article 2014/09/29 v1.4h Standard LaTeX document class
+(size10.clo
+File: size10.clo 2014/09/29 v1.4h Standard LaTeX file (size option)
+))
+(doc.aux)
+
+LaTeX Warning: Citation `knuth' on page 1 undefined on input line 5.
+
+! Undefined control sequence.
+l.7 \\foo
+
+The control sequence at the end of the top line
+of your error message was never \\def'ed.
+
+Overfull \\hbox (12.0pt too wide) in paragraph at lines 9--10
+[]\\OT1/cmr/m/n/10 (a) b
+ []
+
+Package hyperref Warning: Token not allowed in a PDF string (Unicode):
+(hyperref)                removing `\\\\' on input line 12.
+
+[1] (doc.aux) )
+Output written on doc.xdv (1 page, 228 bytes).
+";
+
+    #[test]
+    fn sample_log() {
+        let diags: Vec<Diagnostic> = parse_log(SAMPLE.as_bytes()).into_iter()
+            .filter(|d| d.kind != DiagnosticKind::FileOpened)
+            .collect();
+
+        assert_eq!(diags.len(), 4);
+
+        assert_eq!(diags[0].kind, DiagnosticKind::Warning);
+        assert_eq!(diags[0].message, "LaTeX Warning: Citation `knuth' on page 1 undefined on input line 5.");
+        assert_eq!(diags[0].file, Some("doc.tex".to_owned()));
+        assert_eq!(diags[0].line, Some(5));
+
+        assert_eq!(diags[1].kind, DiagnosticKind::Error);
+        assert_eq!(diags[1].message, "Undefined control sequence.");
+        assert_eq!(diags[1].file, Some("doc.tex".to_owned()));
+        assert_eq!(diags[1].line, Some(7));
+
+        assert_eq!(diags[2].kind, DiagnosticKind::BadBox);
+        assert_eq!(diags[2].line, Some(9));
+
+        assert_eq!(diags[3].kind, DiagnosticKind::Warning);
+        assert_eq!(diags[3].message, "Package hyperref Warning: Token not allowed in a PDF string (Unicode): \
+                                      removing `\\\\' on input line 12.");
+        assert_eq!(diags[3].line, Some(12));
+    }
+
+    #[test]
+    fn problem_summary() {
+        let mut diags = parse_log(SAMPLE.as_bytes());
+        diags.extend(parse_log(b"LaTeX Warning: Reference `fig:a' on page 1 undefined on input line 3.\n\
+                                 \n\
+                                 LaTeX Warning: Citation `knuth' on page 2 undefined on input line 8.\n\
+                                 \n\
+                                 ! LaTeX Error: File `missing.sty' not found.\n"));
+
+        let summary = ProblemSummary::from_diagnostics(&diags);
+        assert_eq!(summary.undefined_references, vec!["fig:a"]);
+        assert_eq!(summary.undefined_citations, vec!["knuth"]);
+        assert_eq!(summary.missing_files, vec!["missing.sty"]);
+        assert_eq!(summary.overfull_boxes, vec!["doc.tex:9: Overfull \\hbox (12.0pt too wide) in paragraph at lines 9--10"]);
+    }
+
+    #[test]
+    fn files_opened() {
+        let files: Vec<String> = parse_log(SAMPLE.as_bytes()).into_iter()
+            .filter(|d| d.kind == DiagnosticKind::FileOpened)
+            .map(|d| d.message)
+            .collect();
+
+        assert_eq!(files, vec!["doc.tex", "article.cls", "size10.clo", "doc.aux", "doc.aux"]);
+    }
+
+    #[test]
+    fn wrapped_lines() {
+        let mut text = String::from("LaTeX Warning: ");
+
+        while text.len() < MAX_PRINT_LINE {
+            text.push('x');
+        }
+
+        text.push_str("\nyz on input line 3.\n\n");
+        let diags = parse_log(text.as_bytes());
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0].message.ends_with("xxyz on input line 3."));
+        assert_eq!(diags[0].line, Some(3));
+    }
+
+    #[test]
+    fn error_context() {
+        let log = b"(doc.tex
+! Undefined control sequence.
+\\mymacro ->\\foo
+                bar
+l.7 \\mymacro
+             {baz}
+The control sequence at the end of the top line
+of your error message was never \\def'ed.
+
+)
+";
+        let diags: Vec<Diagnostic> = parse_log(log).into_iter()
+            .filter(|d| d.kind == DiagnosticKind::Error)
+            .collect();
+
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].line, Some(7));
+
+        let mut context = diags[0].context.clone().unwrap();
+        assert_eq!(context.expansions, vec![ContextLevel { read: "\\mymacro ->\\foo".to_owned(),
+                                                           unread: "bar".to_owned() }]);
+        assert_eq!(context.input, Some(ContextLevel { read: "l.7 \\mymacro".to_owned(),
+                                                      unread: "{baz}".to_owned() }));
+
+        context.set_source("a\nb\nc\nd\ne\nf\n\\mymacro{baz}\nh\ni\nj\n", 7);
+        let lines: Vec<u32> = context.source.iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec![5, 6, 7, 8, 9]);
+        assert_eq!(context.source[2].text, "\\mymacro{baz}");
+    }
+
+    #[test]
+    fn display() {
+        let mut d = Diagnostic::missing_character("lmroman10-regular", 0x3b1);
+        assert_eq!(d.to_string(), "there is no character U+03B1 (\u{3b1}) in font lmroman10-regular");
+
+        d.file = Some("doc.tex".to_owned());
+        d.line = Some(4);
+        assert_eq!(d.to_string(), "doc.tex:4: there is no character U+03B1 (\u{3b1}) in font lmroman10-regular");
+    }
+}
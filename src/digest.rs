@@ -51,6 +51,32 @@ pub fn create() -> DigestComputer {
 }
 
 
+/// Compute an HMAC (RFC 2104) of `data` keyed with `key`, using our digest
+/// function. This is needed to sign requests to some web services.
+pub fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+
+    if key.len() > BLOCK_SIZE {
+        let mut dc = create();
+        dc.input(key);
+        block[..N_BYTES].copy_from_slice(dc.result().as_slice());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = create();
+    inner.input(&block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+    inner.input(data);
+
+    let mut outer = create();
+    outer.input(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+    outer.input(inner.result().as_slice());
+    outer.result().as_slice().to_vec()
+}
+
+
 #[derive(Copy,Clone,Debug,Eq,PartialEq)]
 pub struct DigestData([u8; N_BYTES]);
 
@@ -0,0 +1,3183 @@
+// src/driver.rs -- high-level interface for processing documents.
+// Copyright 2016-2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A high-level interface for processing documents the way the `tectonic`
+//! program does: running TeX as many times as needed, along with BibTeX and
+//! friends, then producing the final output and saving the results to disk.
+//! Set up a `ProcessingSession` with a `SessionBuilder`.
+
+use serde_json;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempdir::TempDir;
+
+use config_schema::suggest;
+use diagnostics::{self, Diagnostic, DiagnosticKind, ErrorContext, ProblemSummary};
+use digest::{self, Digest, DigestData};
+use engines::{CancellationToken, IoEventBackend};
+use engines::bibtex::BibtexEngine;
+use engines::makeindex::MakeindexEngine;
+use engines::tex::{InteractionMode, TexEngine, TexResult};
+use engines::xdvipdfmx::{PdfAttachment, XdvipdfmxEngine};
+use engines::xdvipng::XdvipngEngine;
+use engines::xdvisvg::XdvisvgEngine;
+use engines::xdvitxt::XdvitxtEngine;
+use errors::{Error, ErrorKind, Result, ResultExt};
+use io::{FilesystemIo, FilesystemPrimaryInputIo, GenuineStdoutIo, InputOrigin, IoProvider, IoStack, MemoryIo,
+         OpenResult};
+use io::allowlist::AllowlistIo;
+use io::audit::IoAuditLog;
+use io::casefold::CaseFoldIo;
+use io::limits::LimitedIo;
+use io::searchpath::SearchPathIo;
+use io::stdstreams::BufferedPrimaryIo;
+use status::{ChatterLevel, ProgressUnit, StatusBackend};
+
+
+/// The IoSetup struct encapsulates, well, the input/output setup used by
+/// the Tectonic engines in a processing session.
+///
+/// The IoStack struct must necessarily erase types (i.e., turn I/O layers
+/// into IoProvider trait objects) while it lives. But, between invocations of
+/// various engines, we want to look at our individual typed I/O providers and
+/// interrogate them (i.e., see what files were created in the memory layer.
+/// The IoSetup struct helps us maintain detailed knowledge of types while
+/// creating an IoStack when needed. In principle we could reuse the same
+/// IoStack for each processing step, but the borrow checker doesn't let us
+/// poke at (e.g.) io.mem while the IoStack exists, since the IoStack keeps a
+/// mutable borrow of it.
+
+struct IoSetup {
+    primary_input: Box<IoProvider>,
+    bundles: Vec<Box<IoProvider>>,
+    mem: MemoryIo,
+
+    /// For untrusted documents, the engines get at `mem` through this,
+    /// which also holds the limits shared with the filesystem providers.
+    limited_mem: Option<LimitedIo<MemoryIo>>,
+
+    /// The providers of files from the filesystem, in search order.
+    filesystem: Vec<(&'static str, Box<IoProvider>)>,
+    genuine_stdout: Option<GenuineStdoutIo>,
+    format_primary: Option<BufferedPrimaryIo>,
+    audit: Option<IoAuditLog>,
+}
+
+impl IoSetup {
+    fn as_stack<'a> (&'a mut self) -> IoStack<'a> {
+        let mut providers: Vec<(&str, &mut IoProvider)> = Vec::new();
+
+        if let Some(ref mut p) = self.genuine_stdout {
+            providers.push(("stdout", p));
+        }
+
+        providers.push(("primary input", &mut *self.primary_input));
+
+        match self.limited_mem {
+            Some(ref mut lm) => providers.push(("memory", lm)),
+            None => providers.push(("memory", &mut self.mem)),
+        }
+
+        for item in self.filesystem.iter_mut() {
+            providers.push((item.0, &mut *item.1));
+        }
+
+        for b in self.bundles.iter_mut() {
+            providers.push(("bundle", &mut **b));
+        }
+
+        let mut stack = IoStack::new_named(providers);
+
+        if let Some(ref log) = self.audit {
+            stack.audit(log.clone());
+        }
+
+        stack
+    }
+
+    fn as_stack_for_format<'a> (&'a mut self, kickstart: &str) -> IoStack<'a> {
+        let mut providers: Vec<(&str, &mut IoProvider)> = Vec::new();
+
+        if let Some(ref mut p) = self.genuine_stdout {
+            providers.push(("stdout", p));
+        }
+
+
+        self.format_primary = Some(BufferedPrimaryIo::from_text(kickstart));
+        providers.push(("primary input", self.format_primary.as_mut().unwrap()));
+        providers.push(("memory", &mut self.mem));
+
+        for b in self.bundles.iter_mut() {
+            providers.push(("bundle", &mut **b));
+        }
+
+        let mut stack = IoStack::new_named(providers);
+
+        if let Some(ref log) = self.audit {
+            stack.audit(log.clone());
+        }
+
+        stack
+    }
+}
+
+/// Input files from the filesystem that are at least this large are
+/// memory-mapped rather than read through a buffer.
+const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+/// The limits imposed on untrusted documents, unless others are given.
+const UNTRUSTED_MAX_SECONDS: u64 = 300;
+const UNTRUSTED_MAX_PAGES: u64 = 1000;
+const UNTRUSTED_MAX_PASSES: usize = 10;
+const UNTRUSTED_MAX_BYTES_READ: u64 = 1 << 30;
+const UNTRUSTED_MAX_BYTES_WRITTEN: u64 = 1 << 30;
+const UNTRUSTED_MAX_FILES_OPENED: u64 = 100000;
+
+/// The only kinds of files that untrusted documents can read from the
+/// filesystem. The empty extension covers things like `\input{chapter1}`,
+/// which the engines first try to open without an extension.
+const UNTRUSTED_INPUT_EXTENSIONS: &'static [&'static str] = &[
+    "", "tex", "ltx", "sty", "cls", "clo", "cfg", "def", "fd", "bib", "bst", "bbx", "cbx", "lbx", "dbx",
+    "aux", "bbl", "toc", "lof", "lot", "out", "idx", "ind", "nav", "snm", "vrb", "png", "jpg", "jpeg",
+    "pdf", "eps", "mps", "pgf", "tikz", "csv", "dat", "txt", "otf", "ttf",
+];
+
+/// The IoSetupBuilder provides a convenient builder interface for specifying
+/// the I/O setup.
+
+struct IoSetupBuilder {
+    primary_input_path: Option<PathBuf>,
+    primary_input_buffer: Option<Vec<u8>>,
+    filesystem_root: PathBuf,
+    use_stdin: bool,
+    bundles: Vec<Box<IoProvider>>,
+    use_genuine_stdout: bool,
+    hidden_input_paths: HashSet<PathBuf>,
+    search_path: SearchPathIo,
+    case_insensitive: bool,
+    confine_to_root: bool,
+    audit: Option<IoAuditLog>,
+}
+
+impl Default for IoSetupBuilder {
+    fn default() -> Self {
+        IoSetupBuilder {
+            primary_input_path: None,
+            primary_input_buffer: None,
+            filesystem_root: PathBuf::new(),
+            use_stdin: false,
+            bundles: Vec::new(),
+            use_genuine_stdout: false,
+            hidden_input_paths: HashSet::new(),
+            search_path: SearchPathIo::new(),
+            case_insensitive: false,
+            confine_to_root: false,
+            audit: None,
+        }
+    }
+}
+
+impl IoSetupBuilder {
+    fn primary_input_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        if self.use_stdin || self.primary_input_buffer.is_some() {
+            panic!("cannot use more than one primary input mechanism");
+        }
+
+        self.primary_input_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    fn primary_input_stdin(&mut self) -> &mut Self {
+        if self.primary_input_path.is_some() || self.primary_input_buffer.is_some() {
+            panic!("cannot use more than one primary input mechanism");
+        }
+
+        self.use_stdin = true;
+        self
+    }
+
+    fn primary_input_buffer(&mut self, buf: Vec<u8>) -> &mut Self {
+        if self.primary_input_path.is_some() || self.use_stdin {
+            panic!("cannot use more than one primary input mechanism");
+        }
+
+        self.primary_input_buffer = Some(buf);
+        self
+    }
+
+    fn filesystem_root<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.filesystem_root = path.as_ref().to_owned();
+        self
+    }
+
+    fn bundle<T: 'static + IoProvider>(&mut self, bundle: T) -> &mut Self {
+        self.bundles.push(Box::new(bundle));
+        self
+    }
+
+    fn boxed_bundle(&mut self, bundle: Box<IoProvider>) -> &mut Self {
+        self.bundles.push(bundle);
+        self
+    }
+
+    fn use_genuine_stdout(&mut self, setting: bool) -> &mut Self {
+        self.use_genuine_stdout = setting;
+        self
+    }
+
+    fn hide_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.hidden_input_paths.insert(path.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to the search path for input files. As with
+    /// kpathsea, relative directories are interpreted relative to the
+    /// current directory, not the directory of the input file.
+    fn search_dir(&mut self, spec: &str) -> &mut Self {
+        self.search_path.add_dir(spec, Path::new(""));
+        self
+    }
+
+    fn search_path_spec(&mut self, spec: &OsStr) -> &mut Self {
+        self.search_path.add_path_spec(spec, Path::new(""));
+        self
+    }
+
+    fn case_insensitive(&mut self, setting: bool) -> &mut Self {
+        self.case_insensitive = setting;
+        self
+    }
+
+    fn confine_to_root(&mut self, setting: bool) -> &mut Self {
+        self.confine_to_root = setting;
+        self
+    }
+
+    fn audit(&mut self, log: IoAuditLog) -> &mut Self {
+        self.audit = Some(log);
+        self
+    }
+
+    fn create(self) -> Result<IoSetup> {
+        let pio: Box<IoProvider> = if self.use_stdin {
+            Box::new(ctry!(BufferedPrimaryIo::from_stdin(); "error reading standard input"))
+        } else if let Some(pip) = self.primary_input_path {
+            Box::new(FilesystemPrimaryInputIo::new(&pip))
+        } else if let Some(buf) = self.primary_input_buffer {
+            Box::new(BufferedPrimaryIo::from_buffer(buf))
+        } else {
+            panic!("no primary input mechanism specified");
+        };
+
+        let absolute_allowed = !self.confine_to_root;
+        let mem = MemoryIo::new(true);
+
+        // Untrusted documents are confined to their own directory, can only
+        // read the usual kinds of TeX inputs from it, and are limited in how
+        // much I/O they do.
+
+        let limited_mem = if self.confine_to_root {
+            let mut lm = LimitedIo::new(mem.share());
+            lm.max_bytes_read(Some(UNTRUSTED_MAX_BYTES_READ))
+              .max_bytes_written(Some(UNTRUSTED_MAX_BYTES_WRITTEN))
+              .max_files_opened(Some(UNTRUSTED_MAX_FILES_OPENED));
+            Some(lm)
+        } else {
+            None
+        };
+
+        let mut fs = FilesystemIo::new(&self.filesystem_root, false, absolute_allowed,
+                                       self.hidden_input_paths.clone());
+        fs.mmap_threshold(Some(MMAP_THRESHOLD))
+          .confine_to_root(self.confine_to_root);
+        let mut filesystem = vec![("filesystem", guard_filesystem(fs, &limited_mem))];
+
+        if self.case_insensitive {
+            let mut fs = FilesystemIo::new(&self.filesystem_root, false, absolute_allowed,
+                                           self.hidden_input_paths);
+            fs.confine_to_root(self.confine_to_root);
+            let cf = ctry!(CaseFoldIo::for_directory(fs, &self.filesystem_root);
+                           "couldn\'t index the files in \"{}\"", self.filesystem_root.display());
+            filesystem.push(("filesystem (case-insensitive)", guard_filesystem(cf, &limited_mem)));
+        }
+
+        // The search path is the one way to reach files outside of the
+        // document's directory, so untrusted documents don't get one.
+
+        if !self.search_path.is_empty() && !self.confine_to_root {
+            filesystem.push(("search path", Box::new(self.search_path)));
+        }
+
+        Ok(IoSetup {
+            primary_input: pio,
+            mem: mem,
+            limited_mem: limited_mem,
+            filesystem: filesystem,
+            bundles: self.bundles,
+            genuine_stdout: if self.use_genuine_stdout {
+                Some(GenuineStdoutIo::new())
+            } else {
+                None
+            },
+            format_primary: None,
+            audit: self.audit,
+        })
+    }
+}
+
+/// Box up a provider of files from the filesystem. If `limits` is given, the
+/// document is untrusted, so the provider only serves the kinds of files in
+/// `UNTRUSTED_INPUT_EXTENSIONS` and counts against the limits.
+fn guard_filesystem<P: 'static + IoProvider>(provider: P, limits: &Option<LimitedIo<MemoryIo>>) -> Box<IoProvider> {
+    let limits = match *limits {
+        Some(ref l) => l,
+        None => return Box::new(provider),
+    };
+
+    let mut allowlist = AllowlistIo::new(provider);
+
+    for ext in UNTRUSTED_INPUT_EXTENSIONS {
+        allowlist.allow_extension(ext);
+    }
+
+    Box::new(limits.share(allowlist))
+}
+
+
+/// Different patterns with which files may have been accessed by the
+/// underlying engines. Once a file is marked as ReadThenWritten or
+/// WrittenThenRead, its pattern does not evolve further.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum AccessPattern {
+    /// This file is only ever read.
+    Read,
+
+    /// This file is only ever written. This suggests that it is
+    /// a final output of the processing session.
+    Written,
+
+    /// This file is read, then written. We call this a "circular" access
+    /// pattern. Multiple passes of an engine will result in outputs that
+    /// change if this file's contents change, or if the file did not exist at
+    /// the time of the first pass.
+    ReadThenWritten,
+
+    /// This file is written, then read. We call this a "temporary" access
+    /// pattern. This file is likely a temporary buffer that is not of
+    /// interest to the user.
+    WrittenThenRead,
+}
+
+
+/// A summary of the I/O that happened on a file. We record its access
+/// pattern; where it came from, if it was used as an input, and which I/O
+/// provider supplied it; the cryptographic digest of the file when it was
+/// last read; and the cryptographic digest of the file as it was last
+/// written. We also track whether the file was read before being written
+/// during the current TeX pass, since the overall access pattern doesn't
+/// tell us that once the file has been processed a few times.
+#[derive(Clone,Debug,Eq,PartialEq)]
+struct FileSummary {
+    access_pattern: AccessPattern,
+    input_origin: InputOrigin,
+    input_provider: Option<String>,
+    abspath: Option<PathBuf>,
+    read_digest: Option<DigestData>,
+    write_digest: Option<DigestData>,
+    got_written_to_disk: bool,
+    read_in_pass: bool,
+    written_in_pass: bool,
+}
+
+impl FileSummary {
+    fn new(access_pattern: AccessPattern, input_origin: InputOrigin) -> FileSummary {
+        FileSummary {
+            access_pattern: access_pattern,
+            input_origin: input_origin,
+            input_provider: None,
+            abspath: None,
+            read_digest: None,
+            write_digest: None,
+            got_written_to_disk: false,
+            read_in_pass: access_pattern == AccessPattern::Read,
+            written_in_pass: access_pattern == AccessPattern::Written,
+        }
+    }
+
+    fn note_read(&mut self) {
+        if !self.written_in_pass {
+            self.read_in_pass = true;
+        }
+    }
+}
+
+
+/// The IoEvents type implements the IoEventBackend. The session uses it to
+/// figure out when to rerun the TeX engine; to figure out which files should
+/// be written to disk; and to emit Makefile rules.
+struct IoEvents(HashMap<OsString, FileSummary>);
+
+impl IoEvents {
+    fn new() -> IoEvents { IoEvents(HashMap::new()) }
+
+    /// Reset the per-pass access information at the start of a TeX pass.
+    fn start_pass(&mut self) {
+        for summ in self.0.values_mut() {
+            summ.read_in_pass = false;
+            summ.written_in_pass = false;
+        }
+    }
+}
+
+impl IoEventBackend for IoEvents {
+    fn output_opened(&mut self, name: &OsStr) {
+        if let Some(summ) = self.0.get_mut(name) {
+            summ.access_pattern = match summ.access_pattern {
+                AccessPattern::Read => AccessPattern::ReadThenWritten,
+                c => c, // identity mapping makes sense for remaining options
+            };
+            summ.written_in_pass = true;
+            return;
+        }
+
+        self.0.insert(name.to_os_string(), FileSummary::new(AccessPattern::Written, InputOrigin::NotInput));
+    }
+
+    fn stdout_opened(&mut self) {
+        // Life is easier if we track stdout in the same way that we do other
+        // output files.
+
+        if let Some(summ) = self.0.get_mut(OsStr::new("")) {
+            summ.access_pattern = match summ.access_pattern {
+                AccessPattern::Read => AccessPattern::ReadThenWritten,
+                c => c, // identity mapping makes sense for remaining options
+            };
+            return;
+        }
+
+        self.0.insert(OsString::from(""), FileSummary::new(AccessPattern::Written, InputOrigin::NotInput));
+    }
+
+    fn output_closed(&mut self, name: OsString, digest: DigestData) {
+        let mut summ = self.0.get_mut(&name).expect("closing file that wasn't opened?");
+        summ.write_digest = Some(digest);
+    }
+
+    fn input_not_available(&mut self, name: &OsStr) {
+        // For the purposes of file access pattern tracking, an attempt to
+        // open a nonexistent file counts as a read of a zero-size file. I
+        // don't see how such a file could have previously been written, but
+        // let's use the full update logic just in case.
+
+        if let Some(summ) = self.0.get_mut(name) {
+            summ.access_pattern = match summ.access_pattern {
+                AccessPattern::Written => AccessPattern::WrittenThenRead,
+                c => c, // identity mapping makes sense for remaining options
+            };
+            summ.note_read();
+
+            if summ.read_digest.is_none() {
+                summ.read_digest = Some(DigestData::of_nothing());
+            }
+            return;
+        }
+
+        // Unlike other cases, here we need to fill in the read_digest. `None`
+        // is not an appropriate value since, if the file is written and then
+        // read again later, the `None` will be overwritten; but what matters
+        // is the contents of the file the very first time it was read.
+        let mut fs = FileSummary::new(AccessPattern::Read, InputOrigin::NotInput);
+        fs.read_digest = Some(DigestData::of_nothing());
+        self.0.insert(name.to_os_string(), fs);
+    }
+
+    fn input_opened(&mut self, name: &OsStr, origin: InputOrigin) {
+        if let Some(summ) = self.0.get_mut(name) {
+            summ.access_pattern = match summ.access_pattern {
+                AccessPattern::Written => AccessPattern::WrittenThenRead,
+                c => c, // identity mapping makes sense for remaining options
+            };
+            summ.note_read();
+            return;
+        }
+
+        self.0.insert(name.to_os_string(), FileSummary::new(AccessPattern::Read, origin));
+    }
+
+    fn input_provider(&mut self, name: &OsStr, provider: &str) {
+        // Only the first provider matters: that's the version of the file
+        // whose contents were used to decide about reruns.
+
+        if let Some(summ) = self.0.get_mut(name) {
+            if summ.input_provider.is_none() {
+                summ.input_provider = Some(provider.to_owned());
+            }
+        }
+    }
+
+    fn input_abspath(&mut self, name: &OsStr, path: &Path) {
+        if let Some(summ) = self.0.get_mut(name) {
+            if summ.abspath.is_none() {
+                summ.abspath = Some(path.to_owned());
+            }
+        }
+    }
+
+    //fn primary_input_opened(&mut self, _origin: InputOrigin) {}
+
+    fn input_closed(&mut self, name: OsString, digest: Option<DigestData>) {
+        let mut summ = self.0.get_mut(&name).expect("closing file that wasn't opened?");
+
+        // It's what was in the file the *first* time that it was read that
+        // matters, so don't replace the read digest if it's already got one.
+
+        if summ.read_digest.is_none() {
+            summ.read_digest = digest;
+        }
+    }
+}
+
+
+/// Quote a path for use in a Make-style dependency file. Both Make and Ninja
+/// understand backslash-escaped spaces and doubled dollar signs.
+fn escape_make_path(path: &Path) -> String {
+    let mut s = String::new();
+
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => { s.push('\\'); s.push(c); },
+            '$' => s.push_str("$$"),
+            _ => s.push(c),
+        }
+    }
+
+    s
+}
+
+
+/// The kind of output that a processing session generates.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum OutputFormat {
+    /// Stop after the TeX passes and only save the `.aux` file.
+    Aux,
+
+    /// Stop after the TeX passes and save the `.xdv` file as the final
+    /// product, without running xdvipdfmx. This is for downstream tools that
+    /// want to render the extended DVI format themselves.
+    Xdv,
+
+    /// Run xdvipdfmx after the TeX passes to produce a PDF.
+    Pdf,
+
+    /// Convert the XDV file to SVG after the TeX passes, with one file per
+    /// page.
+    Svg,
+
+    /// Render the pages of the XDV file as PNG images after the TeX passes.
+    Png,
+
+    /// Extract the text of the XDV file after the TeX passes, into a single
+    /// plain-text file.
+    Txt,
+
+    /// Run TeX in "initex" mode to generate a format file.
+    Format,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    /// Parse the names used by the `--outfmt` option, such as "pdf".
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s {
+            "aux" => Ok(OutputFormat::Aux),
+            "xdv" => Ok(OutputFormat::Xdv),
+            "pdf" => Ok(OutputFormat::Pdf),
+            "svg" => Ok(OutputFormat::Svg),
+            "png" => Ok(OutputFormat::Png),
+            "txt" => Ok(OutputFormat::Txt),
+            "format" => Ok(OutputFormat::Format),
+            _ => Err(ErrorKind::Msg(format!("unknown output format \"{}\"", s)).into()),
+        }
+    }
+}
+
+/// Which engines a processing session runs.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum PassSetting {
+    /// Run the TeX engine just once.
+    Tex,
+
+    /// Run TeX, and then BibTeX, makeindex, or biber if the document needs
+    /// them, rerunning TeX until its inputs stop changing.
+    Default,
+
+    /// Like `Default`, but start by running BibTeX.
+    BibtexFirst,
+}
+
+
+/// A machine-readable account of what a processing session did, for
+/// continuous integration systems, editors, and other programs that would
+/// otherwise have to pick apart the messages that we print. Get one from
+/// `ProcessingSession::report` after running the session.
+#[derive(Clone,Debug,Serialize)]
+pub struct BuildReport {
+    /// Whether the processing succeeded.
+    pub success: bool,
+
+    /// If it didn't, the error that stopped it, followed by its causes.
+    pub error: Vec<String>,
+
+    /// If it didn't, the code of the error that stopped it (see
+    /// `Error::code()`).
+    pub error_code: Option<String>,
+
+    /// How long the whole run took, in seconds.
+    pub elapsed: f64,
+
+    /// The engine runs, in order.
+    pub passes: Vec<PassReport>,
+
+    /// The paths of the output files that were written to disk, not
+    /// counting logs.
+    pub outputs: Vec<String>,
+
+    /// The errors and warnings from the last TeX pass.
+    pub diagnostics: Vec<DiagnosticReport>,
+
+    /// A summary of the most common problems in the last TeX pass.
+    pub problems: ProblemSummary,
+
+    /// Every file that the engines opened.
+    pub files: Vec<FileReport>,
+}
+
+/// One run of one of the engines in a `BuildReport`.
+#[derive(Clone,Debug,Serialize)]
+pub struct PassReport {
+    /// The name of the engine, such as "tex" or "bibtex".
+    pub engine: String,
+
+    /// How long it ran, in seconds.
+    pub elapsed: f64,
+}
+
+/// An error or warning in a `BuildReport`. The `kind` is one of "error",
+/// "warning", "badbox", "missing-character", and "font-substitution". For
+/// errors, the `context` says where TeX was and what the input looked like
+/// there.
+#[derive(Clone,Debug,Serialize)]
+pub struct DiagnosticReport {
+    pub kind: String,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub context: Option<ErrorContext>,
+}
+
+/// A file in a `BuildReport`. The `access` is "read", "written",
+/// "read-then-written", or "written-then-read"; files that were looked for
+/// but not found count as read.
+#[derive(Clone,Debug,Serialize)]
+pub struct FileReport {
+    pub name: String,
+    pub access: String,
+
+    /// Where the file came from, such as the filesystem or the bundle, if
+    /// it was read and we know.
+    pub provider: Option<String>,
+
+    /// The absolute path of the file, if it was read from the filesystem.
+    pub path: Option<String>,
+}
+
+impl BuildReport {
+    pub fn write_json<W: Write>(&self, dest: &mut W) -> Result<()> {
+        serde_json::to_writer_pretty(dest, self)?;
+        Ok(())
+    }
+}
+
+
+/// Things to do before and after a processing session runs, such as
+/// generating a data table that the document reads, or copying the PDF
+/// somewhere. Both methods do nothing by default.
+pub trait BuildHooks {
+    /// Called before anything else happens. If this fails, the session
+    /// does too.
+    fn before_build(&mut self, _status: &mut StatusBackend) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called at the end of the run, whether or not it succeeded. If this
+    /// fails after a successful run, the session fails.
+    fn after_build(&mut self, _report: &BuildReport, _status: &mut StatusBackend) -> Result<()> {
+        Ok(())
+    }
+}
+
+
+/// Hooks that run shell commands. The commands that run after processing
+/// get the build report in JSON format, in a file whose path is in the
+/// environment variable `TECTONIC_BUILD_REPORT`, and `TECTONIC_BUILD_SUCCESS`
+/// is set to "1" or "0".
+#[derive(Clone,Debug,Default)]
+pub struct CommandHooks {
+    before: Vec<String>,
+    after: Vec<String>,
+    dir: Option<PathBuf>,
+}
+
+impl CommandHooks {
+    pub fn new() -> CommandHooks {
+        CommandHooks::default()
+    }
+
+    /// Run `command` before processing.
+    pub fn before(&mut self, command: &str) -> &mut Self {
+        self.before.push(command.to_owned());
+        self
+    }
+
+    /// Run `command` after processing.
+    pub fn after(&mut self, command: &str) -> &mut Self {
+        self.after.push(command.to_owned());
+        self
+    }
+
+    /// Run the commands in `dir` rather than the current directory.
+    pub fn dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    fn run_command(&self, command: &str, env: &[(&str, &OsStr)], status: &mut StatusBackend) -> Result<()> {
+        let mut cmd = process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+
+        if let Some(ref d) = self.dir {
+            cmd.current_dir(d);
+        }
+
+        for &(k, v) in env {
+            cmd.env(k, v);
+        }
+
+        status.note_highlighted("Running ", command, " ...");
+        let exit = ctry!(cmd.status(); "couldn't run the hook command \"{}\"", command);
+
+        if !exit.success() {
+            return Err(ErrorKind::Msg(format!("the hook command \"{}\" failed ({})", command, exit)).into());
+        }
+
+        Ok(())
+    }
+}
+
+impl BuildHooks for CommandHooks {
+    fn before_build(&mut self, status: &mut StatusBackend) -> Result<()> {
+        for command in &self.before {
+            self.run_command(command, &[], status)?;
+        }
+
+        Ok(())
+    }
+
+    fn after_build(&mut self, report: &BuildReport, status: &mut StatusBackend) -> Result<()> {
+        if self.after.is_empty() {
+            return Ok(());
+        }
+
+        let workdir = ctry!(TempDir::new("tectonic_hooks"); "couldn't create a temporary directory for hooks");
+        let report_path = workdir.path().join("report.json");
+        ctry!(File::create(&report_path).map_err(|e| e.into()).and_then(|mut f| report.write_json(&mut f));
+              "couldn't write \"{}\"", report_path.display());
+
+        let success = OsStr::new(if report.success { "1" } else { "0" });
+        let env = [("TECTONIC_BUILD_REPORT", report_path.as_os_str()), ("TECTONIC_BUILD_SUCCESS", success)];
+
+        for command in &self.after {
+            self.run_command(command, &env, status)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// What we remember about a successful run, to decide whether the next one
+/// can be skipped: the files that it read from the filesystem, with the
+/// digests of their contents, and the files that it wrote.
+#[derive(Deserialize,Serialize)]
+struct BuildState {
+    inputs: Vec<(String, String)>,
+    outputs: Vec<String>,
+}
+
+/// Check whether `path` is relative and doesn't climb up with `..`, so
+/// that joining it to a directory gives something inside that directory.
+pub fn is_plain_relative_path(path: &Path) -> bool {
+    path.components().all(|c| match c {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    })
+}
+
+/// Compute the digest of the contents of the file at `path`, or `None` if
+/// it can't be read.
+fn file_digest(path: &Path) -> Option<DigestData> {
+    let mut data = Vec::new();
+
+    match File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+        Ok(_) => {
+            let mut dc = digest::create();
+            dc.input(&data);
+            Some(DigestData::from(dc))
+        },
+        Err(_) => None,
+    }
+}
+
+
+fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+/// The ProcessingSession struct runs the whole show when we're actually
+/// processing a file. Sessions are set up with a `SessionBuilder`.
+pub struct ProcessingSession {
+    io: IoSetup,
+    events: IoEvents,
+
+    /// If our primary input is an actual file on disk, this is its path.
+    primary_input_path: Option<PathBuf>,
+
+    /// This is the name of the input that we tell TeX. It is the basename of
+    /// the UTF8-ified version of `primary_input_path`; or something anodyne
+    /// if the latter is None. (Name, "texput.tex").
+    primary_input_tex_path: String,
+
+    /// This is the virtual "CWD" that our filesystem accesses use. It is the
+    /// dirname of `primary_input_path`, or an empty path (i.e., corresponding
+    /// to the CWD if `primary_input_path` is None.
+    fs_root: PathBuf,
+
+    /// Where we write the final outputs, and the intermediate files and
+    /// logs that we keep. Both default to `fs_root`.
+    output_dir: PathBuf,
+    aux_dir: PathBuf,
+
+    /// This is the name of the format file to use. TeX has to open it by name
+    /// internally, so it has to be String compatible.
+    format_path: String,
+
+    /// These are the paths of the various output files as TeX knows them --
+    /// just `primary_input_tex_path` with the extension changed. We store
+    /// them as OsStrings since that's what the main crate currently uses for
+    /// TeX paths, even though I've since realized that it should really just
+    /// use String.
+    tex_aux_path: OsString,
+    tex_bbl_path: OsString,
+    tex_bcf_path: OsString,
+    tex_blg_path: OsString,
+    tex_idx_path: OsString,
+    tex_xdv_path: OsString,
+    tex_pdf_path: OsString,
+
+    /// The external program used to process biblatex control files, the
+    /// control file that it was last run on, and whether we've found that
+    /// it can't be run at all.
+    biber_command: OsString,
+    biber_input: Option<Vec<u8>>,
+    biber_unavailable: bool,
+
+    /// If we're writing out Makefile rules, this is where they go. The TeX
+    /// engine doesn't know about this path at all.
+    makefile_output_path: Option<PathBuf>,
+
+    /// Whether to write a Make-style dependency file next to the outputs,
+    /// and the files that we've written to disk so far, which are its
+    /// targets.
+    dep_file: bool,
+    written_paths: Vec<PathBuf>,
+
+    /// If we're recording an audit trail of I/O accesses, this is the log
+    /// and the path where it should be written as JSON.
+    audit_output: Option<(PathBuf, IoAuditLog)>,
+
+    pass: PassSetting,
+    output_format: OutputFormat,
+    tex_rerun_specification: Option<usize>,
+    keep_intermediates: bool,
+    keep_logs: bool,
+    dry_run: bool,
+
+    /// If set, the files that would be written to disk are kept in
+    /// `output_files` instead, for the caller to take.
+    outputs_in_memory: bool,
+    output_files: Vec<(String, Vec<u8>)>,
+    noted_tex_warnings: bool,
+    synctex_enabled: bool,
+    precompile_preamble: bool,
+
+    /// If set, the names to put in an `\includeonly` command at the start
+    /// of the primary input. The `.aux` files are then always written to
+    /// disk, so that the next partial build can use them.
+    include_only: Option<Vec<String>>,
+
+    /// Whether to put the `graphicx` package in draft mode, by passing it
+    /// an option at the start of the primary input.
+    draft: bool,
+    interaction: InteractionMode,
+
+    /// The contents of the memory layer at the start of the last TeX pass,
+    /// and fingerprints of the files that TeX read in each pass that needed
+    /// to be rerun. These are used to figure out whether we need to rerun
+    /// TeX.
+    pass_snapshot: HashMap<OsString, Vec<u8>>,
+    rerun_states: Vec<DigestData>,
+
+    /// The xdvipdfmx engine, as configured by the builder.
+    xdvipdfmx: XdvipdfmxEngine,
+
+    /// Whether to attach the document's sources to the PDF output.
+    attach_sources: bool,
+
+    /// The SVG converter, PNG renderer, and text extractor, likewise.
+    xdvisvg: XdvisvgEngine,
+    xdvipng: XdvipngEngine,
+    xdvitxt: XdvitxtEngine,
+
+    /// Triggered to stop processing early, e.g. when the user hits
+    /// Control-C.
+    cancel: CancellationToken,
+
+    /// Limits that keep a runaway document from tying us up forever, and
+    /// the number of TeX passes that we've run so far.
+    max_seconds: Option<u64>,
+    max_pages: Option<u64>,
+    max_passes: Option<usize>,
+    tex_passes: usize,
+
+    /// The most TeX passes that the current run can take, if we know, for
+    /// reporting progress.
+    expected_tex_passes: Option<usize>,
+
+    /// Whether the document might be malicious; see
+    /// `SessionBuilder::untrusted`.
+    untrusted: bool,
+    font_cache_dir: Option<PathBuf>,
+
+    /// If we're skipping runs whose inputs haven't changed, the file where
+    /// we remember the inputs and outputs of the last successful run.
+    build_state_path: Option<PathBuf>,
+
+    /// Things to do before and after processing.
+    hooks: Vec<Box<BuildHooks>>,
+
+    /// The parts of the `.aux` file that BibTeX read the last time we ran
+    /// it.
+    bibtex_input: Option<Vec<u8>>,
+
+    /// The `.idx` file that makeindex read the last time we ran it.
+    makeindex_input: Option<Vec<u8>>,
+
+    /// For the build report: the engines that we've run and how long they
+    /// took, the diagnostics from the last TeX pass, and how long the whole
+    /// run took.
+    pass_log: Vec<PassReport>,
+    tex_diagnostics: Vec<Diagnostic>,
+    elapsed: f64,
+
+    /// Other bundles to suggest if a package can't be found.
+    hint_bundles: Vec<(String, Box<IoProvider>)>,
+}
+
+
+const DEFAULT_MAX_TEX_PASSES: usize = 6;
+
+/// How many of each kind of problem to list at the end of a run.
+const MAX_PROBLEMS_LISTED: usize = 10;
+
+/// The name under which the TeX passes are reported as progress.
+const TEX_PASS_TASK: &'static str = "TeX pass";
+
+
+/// Get the stem of a format file name, which is what the bundles use to
+/// name saved formats.
+fn format_stem(name: &str) -> Result<&str> {
+    // PathBuf.file_stem() doesn't do what we want since it only strips
+    // one extension.
+    name.splitn(2, ".").next().ok_or_else(
+        || ErrorKind::Msg(format!("incomprehensible format file name \"{}\"", name)).into()
+    )
+}
+
+
+/// Find where the preamble of a LaTeX document ends. This is the start of
+/// the first line that contains `\begin{document}` outside of a comment.
+fn find_preamble_end(text: &[u8]) -> Option<usize> {
+    const BEGIN_DOCUMENT: &'static [u8] = b"\\begin{document}";
+    let mut line_start = 0;
+
+    for line in text.split(|c| *c == b'\n') {
+        let mut code = line;
+        let mut i = 0;
+
+        while i < line.len() {
+            match line[i] {
+                b'\\' => { i += 1; },
+                b'%' => {
+                    code = &line[..i];
+                    break;
+                },
+                _ => {},
+            }
+
+            i += 1;
+        }
+
+        if code.windows(BEGIN_DOCUMENT.len()).any(|w| w == BEGIN_DOCUMENT) {
+            return Some(line_start);
+        }
+
+        line_start += line.len() + 1;
+    }
+
+    None
+}
+
+
+/// Find TeX's report of the last error in its terminal output: the text
+/// from the last line that starts with "! " onwards.
+fn last_error_report(output: &[u8]) -> Option<&[u8]> {
+    if output.starts_with(b"! ") {
+        return Some(output);
+    }
+
+    output.windows(3).rposition(|w| w == b"\n! ").map(|i| &output[i + 1..])
+}
+
+
+/// Collect the lines of an `.aux` file that BibTeX pays attention to,
+/// following `\@input` into the `.aux` files of `\include`d documents as
+/// BibTeX does.
+fn collect_bibtex_lines(files: &HashMap<OsString, Vec<u8>>, name: &OsStr, seen: &mut Vec<OsString>,
+                        dest: &mut Vec<u8>) {
+    if seen.iter().any(|n| n == name) {
+        return;
+    }
+
+    seen.push(name.to_os_string());
+
+    let data = match files.get(name) {
+        Some(d) => d,
+        None => return,
+    };
+
+    for line in data.split(|&b| b == b'\n') {
+        if line.starts_with(b"\\@input{") {
+            if let Some(end) = line.iter().position(|&b| b == b'}') {
+                collect_bibtex_lines(files, OsStr::from_bytes(&line[8..end]), seen, dest);
+            }
+        } else if line.starts_with(b"\\citation{") || line.starts_with(b"\\bibdata{")
+            || line.starts_with(b"\\bibstyle{") {
+            dest.extend_from_slice(line);
+            dest.push(b'\n');
+        }
+    }
+}
+
+
+/// Count the lines that were added and removed between two versions of a
+/// file, ignoring their order.
+fn count_changed_lines(old: &[u8], new: &[u8]) -> (usize, usize) {
+    let mut counts: HashMap<&[u8], isize> = HashMap::new();
+
+    for line in old.split(|&b| b == b'\n') {
+        *counts.entry(line).or_insert(0) -= 1;
+    }
+
+    for line in new.split(|&b| b == b'\n') {
+        *counts.entry(line).or_insert(0) += 1;
+    }
+
+    let added = counts.values().filter(|&&n| n > 0).map(|&n| n as usize).sum();
+    let removed = counts.values().filter(|&&n| n < 0).map(|&n| (-n) as usize).sum();
+    (added, removed)
+}
+
+
+/// A builder for processing sessions. This is the way to drive Tectonic
+/// programmatically: set up the session, then call `run()` on the
+/// `ProcessingSession` that comes out of `build()`. For instance:
+///
+/// ```ignore
+/// let mut status = NoopStatusBackend::new();
+/// let config = PersistentConfig::open(false)?;
+/// let mut sb = SessionBuilder::new();
+///
+/// for bundle in config.default_io_providers(false, &mut status)? {
+///     sb.boxed_bundle(bundle);
+/// }
+///
+/// let mut sess = sb.primary_input_path("paper.tex")
+///     .output_format(OutputFormat::Pdf)
+///     .build()?;
+/// sess.run(&mut status)?;
+/// ```
+///
+/// The outputs are written next to the primary input, or in the current
+/// directory if the input doesn't come from a file.
+pub struct SessionBuilder {
+    io: IoSetupBuilder,
+    tex_input_name: Option<String>,
+    format_name: String,
+    output_format: OutputFormat,
+    pass: PassSetting,
+    reruns: Option<usize>,
+    keep_intermediates: bool,
+    keep_logs: bool,
+    dry_run: bool,
+    outputs_in_memory: bool,
+    synctex: bool,
+    precompiled_preamble: bool,
+    include_only: Option<Vec<String>>,
+    draft: bool,
+    interaction: InteractionMode,
+    makefile_rules: Option<PathBuf>,
+    dep_file: bool,
+    audit_io: Option<PathBuf>,
+    biber_command: OsString,
+    max_seconds: Option<u64>,
+    max_pages: Option<u64>,
+    max_passes: Option<usize>,
+    untrusted: bool,
+    font_cache_dir: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    aux_dir: Option<PathBuf>,
+    skip_unchanged: Option<(PathBuf, String)>,
+    hooks: Vec<Box<BuildHooks>>,
+    xdvipdfmx: XdvipdfmxEngine,
+    attach_sources: bool,
+    xdvisvg: XdvisvgEngine,
+    xdvipng: XdvipngEngine,
+    hint_bundles: Vec<(String, Box<IoProvider>)>,
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        SessionBuilder {
+            io: IoSetupBuilder::default(),
+            tex_input_name: None,
+            format_name: "latex".to_owned(),
+            output_format: OutputFormat::Pdf,
+            pass: PassSetting::Default,
+            reruns: None,
+            keep_intermediates: false,
+            keep_logs: false,
+            dry_run: false,
+            outputs_in_memory: false,
+            synctex: false,
+            precompiled_preamble: false,
+            include_only: None,
+            draft: false,
+            interaction: InteractionMode::ErrorStop,
+            makefile_rules: None,
+            dep_file: false,
+            audit_io: None,
+            biber_command: OsString::from("biber"),
+            max_seconds: None,
+            max_pages: None,
+            max_passes: None,
+            untrusted: false,
+            font_cache_dir: None,
+            output_dir: None,
+            aux_dir: None,
+            skip_unchanged: None,
+            hooks: Vec::new(),
+            xdvipdfmx: XdvipdfmxEngine::new(),
+            attach_sources: false,
+            xdvisvg: XdvisvgEngine::new(),
+            xdvipng: XdvipngEngine::new(),
+            hint_bundles: Vec::new(),
+        }
+    }
+}
+
+impl SessionBuilder {
+    pub fn new() -> SessionBuilder {
+        SessionBuilder::default()
+    }
+
+    /// Process the file at `path`. Other files that the document reads are
+    /// looked up relative to its directory, and the outputs go there too.
+    /// Only one kind of primary input can be chosen.
+    pub fn primary_input_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.io.primary_input_path(path);
+        self
+    }
+
+    /// Process whatever is on standard input. It's all read into memory
+    /// when the session is built, since TeX may go through it several times.
+    /// Other files are looked up relative to the current directory, and the
+    /// outputs are named after `tex_input_name`.
+    pub fn primary_input_stdin(&mut self) -> &mut Self {
+        self.io.primary_input_stdin();
+        self
+    }
+
+    /// Process the document in `buf`.
+    pub fn primary_input_buffer(&mut self, buf: Vec<u8>) -> &mut Self {
+        self.io.primary_input_buffer(buf);
+        self
+    }
+
+    /// Set the name that TeX knows the primary input by when it doesn't
+    /// come from a file, which determines the names of the outputs. The
+    /// default is "texput.tex".
+    pub fn tex_input_name(&mut self, name: &str) -> &mut Self {
+        self.tex_input_name = Some(name.to_owned());
+        self
+    }
+
+    /// Add a bundle of support files. Bundles are searched in the order
+    /// that they're added, after the document's own files. A session needs
+    /// at least one bundle to generate formats.
+    pub fn bundle<T: 'static + IoProvider>(&mut self, bundle: T) -> &mut Self {
+        self.io.bundle(bundle);
+        self
+    }
+
+    /// Like `bundle()`, for bundles that are already boxed up, such as the
+    /// ones from `PersistentConfig::default_io_providers()`.
+    pub fn boxed_bundle(&mut self, bundle: Box<IoProvider>) -> &mut Self {
+        self.io.boxed_bundle(bundle);
+        self
+    }
+
+    /// Add a bundle that the document doesn't read from, but that's worth
+    /// mentioning, under the name `name`, if it has a package that the
+    /// document's own bundles don't. It's only looked at if a package can't
+    /// be found.
+    pub fn hint_bundle(&mut self, name: &str, bundle: Box<IoProvider>) -> &mut Self {
+        self.hint_bundles.push((name.to_owned(), bundle));
+        self
+    }
+
+    /// Set the name of the format file to start TeX from. The default is
+    /// "latex".
+    pub fn format_name(&mut self, name: &str) -> &mut Self {
+        self.format_name = name.to_owned();
+        self
+    }
+
+    /// Set the kind of output to generate. The default is PDF.
+    pub fn output_format(&mut self, format: OutputFormat) -> &mut Self {
+        self.output_format = format;
+        self
+    }
+
+    /// Set which engines to run.
+    pub fn pass(&mut self, pass: PassSetting) -> &mut Self {
+        self.pass = pass;
+        self
+    }
+
+    /// Rerun TeX exactly this many times after the first pass, rather than
+    /// as many times as seem needed.
+    pub fn reruns(&mut self, reruns: usize) -> &mut Self {
+        self.reruns = Some(reruns);
+        self
+    }
+
+    /// Set whether to write the intermediate files, like the `.aux` file,
+    /// to disk.
+    pub fn keep_intermediates(&mut self, keep: bool) -> &mut Self {
+        self.keep_intermediates = keep;
+        self
+    }
+
+    /// Set whether to write the log files to disk.
+    pub fn keep_logs(&mut self, keep: bool) -> &mut Self {
+        self.keep_logs = keep;
+        self
+    }
+
+    /// If `enabled`, process the document as usual, but don't write anything
+    /// to disk: no outputs, intermediate files, logs, or Makefile rules. Use
+    /// `ProcessingSession::inputs_used` afterwards to find out which files
+    /// the document needs, e.g. to vendor them or to fill the bundle cache
+    /// ahead of time.
+    pub fn dry_run(&mut self, enabled: bool) -> &mut Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// If `enabled`, keep the files that would be written to disk in memory
+    /// instead, so that they can be had from
+    /// `ProcessingSession::take_output_files` without going through the
+    /// filesystem. The same files are kept as would be written, so logs are
+    /// only included with `keep_logs`. Makefile rules, dependency files,
+    /// and audit logs are still written to disk if asked for.
+    pub fn outputs_in_memory(&mut self, enabled: bool) -> &mut Self {
+        self.outputs_in_memory = enabled;
+        self
+    }
+
+    /// Set whether to generate SyncTeX data.
+    pub fn synctex(&mut self, enabled: bool) -> &mut Self {
+        self.synctex = enabled;
+        self
+    }
+
+    /// Set whether to save the engine state after the document preamble as
+    /// a format file, and reuse it while the preamble is unchanged.
+    pub fn precompiled_preamble(&mut self, enabled: bool) -> &mut Self {
+        self.precompiled_preamble = enabled;
+        self
+    }
+
+    /// Only typeset the files named in `names` out of those that the
+    /// document pulls in with `\include`, as if it started with
+    /// `\includeonly`. The `.aux` files are saved to disk, even without
+    /// `keep_intermediates`, so that the page numbers and cross-references
+    /// of the parts that are left out come from the last time that they
+    /// were typeset.
+    pub fn include_only<S: AsRef<str>>(&mut self, names: &[S]) -> &mut Self {
+        self.include_only = Some(names.iter().map(|s| s.as_ref().to_owned()).collect());
+        self
+    }
+
+    /// Set whether to typeset the document in draft mode, where the
+    /// `graphicx` package draws the outlines of images rather than loading
+    /// them. This saves a lot of time for documents with many large
+    /// figures.
+    pub fn draft(&mut self, enabled: bool) -> &mut Self {
+        self.draft = enabled;
+        self
+    }
+
+    /// Set the TeX interaction mode. The default stops at the first error.
+    pub fn interaction(&mut self, mode: InteractionMode) -> &mut Self {
+        self.interaction = mode;
+        self
+    }
+
+    /// Set whether the engines' chatter goes to the real standard output.
+    pub fn print_stdout(&mut self, enabled: bool) -> &mut Self {
+        self.io.use_genuine_stdout(enabled);
+        self
+    }
+
+    /// Set whether input files may be found under names that differ only in
+    /// case.
+    pub fn case_insensitive(&mut self, enabled: bool) -> &mut Self {
+        self.io.case_insensitive(enabled);
+        self
+    }
+
+    /// Pretend that there is no file at `path`.
+    pub fn hide_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.io.hide_path(path);
+        self
+    }
+
+    /// Add a directory to search for input files, after the document's own
+    /// directory. Relative directories are relative to the current
+    /// directory.
+    pub fn search_dir(&mut self, dir: &str) -> &mut Self {
+        self.io.search_dir(dir);
+        self
+    }
+
+    /// Add the directories of a kpathsea-style search path, like the value
+    /// of `TEXINPUTS`.
+    pub fn search_path_spec(&mut self, spec: &OsStr) -> &mut Self {
+        self.io.search_path_spec(spec);
+        self
+    }
+
+    /// Write Makefile rules describing the dependencies of the run to
+    /// `path`.
+    pub fn makefile_rules<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.makefile_rules = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Write a dependency file, in the format used by Make and Ninja, next
+    /// to the outputs. Its name is that of the document with the extension
+    /// ".d". It lists the outputs as depending on every file on the
+    /// filesystem that the run read, with absolute paths where they're
+    /// known, so that build systems can tell exactly when to rerun.
+    pub fn dep_file(&mut self, enabled: bool) -> &mut Self {
+        self.dep_file = enabled;
+        self
+    }
+
+    /// Write a JSON record of every file that the engines opened to `path`.
+    pub fn audit_io<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.audit_io = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Set the external program that processes the bibliographies of
+    /// documents using biblatex. The default is "biber".
+    pub fn biber_command<S: AsRef<OsStr>>(&mut self, command: S) -> &mut Self {
+        self.biber_command = command.as_ref().to_owned();
+        self
+    }
+
+    /// Give up if processing takes longer than this many seconds.
+    pub fn max_seconds(&mut self, secs: u64) -> &mut Self {
+        self.max_seconds = Some(secs);
+        self
+    }
+
+    /// Give up if the TeX engine produces more than this many pages.
+    pub fn max_pages(&mut self, pages: u64) -> &mut Self {
+        self.max_pages = Some(pages);
+        self
+    }
+
+    /// Give up if the document needs more than this many TeX passes.
+    pub fn max_passes(&mut self, passes: usize) -> &mut Self {
+        self.max_passes = Some(passes);
+        self
+    }
+
+    /// Treat the document as possibly malicious, for services that process
+    /// documents from anyone. Files on the filesystem can only be read from
+    /// the directory of the primary input and below, and only if they have
+    /// the extensions of the usual kinds of TeX inputs. Search directories,
+    /// including those from `TEXINPUTS` and the configuration, are ignored.
+    /// External programs such as biber aren't run, outputs can only be
+    /// written inside the output directories, and there are limits on the
+    /// amount of I/O, and on the processing time, pages, and TeX passes if
+    /// none were set. The engines never run shell commands in any case.
+    /// Bundles are trusted.
+    pub fn untrusted(&mut self, enabled: bool) -> &mut Self {
+        self.untrusted = enabled;
+        self
+    }
+
+    /// Write the final outputs to `dir` rather than next to the primary
+    /// input. Relative paths are relative to the current directory. It's
+    /// created if needed.
+    pub fn output_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.output_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Write the intermediate files and logs that are kept, such as `.aux`
+    /// and `.log` files, to `dir` rather than with the final outputs. If
+    /// they're not kept, they only ever live in memory. Files from earlier
+    /// runs are looked for in `dir` too.
+    pub fn aux_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.aux_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Don't do anything if none of the files that the last successful run
+    /// read from the filesystem have changed, and its outputs are still
+    /// there. Files are compared by the digests of their contents, not by
+    /// modification times. What the last run did is remembered in a file in
+    /// `state_dir`, named for the primary input and `settings`, which should
+    /// capture everything else that affects the outputs, such as the
+    /// command-line arguments. Files from bundles aren't checked. This only
+    /// works when the primary input is a file.
+    pub fn skip_unchanged<P: AsRef<Path>>(&mut self, state_dir: P, settings: &str) -> &mut Self {
+        self.skip_unchanged = Some((state_dir.as_ref().to_owned(), settings.to_owned()));
+        self
+    }
+
+    /// Add hooks to run before and after processing. Hooks run in the order
+    /// that they were added.
+    pub fn hooks(&mut self, hooks: Box<BuildHooks>) -> &mut Self {
+        self.hooks.push(hooks);
+        self
+    }
+
+    /// Cache information about system fonts in this directory.
+    pub fn font_cache_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.font_cache_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Use this engine, with its settings, to generate PDF output.
+    pub fn xdvipdfmx_engine(&mut self, engine: XdvipdfmxEngine) -> &mut Self {
+        self.xdvipdfmx = engine;
+        self
+    }
+
+    /// Set whether to attach the document's sources to PDF output: the
+    /// primary input, if it's a file, and every other file that it read
+    /// from the filesystem.
+    pub fn attach_sources(&mut self, enabled: bool) -> &mut Self {
+        self.attach_sources = enabled;
+        self
+    }
+
+    /// Use this engine, with its settings, to generate SVG output.
+    pub fn xdvisvg_engine(&mut self, engine: XdvisvgEngine) -> &mut Self {
+        self.xdvisvg = engine;
+        self
+    }
+
+    /// Use this engine, with its settings, to generate PNG output.
+    pub fn xdvipng_engine(&mut self, engine: XdvipngEngine) -> &mut Self {
+        self.xdvipng = engine;
+        self
+    }
+
+    /// Create the session. This takes the settings out of the builder,
+    /// leaving it as if it were new.
+    pub fn build(&mut self) -> Result<ProcessingSession> {
+        let mut b = mem::replace(self, SessionBuilder::default());
+
+        // Input and path setup
+
+        let (primary_input_path, fs_root, tex_input_stem) = match b.io.primary_input_path.clone() {
+            Some(tex_path) => {
+                let stem = match tex_path.file_name() {
+                    Some(fname) => fname.to_owned(),
+                    None => { return Err(ErrorKind::Msg(format!("can't figure out a basename for input path \"{}\"",
+                                                                tex_path.to_string_lossy())).into()); },
+                };
+
+                let fs_root = match tex_path.parent() {
+                    Some(par) => par.to_owned(),
+                    None => {
+                        return Err(ErrorKind::Msg(format!("can't figure out a parent directory for input path \"{}\"",
+                                                          tex_path.to_string_lossy())).into());
+                    }
+                };
+
+                (Some(tex_path), fs_root, stem)
+            },
+            None => {
+                let name = b.tex_input_name.take().unwrap_or_else(|| "texput.tex".to_owned());
+                (None, PathBuf::new(), OsString::from(name))
+            },
+        };
+
+        b.io.filesystem_root(&fs_root)
+            .confine_to_root(b.untrusted);
+
+        if b.untrusted {
+            b.max_seconds = b.max_seconds.or(Some(UNTRUSTED_MAX_SECONDS));
+            b.max_pages = b.max_pages.or(Some(UNTRUSTED_MAX_PAGES));
+            b.max_passes = b.max_passes.or(Some(UNTRUSTED_MAX_PASSES));
+        }
+
+        let output_dir = b.output_dir.take().unwrap_or_else(|| fs_root.clone());
+        let aux_dir = b.aux_dir.take().unwrap_or_else(|| output_dir.clone());
+
+        if aux_dir != fs_root {
+            b.io.search_dir(&aux_dir.to_string_lossy());
+        }
+
+        let mut aux_path = Path::new(&tex_input_stem).to_owned();
+        aux_path.set_extension("aux");
+        let mut bbl_path = aux_path.clone();
+        bbl_path.set_extension("bbl");
+        let mut bcf_path = aux_path.clone();
+        bcf_path.set_extension("bcf");
+        let mut blg_path = aux_path.clone();
+        blg_path.set_extension("blg");
+        let mut idx_path = aux_path.clone();
+        idx_path.set_extension("idx");
+        let mut xdv_path = aux_path.clone();
+        xdv_path.set_extension("xdv");
+        let mut pdf_path = aux_path.clone();
+        pdf_path.set_extension("pdf");
+
+        let audit_output = b.audit_io.map(|p| (p, IoAuditLog::new()));
+
+        let build_state_path = match (b.skip_unchanged.take(), primary_input_path.as_ref()) {
+            (Some((dir, settings)), Some(input)) => {
+                let input = ctry!(input.canonicalize(); "couldn't find \"{}\"", input.display());
+                let mut dc = digest::create();
+                dc.input(input.as_os_str().as_bytes());
+                dc.input(&[0]);
+                dc.input(settings.as_bytes());
+                let key = DigestData::from(dc).to_string();
+                Some(dir.join(format!("{}-{}.json", tex_input_stem.to_string_lossy(), &key[..16])))
+            },
+            _ => None,
+        };
+
+        if let Some((_, ref log)) = audit_output {
+            b.io.audit(log.clone());
+        }
+
+        let io = b.io.create()?;
+
+        // All of the engines stop when the session is cancelled.
+
+        let cancel = CancellationToken::new();
+        b.xdvipdfmx.cancellation_token(&cancel);
+        b.xdvisvg.cancellation_token(&cancel);
+        b.xdvipng.cancellation_token(&cancel);
+
+        let mut xdvitxt = XdvitxtEngine::new();
+        xdvitxt.cancellation_token(&cancel);
+
+        Ok(ProcessingSession {
+            io: io,
+            events: IoEvents::new(),
+            pass: b.pass,
+            primary_input_path: primary_input_path,
+            primary_input_tex_path: tex_input_stem.to_string_lossy().into_owned(),
+            fs_root: fs_root,
+            output_dir: output_dir,
+            aux_dir: aux_dir,
+            format_path: b.format_name,
+            tex_aux_path: aux_path.into_os_string(),
+            tex_bbl_path: bbl_path.into_os_string(),
+            tex_bcf_path: bcf_path.into_os_string(),
+            tex_blg_path: blg_path.into_os_string(),
+            tex_idx_path: idx_path.into_os_string(),
+            biber_command: b.biber_command,
+            biber_input: None,
+            biber_unavailable: false,
+            tex_xdv_path: xdv_path.into_os_string(),
+            tex_pdf_path: pdf_path.into_os_string(),
+            output_format: b.output_format,
+            makefile_output_path: b.makefile_rules,
+            dep_file: b.dep_file,
+            written_paths: Vec::new(),
+            audit_output: audit_output,
+            tex_rerun_specification: b.reruns,
+            keep_intermediates: b.keep_intermediates,
+            keep_logs: b.keep_logs,
+            dry_run: b.dry_run,
+            outputs_in_memory: b.outputs_in_memory,
+            output_files: Vec::new(),
+            noted_tex_warnings: false,
+            synctex_enabled: b.synctex,
+            precompile_preamble: b.precompiled_preamble,
+            include_only: b.include_only,
+            draft: b.draft,
+            interaction: b.interaction,
+            pass_snapshot: HashMap::new(),
+            rerun_states: Vec::new(),
+            xdvipdfmx: b.xdvipdfmx,
+            attach_sources: b.attach_sources,
+            xdvisvg: b.xdvisvg,
+            xdvipng: b.xdvipng,
+            xdvitxt: xdvitxt,
+            cancel: cancel,
+            max_seconds: b.max_seconds,
+            max_pages: b.max_pages,
+            max_passes: b.max_passes,
+            untrusted: b.untrusted,
+            tex_passes: 0,
+            expected_tex_passes: None,
+            font_cache_dir: b.font_cache_dir,
+            build_state_path: build_state_path,
+            hooks: b.hooks,
+            bibtex_input: None,
+            makeindex_input: None,
+            pass_log: Vec::new(),
+            tex_diagnostics: Vec::new(),
+            hint_bundles: b.hint_bundles,
+            elapsed: 0.,
+        })
+    }
+}
+
+
+impl ProcessingSession {
+    /// Assess whether we need to rerun an engine. This is the case if there
+    /// was a file that the TeX engine read during its last pass and that was
+    /// then rewritten, either by TeX or by one of the helper programs, with
+    /// different contents. If so, we return an explanation of what changed.
+    fn rerun_needed(&mut self, status: &mut StatusBackend) -> Option<String> {
+        let files = self.io.mem.files.borrow();
+        let mut changes = Vec::new();
+        let mut state = digest::create();
+
+        let mut names: Vec<&OsString> = self.events.0.iter()
+            .filter(|&(_, info)| info.read_in_pass && info.written_in_pass)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        for name in names {
+            let new = match files.get(name) {
+                Some(data) => data,
+                None => continue,
+            };
+
+            state.input(name.as_bytes());
+            state.input(&[0]);
+            state.input(new);
+
+            let change = match self.pass_snapshot.get(name) {
+                Some(old) => {
+                    if old == new {
+                        continue;
+                    }
+
+                    let (added, removed) = count_changed_lines(old, new);
+                    format!("\"{}\" changed ({} lines added, {} removed)",
+                            name.to_string_lossy(), added, removed)
+                },
+                None => {
+                    // TeX read the file from somewhere other than the memory
+                    // layer, so all we have to go on is the digest.
+                    let mut dc = digest::create();
+                    dc.input(new);
+
+                    if self.events.0[name].read_digest == Some(DigestData::from(dc)) {
+                        continue;
+                    }
+
+                    format!("\"{}\" changed", name.to_string_lossy())
+                }
+            };
+
+            changes.push(change);
+        }
+
+        if changes.is_empty() {
+            return None;
+        }
+
+        // If the files have returned to a state that we've already seen,
+        // another pass will just take us around the same loop again.
+
+        let state = DigestData::from(state);
+
+        if self.rerun_states.contains(&state) {
+            tt_warning!(status, "TeX rerun seems needed, but the files it reads are cycling between the same \
+                                 contents ({}); not rerunning", changes.join("; "));
+            return None;
+        }
+
+        self.rerun_states.push(state);
+        Some(changes.join("; "))
+    }
+
+    #[allow(dead_code)]
+    fn _dump_access_info(&self, status: &mut StatusBackend) {
+        for (name, info) in &self.events.0 {
+            if info.access_pattern != AccessPattern::Read {
+                use std::string::ToString;
+                let r = match info.read_digest {
+                    Some(ref d) => d.to_string(),
+                    None => "-".into()
+                };
+                let w = match info.write_digest {
+                    Some(ref d) => d.to_string(),
+                    None => "-".into()
+                };
+                tt_note!(status, "ACCESS: {} {:?} {:?} {:?} {:?}",
+                         name.to_string_lossy(),
+                         info.access_pattern, info.input_provider, r, w);
+            }
+        }
+    }
+
+    /// Get the token that cancels this session's processing when it's
+    /// triggered, for instance from a signal handler.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// Get the paths of the files on the filesystem that the session read
+    /// when it was last run, starting with the primary input. Files that
+    /// the session wrote out itself aren't included. These are the files
+    /// whose changes can affect the outputs, so a program can watch them
+    /// and run a new session when they change.
+    pub fn filesystem_inputs(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+
+        for info in self.events.0.values() {
+            if info.input_origin != InputOrigin::Filesystem || info.got_written_to_disk {
+                continue;
+            }
+
+            if let Some(ref p) = info.abspath {
+                paths.push(p.clone());
+            }
+        }
+
+        paths.sort();
+        paths.dedup();
+
+        if let Some(ref p) = self.primary_input_path {
+            paths.insert(0, p.clone());
+        }
+
+        paths
+    }
+
+    /// Get the paths of the files that the session wrote to disk when it
+    /// was last run, not counting logs.
+    pub fn output_paths(&self) -> &[PathBuf] {
+        &self.written_paths
+    }
+
+    /// Take the files that the session produced when it was last run, as
+    /// pairs of names and contents, if it was set up to keep them in memory
+    /// with `SessionBuilder::outputs_in_memory`. Otherwise, or if this has
+    /// already been called, the list is empty.
+    pub fn take_output_files(&mut self) -> Vec<(String, Vec<u8>)> {
+        mem::replace(&mut self.output_files, Vec::new())
+    }
+
+    /// Get the names of the files that the engines read when the session was
+    /// run, not counting ones that they created themselves, along with the
+    /// name of the provider that each came from, if known. The list is
+    /// sorted by name.
+    pub fn inputs_used(&self) -> Vec<(String, Option<String>)> {
+        let mut inputs: Vec<_> = self.events.0.iter()
+            .filter(|&(_, info)| info.input_origin != InputOrigin::NotInput &&
+                    (info.access_pattern == AccessPattern::Read ||
+                     info.access_pattern == AccessPattern::ReadThenWritten))
+            .map(|(name, info)| (name.to_string_lossy().into_owned(), info.input_provider.clone()))
+            .collect();
+        inputs.sort();
+        inputs
+    }
+
+    /// Summarize what happened when the session was run. `result` is what
+    /// `run` returned.
+    pub fn report(&self, result: &Result<i32>) -> BuildReport {
+        let (error, error_code) = match *result {
+            Ok(_) => (Vec::new(), None),
+            Err(ref e) => (e.iter().map(|e| e.to_string()).collect(), Some(e.code().to_owned())),
+        };
+
+        let diagnostics = self.tex_diagnostics.iter().filter(|d| d.kind != DiagnosticKind::FileOpened).map(|d| {
+            DiagnosticReport {
+                kind: d.kind.name().to_owned(),
+                message: d.message.clone(),
+                file: d.file.clone(),
+                line: d.line,
+                context: d.context.clone(),
+            }
+        }).collect();
+
+        let mut files: Vec<FileReport> = self.events.0.iter().map(|(name, info)| {
+            let access = match info.access_pattern {
+                AccessPattern::Read => "read",
+                AccessPattern::Written => "written",
+                AccessPattern::ReadThenWritten => "read-then-written",
+                AccessPattern::WrittenThenRead => "written-then-read",
+            };
+
+            FileReport {
+                name: name.to_string_lossy().into_owned(),
+                access: access.to_owned(),
+                provider: info.input_provider.clone(),
+                path: info.abspath.as_ref().map(|p| p.to_string_lossy().into_owned()),
+            }
+        }).collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        BuildReport {
+            success: result.is_ok(),
+            error: error,
+            error_code: error_code,
+            elapsed: self.elapsed,
+            passes: self.pass_log.clone(),
+            outputs: self.written_paths.iter().map(|p| p.to_string_lossy().into_owned()).collect(),
+            diagnostics: diagnostics,
+            problems: self.problem_summary(),
+            files: files,
+        }
+    }
+
+    /// The errors from the last TeX pass, with what TeX said about where
+    /// they happened and the lines of the input around them, if they could
+    /// be found (see `diagnostics::ErrorContext`).
+    pub fn tex_errors(&self) -> Vec<&Diagnostic> {
+        self.tex_diagnostics.iter().filter(|d| d.kind == DiagnosticKind::Error).collect()
+    }
+
+    /// Read the lines around each of the errors from the last TeX pass from
+    /// the files that they happened in. It's not a problem if we can't.
+    fn add_error_sources(&mut self, status: &mut StatusBackend) {
+        let mut stack = self.io.as_stack();
+
+        for d in self.tex_diagnostics.iter_mut().filter(|d| d.kind == DiagnosticKind::Error) {
+            let (name, line, context) = match (&d.file, d.line, d.context.as_mut()) {
+                (&Some(ref name), Some(line), Some(context)) => (name, line, context),
+                _ => continue,
+            };
+
+            let name = name.trim_left_matches("./");
+
+            let mut ih = match stack.input_open_name(OsStr::new(name), status) {
+                OpenResult::Ok(ih) => ih,
+                _ => continue,
+            };
+
+            let mut data = Vec::new();
+
+            if ih.read_to_end(&mut data).is_ok() {
+                context.set_source(&String::from_utf8_lossy(&data), line);
+            }
+        }
+    }
+
+    /// If the last TeX pass couldn't find a package or class, say where it
+    /// can be found: in one of the hint bundles, or under a similar name in
+    /// the document's bundles, which usually means that the name was
+    /// mistyped.
+    fn add_missing_package_hints(&mut self, status: &mut StatusBackend) {
+        let is_package = |name: &str| name.ends_with(".sty") || name.ends_with(".cls");
+
+        if !self.tex_diagnostics.iter().any(|d| {
+            d.kind == DiagnosticKind::Error && diagnostics::missing_file_name(&d.message).map_or(false, &is_package)
+        }) {
+            return;
+        }
+
+        let mut available = Vec::new();
+
+        for b in self.io.bundles.iter_mut() {
+            available.extend(b.file_names(status).into_iter().filter_map(|n| n.into_string().ok()));
+        }
+
+        let mut elsewhere = Vec::new();
+
+        for &mut (ref name, ref mut b) in self.hint_bundles.iter_mut() {
+            let names: HashSet<OsString> = b.file_names(status).into_iter().collect();
+            elsewhere.push((name, names));
+        }
+
+        for d in self.tex_diagnostics.iter_mut().filter(|d| d.kind == DiagnosticKind::Error) {
+            let hint = match diagnostics::missing_file_name(&d.message) {
+                Some(file) if is_package(file) => {
+                    let bundle = elsewhere.iter().find(|&&(_, ref names)| names.contains(OsStr::new(file)));
+
+                    if let Some(&(bundle, _)) = bundle {
+                        Some(format!("\"{}\" is not in this bundle, but it is in bundle \"{}\"", file, bundle))
+                    } else {
+                        let ext = &file[file.len() - 4..];
+                        suggest(file, available.iter().map(|n| &n[..]).filter(|n| n.ends_with(ext)))
+                            .map(|n| format!("\"{}\" is not in this bundle; did you mean \"{}\"?", file, n))
+                    }
+                },
+                _ => None,
+            };
+
+            if let (Some(hint), Some(context)) = (hint, d.context.as_mut()) {
+                context.hint = Some(hint);
+            }
+        }
+    }
+
+    /// Summarize the most common problems in the last TeX pass: undefined
+    /// references and citations, missing files, and overfull boxes.
+    pub fn problem_summary(&self) -> ProblemSummary {
+        ProblemSummary::from_diagnostics(&self.tex_diagnostics)
+    }
+
+    /// Tell the user about the problems in the problem summary, so that
+    /// they don't have to dig through the log for them.
+    fn report_problems(&self, status: &mut StatusBackend) {
+        let summary = self.problem_summary();
+
+        for &(what, ref items) in &[("undefined reference", &summary.undefined_references),
+                                    ("undefined citation", &summary.undefined_citations),
+                                    ("missing file", &summary.missing_files),
+                                    ("overfull box", &summary.overfull_boxes)] {
+            if items.is_empty() {
+                continue;
+            }
+
+            let mut list = items.iter().take(MAX_PROBLEMS_LISTED).map(|s| &s[..]).collect::<Vec<_>>().join("; ");
+
+            if items.len() > MAX_PROBLEMS_LISTED {
+                list.push_str(&format!("; and {} more", items.len() - MAX_PROBLEMS_LISTED));
+            }
+
+            let plural = if items.len() == 1 { "" } else if what.ends_with('x') { "es" } else { "s" };
+            tt_warning!(status, "{} {}{}: {}", items.len(), what, plural, list);
+        }
+    }
+
+    /// Note that we ran `engine`, starting at `start`, for the build report.
+    fn record_pass(&mut self, engine: &str, start: Instant, status: &mut StatusBackend) {
+        let elapsed = duration_secs(start.elapsed());
+        tt_verbose!(status, ChatterLevel::Verbose, "{} took {:.2} s", engine, elapsed);
+
+        self.pass_log.push(PassReport {
+            engine: engine.to_owned(),
+            elapsed: elapsed,
+        });
+    }
+
+    /// Process the document: run the engines, write out the results, and
+    /// record the audit trail and Makefile rules, if requested.
+    pub fn run(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        // The hooks might generate some of the inputs, so they come before
+        // we check whether anything has changed.
+
+        for hooks in &mut self.hooks {
+            hooks.before_build(status)?;
+        }
+
+        if !self.dry_run && !self.outputs_in_memory && self.is_up_to_date() {
+            status.note_highlighted("", &self.primary_input_tex_path, " is up to date");
+            return Ok(0);
+        }
+
+        // If there's a time limit, a watchdog thread cancels the processing
+        // when it runs out. We have to remember that that's what happened
+        // so that we can give a sensible error. If the processing finishes
+        // first, dropping the sender wakes the watchdog up so that it can't
+        // cancel some later run.
+
+        let start = Instant::now();
+        let timed_out = Arc::new(AtomicBool::new(false));
+
+        let watchdog = self.max_seconds.map(|secs| {
+            let (tx, rx) = mpsc::channel::<()>();
+            let cancel = self.cancel.clone();
+            let timed_out = timed_out.clone();
+
+            let handle = thread::spawn(move || {
+                if let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(Duration::from_secs(secs)) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    cancel.cancel();
+                }
+            });
+
+            (tx, handle)
+        });
+
+        let mut result = self.run_passes(status);
+
+        if let Some((tx, handle)) = watchdog {
+            drop(tx);
+            let _ = handle.join();
+        }
+
+        status.end_progress(TEX_PASS_TASK);
+        self.report_problems(status);
+        self.elapsed = duration_secs(start.elapsed());
+        self.write_audit_log(status);
+
+        if result.is_ok() && !self.dry_run && !self.outputs_in_memory {
+            self.save_build_state(status);
+        }
+
+        let was_timed_out = match result {
+            Err(ref e) => match *e.kind() {
+                ErrorKind::Cancelled => timed_out.load(Ordering::SeqCst),
+                _ => false,
+            },
+            Ok(_) => false,
+        };
+
+        if was_timed_out {
+            result = Err(ErrorKind::ResourceLimitExceeded("seconds of processing time".to_owned(),
+                                                          self.max_seconds.unwrap()).into());
+        }
+
+        // If the processing failed, that's the error that matters, not any
+        // problems with the hooks.
+
+        if !self.hooks.is_empty() {
+            let report = self.report(&result);
+
+            for hooks in &mut self.hooks {
+                if let Err(e) = hooks.after_build(&report, status) {
+                    if result.is_ok() {
+                        return Err(e);
+                    }
+
+                    tt_warning!(status, "a post-build hook failed"; e);
+                }
+            }
+        }
+
+        result
+    }
+
+
+    fn run_passes(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        // Do we need to generate the format file?
+
+        let generate_format = if self.output_format == OutputFormat::Format {
+            false
+        } else {
+            let fmt_result = {
+                let mut stack = self.io.as_stack();
+                stack.input_open_format(OsStr::new(&self.format_path), status)
+            };
+
+            match fmt_result {
+                OpenResult::Ok(_) => false,
+                OpenResult::NotAvailable => true,
+                OpenResult::Err(e) => {
+                    return Err(e).chain_err(|| format!("could not open format file {}", self.format_path));
+                },
+            }
+        };
+
+        if generate_format {
+            tt_note!(status, "generating format \"{}\"", self.format_path);
+            self.make_format_pass(status)?;
+        }
+
+        if (self.include_only.is_some() || self.draft) && self.output_format != OutputFormat::Format {
+            self.inject_prefix(status)?;
+        }
+
+        if self.precompile_preamble && self.output_format != OutputFormat::Format {
+            self.use_preamble_format(status)?;
+        }
+
+        // Do the meat of the work.
+
+        let result = match self.pass {
+            PassSetting::Tex => {
+                self.expected_tex_passes = Some(1);
+                self.tex_pass(None, status)
+            },
+            PassSetting::Default => self.default_pass(false, status),
+            PassSetting::BibtexFirst => self.default_pass(true, status),
+        };
+
+        if let Err(e) = result {
+            if !self.dry_run {
+                self.write_files(None, status, true)?;
+            }
+            return Err(e);
+        };
+
+        if self.dry_run {
+            return Ok(0);
+        }
+
+        // Write output files and the first line of our Makefile output.
+
+        let mut mf_dest_maybe = match self.makefile_output_path {
+            Some(ref p) => Some(File::create(p)?),
+            None => None
+        };
+
+        let n_skipped_intermediates = self.write_files(mf_dest_maybe.as_mut(), status, false)?;
+
+        if n_skipped_intermediates > 0 {
+            status.note_highlighted("Skipped writing ", &format!("{}", n_skipped_intermediates),
+                                    " intermediate files (use --keep-intermediates to keep them)");
+        }
+
+        // Finish Makefile rules, maybe.
+
+        if let Some(ref mut mf_dest) = mf_dest_maybe {
+            ctry!(write!(mf_dest, ": "); "couldn't write to Makefile-rules file");
+
+            if let Some(ref pip) = self.primary_input_path {
+                ctry!(mf_dest.write_all(pip.as_os_str().as_bytes()); "couldn't write to Makefile-rules file");
+            }
+
+            for (name, info) in &self.events.0 {
+                if info.input_origin != InputOrigin::Filesystem {
+                    continue;
+                }
+
+                if info.got_written_to_disk {
+                    // If the file originally came from the filesystem, and it
+                    // was written as well as read, and we actually wrote it
+                    // to disk, there's a circular dependency that's
+                    // inappropriate to express in a Makefile. If it was
+                    // "written" by the engine but we didn't actually write
+                    // those modifications to disk, we're OK. If there's a
+                    // two-stage compilation involving the .aux file, the
+                    // latter case is what arises unless --keep-intermediates
+                    // is specified.
+                    tt_warning!(status, "omitting circular Makefile dependency for {}", name.to_string_lossy());
+                    continue;
+                }
+
+                ctry!(write!(mf_dest, " \\\n  {}", name.to_string_lossy()); "couldn't write to Makefile-rules file");
+            }
+
+            ctry!(writeln!(mf_dest, ""); "couldn't write to Makefile-rules file");
+        }
+
+        if self.dep_file {
+            self.write_dep_file(status)?;
+        }
+
+        // All done.
+
+        Ok(0)
+    }
+
+
+    fn write_files(&mut self, mut mf_dest_maybe: Option<&mut File>, status: &mut
+                   StatusBackend, only_logs: bool) -> Result<u32> {
+        let mut n_skipped_intermediates = 0;
+        for (name, contents) in &*self.io.mem.files.borrow() {
+            if name == self.io.mem.stdout_key() {
+                continue;
+            }
+
+            let sname = name.to_string_lossy();
+            let mut summ = self.events.0.get_mut(name).unwrap();
+
+            if self.untrusted && !is_plain_relative_path(Path::new(name)) {
+                tt_warning!(status, "not writing \"{}\": untrusted documents can only write files inside \
+                                     the output directory", sname);
+                continue;
+            }
+
+            if !only_logs && (self.output_format == OutputFormat::Aux) {
+                // In this mode we're only writing the .aux file. I initially
+                // wanted to be clever-ish and output all auxiliary-type
+                // files, but doing so ended up causing non-obvious problems
+                // for my use case, which involves using Ninja to manage
+                // dependencies.
+                if !sname.ends_with(".aux") {
+                    continue;
+                }
+            } else if summ.access_pattern != AccessPattern::Written && !self.keep_intermediates &&
+                      !(self.include_only.is_some() && sname.ends_with(".aux")) {
+                n_skipped_intermediates += 1;
+                continue;
+            }
+
+            let is_logfile = sname.ends_with(".log") || sname.ends_with(".blg") || sname.ends_with(".ilg");
+
+            if is_logfile && !self.keep_logs {
+                continue;
+            }
+
+            if !is_logfile && only_logs {
+                continue;
+            }
+
+            if contents.len() == 0 {
+                status.note_highlighted("Not writing ", &sname, ": it would be empty.");
+                continue;
+            }
+
+            if self.outputs_in_memory {
+                self.output_files.push((sname.into_owned(), contents.clone()));
+                continue;
+            }
+
+            // In "aux" mode, the .aux file is the final output.
+            let is_intermediate = summ.access_pattern != AccessPattern::Written &&
+                self.output_format != OutputFormat::Aux;
+
+            let mut real_path = if is_logfile || is_intermediate {
+                self.aux_dir.clone()
+            } else {
+                self.output_dir.clone()
+            };
+            real_path.push(name);
+
+            if let Some(dir) = real_path.parent() {
+                ctry!(fs::create_dir_all(dir); "couldn't create directory \"{}\"", dir.display());
+            }
+
+            status.note_highlighted("Writing ", &real_path.to_string_lossy(), &format!(" ({} bytes)", contents.len()));
+
+            let mut f = File::create(&real_path)?;
+            f.write_all(contents)?;
+            summ.got_written_to_disk = true;
+
+            if !is_logfile {
+                self.written_paths.push(real_path.clone());
+            }
+
+            if let Some(ref mut mf_dest) = mf_dest_maybe {
+                // Maybe it'd be better to have this just be a warning? But if
+                // the program is supposed to write the file, you don't want
+                // it exiting with error code zero if it couldn't do that
+                // successfully.
+                //
+                // Not quite sure why, but I can't pull out the target path
+                // here. I think 'self' is borrow inside the loop?
+                ctry!(write!(mf_dest, "{} ", real_path.to_string_lossy()); "couldn't write to Makefile-rules file");
+            }
+        }
+        Ok(n_skipped_intermediates)
+    }
+
+    /// Write the Make-style dependency file. The targets are the files that
+    /// we wrote out, and the prerequisites are the ones that we read from
+    /// the filesystem; see `filesystem_inputs`.
+    fn write_dep_file(&mut self, status: &mut StatusBackend) -> Result<()> {
+        let mut path = self.output_dir.join(&self.tex_aux_path);
+        path.set_extension("d");
+
+        if self.written_paths.is_empty() {
+            tt_warning!(status, "no outputs were written, so not writing \"{}\"", path.display());
+            return Ok(());
+        }
+
+        let mut text = String::new();
+
+        for (i, p) in self.written_paths.iter().enumerate() {
+            if i > 0 {
+                text.push(' ');
+            }
+            text.push_str(&escape_make_path(p));
+        }
+
+        text.push(':');
+
+        for p in self.filesystem_inputs() {
+            text.push_str(" \\\n  ");
+            text.push_str(&escape_make_path(&p));
+        }
+
+        text.push('\n');
+
+        status.note_highlighted("Writing ", &path.to_string_lossy(), &format!(" ({} bytes)", text.len()));
+        let mut f = ctry!(File::create(&path); "couldn't create dependency file \"{}\"", path.display());
+        ctry!(f.write_all(text.as_bytes()); "couldn't write dependency file \"{}\"", path.display());
+        Ok(())
+    }
+
+    /// The "default" pass really runs a bunch of sub-passes. It is a "Do What
+    /// I Mean" operation.
+    fn default_pass(&mut self, bibtex_first: bool, status: &mut StatusBackend) -> Result<i32> {
+        // If `bibtex_first` is true, we start by running bibtex, and run
+        // proceed with the standard rerun logic. Otherwise, we run TeX,
+        // auto-detect whether we need to run bibtex, possibly run it, and
+        // then go ahead.
+
+        let (pass_count, reruns_fixed) = match self.tex_rerun_specification {
+            Some(n) => (n, true),
+            None => (DEFAULT_MAX_TEX_PASSES, false),
+        };
+
+        let expected = if bibtex_first { pass_count } else { pass_count + 1 };
+        self.expected_tex_passes = Some(match self.max_passes {
+            Some(limit) => cmp::min(expected, limit),
+            None => expected,
+        });
+
+        let mut rerun_result = if bibtex_first {
+            self.bibtex_pass(status)?;
+            Some("bibtex was run".to_owned())
+        } else {
+            self.tex_pass(None, status)?;
+            self.maybe_makeindex_pass(status)?;
+            self.maybe_biber_pass(status)?;
+
+            if self.maybe_bibtex_pass(status)? {
+                Some("bibtex was run".to_owned())
+            } else {
+                self.rerun_needed(status)
+            }
+        };
+
+        // Now we enter the main rerun loop.
+
+        for i in 0..pass_count {
+            let rerun_explanation = if reruns_fixed {
+                "I was told to".to_owned()
+            } else {
+                match rerun_result {
+                    Some(ref s) => s.clone(),
+                    None => {
+                        break;
+                    }
+                }
+            };
+
+            // We're restarting the engine afresh, so clear the read inputs.
+            // We do *not* clear the entire HashMap since we want to remember,
+            // e.g., that bibtex wrote out the .bbl file, since that way we
+            // can later know that it's OK to delete. I am not super confident
+            // that the access_pattern data can just be left as-is when we do
+            // this, but, uh, so far it seems to work.
+            for summ in self.events.0.values_mut() {
+                summ.read_digest = None;
+            }
+
+            self.tex_pass(Some(&rerun_explanation), status)?;
+            self.maybe_makeindex_pass(status)?;
+            self.maybe_biber_pass(status)?;
+            let bibtex_ran = self.maybe_bibtex_pass(status)?;
+
+            if !reruns_fixed {
+                rerun_result = self.rerun_needed(status);
+
+                // If TeX didn't read the bibliography during this pass,
+                // rerun_needed() can't tell that BibTeX has just created it.
+                if rerun_result.is_none() && bibtex_ran {
+                    let bbl_read = self.events.0.get(&self.tex_bbl_path).map(|s| s.read_in_pass).unwrap_or(false);
+
+                    if !bbl_read {
+                        rerun_result = Some("bibtex was run".to_owned());
+                    }
+                }
+
+                if rerun_result.is_some() && i == DEFAULT_MAX_TEX_PASSES - 1 {
+                    tt_warning!(status, "TeX rerun seems needed, but stopping at {} passes", DEFAULT_MAX_TEX_PASSES);
+                    break;
+                }
+            }
+        }
+
+        // And finally, xdvipdfmx or the SVG converter. Maybe.
+
+        match self.output_format {
+            OutputFormat::Pdf => { self.xdvipdfmx_pass(status)?; },
+            OutputFormat::Svg => { self.xdvisvg_pass(status)?; },
+            OutputFormat::Png => { self.xdvipng_pass(status)?; },
+            OutputFormat::Txt => { self.xdvitxt_pass(status)?; },
+            _ => {},
+        }
+
+        Ok(0)
+    }
+
+
+    /// Prepend the commands for `\includeonly` and draft mode to the
+    /// primary input. They go on its first line, so that line numbers in
+    /// error messages stay the same.
+    fn inject_prefix(&mut self, status: &mut StatusBackend) -> Result<()> {
+        let mut text = Vec::new();
+
+        if self.draft {
+            text.extend_from_slice(b"\\PassOptionsToPackage{draft}{graphicx}");
+        }
+
+        if let Some(ref names) = self.include_only {
+            text.extend_from_slice(format!("\\includeonly{{{}}}", names.join(",")).as_bytes());
+        }
+
+        match self.io.primary_input.input_open_primary(status) {
+            OpenResult::Ok(mut ih) => { ih.read_to_end(&mut text)?; },
+            OpenResult::NotAvailable => {
+                return Err(ErrorKind::Msg("the primary input is not available".to_owned()).into());
+            },
+            OpenResult::Err(e) => { return Err(e); },
+        }
+
+        self.io.primary_input = Box::new(BufferedPrimaryIo::from_buffer(text));
+        Ok(())
+    }
+
+
+    /// Arrange for TeX to start from a format file that has the document's
+    /// preamble already loaded, generating that format if needed. The format
+    /// is named by a digest of the preamble text, so it's regenerated
+    /// whenever the preamble changes. Files that the preamble reads aren't
+    /// part of the digest, so changes to them go unnoticed.
+    ///
+    /// The engine then reads only the body of the document. The preamble
+    /// lines are replaced with comments so that line numbers don't change.
+    fn use_preamble_format(&mut self, status: &mut StatusBackend) -> Result<()> {
+        let mut text = Vec::new();
+
+        match self.io.primary_input.input_open_primary(status) {
+            OpenResult::Ok(mut ih) => { ih.read_to_end(&mut text)?; },
+            OpenResult::NotAvailable => {
+                return Err(ErrorKind::Msg("the primary input is not available".to_owned()).into());
+            },
+            OpenResult::Err(e) => { return Err(e); },
+        }
+
+        let preamble_len = match find_preamble_end(&text) {
+            Some(n) => n,
+            None => {
+                tt_warning!(status, "no \\begin{{document}} found in the input; not precompiling its preamble");
+                return Ok(());
+            }
+        };
+
+        let preamble_format = {
+            let mut dc = digest::create();
+            dc.input(&text[..preamble_len]);
+            format!("{}-preamble-{}", format_stem(&self.format_path)?, DigestData::from(dc))
+        };
+
+        let fmt_result = {
+            let mut stack = self.io.as_stack();
+            stack.input_open_format(OsStr::new(&preamble_format), status)
+        };
+
+        let mut body = Vec::with_capacity(text.len());
+
+        for _ in text[..preamble_len].iter().filter(|c| **c == b'\n') {
+            body.extend_from_slice(b"%\n");
+        }
+
+        body.extend_from_slice(&text[preamble_len..]);
+
+        match fmt_result {
+            OpenResult::Ok(_) => {},
+            OpenResult::NotAvailable => {
+                // The format is dumped with the `\primitive` prefix since
+                // LaTeX redefines `\dump`.
+                text.truncate(preamble_len);
+                text.extend_from_slice(b"\\primitive\\dump\n");
+
+                let document = mem::replace(&mut self.io.primary_input,
+                                            Box::new(BufferedPrimaryIo::from_buffer(text)));
+                status.note_highlighted("Precompiling ", "the preamble", " ...");
+
+                let start = Instant::now();
+                let result = {
+                    let mut stack = self.io.as_stack();
+                    TexEngine::new()
+                            .cancellation_token(&self.cancel)
+                            .halt_on_error_mode(true)
+                            .initex_mode(true)
+                            .preload_format(true)
+                            .process(&mut stack, &mut self.events, status, &self.format_path,
+                                     &self.primary_input_tex_path)
+                };
+                self.record_pass("tex-preamble", start, status);
+
+                if let Err(e) = self.finish_format_pass(result, &preamble_format, status) {
+                    if let ErrorKind::Cancelled = *e.kind() {
+                        return Err(e);
+                    }
+
+                    tt_warning!(status, "couldn't precompile the preamble; processing the whole document"; e);
+                    self.io.mem.files.borrow_mut().clear();
+                    self.io.primary_input = document;
+                    return Ok(());
+                }
+            },
+            OpenResult::Err(e) => {
+                return Err(e).chain_err(|| format!("could not open format file {}", preamble_format));
+            },
+        }
+
+        self.io.primary_input = Box::new(BufferedPrimaryIo::from_buffer(body));
+        self.format_path = preamble_format;
+        Ok(())
+    }
+
+
+    /// Use the TeX engine to generate a format file.
+    fn make_format_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        if self.io.bundles.is_empty() {
+            return Err(ErrorKind::Msg("cannot create formats without using a bundle".to_owned()).into())
+        }
+
+        let stem = format_stem(&self.format_path)?.to_owned();
+
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack_for_format(&format!("\\input tectonic-format-{}.tex", stem));
+            TexEngine::new()
+                    .cancellation_token(&self.cancel)
+                    .halt_on_error_mode(true)
+                    .initex_mode(true)
+                    .process(&mut stack, &mut self.events, status, "UNUSED.fmt.gz", "texput")
+        };
+        self.record_pass("tex-format", start, status);
+
+        self.finish_format_pass(result, &stem, status)
+    }
+
+
+    /// Check the outcome of a TeX pass run in "initex" mode and save the
+    /// format file that it generated under the name `stem`.
+    fn finish_format_pass(&mut self, result: Result<TexResult>, stem: &str,
+                          status: &mut StatusBackend) -> Result<i32> {
+        match result {
+            Ok(TexResult::Spotless) => {},
+            Ok(TexResult::Warnings) => {
+                tt_warning!(status, "warnings were issued by the TeX engine; use --print and/or --keep-logs for details.");
+            },
+            Ok(TexResult::Errors) => {
+                tt_error!(status, "errors were issued by the TeX engine; use --print and/or --keep-logs for details.");
+                return Err(ErrorKind::Msg("unhandled TeX engine error".to_owned()).into());
+            },
+            Err(e) => {
+                if let ErrorKind::Cancelled = *e.kind() {
+                    return Err(e);
+                }
+
+                if let Some(output) = self.io.mem.files.borrow().get(self.io.mem.stdout_key()) {
+                    tt_error!(status, "something bad happened inside TeX; its output follows:\n");
+                    status.dump_error_logs(&output);
+                }
+
+                return Err(e);
+            }
+        }
+
+        // Now we can write the format file to its special location. In
+        // principle we could stream the format file directly to the staging
+        // area as we ran the TeX engine, but we don't bother.
+
+        for (name, contents) in &*self.io.mem.files.borrow() {
+            if name == self.io.mem.stdout_key() {
+                continue;
+            }
+
+            let sname = name.to_string_lossy();
+
+            if !sname.ends_with(".fmt.gz") {
+                continue;
+            }
+
+            // Note that we intentionally pass 'stem', not 'name'. With
+            // layered bundles, the first one that can save formats gets it.
+            let mut result = Ok(());
+
+            for bundle in self.io.bundles.iter_mut() {
+                result = bundle.write_format(stem, contents, status);
+
+                if result.is_ok() {
+                    break;
+                }
+            }
+
+            ctry!(result; "cannot write format file {}", sname);
+        }
+
+        // All done. Clear the memory layer since this was a special preparatory step.
+        self.io.mem.files.borrow_mut().clear();
+
+        Ok(0)
+    }
+
+
+    /// Run one pass of the TeX engine.
+    fn tex_pass(&mut self, rerun_explanation: Option<&str>, status: &mut StatusBackend) -> Result<i32> {
+        if let Some(limit) = self.max_passes {
+            if self.tex_passes >= limit {
+                return Err(ErrorKind::ResourceLimitExceeded("TeX passes".to_owned(), limit as u64).into());
+            }
+        }
+
+        self.tex_passes += 1;
+        status.report_progress(TEX_PASS_TASK, self.tex_passes as u64,
+                               self.expected_tex_passes.map(|n| n as u64), ProgressUnit::Passes);
+        self.events.start_pass();
+        self.pass_snapshot.clear();
+
+        for (name, contents) in &*self.io.mem.files.borrow() {
+            if name != self.io.mem.stdout_key() && *name != self.tex_xdv_path && *name != self.tex_pdf_path {
+                self.pass_snapshot.insert(name.clone(), contents.clone());
+            }
+        }
+
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack();
+            if let Some(s) = rerun_explanation {
+                status.note_highlighted("Rerunning ", "TeX", &format!(" because {} ...", s));
+            } else {
+                status.note_highlighted("Running ", "TeX", " ...");
+            }
+
+            let mut engine = TexEngine::new();
+            engine.cancellation_token(&self.cancel)
+                  .max_pages(self.max_pages)
+                  .halt_on_error_mode(self.interaction == InteractionMode::ErrorStop)
+                  .interaction_mode(self.interaction)
+                  .initex_mode(self.output_format == OutputFormat::Format)
+                  .synctex(self.synctex_enabled)
+                  .font_cache_dir(self.font_cache_dir.as_ref().map(|d| d.as_path()));
+            let result = engine.process(&mut stack, &mut self.events, status, &self.format_path,
+                                        &self.primary_input_tex_path);
+            self.tex_diagnostics = engine.diagnostics().to_vec();
+            result
+        };
+
+        // TeX describes its errors in the log at length; pick out where each
+        // one happened, so that they can be found without reading through
+        // the whole thing.
+        match result {
+            Ok(TexResult::Errors) | Err(_) => {
+                self.add_error_sources(status);
+                self.add_missing_package_hints(status);
+
+                for d in self.tex_diagnostics.iter().filter(|d| d.kind == DiagnosticKind::Error) {
+                    status.report_diagnostic(d);
+
+                    if let Some(hint) = d.context.as_ref().and_then(|c| c.hint.as_ref()) {
+                        tt_note!(status, "{}", hint);
+                    }
+                }
+            },
+            _ => {},
+        }
+        self.record_pass("tex", start, status);
+
+        match result {
+            Ok(TexResult::Spotless) => {},
+            Ok(TexResult::Warnings) => {
+                if !self.noted_tex_warnings {
+                    tt_note!(status, "warnings were issued by the TeX engine; use --print and/or --keep-logs for details.");
+                    self.noted_tex_warnings = true;
+                }
+            },
+            Ok(TexResult::Errors) => {
+                if !self.noted_tex_warnings {
+                    // Weakness: if a first pass produces warnings and a
+                    // second pass produces ignored errors, we won't say so.
+                    tt_warning!(status, "errors were issued by the TeX engine, but were ignored; \
+                                         use --print and/or --keep-logs for details.");
+                    self.noted_tex_warnings = true;
+                }
+            },
+            Err(e) => {
+                // Errors that say what went wrong themselves, like being
+                // cancelled or not being able to get the format file from the
+                // bundle, aren't failures of TeX, and keep their own kinds so
+                // that they get the right exit codes.
+                if e.specific_kind().is_some() || e.io_cause().is_some() {
+                    return Err(e);
+                }
+
+                if let Some(output) = self.io.mem.files.borrow().get(self.io.mem.stdout_key()) {
+                    // If we stopped at the first error, TeX's report of it
+                    // is all that's worth showing.
+                    let report = if self.interaction == InteractionMode::ErrorStop {
+                        last_error_report(output)
+                    } else {
+                        None
+                    };
+
+                    if let Some(report) = report {
+                        tt_error!(status, "TeX stopped at the first error:\n");
+                        status.dump_error_logs(report);
+                    } else {
+                        tt_error!(status, "something bad happened inside TeX; its output follows:\n");
+                        status.dump_error_logs(&output);
+                    }
+                }
+
+                return Err(e).chain_err(|| ErrorKind::EngineFailed("TeX".to_owned()));
+            }
+        }
+
+        Ok(0)
+    }
+
+
+    fn bibtex_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = BibtexEngine::new ();
+            engine.cancellation_token(&self.cancel);
+            status.note_highlighted("Running ", "BibTeX", " ...");
+            engine.process(&mut stack, &mut self.events, status,
+                           &self.tex_aux_path.to_str().unwrap())
+        };
+        self.record_pass("bibtex", start, status);
+
+        match result {
+            Ok(TexResult::Spotless) => {},
+            Ok(TexResult::Warnings) => {
+                tt_note!(status, "warnings were issued by BibTeX; use --print and/or --keep-logs for details.");
+            },
+            Ok(TexResult::Errors) => {
+                tt_warning!(status, "errors were issued by BibTeX, but were ignored; \
+                                          use --print and/or --keep-logs for details.");
+            },
+            Err(e) => {
+                // As for TeX, only genuine failures of BibTeX are reported
+                // as such.
+                if e.specific_kind().is_some() || e.io_cause().is_some() {
+                    return Err(e);
+                }
+
+                if let Some(output) = self.io.mem.files.borrow().get(self.io.mem.stdout_key()) {
+                    tt_error!(status, "something bad happened inside BibTeX; its output follows:\n");
+                    status.dump_error_logs(&output);
+                }
+
+                return Err(e).chain_err(|| ErrorKind::EngineFailed("BibTeX".to_owned()));
+            }
+        }
+
+        Ok(0)
+    }
+
+
+    /// Run BibTeX if the document has a bibliography and the citations,
+    /// databases, or style that BibTeX would read from the `.aux` file have
+    /// changed since we last ran it. Citations can change from one pass to
+    /// the next, e.g. when a `\cite` is inside text that moves around, so we
+    /// check after every pass. Returns whether BibTeX was run.
+    fn maybe_bibtex_pass(&mut self, status: &mut StatusBackend) -> Result<bool> {
+        let input = {
+            let files = self.io.mem.files.borrow();
+            let mut input = Vec::new();
+            collect_bibtex_lines(&files, &self.tex_aux_path, &mut Vec::new(), &mut input);
+            input
+        };
+
+        // Without both citations and databases, BibTeX would just complain.
+
+        let needed = {
+            let has_command = |prefix: &[u8]| input.split(|&b| b == b'\n').any(|l| l.starts_with(prefix));
+            has_command(b"\\citation{") && has_command(b"\\bibdata{")
+        };
+
+        if !needed || self.bibtex_input.as_ref() == Some(&input) {
+            return Ok(false);
+        }
+
+        self.bibtex_pass(status)?;
+        self.bibtex_input = Some(input);
+        Ok(true)
+    }
+
+
+    /// If the last TeX pass wrote a new index file, process it. If the
+    /// resulting `.ind` file differs from the one that TeX read, the usual
+    /// rerun detection will notice.
+    fn maybe_makeindex_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        let idx = match self.io.mem.files.borrow().get(&self.tex_idx_path) {
+            Some(data) => data.clone(),
+            None => return Ok(0),
+        };
+
+        if self.makeindex_input.as_ref() == Some(&idx) {
+            return Ok(0);
+        }
+
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = MakeindexEngine::new ();
+            status.note_highlighted("Running ", "makeindex", " ...");
+            engine.process(&mut stack, &mut self.events, status,
+                           &self.tex_idx_path.to_str().unwrap())
+        };
+        self.record_pass("makeindex", start, status);
+
+        match result? {
+            TexResult::Spotless => {},
+            _ => {
+                tt_note!(status, "warnings were issued by makeindex; use --keep-logs for details.");
+            },
+        }
+
+        self.makeindex_input = Some(idx);
+        Ok(0)
+    }
+
+
+    /// Documents using biblatex with its default backend write out a `.bcf`
+    /// control file that has to be processed by biber. We don't have an
+    /// embedded biber, so if the last TeX pass wrote out a new control file,
+    /// we run the external program in a temporary directory and load the
+    /// `.bbl` file that it creates into the memory layer. As with makeindex,
+    /// the usual rerun detection will notice if the `.bbl` file changed.
+    fn maybe_biber_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        let bcf = match self.io.mem.files.borrow().get(&self.tex_bcf_path) {
+            Some(data) => data.clone(),
+            None => return Ok(0),
+        };
+
+        if self.biber_unavailable || self.biber_input.as_ref() == Some(&bcf) {
+            return Ok(0);
+        }
+
+        if self.untrusted {
+            tt_warning!(status, "this document uses biblatex, but biber isn't run for untrusted documents, \
+                                 so its bibliography will be empty");
+            self.biber_unavailable = true;
+            return Ok(0);
+        }
+
+        // None of the engines read the control file, but it's an
+        // intermediate file, not a final product.
+        self.events.input_opened(&self.tex_bcf_path, InputOrigin::Other);
+        self.events.input_closed(self.tex_bcf_path.clone(), None);
+
+        let workdir = ctry!(TempDir::new("tectonic_biber"); "couldn't create a temporary directory for biber");
+        let bcf_path = workdir.path().join(&self.tex_bcf_path);
+        ctry!(File::create(&bcf_path).and_then(|mut f| f.write_all(&bcf));
+              "couldn't write \"{}\"", bcf_path.display());
+
+        let mut cmd = process::Command::new(&self.biber_command);
+        cmd.arg("--output-directory").arg(workdir.path());
+
+        if self.fs_root != Path::new("") {
+            cmd.arg("--input-directory").arg(&self.fs_root);
+        }
+
+        cmd.arg(&bcf_path);
+        status.note_highlighted("Running ", "biber", " ...");
+
+        let start = Instant::now();
+        let output = cmd.output();
+        self.record_pass("biber", start, status);
+
+        let output = match output {
+            Ok(o) => o,
+            Err(e) => {
+                tt_warning!(status, "this document uses biblatex, but the program \"{}\" could not be run, \
+                                     so its bibliography will be empty; use --biber to specify where to find biber",
+                            self.biber_command.to_string_lossy(); e.into());
+                self.biber_unavailable = true;
+                return Ok(0);
+            }
+        };
+
+        // Load up the results even if biber failed, so that the log is
+        // available.
+        for name in &[self.tex_bbl_path.clone(), self.tex_blg_path.clone()] {
+            let path = workdir.path().join(name);
+            let mut data = Vec::new();
+
+            match File::open(&path) {
+                Ok(mut f) => { ctry!(f.read_to_end(&mut data); "couldn't read \"{}\"", path.display()); },
+                Err(_) => continue,
+            }
+
+            let mut oh = self.io.mem.output_open_name(name).unwrap();
+            self.events.output_opened(name);
+            oh.write_all(&data)?;
+            let (name, digest) = oh.into_name_digest();
+            self.events.output_closed(name, digest);
+        }
+
+        if !output.status.success() {
+            tt_error!(status, "biber failed; its output follows:\n");
+            let mut logs = output.stdout.clone();
+            logs.extend_from_slice(&output.stderr);
+            status.dump_error_logs(&logs);
+            return Err(ErrorKind::EngineFailed("biber".to_owned()).into());
+        }
+
+        self.biber_input = Some(bcf);
+        Ok(0)
+    }
+
+
+    /// Gather the files to attach to the PDF for `--attach-sources`: the
+    /// primary input, if it's a file, and everything else that the engines
+    /// read from the filesystem without having written it themselves.
+    /// Files from the bundle are left out, since anyone can get them.
+    fn source_attachments(&mut self, status: &mut StatusBackend) -> Result<Vec<PdfAttachment>> {
+        let mut attachments = Vec::new();
+
+        if let Some(ref path) = self.primary_input_path {
+            let mut data = Vec::new();
+            let mut f = ctry!(File::open(path); "couldn't open \"{}\"", path.display());
+            ctry!(f.read_to_end(&mut data); "couldn't read \"{}\"", path.display());
+            attachments.push(PdfAttachment {
+                name: self.primary_input_tex_path.clone(),
+                data: data,
+            });
+        }
+
+        let mut names: Vec<_> = self.events.0.iter()
+            .filter(|&(_, summ)| summ.input_origin == InputOrigin::Filesystem &&
+                    summ.access_pattern == AccessPattern::Read)
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+
+        let mut stack = self.io.as_stack();
+
+        for name in names {
+            let name_str = name.to_string_lossy().into_owned();
+
+            if attachments.iter().any(|a: &PdfAttachment| a.name == name_str) {
+                continue;
+            }
+
+            let mut ih = match stack.input_open_name(&name, status) {
+                OpenResult::Ok(ih) => ih,
+                OpenResult::NotAvailable => {
+                    tt_warning!(status, "couldn't find \"{}\" again to attach it to the PDF", name_str);
+                    continue;
+                },
+                OpenResult::Err(e) => return Err(e),
+            };
+
+            let mut data = Vec::new();
+            ctry!(ih.read_to_end(&mut data); "couldn't read \"{}\"", name_str);
+            attachments.push(PdfAttachment {
+                name: name_str,
+                data: data,
+            });
+        }
+
+        Ok(attachments)
+    }
+
+
+    fn xdvipdfmx_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        if self.attach_sources {
+            let attachments = self.source_attachments(status)?;
+            self.xdvipdfmx.attachments(attachments);
+        }
+
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack();
+            status.note_highlighted("Running ", "xdvipdfmx", " ...");
+            self.xdvipdfmx.process(&mut stack, &mut self.events, status,
+                                   &self.tex_xdv_path.to_str().unwrap(), &self.tex_pdf_path.to_str().unwrap())
+        };
+        self.record_pass("xdvipdfmx", start, status);
+        result?;
+
+        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+
+    fn xdvisvg_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        let stem = PathBuf::from(&self.tex_pdf_path).with_extension("");
+
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack();
+            status.note_highlighted("Converting ", "to SVG", " ...");
+            self.xdvisvg.process(&mut stack, &mut self.events, status,
+                                 &self.tex_xdv_path.to_str().unwrap(), &stem.to_str().unwrap())
+        };
+        self.record_pass("xdvisvg", start, status);
+        result?;
+
+        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+
+    fn xdvipng_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        let stem = PathBuf::from(&self.tex_pdf_path).with_extension("");
+
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack();
+            status.note_highlighted("Rendering ", "PNG images", " ...");
+            self.xdvipng.process(&mut stack, &mut self.events, status,
+                                 &self.tex_xdv_path.to_str().unwrap(), &stem.to_str().unwrap())
+        };
+        self.record_pass("xdvipng", start, status);
+        result?;
+
+        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+
+    fn xdvitxt_pass(&mut self, status: &mut StatusBackend) -> Result<i32> {
+        let stem = PathBuf::from(&self.tex_pdf_path).with_extension("");
+
+        let start = Instant::now();
+        let result = {
+            let mut stack = self.io.as_stack();
+            status.note_highlighted("Extracting ", "text", " ...");
+            self.xdvitxt.process(&mut stack, &mut self.events, status,
+                                 &self.tex_xdv_path.to_str().unwrap(), &stem.to_str().unwrap())
+        };
+        self.record_pass("xdvitxt", start, status);
+        result?;
+
+        self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+
+    /// Check whether the last successful run, as recorded in the build state
+    /// file, read exactly the same files from the filesystem as are there
+    /// now, and whether its outputs are still around.
+    fn is_up_to_date(&self) -> bool {
+        let path = match self.build_state_path {
+            Some(ref p) => p,
+            None => return false,
+        };
+
+        let state: BuildState = match File::open(path).ok().and_then(|f| serde_json::from_reader(f).ok()) {
+            Some(s) => s,
+            None => return false,
+        };
+
+        state.outputs.iter().all(|p| Path::new(p).is_file()) &&
+            state.inputs.iter().all(|&(ref p, ref d)| {
+                file_digest(Path::new(p)).map(|fd| fd.to_string()).as_ref() == Some(d)
+            })
+    }
+
+    /// Record the inputs and outputs of a successful run in the build state
+    /// file, if we're keeping one. We use the digests of the inputs as they
+    /// were when they were read, so that if one changes while we're running,
+    /// the next run won't be skipped.
+    fn save_build_state(&self, status: &mut StatusBackend) {
+        let path = match self.build_state_path {
+            Some(ref p) => p,
+            None => return,
+        };
+
+        let mut inputs = Vec::new();
+
+        if let Some(ref p) = self.primary_input_path {
+            if let Some(d) = file_digest(p) {
+                inputs.push((p.canonicalize().unwrap_or(p.clone()).to_string_lossy().into_owned(), d.to_string()));
+            }
+        }
+
+        for info in self.events.0.values() {
+            if info.input_origin != InputOrigin::Filesystem || info.got_written_to_disk {
+                continue;
+            }
+
+            if let (Some(ref p), Some(ref d)) = (info.abspath.as_ref(), info.read_digest.as_ref()) {
+                inputs.push((p.to_string_lossy().into_owned(), d.to_string()));
+            }
+        }
+
+        inputs.sort();
+        inputs.dedup();
+
+        let state = BuildState {
+            inputs: inputs,
+            outputs: self.written_paths.iter()
+                .map(|p| p.canonicalize().unwrap_or(p.clone()).to_string_lossy().into_owned())
+                .collect(),
+        };
+
+        let result = File::create(path)
+            .map_err(|e| e.into())
+            .and_then(|mut f| serde_json::to_writer(&mut f, &state).map_err(|e| e.into()));
+
+        if let Err(e) = result {
+            tt_warning!(status, "failed to save the build state to \"{}\"", path.display(); e);
+        }
+    }
+
+
+    /// Write out the I/O audit trail, if one was requested. This is done
+    /// even if processing failed, since that's when it's most interesting.
+    fn write_audit_log(&self, status: &mut StatusBackend) {
+        if let Some((ref path, ref log)) = self.audit_output {
+            let result = File::create(path)
+                .map_err(|e| e.into())
+                .and_then(|mut f| log.write_json(&mut f));
+
+            if let Err(e) = result {
+                tt_warning!(status, "failed to write I/O audit log to \"{}\"", path.display(); e);
+            }
+        }
+    }
+}
@@ -7,17 +7,27 @@ use std::ffi::{CStr, CString};
 use errors::{ErrorKind, Result};
 use io::IoStack;
 use status::StatusBackend;
-use super::{IoEventBackend, ExecutionState, TectonicBridgeApi};
+use super::{was_cancelled, CancellationToken, IoEventBackend, ExecutionState, TectonicBridgeApi};
 use super::tex::TexResult;
 
 
 pub struct BibtexEngine {
+    cancel: Option<CancellationToken>,
 }
 
 
 impl BibtexEngine {
     pub fn new () -> BibtexEngine {
-        BibtexEngine {}
+        BibtexEngine {
+            cancel: None,
+        }
+    }
+
+    /// Have the engine stop with an error if `token` is triggered while it
+    /// is running.
+    pub fn cancellation_token (&mut self, token: &CancellationToken) -> &mut Self {
+        self.cancel = Some(token.clone());
+        self
     }
 
     pub fn process (&mut self, io: &mut IoStack,
@@ -25,7 +35,7 @@ impl BibtexEngine {
                     status: &mut StatusBackend, aux: &str) -> Result<TexResult> {
         let caux = CString::new(aux)?;
 
-        let /*mut*/ state = ExecutionState::new(io, events, status);
+        let /*mut*/ state = ExecutionState::new(io, events, status, self.cancel.clone());
         let bridge = TectonicBridgeApi::new(&state);
 
         unsafe {
@@ -36,6 +46,7 @@ impl BibtexEngine {
                 3 => {
                     Err(ErrorKind::Msg("unspecified fatal bibtex error".into()).into())
                 },
+                99 if was_cancelled(&self.cancel) => Err(ErrorKind::Cancelled.into()),
                 99 => {
                     let ptr = super::tt_get_error_message();
                     let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
@@ -0,0 +1,231 @@
+// src/engines/font_cache.rs -- on-disk caching of font metadata
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! To find the fonts that a document asks for by name, the XeTeX engine has
+//! to read the name tables and a few metrics out of every candidate font on
+//! the system. Documents that use fontspec can make it look at hundreds of
+//! fonts, and it does all of that work over again on each pass. This module
+//! lets the engine save what it learned about each font face so that later
+//! runs can skip the parsing.
+//!
+//! The engine describes each face with a short text record, which we treat
+//! as opaque. Records are stored one per file, named by the digest of the
+//! font file and the index of the face within it, so that a font that is
+//! changed or replaced is never confused with the old one. Computing the
+//! digest of a large font is itself not cheap, so we also keep an index of
+//! the digests of the font files that we've seen, keyed by their path,
+//! size, and modification time.
+
+use mkstemp;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use digest::{self, Digest, DigestData};
+use errors::Result;
+
+
+/// The name of the file holding the index of font digests.
+const INDEX_NAME: &'static str = "digests.txt";
+
+
+/// The identifying characteristics of a font file, which we use to decide
+/// whether its digest might have changed.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+struct FileStamp {
+    size: u64,
+    mtime: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Result<FileStamp> {
+        let md = fs::metadata(path)?;
+        let mtime = md.modified()?.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Ok(FileStamp { size: md.len(), mtime: mtime })
+    }
+}
+
+
+pub struct FontCache {
+    cache_dir: PathBuf,
+    digests: HashMap<PathBuf, (FileStamp, DigestData)>,
+    digests_changed: bool,
+}
+
+
+impl FontCache {
+    /// Open the font cache stored in `cache_dir`. Problems reading the
+    /// index aren't fatal; they just mean that we'll have to recompute the
+    /// digests.
+    pub fn open(cache_dir: &Path) -> FontCache {
+        let mut cache = FontCache {
+            cache_dir: cache_dir.to_owned(),
+            digests: HashMap::new(),
+            digests_changed: false,
+        };
+
+        if let Ok(f) = File::open(cache_dir.join(INDEX_NAME)) {
+            for line in BufReader::new(f).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => break,
+                };
+
+                // Each line is "size mtime digest path"; the path goes last
+                // since it may contain spaces.
+                let bits: Vec<&str> = line.splitn(4, ' ').collect();
+
+                if bits.len() != 4 {
+                    continue;
+                }
+
+                let size = match bits[0].parse() { Ok(n) => n, Err(_) => continue };
+                let mtime = match bits[1].parse() { Ok(n) => n, Err(_) => continue };
+                let digest = match DigestData::from_str(bits[2]) { Ok(d) => d, Err(_) => continue };
+
+                cache.digests.insert(PathBuf::from(bits[3]), (FileStamp { size: size, mtime: mtime }, digest));
+            }
+        }
+
+        cache
+    }
+
+    /// Get the digest of a font file, computing it if we don't already know
+    /// it.
+    fn digest_of(&mut self, path: &Path) -> Result<DigestData> {
+        let stamp = FileStamp::of(path)?;
+
+        if let Some(&(known_stamp, digest)) = self.digests.get(path) {
+            if known_stamp == stamp {
+                return Ok(digest);
+            }
+        }
+
+        let mut dc = digest::create();
+        let mut f = File::open(path)?;
+        let mut buf = [0u8; 65536];
+
+        loop {
+            let n = f.read(&mut buf)?;
+
+            if n == 0 {
+                break;
+            }
+
+            dc.input(&buf[..n]);
+        }
+
+        let digest = DigestData::from(dc);
+        self.digests.insert(path.to_owned(), (stamp, digest));
+        self.digests_changed = true;
+        Ok(digest)
+    }
+
+    fn record_path(&mut self, font_path: &Path, index: u32) -> Result<PathBuf> {
+        let digest = self.digest_of(font_path)?;
+        Ok(self.cache_dir.join(format!("{}-{}.txt", digest.to_string(), index)))
+    }
+
+    /// Get the saved record for face number `index` of the font file
+    /// `font_path`, if there is one.
+    pub fn get(&mut self, font_path: &Path, index: u32) -> Option<String> {
+        let path = match self.record_path(font_path, index) {
+            Ok(p) => p,
+            Err(_) => return None,
+        };
+
+        let mut record = String::new();
+
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut record)) {
+            Ok(_) => Some(record),
+            Err(_) => None,
+        }
+    }
+
+    /// Save the record for face number `index` of the font file `font_path`.
+    /// The record is written under a temporary name and then moved into
+    /// place, so that concurrent processes never see a partial record.
+    pub fn put(&mut self, font_path: &Path, index: u32, record: &str) -> Result<()> {
+        let path = self.record_path(font_path, index)?;
+        self.save_file(&path, record.as_bytes())
+    }
+
+    /// Write out the index of font digests, if we've learned anything new.
+    pub fn save_index(&mut self) -> Result<()> {
+        if !self.digests_changed {
+            return Ok(());
+        }
+
+        let mut text = String::new();
+
+        for (path, &(stamp, digest)) in &self.digests {
+            // A path with a newline would corrupt the index, and can't be
+            // represented as text; such fonts just don't get their digests
+            // saved.
+            let path = match path.to_str() {
+                Some(p) if !p.contains('\n') => p,
+                _ => continue,
+            };
+
+            text.push_str(&format!("{} {} {} {}\n", stamp.size, stamp.mtime, digest.to_string(), path));
+        }
+
+        let index_path = self.cache_dir.join(INDEX_NAME);
+        self.save_file(&index_path, text.as_bytes())?;
+        self.digests_changed = false;
+        Ok(())
+    }
+
+    fn save_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut templ = self.cache_dir.clone();
+        templ.push("font_XXXXXX");
+
+        let temp_path = {
+            let mut temp_dest = mkstemp::TempFile::new(&templ.to_string_lossy(), false)?;
+            temp_dest.write_all(data)?;
+            temp_dest.path().to_owned()
+        };
+
+        fs::rename(&temp_path, path).map_err(|e| e.into())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::File;
+    use std::io::Write;
+    use self::tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new("tectonic_font_cache").unwrap();
+        let font = dir.path().join("font.otf");
+        File::create(&font).unwrap().write_all(b"not really a font").unwrap();
+
+        let mut cache = FontCache::open(dir.path());
+        assert_eq!(cache.get(&font, 0), None);
+        cache.put(&font, 0, "ps Font-Regular\n").unwrap();
+        cache.save_index().unwrap();
+        assert_eq!(cache.get(&font, 1), None);
+
+        let mut cache = FontCache::open(dir.path());
+        assert!(cache.digests.contains_key(&font));
+        assert_eq!(cache.get(&font, 0), Some("ps Font-Regular\n".to_owned()));
+
+        // A font with different contents has a different digest, even if
+        // its stamp in the index still matches.
+        File::create(&font).unwrap().write_all(b"not really a font, but longer").unwrap();
+        assert_eq!(cache.get(&font, 0), None);
+    }
+}
@@ -0,0 +1,664 @@
+// src/engines/makeindex.rs -- a native makeindex-compatible index processor.
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Unlike the other engines, this one is implemented in pure Rust. It reads
+//! the `\indexentry` lines that LaTeX writes to a `.idx` file and produces a
+//! `.ind` file in the format of makeindex's default style, along with an
+//! `.ilg` transcript. It supports the commonly used parts of the makeindex
+//! input syntax: subentries separated by `!` (up to three levels), sort keys
+//! given with `@`, page encapsulators given with `|` (including `|see{...}`
+//! and explicit page ranges with `|(` and `|)`), and the `"` quote
+//! character. Custom index styles are not supported.
+
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use errors::{ErrorKind, Result};
+use io::{IoProvider, IoStack, OpenResult};
+use status::StatusBackend;
+use super::IoEventBackend;
+use super::tex::TexResult;
+
+
+/// Implicit page ranges are formed from runs of at least this many
+/// consecutive pages.
+const MIN_IMPLICIT_RANGE: usize = 3;
+
+/// The maximum nesting depth of index entries.
+const MAX_LEVELS: usize = 3;
+
+
+pub struct MakeindexEngine {
+}
+
+
+impl MakeindexEngine {
+    pub fn new () -> MakeindexEngine {
+        MakeindexEngine {}
+    }
+
+    pub fn process (&mut self, io: &mut IoStack,
+                    events: &mut IoEventBackend,
+                    status: &mut StatusBackend, idx: &str) -> Result<TexResult> {
+        let idx_path = Path::new(idx);
+        let ind = idx_path.with_extension("ind");
+        let ilg = idx_path.with_extension("ilg");
+
+        let mut log = Transcript::new();
+        log.line("This is the Tectonic makeindex-compatible index processor.");
+
+        // Read the input.
+
+        let mut ih = match io.input_open_name(idx_path.as_os_str(), status) {
+            OpenResult::Ok(ih) => ih,
+            OpenResult::NotAvailable => {
+                return Err(ErrorKind::Msg(format!("couldn't open index file \"{}\"", idx)).into());
+            },
+            OpenResult::Err(e) => return Err(e),
+        };
+
+        events.input_opened(ih.name(), ih.origin());
+
+        if let Some(p) = ih.provider() {
+            events.input_provider(ih.name(), p);
+        }
+
+        let mut data = Vec::new();
+        ih.read_to_end(&mut data)?;
+        let (name, digest) = ih.into_name_digest();
+        events.input_closed(name, digest);
+
+        // Process it.
+
+        let text = String::from_utf8_lossy(&data);
+        let mut entries = parse_idx(&text, idx, &mut log);
+        log.line("Sorting entries....");
+        sort_entries(&mut entries);
+        log.line("done.");
+        let output = generate_ind(&entries, &mut log);
+        log.line(format!("Generating output file {}....done ({} lines written, {} warnings).",
+                         ind.display(), output.matches('\n').count(), log.warnings));
+        log.line(format!("Output written in {}.", ind.display()));
+        log.line(format!("Transcript written in {}.", ilg.display()));
+
+        // Write the outputs.
+
+        write_output(io, events, ind.as_os_str(), output.as_bytes())?;
+        write_output(io, events, ilg.as_os_str(), log.text.as_bytes())?;
+
+        Ok(if log.warnings > 0 { TexResult::Warnings } else { TexResult::Spotless })
+    }
+}
+
+
+fn write_output(io: &mut IoStack, events: &mut IoEventBackend, name: &OsStr, data: &[u8]) -> Result<()> {
+    let mut oh = match io.output_open_name(name) {
+        OpenResult::Ok(oh) => oh,
+        OpenResult::NotAvailable => {
+            return Err(ErrorKind::Msg(format!("couldn't open output file \"{}\"",
+                                              name.to_string_lossy())).into());
+        },
+        OpenResult::Err(e) => return Err(e),
+    };
+
+    events.output_opened(oh.name());
+    oh.write_all(data)?;
+    oh.flush()?;
+    let (name, digest) = oh.into_name_digest();
+    events.output_closed(name, digest);
+    Ok(())
+}
+
+
+/// The contents of the `.ilg` file.
+struct Transcript {
+    text: String,
+    warnings: usize,
+}
+
+impl Transcript {
+    fn new() -> Transcript {
+        Transcript { text: String::new(), warnings: 0 }
+    }
+
+    fn line<S: AsRef<str>>(&mut self, text: S) {
+        self.text.push_str(text.as_ref());
+        self.text.push('\n');
+    }
+
+    fn warn<S: AsRef<str>>(&mut self, text: S) {
+        self.warnings += 1;
+        self.line(format!("## Warning: {}", text.as_ref()));
+    }
+}
+
+
+/// One level of an index entry: the key it is sorted by, and the text that
+/// is printed for it.
+#[derive(Clone,Debug,Eq,PartialEq)]
+struct Level {
+    sort: String,
+    display: String,
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum PageKind {
+    Single,
+    RangeOpen,
+    RangeClose,
+}
+
+#[derive(Clone,Debug)]
+struct PageRef {
+    page: String,
+    order: (u8, u32),
+    encap: String,
+    kind: PageKind,
+}
+
+#[derive(Clone,Debug)]
+struct Entry {
+    levels: Vec<Level>,
+    pages: Vec<PageRef>,
+}
+
+
+/// Parse the contents of a `.idx` file, merging references to the same
+/// entry.
+fn parse_idx(text: &str, idx: &str, log: &mut Transcript) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut accepted = 0;
+    let mut rejected = 0;
+    let mut rest = text;
+
+    // The line number of the entry we're looking at, and the offset in
+    // `text` up to which we've counted lines.
+    let mut line = 1;
+    let mut counted = 0;
+
+    log.line(format!("Scanning input file {}....", idx));
+
+    while let Some(start) = rest.find("\\indexentry") {
+        let offset = text.len() - rest.len() + start;
+        line += text[counted..offset].matches('\n').count();
+        counted = offset;
+        rest = &rest[start + "\\indexentry".len()..];
+
+        let (key, page) = match read_group(&mut rest).and_then(|k| read_group(&mut rest).map(|p| (k, p))) {
+            Some(x) => x,
+            None => {
+                log.warn(format!("{}:{}: malformed \\indexentry; rejected.", idx, line));
+                rejected += 1;
+                continue;
+            }
+        };
+
+        match parse_entry(&key, page.trim()) {
+            Ok((levels, pageref)) => {
+                accepted += 1;
+
+                if let Some(e) = entries.iter_mut().find(|e| e.levels == levels) {
+                    e.pages.push(pageref);
+                    continue;
+                }
+
+                entries.push(Entry { levels: levels, pages: vec![pageref] });
+            },
+            Err(msg) => {
+                log.warn(format!("{}:{}: {}; rejected.", idx, line, msg));
+                rejected += 1;
+            }
+        }
+    }
+
+    log.line(format!("done ({} entries accepted, {} rejected).", accepted, rejected));
+    entries
+}
+
+
+/// Read a brace-delimited argument from the start of `text`, skipping
+/// leading whitespace. Braces that are quoted with `"` or escaped with `\`
+/// don't count towards the nesting depth. Returns None if there is no
+/// well-formed group.
+fn read_group(text: &mut &str) -> Option<String> {
+    let s: &str = *text;
+    let s = s.trim_left();
+
+    if !s.starts_with('{') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut chars = s.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' | '\\' => { chars.next(); },
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    *text = &s[i + 1..];
+                    return Some(s[1..i].to_owned());
+                }
+            },
+            _ => {},
+        }
+    }
+
+    None
+}
+
+
+/// Split the argument of `\indexentry` into its levels and a page reference.
+fn parse_entry(key: &str, page: &str) -> ::std::result::Result<(Vec<Level>, PageRef), String> {
+    // Tokenize, keeping track of which characters are quoted.
+
+    let mut toks = Vec::new();
+    let mut chars = key.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => match chars.next() {
+                Some(q) => toks.push((q, true)),
+                None => return Err("dangling quote character".to_owned()),
+            },
+            '\\' => {
+                toks.push(('\\', false));
+
+                if let Some(e) = chars.next() {
+                    toks.push((e, true));
+                }
+            },
+            c => toks.push((c, false)),
+        }
+    }
+
+    let text = |t: &[(char, bool)]| t.iter().map(|&(c, _)| c).collect::<String>();
+
+    let (actual, encap) = match toks.iter().position(|&t| t == ('|', false)) {
+        Some(i) => (&toks[..i], text(&toks[i + 1..])),
+        None => (&toks[..], String::new()),
+    };
+
+    let (kind, encap) = if encap.starts_with('(') {
+        (PageKind::RangeOpen, encap[1..].to_owned())
+    } else if encap.starts_with(')') {
+        (PageKind::RangeClose, encap[1..].to_owned())
+    } else {
+        (PageKind::Single, encap)
+    };
+
+    let mut levels = Vec::new();
+
+    for part in actual.split(|&t| t == ('!', false)) {
+        let level = match part.iter().position(|&t| t == ('@', false)) {
+            Some(i) => Level { sort: text(&part[..i]), display: text(&part[i + 1..]) },
+            None => Level { sort: text(part), display: text(part) },
+        };
+
+        if level.sort.trim().is_empty() {
+            return Err("empty index key".to_owned());
+        }
+
+        levels.push(level);
+    }
+
+    if levels.len() > MAX_LEVELS {
+        return Err(format!("more than {} levels of subentries", MAX_LEVELS));
+    }
+
+    let order = match page_order(page) {
+        Some(o) => o,
+        None => return Err(format!("illegal page number \"{}\"", page)),
+    };
+
+    Ok((levels, PageRef { page: page.to_owned(), order: order, encap: encap, kind: kind }))
+}
+
+
+/// Compute the sort order of a page number, following makeindex's default
+/// precedence: lowercase roman numerals, arabic numerals, lowercase letters,
+/// uppercase roman numerals, uppercase letters.
+fn page_order(page: &str) -> Option<(u8, u32)> {
+    if page.is_empty() {
+        return None;
+    }
+
+    if page.chars().all(|c| c.is_digit(10)) {
+        return page.parse().ok().map(|n| (1, n));
+    }
+
+    if let Some(n) = roman_value(page, "ivxlcdm") {
+        return Some((0, n));
+    }
+
+    if let Some(n) = roman_value(page, "IVXLCDM") {
+        return Some((3, n));
+    }
+
+    let mut chars = page.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_lowercase() => Some((2, c as u32)),
+        (Some(c), None) if c.is_uppercase() => Some((4, c as u32)),
+        _ => None,
+    }
+}
+
+
+fn roman_value(text: &str, digits: &str) -> Option<u32> {
+    const VALUES: [u32; 7] = [1, 5, 10, 50, 100, 500, 1000];
+    let mut total: u32 = 0;
+    let mut prev = 0;
+
+    // Nonsense like "iiiiiiv" would take the total below zero, and very
+    // long numerals past the top of the range; neither is a page number.
+    for c in text.chars().rev() {
+        let v = match digits.find(c) {
+            Some(i) => VALUES[i],
+            None => return None,
+        };
+
+        if v < prev {
+            total = match total.checked_sub(v) {
+                Some(t) => t,
+                None => return None,
+            };
+        } else {
+            total = match total.checked_add(v) {
+                Some(t) => t,
+                None => return None,
+            };
+            prev = v;
+        }
+    }
+
+    Some(total)
+}
+
+
+/// Index entries are divided into groups -- symbols, numbers, and one group
+/// for each initial letter -- that are separated by `\indexspace` in the
+/// output.
+#[derive(Clone,Copy,Debug,Eq,Ord,PartialEq,PartialOrd)]
+enum Group {
+    Symbols,
+    Numbers,
+    Letter(char),
+}
+
+fn group_of(key: &str) -> Group {
+    let key = key.trim();
+
+    if key.chars().all(|c| c.is_digit(10)) {
+        return Group::Numbers;
+    }
+
+    match key.chars().next() {
+        Some(c) if c.is_alphabetic() => Group::Letter(c.to_lowercase().next().unwrap_or(c)),
+        _ => Group::Symbols,
+    }
+}
+
+fn compare_levels(a: &Level, b: &Level) -> Ordering {
+    let ga = group_of(&a.sort);
+    let gb = group_of(&b.sort);
+
+    ga.cmp(&gb)
+        .then_with(|| if ga == Group::Numbers {
+            a.sort.trim().parse::<u64>().ok().cmp(&b.sort.trim().parse::<u64>().ok())
+        } else {
+            Ordering::Equal
+        })
+        .then_with(|| a.sort.to_lowercase().cmp(&b.sort.to_lowercase()))
+        .then_with(|| a.sort.cmp(&b.sort))
+        .then_with(|| a.display.cmp(&b.display))
+}
+
+fn sort_entries(entries: &mut Vec<Entry>) {
+    entries.sort_by(|a, b| {
+        for (la, lb) in a.levels.iter().zip(b.levels.iter()) {
+            let c = compare_levels(la, lb);
+
+            if c != Ordering::Equal {
+                return c;
+            }
+        }
+
+        a.levels.len().cmp(&b.levels.len())
+    });
+
+    for e in entries.iter_mut() {
+        // This sort is stable, so references to the same page stay in
+        // input order; in particular a range opened and closed on the same
+        // page works out.
+        e.pages.sort_by_key(|p| p.order);
+    }
+}
+
+
+/// Generate the contents of the `.ind` file from sorted entries.
+fn generate_ind(entries: &[Entry], log: &mut Transcript) -> String {
+    const ITEMS: [&'static str; MAX_LEVELS] = ["\\item", "\\subitem", "\\subsubitem"];
+
+    let mut out = String::from("\\begin{theindex}\n");
+    let mut prev: Option<&[Level]> = None;
+
+    for e in entries.iter() {
+        let mut first_new = 0;
+
+        if let Some(p) = prev {
+            if group_of(&p[0].sort) != group_of(&e.levels[0].sort) {
+                out.push_str("\n\n  \\indexspace\n");
+            } else {
+                while first_new < p.len() && first_new < e.levels.len() &&
+                    p[first_new] == e.levels[first_new] {
+                    first_new += 1;
+                }
+            }
+        }
+
+        for depth in first_new..e.levels.len() {
+            out.push('\n');
+
+            for _ in 0..depth + 1 {
+                out.push_str("  ");
+            }
+
+            out.push_str(ITEMS[depth]);
+            out.push(' ');
+            out.push_str(&e.levels[depth].display);
+        }
+
+        let name = e.levels.iter().map(|l| l.sort.as_str()).collect::<Vec<_>>().join("!");
+
+        for p in format_pages(&e.pages, &name, log) {
+            out.push_str(", ");
+            out.push_str(&p);
+        }
+
+        prev = Some(&e.levels);
+    }
+
+    out.push_str("\n\n\\end{theindex}\n");
+    out
+}
+
+
+fn encapsulate(encap: &str, text: String) -> String {
+    if encap.is_empty() {
+        text
+    } else {
+        format!("\\{}{{{}}}", encap, text)
+    }
+}
+
+
+/// Turn an entry's sorted page references into the list of items to print,
+/// merging duplicates and forming page ranges.
+fn format_pages(pages: &[PageRef], name: &str, log: &mut Transcript) -> Vec<String> {
+    let mut used = vec![false; pages.len()];
+    let mut items = Vec::new();
+
+    for i in 0..pages.len() {
+        if used[i] {
+            continue;
+        }
+
+        used[i] = true;
+        let p = &pages[i];
+
+        match p.kind {
+            PageKind::RangeClose => {
+                log.warn(format!("range closed at page {} for \"{}\" was never opened.", p.page, name));
+            },
+            PageKind::RangeOpen => {
+                // Everything with the same encapsulator up to the matching
+                // close is swallowed by the range.
+
+                let mut end = None;
+
+                for j in i + 1..pages.len() {
+                    if used[j] || pages[j].encap != p.encap {
+                        continue;
+                    }
+
+                    used[j] = true;
+
+                    if pages[j].kind == PageKind::RangeClose {
+                        end = Some(j);
+                        break;
+                    }
+                }
+
+                match end {
+                    Some(j) if pages[j].order != p.order => {
+                        items.push(encapsulate(&p.encap, format!("{}--{}", p.page, pages[j].page)));
+                    },
+                    Some(_) => items.push(encapsulate(&p.encap, p.page.clone())),
+                    None => {
+                        log.warn(format!("range opened at page {} for \"{}\" was never closed.", p.page, name));
+                        items.push(encapsulate(&p.encap, p.page.clone()));
+                    },
+                }
+            },
+            PageKind::Single => {
+                // Gather a run of consecutive pages with the same
+                // encapsulator, skipping duplicates.
+
+                let mut last = i;
+                let mut count = 1;
+
+                for j in i + 1..pages.len() {
+                    if used[j] || pages[j].kind != PageKind::Single || pages[j].encap != p.encap {
+                        continue;
+                    }
+
+                    let (rank, n) = pages[last].order;
+
+                    if pages[j].order == (rank, n) {
+                        used[j] = true;
+                    } else if n.checked_add(1).map(|next| (rank, next)) == Some(pages[j].order) {
+                        used[j] = true;
+                        last = j;
+                        count += 1;
+                    } else {
+                        break;
+                    }
+                }
+
+                if count >= MIN_IMPLICIT_RANGE {
+                    items.push(encapsulate(&p.encap, format!("{}--{}", p.page, pages[last].page)));
+                } else {
+                    // Too short to be a range; give back the pages that we
+                    // didn't print.
+                    items.push(encapsulate(&p.encap, p.page.clone()));
+
+                    if count == 2 {
+                        items.push(encapsulate(&p.encap, pages[last].page.clone()));
+                    }
+                }
+            },
+        }
+    }
+
+    items
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(idx: &str) -> (String, usize) {
+        let mut log = Transcript::new();
+        let mut entries = parse_idx(idx, "test.idx", &mut log);
+        sort_entries(&mut entries);
+        let ind = generate_ind(&entries, &mut log);
+        (ind, log.warnings)
+    }
+
+    #[test]
+    fn basic_index() {
+        let (ind, warnings) = run("\\indexentry{beta}{3}\n\
+                                   \\indexentry{Alpha}{7}\n\
+                                   \\indexentry{beta}{4}\n\
+                                   \\indexentry{beta}{5}\n\
+                                   \\indexentry{alpha!sub@\\emph{sub}}{2}\n\
+                                   \\indexentry{Alpha}{1}\n\
+                                   \\indexentry{beta}{5}\n\
+                                   \\indexentry{gamma|textbf}{10}\n\
+                                   \\indexentry{gamma|see{beta}}{10}\n\
+                                   \\indexentry{delta|(}{2}\n\
+                                   \\indexentry{delta}{3}\n\
+                                   \\indexentry{delta|)}{6}\n\
+                                   \\indexentry{\"!bang}{1}\n\
+                                   \\indexentry{12}{8}\n");
+
+        assert_eq!(warnings, 0);
+        assert_eq!(ind, "\\begin{theindex}\n\
+                         \n  \\item !bang, 1\
+                         \n\n  \\indexspace\n\
+                         \n  \\item 12, 8\
+                         \n\n  \\indexspace\n\
+                         \n  \\item Alpha, 1, 7\
+                         \n  \\item alpha\
+                         \n    \\subitem \\emph{sub}, 2\
+                         \n\n  \\indexspace\n\
+                         \n  \\item beta, 3--5\
+                         \n\n  \\indexspace\n\
+                         \n  \\item delta, 2--6\
+                         \n\n  \\indexspace\n\
+                         \n  \\item gamma, \\textbf{10}, \\see{beta}{10}\
+                         \n\n\\end{theindex}\n");
+    }
+
+    #[test]
+    fn rejections() {
+        let (ind, warnings) = run("\\indexentry{ok}{1}\n\
+                                   \\indexentry{bad}{1-2}\n\
+                                   \\indexentry{a!b!c!d}{1}\n\
+                                   \\indexentry{open|(}{4}\n");
+
+        assert_eq!(warnings, 3);
+        assert_eq!(ind, "\\begin{theindex}\n\
+                         \n  \\item ok, 1\
+                         \n  \\item open, 4\
+                         \n\n\\end{theindex}\n");
+    }
+
+    #[test]
+    fn overflows() {
+        let (ind, warnings) = run("\\indexentry{big}{4294967295}\n\
+                                   \\indexentry{big}{a}\n\
+                                   \\indexentry{bad}{iiiiiiv}\n");
+
+        assert_eq!(warnings, 1);
+        assert!(ind.contains("\\item big, 4294967295, a"));
+    }
+}
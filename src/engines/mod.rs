@@ -3,38 +3,59 @@
 // Licensed under the MIT License.
 
 //! The Engines module provides access to the various processing backends used
-//! by Tectonic: bibtex, TeX, and xdvipdfmx. The API for each of these is defined
-//! in a sub-module with the corresponding name.
+//! by Tectonic: bibtex, makeindex, TeX, and xdvipdfmx. The API for each of
+//! these is defined in a sub-module with the corresponding name.
 //!
 //! Due to the way Rust's visibility rules work, this module contains a
 //! substantial private API that defines the interface between Tectonic's Rust
 //! code and the C/C++ code that the backends are (currently) implemented in.
 
 use flate2::{Compression, GzBuilder};
-use flate2::read::{GzDecoder};
 use md5::{Md5, Digest};
 use libc;
-use std::ffi::{CStr, OsStr, OsString};
+use std::collections::HashSet;
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::io::{Read, SeekFrom, Write};
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
-use std::{io, ptr, slice};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::UNIX_EPOCH;
+use std::{io, mem, ptr, slice};
 
+use diagnostics::{Diagnostic, LogParser};
 use digest::DigestData;
 use errors::{Error, ErrorKind, Result};
+use io::gzip::open_gzip;
 use io::{InputOrigin, IoProvider, InputFeatures, InputHandle, OpenResult, OutputHandle};
-use status::StatusBackend;
+use status::{ProgressUnit, StatusBackend};
+use self::font_cache::FontCache;
+use self::tex::TexHooks;
 
 
 // Public sub-modules and reexports.
 
+pub mod font_cache;
 pub mod tex;
 pub mod xdvipdfmx;
+pub mod xdvisvg;
+pub mod xdvipng;
+pub mod xdvitxt;
 pub mod bibtex;
+pub mod makeindex;
 
 pub use self::tex::TexEngine;
 pub use self::xdvipdfmx::XdvipdfmxEngine;
+pub use self::xdvisvg::XdvisvgEngine;
+pub use self::xdvipng::XdvipngEngine;
+pub use self::xdvitxt::XdvitxtEngine;
 pub use self::bibtex::BibtexEngine;
+pub use self::makeindex::MakeindexEngine;
+
+
+/// The name under which the pages that TeX ships out are reported as
+/// progress.
+const PAGES_TASK: &'static str = "pages";
 
 
 // Now, the public API.
@@ -64,9 +85,23 @@ pub trait IoEventBackend {
     /// This function is called when a file is opened for input.
     fn input_opened(&mut self, _name: &OsStr, _origin: InputOrigin) {}
 
+    /// This function is called after `input_opened` if the I/O layer knows
+    /// the name of the provider that supplied the file. This lets the driver
+    /// program tell, e.g., whether a file came from the local filesystem or
+    /// from the bundle.
+    fn input_provider(&mut self, _name: &OsStr, _provider: &str) {}
+
+    /// This function is called after `input_opened` if the file lives on
+    /// the filesystem and the I/O layer knows its absolute path.
+    fn input_abspath(&mut self, _name: &OsStr, _path: &Path) {}
+
     /// This function is called when the "primary input" stream is opened.
     fn primary_input_opened(&mut self, _origin: InputOrigin) {}
 
+    /// This function is called after `primary_input_opened` if the I/O layer
+    /// knows the absolute path of the primary input file.
+    fn primary_input_abspath(&mut self, _path: &Path) {}
+
     /// This function is called when the engine attempted to open a file of
     /// the specified name but it was not available.
     fn input_not_available(&mut self, _name: &OsStr) {}
@@ -90,6 +125,38 @@ impl NoopIoEventBackend {
 impl IoEventBackend for NoopIoEventBackend { }
 
 
+/// A CancellationToken lets a program stop the engines while they're
+/// running, from another thread or a signal handler. Clones of a token share
+/// their state, so one clone can be given to the engines while another is
+/// kept to trigger it. The engines check the token periodically; once it has
+/// been triggered, they stop promptly with an error of kind
+/// `ErrorKind::Cancelled`.
+#[derive(Clone,Debug,Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Ask any engines watching this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Get the flag underlying this token. Setting it is equivalent to
+    /// calling `cancel()`. The flag is never freed, so that the reference
+    /// can be stashed somewhere that a signal handler can reach.
+    pub fn leak_flag(&self) -> &'static AtomicBool {
+        unsafe { &*Arc::into_raw(self.0.clone()) }
+    }
+}
+
+
 // Now, the private interfaces for executing various engines implemented in C/C++.
 
 /// During the execution of a C/C++ engine, an ExecutionState structure holds
@@ -101,6 +168,15 @@ struct ExecutionState<'a, I: 'a + IoProvider>  {
     io: &'a mut I,
     events: &'a mut IoEventBackend,
     status: &'a mut StatusBackend,
+    cancel: Option<CancellationToken>,
+    max_pages: Option<u64>,
+    pages_shipped: u64,
+    log_handle: *const OutputHandle,
+    log_parser: LogParser,
+    missing_chars: HashSet<(String, u32)>,
+    font_cache: Option<&'a mut FontCache>,
+    font_record: Option<CString>,
+    hooks: Option<&'a mut TexHooks>,
     input_handles: Vec<Box<InputHandle>>,
     output_handles: Vec<Box<OutputHandle>>,
 }
@@ -108,11 +184,20 @@ struct ExecutionState<'a, I: 'a + IoProvider>  {
 
 impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
     pub fn new (io: &'a mut I, events: &'a mut IoEventBackend,
-                status: &'a mut StatusBackend) -> ExecutionState<'a, I> {
+                status: &'a mut StatusBackend, cancel: Option<CancellationToken>) -> ExecutionState<'a, I> {
         ExecutionState {
             io: io,
             events: events,
             status: status,
+            cancel: cancel,
+            max_pages: None,
+            pages_shipped: 0,
+            log_handle: ptr::null(),
+            log_parser: LogParser::new(),
+            missing_chars: HashSet::new(),
+            font_cache: None,
+            font_record: None,
+            hooks: None,
             output_handles: Vec::new(),
             input_handles: Vec::new(),
         }
@@ -120,11 +205,55 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
 
     // Helpers.
 
-    fn input_open_name_format(&mut self, name: &OsStr, format: FileFormat) -> OpenResult<InputHandle> {
+    /// Get the diagnostics parsed from the engine's log file.
+    fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        mem::replace(&mut self.log_parser, LogParser::new()).finish()
+    }
+
+    /// Pass along a diagnostic that the engine reported to us directly,
+    /// attaching the location in the input given by the C code.
+    fn report_diagnostic(&mut self, mut diag: Diagnostic, file_name: *const i8, line: libc::c_int) {
+        if !file_name.is_null() {
+            diag.file = Some(unsafe { CStr::from_ptr(file_name) }.to_string_lossy().into_owned());
+
+            if line > 0 {
+                diag.line = Some(line as u32);
+            }
+        }
+
+        self.status.report_diagnostic(&diag);
+        self.log_parser.record(diag);
+    }
+
+    /// Save the font cache's index of digests, if we have a cache.
+    fn save_font_cache(&mut self) {
+        if let Some(ref mut cache) = self.font_cache {
+            if let Err(e) = cache.save_index() {
+                tt_warning!(self.status, "failed to save the index of the font cache"; e);
+            }
+        }
+    }
+
+    /// Take down the report of the pages shipped out, now that the engine
+    /// is done.
+    fn end_page_progress(&mut self) {
+        self.status.end_progress(PAGES_TASK);
+    }
+
+    /// Whether the engine shipped out more pages than it was allowed to.
+    fn page_limit_exceeded(&self) -> bool {
+        match self.max_pages {
+            Some(limit) => self.pages_shipped > limit,
+            None => false,
+        }
+    }
+
+    fn input_open_name_format(&mut self, name: &OsStr, format: FileFormat)
+                              -> OpenResult<(InputHandle, Option<PathBuf>)> {
         let r = if let FileFormat::Format = format {
-            self.io.input_open_format(name, self.status)
+            self.io.input_open_format(name, self.status).map(|h| (h, None))
         } else {
-            self.io.input_open_name(name, self.status)
+            self.io.input_open_name_with_abspath(name, self.status)
         };
 
         let path = Path::new(name);
@@ -148,11 +277,11 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             ext.set_extension(e);
 
             if let FileFormat::Format = format {
-                if let r @ OpenResult::Ok(_) = self.io.input_open_format(ext.as_ref(), self.status) {
-                    return r
+                if let OpenResult::Ok(h) = self.io.input_open_format(ext.as_ref(), self.status) {
+                    return OpenResult::Ok((h, None))
                 }
             } else {
-                if let r @ OpenResult::Ok(_) = self.io.input_open_name(ext.as_ref(), self.status) {
+                if let r @ OpenResult::Ok(_) = self.io.input_open_name_with_abspath(ext.as_ref(), self.status) {
                     return r
                 }
             }
@@ -161,7 +290,7 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
     }
 
     fn input_open_name_format_gz(&mut self, name: &OsStr, format: FileFormat,
-                                 is_gz: bool) -> OpenResult<InputHandle> {
+                                 is_gz: bool) -> OpenResult<(InputHandle, Option<PathBuf>)> {
         let base = self.input_open_name_format(name, format);
 
         if !is_gz {
@@ -169,18 +298,40 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
         }
 
         match base {
-            OpenResult::Ok(ih) => {
+            OpenResult::Ok((ih, path)) => {
                 let origin = ih.origin();
-
-                match GzDecoder::new(ih.into_inner()) {
-                    Ok(dr) => OpenResult::Ok(InputHandle::new(name, dr, origin)),
-                    Err(e) => OpenResult::Err(e.into()),
+                let provider = ih.provider().map(|s| s.to_owned());
+
+                match open_gzip(ih.into_inner()) {
+                    Ok(dr) => {
+                        let mut gzih = InputHandle::new(name, dr, origin);
+                        gzih.set_provider(provider);
+                        OpenResult::Ok((gzih, path))
+                    },
+                    Err(e) => OpenResult::Err(e),
                 }
             },
             _ => base
         }
     }
 
+    fn note_input_opened(&mut self, ih: &InputHandle, path: Option<PathBuf>) {
+        // the file name may have had an extension added, so we use ih.name() here:
+        self.events.input_opened(ih.name(), ih.origin());
+
+        if let Some(ref mut hooks) = self.hooks {
+            hooks.file_opened(ih.name());
+        }
+
+        if let Some(p) = ih.provider() {
+            self.events.input_provider(ih.name(), p);
+        }
+
+        if let Some(p) = path {
+            self.events.input_abspath(ih.name(), &p);
+        }
+    }
+
     // These functions are called from C through the bridge API.
 
     fn get_file_md5(&mut self, name: &OsStr, dest: &mut [u8]) -> bool {
@@ -191,8 +342,8 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
         // truncated SHA256 digest as the MD5 ... but it seems like a better
         // idea to just go and read the file.
 
-        let mut ih = match self.input_open_name_format(name, FileFormat::Tex) {
-            OpenResult::Ok(ih) => ih,
+        let (mut ih, path) = match self.input_open_name_format(name, FileFormat::Tex) {
+            OpenResult::Ok(r) => r,
             OpenResult::NotAvailable => {
                 tt_warning!(self.status, "could not calculate MD5 of file \"{}\": it does not exist",
                             name.to_string_lossy());
@@ -205,7 +356,7 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             },
         };
 
-        self.events.input_opened(ih.name(), ih.origin());
+        self.note_input_opened(&ih, path);
 
         // No canned way to stream the whole file into the digest, it seems.
 
@@ -255,9 +406,16 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             oh = OutputHandle::new(&name, GzBuilder::new().write(oh.into_inner(), Compression::Default));
         }
 
+        let is_log = Path::new(oh.name()).extension().map(|e| e == "log").unwrap_or(false);
         self.events.output_opened(oh.name());
         self.output_handles.push(Box::new(oh));
-        &*self.output_handles[self.output_handles.len()-1]
+        let handle: *const OutputHandle = &*self.output_handles[self.output_handles.len()-1];
+
+        if is_log {
+            self.log_handle = handle;
+        }
+
+        handle
     }
 
     fn output_open_stdout(&mut self) -> *const OutputHandle {
@@ -276,6 +434,11 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
     }
 
     fn output_write(&mut self, handle: *mut OutputHandle, buf: &[u8]) -> bool {
+        if handle as *const OutputHandle == self.log_handle {
+            self.log_parser.feed(buf);
+            self.status.engine_log(buf);
+        }
+
         let rhandle: &mut OutputHandle = unsafe { &mut *handle };
         let result = rhandle.write_all(buf);
 
@@ -309,6 +472,10 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             let p: *const OutputHandle = &*self.output_handles[i];
 
             if p == handle {
+                if p == self.log_handle {
+                    self.log_handle = ptr::null();
+                }
+
                 let mut oh = self.output_handles.swap_remove(i);
                 if let Err(e) = oh.flush() {
                     tt_warning!(self.status, "error when closing output {}", oh.name().to_string_lossy(); e.into());
@@ -324,8 +491,8 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
     }
 
     fn input_open(&mut self, name: &OsStr, format: FileFormat, is_gz: bool) -> *const InputHandle {
-        let ih = match self.input_open_name_format_gz(name, format, is_gz) {
-            OpenResult::Ok(ih) => ih,
+        let (ih, path) = match self.input_open_name_format_gz(name, format, is_gz) {
+            OpenResult::Ok(r) => r,
             OpenResult::NotAvailable => {
                 self.events.input_not_available(name);
                 return ptr::null();
@@ -336,15 +503,15 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             },
         };
 
-        // the file name may have had an extension added, so we use ih.name() here:
-        self.events.input_opened(ih.name(), ih.origin());
+        self.note_input_opened(&ih, path);
+
         self.input_handles.push(Box::new(ih));
         &*self.input_handles[self.input_handles.len()-1]
     }
 
     fn input_open_primary(&mut self) -> *const InputHandle {
-        let ih = match self.io.input_open_primary(self.status) {
-            OpenResult::Ok(ih) => ih,
+        let (ih, path) = match self.io.input_open_primary_with_abspath(self.status) {
+            OpenResult::Ok(r) => r,
             OpenResult::NotAvailable => {
                 tt_error!(self.status, "primary input not available (?!)");
                 return ptr::null();
@@ -356,6 +523,10 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
         };
 
         self.events.primary_input_opened(ih.origin());
+
+        if let Some(p) = path {
+            self.events.primary_input_abspath(&p);
+        }
         self.input_handles.push(Box::new(ih));
         &*self.input_handles[self.input_handles.len()-1]
     }
@@ -429,6 +600,7 @@ struct TectonicBridgeApi {
     issue_error: *const libc::c_void,
     get_file_md5: *const libc::c_void,
     get_data_md5: *const libc::c_void,
+    get_file_mtime: *const libc::c_void,
     output_open: *const libc::c_void,
     output_open_stdout: *const libc::c_void,
     output_putc: *const libc::c_void,
@@ -443,6 +615,58 @@ struct TectonicBridgeApi {
     input_getc: *const libc::c_void,
     input_ungetc: *const libc::c_void,
     input_close: *const libc::c_void,
+    check_cancelled: *const libc::c_void,
+    page_shipped: *const libc::c_void,
+    missing_char: *const libc::c_void,
+    font_substituted: *const libc::c_void,
+    font_cache_get: *const libc::c_void,
+    font_cache_put: *const libc::c_void,
+    special: *const libc::c_void,
+}
+
+/// A file to attach to the output PDF; see `tt_pdf_attachment_t` in
+/// **tectonic/core-bridge.h**.
+#[repr(C)]
+struct PdfAttachmentRecord {
+    name: *const i8,
+    data: *const u8,
+    len: libc::size_t,
+}
+
+/// Settings for the xdvipdfmx engine; see `tt_xdvipdfmx_config_t` in
+/// **tectonic/core-bridge.h**.
+#[repr(C)]
+struct XdvipdfmxConfig {
+    paperspec: *const i8,
+    compression_level: libc::c_int,
+    pdf_version: libc::c_int,
+    enable_png_predictors: libc::c_int,
+    pdfa: libc::c_int,
+    title: *const i8,
+    author: *const i8,
+    subject: *const i8,
+    keywords: *const i8,
+    xmp: *const u8,
+    xmp_len: libc::size_t,
+    reproducible: libc::c_int,
+    linearize: libc::c_int,
+    encrypt: libc::c_int,
+    owner_password: *const i8,
+    user_password: *const i8,
+    no_print: libc::c_int,
+    no_copy: libc::c_int,
+    attachments: *const PdfAttachmentRecord,
+    num_attachments: libc::c_int,
+}
+
+/// Settings for the xdvisvg engine; see `tt_xdvisvg_config_t` in
+/// **tectonic/core-bridge.h**.
+#[repr(C)]
+struct XdvisvgConfig {
+    paperspec: *const i8,
+    woff_fonts: libc::c_int,
+    png_dpi: libc::c_int,
+    text_output: libc::c_int,
 }
 
 extern {
@@ -450,8 +674,11 @@ extern {
     fn tt_set_int_variable(var_name: *const u8, value: libc::c_int) -> libc::c_int;
     //fn tt_set_string_variable(var_name: *const u8, value: *const i8) -> libc::c_int;
     fn tex_simple_main(api: *const TectonicBridgeApi, dump_name: *const i8, input_file_name: *const i8) -> libc::c_int;
-    fn dvipdfmx_simple_main(api: *const TectonicBridgeApi, dviname: *const i8, pdfname: *const i8) -> libc::c_int;
+    fn dvipdfmx_simple_main(api: *const TectonicBridgeApi, config: *const XdvipdfmxConfig,
+                            dviname: *const i8, pdfname: *const i8) -> libc::c_int;
     fn bibtex_simple_main(api: *const TectonicBridgeApi, aux_file_name: *const i8) -> libc::c_int;
+    fn xdvisvg_simple_main(api: *const TectonicBridgeApi, config: *const XdvisvgConfig,
+                           dviname: *const i8, svgstem: *const i8) -> libc::c_int;
 }
 
 
@@ -510,6 +737,28 @@ fn get_data_md5<'a, I: 'a + IoProvider>(_es: *mut ExecutionState<'a, I>, data: *
     0
 }
 
+fn get_file_mtime<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, path: *const i8, mtime: *mut libc::time_t) -> libc::c_int {
+    let es = unsafe { &mut *es };
+    let rpath = OsStr::from_bytes(unsafe { CStr::from_ptr(path) }.to_bytes());
+
+    let t = match es.io.input_mtime(rpath) {
+        OpenResult::Ok(t) => t,
+        OpenResult::NotAvailable => return 1,
+        OpenResult::Err(e) => {
+            tt_warning!(es.status, "couldn't get the modification time of \"{}\"", rpath.to_string_lossy(); e);
+            return 1;
+        },
+    };
+
+    let secs = match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as libc::time_t,
+        Err(e) => -(e.duration().as_secs() as libc::time_t),
+    };
+
+    unsafe { *mtime = secs; }
+    0
+}
+
 fn output_open<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, name: *const i8, is_gz: libc::c_int) -> *const libc::c_void {
     let es = unsafe { &mut *es };
     let rname = OsStr::from_bytes(unsafe { CStr::from_ptr(name) }.to_bytes());
@@ -680,6 +929,128 @@ fn input_close<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, handle: *
 }
 
 
+fn check_cancelled<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>) -> libc::c_int {
+    let es = unsafe { &mut *es };
+
+    if was_cancelled(&es.cancel) {
+        1
+    } else {
+        0
+    }
+}
+
+
+fn page_shipped<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, counts: *const i32) -> libc::c_int {
+    let es = unsafe { &mut *es };
+    let rcounts = unsafe { slice::from_raw_parts(counts, 10) };
+    es.pages_shipped += 1;
+    es.status.report_progress(PAGES_TASK, es.pages_shipped, None, ProgressUnit::Pages);
+
+    if let Some(ref mut hooks) = es.hooks {
+        hooks.page_shipped(es.pages_shipped, rcounts);
+    }
+
+    if es.page_limit_exceeded() {
+        1
+    } else {
+        0
+    }
+}
+
+
+fn special<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, text: *const u8, len: libc::size_t) -> libc::c_int {
+    let es = unsafe { &mut *es };
+    let rtext = unsafe { slice::from_raw_parts(text, len) };
+
+    let handled = match es.hooks {
+        Some(ref mut hooks) => hooks.special(rtext),
+        None => false,
+    };
+
+    if handled {
+        1
+    } else {
+        0
+    }
+}
+
+
+fn missing_char<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, font_name: *const i8, codepoint: i32,
+                                        file_name: *const i8, line: libc::c_int) {
+    let es = unsafe { &mut *es };
+    let font = unsafe { CStr::from_ptr(font_name) }.to_string_lossy();
+
+    // A missing character usually goes missing over and over again, so only
+    // mention it the first time.
+    if !es.missing_chars.insert((font.clone().into_owned(), codepoint as u32)) {
+        return;
+    }
+
+    let diag = Diagnostic::missing_character(&font, codepoint as u32);
+    es.report_diagnostic(diag, file_name, line);
+}
+
+
+fn font_substituted<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, requested: *const i8,
+                                            substitute: *const i8, file_name: *const i8, line: libc::c_int) {
+    let es = unsafe { &mut *es };
+    let requested = unsafe { CStr::from_ptr(requested) }.to_string_lossy();
+    let substitute = unsafe { CStr::from_ptr(substitute) }.to_string_lossy();
+
+    let diag = Diagnostic::font_substitution(&requested, &substitute);
+    es.report_diagnostic(diag, file_name, line);
+}
+
+
+fn font_cache_get<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, path: *const i8,
+                                          index: libc::c_int) -> *const i8 {
+    let es = unsafe { &mut *es };
+    let rpath = Path::new(OsStr::from_bytes(unsafe { CStr::from_ptr(path) }.to_bytes()));
+
+    let record = match es.font_cache {
+        Some(ref mut cache) => cache.get(rpath, index as u32),
+        None => None,
+    };
+
+    // The C code only needs the record until its next call, so we can keep
+    // it alive here.
+    es.font_record = record.and_then(|r| CString::new(r).ok());
+
+    match es.font_record {
+        Some(ref r) => r.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+
+fn font_cache_put<'a, I: 'a + IoProvider>(es: *mut ExecutionState<'a, I>, path: *const i8,
+                                          index: libc::c_int, record: *const i8) {
+    let es = unsafe { &mut *es };
+    let rpath = Path::new(OsStr::from_bytes(unsafe { CStr::from_ptr(path) }.to_bytes()));
+    let rrecord = unsafe { CStr::from_ptr(record) }.to_string_lossy();
+
+    let result = match es.font_cache {
+        Some(ref mut cache) => cache.put(rpath, index as u32, &rrecord),
+        None => return,
+    };
+
+    // If saving fails once, it'll probably keep failing, so give up on the
+    // cache rather than complaining about every font.
+    if let Err(e) = result {
+        tt_warning!(es.status, "failed to save information about font \"{}\" in the cache",
+                    rpath.display(); e);
+        es.font_cache = None;
+    }
+}
+
+
+/// Determine whether an engine was stopped by its cancellation token. The
+/// engines use this to tell a cancellation apart from other fatal errors.
+fn was_cancelled(cancel: &Option<CancellationToken>) -> bool {
+    cancel.as_ref().map(|t| t.is_cancelled()).unwrap_or(false)
+}
+
+
 // All of these entry points are used to populate the bridge API struct:
 
 impl TectonicBridgeApi {
@@ -691,6 +1062,7 @@ impl TectonicBridgeApi {
             issue_error: issue_error::<'a, I> as *const libc::c_void,
             get_file_md5: get_file_md5::<'a, I> as *const libc::c_void,
             get_data_md5: get_data_md5::<'a, I> as *const libc::c_void,
+            get_file_mtime: get_file_mtime::<'a, I> as *const libc::c_void,
             output_open: output_open::<'a, I> as *const libc::c_void,
             output_open_stdout: output_open_stdout::<'a, I> as *const libc::c_void,
             output_putc: output_putc::<'a, I> as *const libc::c_void,
@@ -705,6 +1077,13 @@ impl TectonicBridgeApi {
             input_getc: input_getc::<'a, I> as *const libc::c_void,
             input_ungetc: input_ungetc::<'a, I> as *const libc::c_void,
             input_close: input_close::<'a, I> as *const libc::c_void,
+            check_cancelled: check_cancelled::<'a, I> as *const libc::c_void,
+            page_shipped: page_shipped::<'a, I> as *const libc::c_void,
+            missing_char: missing_char::<'a, I> as *const libc::c_void,
+            font_substituted: font_substituted::<'a, I> as *const libc::c_void,
+            font_cache_get: font_cache_get::<'a, I> as *const libc::c_void,
+            font_cache_put: font_cache_put::<'a, I> as *const libc::c_void,
+            special: special::<'a, I> as *const libc::c_void,
         }
     }
 }
@@ -2,12 +2,18 @@
 // Copyright 2017 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::{CStr, CString};
+use libc;
+use std::ffi::{CStr, CString, OsStr};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use errors::{DefinitelySame, ErrorKind, Result};
+use diagnostics::Diagnostic;
+use errors::{Error, ErrorKind, Result};
 use io::IoStack;
 use status::StatusBackend;
-use super::{IoEventBackend, ExecutionState, TectonicBridgeApi};
+use super::font_cache::FontCache;
+use super::{was_cancelled, CancellationToken, IoEventBackend, ExecutionState, TectonicBridgeApi};
 
 
 #[derive(Clone,Copy,Debug,Eq,PartialEq)]
@@ -21,10 +27,85 @@ pub enum TexResult {
     Errors,
 }
 
-// Sigh, have to do this manually because of the Result/PartialEq conflict in errors.rs
-impl DefinitelySame for TexResult {
-    fn definitely_same(&self, other: &Self) -> bool {
-        self == other
+/// The TeX "interaction mode", which governs how much the engine writes to
+/// the terminal and what it does when it encounters an error. The engine
+/// can't ask the user for help, so errors never stop it to wait for input.
+/// Instead, in `ErrorStop` mode, errors are handled as in `Scroll` mode
+/// unless the engine is set to halt on errors.
+///
+/// The TeX code being processed can change the mode with commands such as
+/// `\batchmode`; this setting only determines the mode that the engine
+/// starts in.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum InteractionMode {
+    /// Don't write anything to the terminal and keep going after errors.
+    Batch,
+
+    /// Keep going after errors, including ones that would ordinarily prompt
+    /// the user for a file name.
+    Nonstop,
+
+    /// Keep going after errors, reporting them on the terminal.
+    Scroll,
+
+    /// TeX's standard mode, in which it would stop to consult the user after
+    /// every error.
+    ErrorStop,
+}
+
+impl InteractionMode {
+    /// The value of the engine's `interaction` variable for this mode.
+    fn as_int(&self) -> libc::c_int {
+        match *self {
+            InteractionMode::Batch => 0,
+            InteractionMode::Nonstop => 1,
+            InteractionMode::Scroll => 2,
+            InteractionMode::ErrorStop => 3,
+        }
+    }
+}
+
+impl FromStr for InteractionMode {
+    type Err = Error;
+
+    /// Parse the names used by the `--interaction` option, which are those
+    /// of the TeX commands that set the modes, such as "batchmode".
+    fn from_str(s: &str) -> Result<InteractionMode> {
+        match s {
+            "batchmode" => Ok(InteractionMode::Batch),
+            "nonstopmode" => Ok(InteractionMode::Nonstop),
+            "scrollmode" => Ok(InteractionMode::Scroll),
+            "errorstopmode" => Ok(InteractionMode::ErrorStop),
+            _ => Err(ErrorKind::Msg(format!("unknown interaction mode \"{}\"", s)).into()),
+        }
+    }
+}
+
+/// Hooks that let a program embedding the TeX engine observe and take part
+/// in its processing, without patching the C code. All of the methods do
+/// nothing by default. The hooks are called on the engine's thread while it
+/// is running, so they shouldn't take too long.
+pub trait TexHooks {
+    /// Called when the engine ships out a page. `page` counts the pages
+    /// shipped out so far, starting at 1; `counts` holds the values of
+    /// `\count0` through `\count9`, which LaTeX uses for the page number.
+    fn page_shipped(&mut self, _page: u64, _counts: &[i32]) {}
+
+    /// Called when the engine opens a file for input.
+    fn file_opened(&mut self, _name: &OsStr) {}
+
+    /// Called with the text of each `\special` as its page is shipped out.
+    /// Return true if the special has been dealt with, in which case it is
+    /// left out of the output; return false to have it written out as
+    /// usual.
+    fn special(&mut self, _text: &[u8]) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for TexHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TexHooks")
     }
 }
 
@@ -34,16 +115,30 @@ pub struct TexEngine {
     // though, it's just a proxy for the global constants in the C code.
 
     halt_on_error: bool,
+    interaction: InteractionMode,
     initex_mode: bool,
+    preload_format: bool,
     synctex_enabled: bool,
+    cancel: Option<CancellationToken>,
+    max_pages: Option<u64>,
+    font_cache_dir: Option<PathBuf>,
+    hooks: Option<Box<TexHooks>>,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Default for TexEngine {
     fn default() -> Self {
         TexEngine {
             halt_on_error: true,
+            interaction: InteractionMode::ErrorStop,
             initex_mode: false,
+            preload_format: false,
             synctex_enabled: false,
+            cancel: None,
+            max_pages: None,
+            font_cache_dir: None,
+            hooks: None,
+            diagnostics: Vec::new(),
         }
     }
 }
@@ -58,6 +153,12 @@ impl TexEngine {
         self
     }
 
+    /// Set the interaction mode that the engine starts in.
+    pub fn interaction_mode (&mut self, mode: InteractionMode) -> &mut Self {
+        self.interaction = mode;
+        self
+    }
+
     /// Configure the engine to run in "initex" mode, in which it generates a
     /// "format" file that serializes the engine state rather than a PDF
     /// document.
@@ -66,12 +167,62 @@ impl TexEngine {
         self
     }
 
+    /// In "initex" mode, load the format file before reading the input, so
+    /// that the format file that is generated extends it rather than starting
+    /// from scratch. This has no effect outside of "initex" mode, where the
+    /// format file is always loaded.
+    pub fn preload_format (&mut self, preload: bool) -> &mut Self {
+        self.preload_format = preload;
+        self
+    }
+
     /// Configure the engine to produce SyncTeX data.
     pub fn synctex (&mut self, synctex_enabled: bool) -> &mut Self {
         self.synctex_enabled = synctex_enabled;
         self
     }
 
+    /// Have the engine stop with an error if `token` is triggered while it
+    /// is running.
+    pub fn cancellation_token (&mut self, token: &CancellationToken) -> &mut Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    /// Stop with an error of kind `ErrorKind::ResourceLimitExceeded` if the
+    /// engine ships out more than this many pages.
+    pub fn max_pages (&mut self, limit: Option<u64>) -> &mut Self {
+        self.max_pages = limit;
+        self
+    }
+
+    /// Save information about the system fonts that the engine looks at in
+    /// the directory `dir`, and use what was saved on earlier runs, so that
+    /// the fonts don't have to be parsed every time. By default there is no
+    /// cache.
+    pub fn font_cache_dir (&mut self, dir: Option<&Path>) -> &mut Self {
+        self.font_cache_dir = dir.map(|d| d.to_owned());
+        self
+    }
+
+    /// Install hooks to be called as the engine runs, replacing any that
+    /// were installed before.
+    pub fn hooks (&mut self, hooks: Box<TexHooks>) -> &mut Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Remove the hooks from the engine, returning them.
+    pub fn take_hooks (&mut self) -> Option<Box<TexHooks>> {
+        self.hooks.take()
+    }
+
+    /// The errors, warnings, and other notable events that the engine
+    /// recorded in its log file during the most recent call to `process`.
+    pub fn diagnostics (&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
     // This function can't be generic across the IoProvider trait, for now,
     // since the global pointer that stashes the ExecutionState must have a
     // complete type.
@@ -83,22 +234,34 @@ impl TexEngine {
         let cformat = CString::new(format_file_name)?;
         let cinput = CString::new(input_file_name)?;
 
-        let /*mut*/ state = ExecutionState::new(io, events, status);
+        let mut font_cache = self.font_cache_dir.as_ref().map(|d| FontCache::open(d));
+        let mut state = ExecutionState::new(io, events, status, self.cancel.clone());
+        state.max_pages = self.max_pages;
+        state.font_cache = font_cache.as_mut();
+        state.hooks = self.hooks.as_mut().map(|h| &mut **h as &mut TexHooks);
         let bridge = TectonicBridgeApi::new(&state);
 
         // initialize globals
         let v = if self.halt_on_error { 1 } else { 0 };
         unsafe { super::tt_set_int_variable(b"halt_on_error_p\0".as_ptr(), v); }
+        unsafe { super::tt_set_int_variable(b"interaction_mode\0".as_ptr(), self.interaction.as_int()); }
         let v = if self.initex_mode { 1 } else { 0 };
         unsafe { super::tt_set_int_variable(b"in_initex_mode\0".as_ptr(), v); }
+        let v = if self.preload_format { 1 } else { 0 };
+        unsafe { super::tt_set_int_variable(b"initex_preload_format\0".as_ptr(), v); }
         let v = if self.synctex_enabled { 1 } else { 0 };
         unsafe { super::tt_set_int_variable(b"synctex_enabled\0".as_ptr(), v); }
 
-        unsafe {
+        let result = unsafe {
             match super::tex_simple_main(&bridge, cformat.as_ptr(), cinput.as_ptr()) {
                 0 => Ok(TexResult::Spotless),
                 1 => Ok(TexResult::Warnings),
                 2 => Ok(TexResult::Errors),
+                3 if was_cancelled(&self.cancel) => Err(ErrorKind::Cancelled.into()),
+                3 if state.page_limit_exceeded() => {
+                    Err(ErrorKind::ResourceLimitExceeded("pages shipped out".to_owned(),
+                                                         self.max_pages.unwrap()).into())
+                },
                 3 => {
                     let ptr = super::tt_get_error_message();
                     let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
@@ -106,6 +269,11 @@ impl TexEngine {
                 },
                 x => Err(ErrorKind::Msg(format!("internal error: unexpected 'history' value {}", x)).into())
             }
-        }
+        };
+
+        state.end_page_progress();
+        state.save_font_cache();
+        self.diagnostics = state.take_diagnostics();
+        result
     }
 }
@@ -2,21 +2,183 @@
 // Copyright 2017 the Tectonic Project
 // Licensed under the MIT License.
 
+use libc;
 use std::ffi::{CStr, CString};
+use std::ptr;
 
 use errors::{ErrorKind, Result};
 use io::IoStack;
 use status::StatusBackend;
-use super::{IoEventBackend, ExecutionState, TectonicBridgeApi};
+use super::{was_cancelled, CancellationToken, IoEventBackend, ExecutionState, PdfAttachmentRecord,
+            TectonicBridgeApi, XdvipdfmxConfig};
 
 
+/// The oldest and newest PDF minor versions that xdvipdfmx can produce.
+const PDF_VERSION_MIN: u8 = 3;
+const PDF_VERSION_MAX: u8 = 7;
+
+
+/// Metadata to store in the output PDF. Anything set here takes precedence
+/// over what the document itself specifies, for instance with hyperref's
+/// `pdftitle` option.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct PdfMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+
+    /// A complete XMP packet to use as the document's metadata stream.
+    pub xmp: Option<Vec<u8>>,
+}
+
+
+/// Settings for encrypting the output PDF. Encryption always uses AES-256,
+/// which requires PDF 1.7.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct PdfEncryption {
+    /// The password that grants full access to the document, regardless of
+    /// the permission flags below.
+    pub owner_password: String,
+
+    /// The password needed to open the document. If it's empty, anyone can
+    /// open the document, but the permission flags still apply.
+    pub user_password: String,
+
+    /// Forbid printing the document.
+    pub no_print: bool,
+
+    /// Forbid copying text and graphics out of the document.
+    pub no_copy: bool,
+}
+
+
+/// A file to attach to the output PDF, such as one of the document's
+/// sources. Viewers list attachments by their names, which must be unique.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct PdfAttachment {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+
+fn optional_cstring(s: &Option<String>) -> Result<Option<CString>> {
+    match *s {
+        Some(ref s) => Ok(Some(CString::new(s.as_str())?)),
+        None => Ok(None),
+    }
+}
+
+
+fn cstring_ptr(s: &Option<CString>) -> *const i8 {
+    s.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null())
+}
+
+
+#[derive(Clone,Debug,Default)]
 pub struct XdvipdfmxEngine {
+    paper_spec: Option<String>,
+    compression_level: Option<u8>,
+    pdf_version: Option<u8>,
+    disable_png_predictors: bool,
+    pdfa: bool,
+    metadata: PdfMetadata,
+    reproducible: bool,
+    linearize: bool,
+    encryption: Option<PdfEncryption>,
+    attachments: Vec<PdfAttachment>,
+    cancel: Option<CancellationToken>,
 }
 
 
 impl XdvipdfmxEngine {
     pub fn new () -> XdvipdfmxEngine {
-        XdvipdfmxEngine {}
+        XdvipdfmxEngine::default()
+    }
+
+    /// Set the default paper size, either as a name like "a4" or as a
+    /// comma-separated width and height like "20cm,30cm". Documents can
+    /// still override this with `\special{papersize=...}`.
+    pub fn paper_spec (&mut self, spec: &str) -> &mut Self {
+        self.paper_spec = Some(spec.to_owned());
+        self
+    }
+
+    /// Set the zlib compression level of the PDF streams, from 0 (no
+    /// compression) to 9 (best).
+    pub fn compression_level (&mut self, level: u8) -> &mut Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    /// Set the PDF version to produce, given as its minor version number:
+    /// for instance, 5 for PDF 1.5.
+    pub fn pdf_version (&mut self, minor: u8) -> &mut Self {
+        self.pdf_version = Some(minor);
+        self
+    }
+
+    /// Set whether PNG predictors are used when compressing bitmap images.
+    /// They usually make images smaller but slow down processing.
+    pub fn png_predictors (&mut self, enabled: bool) -> &mut Self {
+        self.disable_png_predictors = !enabled;
+        self
+    }
+
+    /// Set whether to produce PDF/A-1b output, for long-term archiving. This
+    /// always generates PDF 1.4 and requires the sRGB color profile from
+    /// the "colorprofiles" package. Processing fails if the document uses
+    /// anything that PDF/A forbids, such as transparency or fonts that
+    /// can't be embedded.
+    pub fn pdfa (&mut self, enabled: bool) -> &mut Self {
+        self.pdfa = enabled;
+        self
+    }
+
+    /// Set the metadata to store in the output PDF.
+    pub fn metadata (&mut self, metadata: PdfMetadata) -> &mut Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Set whether to produce reproducible output, which is identical
+    /// whenever the input is. This leaves out the creation time and
+    /// producer from the PDF metadata, uses an all-zero document ID, and
+    /// generates font subset names deterministically.
+    pub fn reproducible (&mut self, enabled: bool) -> &mut Self {
+        self.reproducible = enabled;
+        self
+    }
+
+    /// Set whether to produce linearized ("fast web view") output, which
+    /// lets viewers display the first page of a document before the rest
+    /// of it has been downloaded. The whole PDF is kept in memory until it
+    /// is finished.
+    pub fn linearize (&mut self, enabled: bool) -> &mut Self {
+        self.linearize = enabled;
+        self
+    }
+
+    /// Set the encryption settings of the output, or None for an
+    /// unencrypted PDF. These take precedence over a `pdf:encrypt` special
+    /// in the document.
+    pub fn encryption (&mut self, encryption: Option<PdfEncryption>) -> &mut Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Set the files to attach to the output PDF, replacing any that were
+    /// set before. Attachments can't be combined with PDF/A output.
+    pub fn attachments (&mut self, attachments: Vec<PdfAttachment>) -> &mut Self {
+        self.attachments = attachments;
+        self
+    }
+
+    /// Have the engine stop with an error if `token` is triggered while it
+    /// is running.
+    pub fn cancellation_token (&mut self, token: &CancellationToken) -> &mut Self {
+        self.cancel = Some(token.clone());
+        self
     }
 
     pub fn process (&mut self, io: &mut IoStack,
@@ -25,11 +187,112 @@ impl XdvipdfmxEngine {
         let cdvi = CString::new(dvi)?;
         let cpdf = CString::new(pdf)?;
 
-        let /*mut*/ state = ExecutionState::new(io, events, status);
+        if let Some(level) = self.compression_level {
+            if level > 9 {
+                return Err(ErrorKind::Msg(format!("invalid PDF compression level {}; it must be between 0 and 9",
+                                                  level)).into());
+            }
+        }
+
+        if let Some(v) = self.pdf_version {
+            if v < PDF_VERSION_MIN || v > PDF_VERSION_MAX {
+                return Err(ErrorKind::Msg(format!("unsupported PDF version 1.{}; it must be between 1.{} and 1.{}",
+                                                  v, PDF_VERSION_MIN, PDF_VERSION_MAX)).into());
+            }
+        }
+
+        if self.pdfa {
+            if let Some(v) = self.pdf_version {
+                if v != 4 {
+                    return Err(ErrorKind::Msg(format!("PDF/A output is always PDF 1.4, so PDF version 1.{} \
+                                                       can't be used with it", v)).into());
+                }
+            }
+        }
+
+        if !self.attachments.is_empty() {
+            if self.pdfa {
+                return Err(ErrorKind::Msg("PDF/A-1 output can't have file attachments".to_owned()).into());
+            }
+
+            for (i, a) in self.attachments.iter().enumerate() {
+                if self.attachments[..i].iter().any(|b| b.name == a.name) {
+                    return Err(ErrorKind::Msg(format!("more than one attachment is named \"{}\"",
+                                                      a.name)).into());
+                }
+            }
+        }
+
+        if self.encryption.is_some() {
+            if self.pdfa {
+                return Err(ErrorKind::Msg("PDF/A output can't be encrypted".to_owned()).into());
+            }
+
+            if self.linearize {
+                return Err(ErrorKind::Msg("linearized output can't be encrypted".to_owned()).into());
+            }
+
+            if self.reproducible {
+                return Err(ErrorKind::Msg("reproducible output can't be encrypted, since encryption \
+                                           uses random salts".to_owned()).into());
+            }
+
+            if let Some(v) = self.pdf_version {
+                if v < 7 {
+                    return Err(ErrorKind::Msg(format!("AES-256 encryption requires PDF 1.7, so PDF version \
+                                                       1.{} can't be used with it", v)).into());
+                }
+            }
+        }
+
+        let cpaper = optional_cstring(&self.paper_spec)?;
+        let ctitle = optional_cstring(&self.metadata.title)?;
+        let cauthor = optional_cstring(&self.metadata.author)?;
+        let csubject = optional_cstring(&self.metadata.subject)?;
+        let ckeywords = optional_cstring(&self.metadata.keywords)?;
+        let cowner = optional_cstring(&self.encryption.as_ref().map(|e| e.owner_password.clone()))?;
+        let cuser = optional_cstring(&self.encryption.as_ref().map(|e| e.user_password.clone()))?;
+
+        let mut cnames = Vec::with_capacity(self.attachments.len());
+        for a in &self.attachments {
+            cnames.push(CString::new(a.name.as_str())?);
+        }
+
+        let records: Vec<_> = self.attachments.iter().zip(cnames.iter()).map(|(a, n)| PdfAttachmentRecord {
+            name: n.as_ptr(),
+            data: a.data.as_ptr(),
+            len: a.data.len() as libc::size_t,
+        }).collect();
+
+        let config = XdvipdfmxConfig {
+            paperspec: cstring_ptr(&cpaper),
+            compression_level: self.compression_level.map(|l| l as libc::c_int).unwrap_or(-1),
+            pdf_version: self.pdf_version.map(|v| v as libc::c_int).unwrap_or(-1),
+            enable_png_predictors: if self.disable_png_predictors { 0 } else { 1 },
+            pdfa: if self.pdfa { 1 } else { 0 },
+            title: cstring_ptr(&ctitle),
+            author: cstring_ptr(&cauthor),
+            subject: cstring_ptr(&csubject),
+            keywords: cstring_ptr(&ckeywords),
+            xmp: self.metadata.xmp.as_ref().map(|x| x.as_ptr()).unwrap_or(ptr::null()),
+            xmp_len: self.metadata.xmp.as_ref().map(|x| x.len()).unwrap_or(0) as libc::size_t,
+            reproducible: if self.reproducible { 1 } else { 0 },
+            linearize: if self.linearize { 1 } else { 0 },
+            encrypt: if self.encryption.is_some() { 1 } else { 0 },
+            owner_password: cstring_ptr(&cowner),
+            user_password: cstring_ptr(&cuser),
+            no_print: if self.encryption.as_ref().map(|e| e.no_print).unwrap_or(false) { 1 } else { 0 },
+            no_copy: if self.encryption.as_ref().map(|e| e.no_copy).unwrap_or(false) { 1 } else { 0 },
+            attachments: if records.is_empty() { ptr::null() } else { records.as_ptr() },
+            num_attachments: records.len() as libc::c_int,
+        };
+
+        let /*mut*/ state = ExecutionState::new(io, events, status, self.cancel.clone());
         let bridge = TectonicBridgeApi::new(&state);
 
         unsafe {
-            match super::dvipdfmx_simple_main(&bridge, cdvi.as_ptr(), cpdf.as_ptr()) {
+            match super::dvipdfmx_simple_main(&bridge, &config, cdvi.as_ptr(), cpdf.as_ptr()) {
+                99 if was_cancelled(&self.cancel) => Err(ErrorKind::Cancelled.into()),
                 99 => {
                     let ptr = super::tt_get_error_message();
                     let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
@@ -40,3 +303,4 @@ impl XdvipdfmxEngine {
         }
     }
 }
+
@@ -0,0 +1,110 @@
+// src/engines/xdvipng.rs -- Rustic interface to the XDV-to-PNG rasterizer.
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+use libc;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use errors::{ErrorKind, Result};
+use io::IoStack;
+use status::StatusBackend;
+use super::{was_cancelled, CancellationToken, IoEventBackend, ExecutionState, TectonicBridgeApi,
+            XdvisvgConfig};
+
+
+/// The resolution of the images if none is chosen, and the largest one
+/// that we accept.
+const DEFAULT_DPI: u32 = 150;
+const MAX_DPI: u32 = 2400;
+
+
+/// Renders XDV files as PNG images, one per page, for previews and
+/// thumbnails. The pages of a file named "doc.xdv" end up in "doc-1.png",
+/// "doc-2.png", and so on. This uses the same machinery as the SVG
+/// converter, so it has the same limitations.
+#[derive(Clone,Debug)]
+pub struct XdvipngEngine {
+    paper_spec: Option<String>,
+    dpi: u32,
+    cancel: Option<CancellationToken>,
+}
+
+
+impl Default for XdvipngEngine {
+    fn default() -> Self {
+        XdvipngEngine {
+            paper_spec: None,
+            dpi: DEFAULT_DPI,
+            cancel: None,
+        }
+    }
+}
+
+
+impl XdvipngEngine {
+    pub fn new () -> XdvipngEngine {
+        XdvipngEngine::default()
+    }
+
+    /// Set the default paper size, either as a name like "a4" or as a
+    /// comma-separated width and height like "20cm,30cm". Documents can
+    /// still override this with `\special{papersize=...}`.
+    pub fn paper_spec (&mut self, spec: &str) -> &mut Self {
+        self.paper_spec = Some(spec.to_owned());
+        self
+    }
+
+    /// Set the resolution of the images, in pixels per inch.
+    pub fn dpi (&mut self, dpi: u32) -> &mut Self {
+        self.dpi = dpi;
+        self
+    }
+
+    /// Have the engine stop with an error if `token` is triggered while it
+    /// is running.
+    pub fn cancellation_token (&mut self, token: &CancellationToken) -> &mut Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    /// Render `dvi` as PNG images. The names of the output files start
+    /// with `stem`, which may include a directory.
+    pub fn process (&mut self, io: &mut IoStack,
+                    events: &mut IoEventBackend,
+                    status: &mut StatusBackend, dvi: &str, stem: &str) -> Result<i32> {
+        if self.dpi == 0 || self.dpi > MAX_DPI {
+            return Err(ErrorKind::Msg(format!("invalid image resolution {}; it must be between 1 and {} DPI",
+                                              self.dpi, MAX_DPI)).into());
+        }
+
+        let cdvi = CString::new(dvi)?;
+        let cstem = CString::new(stem)?;
+        let cpaper = match self.paper_spec {
+            Some(ref s) => Some(CString::new(s.as_str())?),
+            None => None,
+        };
+
+        let config = XdvisvgConfig {
+            paperspec: cpaper.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()),
+            woff_fonts: 0,
+            png_dpi: self.dpi as libc::c_int,
+            text_output: 0,
+        };
+
+        let /*mut*/ state = ExecutionState::new(io, events, status, self.cancel.clone());
+        let bridge = TectonicBridgeApi::new(&state);
+
+        unsafe {
+            match super::xdvisvg_simple_main(&bridge, &config, cdvi.as_ptr(), cstem.as_ptr()) {
+                99 if was_cancelled(&self.cancel) => Err(ErrorKind::Cancelled.into()),
+                99 => {
+                    let ptr = super::tt_get_error_message();
+                    let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+                    Err(ErrorKind::Msg(msg).into())
+                },
+                x => Ok(x as i32)
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// src/engines/xdvisvg.rs -- Rustic interface to the XDV-to-SVG converter.
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+use libc;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use errors::{ErrorKind, Result};
+use io::IoStack;
+use status::StatusBackend;
+use super::{was_cancelled, CancellationToken, IoEventBackend, ExecutionState, TectonicBridgeApi,
+            XdvisvgConfig};
+
+
+/// How the glyphs of the document's fonts are represented in SVG output.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum SvgFontMode {
+    /// Every glyph is drawn as a path, so that the output looks the same
+    /// everywhere but its text can't be selected or searched.
+    Paths,
+
+    /// Glyphs of OpenType and TrueType fonts are drawn as text, with the
+    /// fonts saved as WOFF files next to the pages. Glyphs of other fonts,
+    /// and glyphs that don't correspond to a character, are still drawn as
+    /// paths.
+    Woff,
+}
+
+impl Default for SvgFontMode {
+    fn default() -> Self { SvgFontMode::Paths }
+}
+
+
+/// Converts XDV files to SVG, with one SVG document per page. The pages of
+/// a file named "doc.xdv" end up in "doc-1.svg", "doc-2.svg", and so on.
+#[derive(Clone,Debug,Default)]
+pub struct XdvisvgEngine {
+    paper_spec: Option<String>,
+    font_mode: SvgFontMode,
+    cancel: Option<CancellationToken>,
+}
+
+
+impl XdvisvgEngine {
+    pub fn new () -> XdvisvgEngine {
+        XdvisvgEngine::default()
+    }
+
+    /// Set the default paper size, either as a name like "a4" or as a
+    /// comma-separated width and height like "20cm,30cm". Documents can
+    /// still override this with `\special{papersize=...}`.
+    pub fn paper_spec (&mut self, spec: &str) -> &mut Self {
+        self.paper_spec = Some(spec.to_owned());
+        self
+    }
+
+    /// Set how glyphs are represented in the output.
+    pub fn font_mode (&mut self, mode: SvgFontMode) -> &mut Self {
+        self.font_mode = mode;
+        self
+    }
+
+    /// Have the engine stop with an error if `token` is triggered while it
+    /// is running.
+    pub fn cancellation_token (&mut self, token: &CancellationToken) -> &mut Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    /// Convert `dvi` to SVG. The names of the output files start with
+    /// `stem`, which may include a directory.
+    pub fn process (&mut self, io: &mut IoStack,
+                    events: &mut IoEventBackend,
+                    status: &mut StatusBackend, dvi: &str, stem: &str) -> Result<i32> {
+        let cdvi = CString::new(dvi)?;
+        let cstem = CString::new(stem)?;
+        let cpaper = match self.paper_spec {
+            Some(ref s) => Some(CString::new(s.as_str())?),
+            None => None,
+        };
+
+        let config = XdvisvgConfig {
+            paperspec: cpaper.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()),
+            woff_fonts: if self.font_mode == SvgFontMode::Woff { 1 } else { 0 },
+            png_dpi: 0,
+            text_output: 0,
+        };
+
+        let /*mut*/ state = ExecutionState::new(io, events, status, self.cancel.clone());
+        let bridge = TectonicBridgeApi::new(&state);
+
+        unsafe {
+            match super::xdvisvg_simple_main(&bridge, &config, cdvi.as_ptr(), cstem.as_ptr()) {
+                99 if was_cancelled(&self.cancel) => Err(ErrorKind::Cancelled.into()),
+                99 => {
+                    let ptr = super::tt_get_error_message();
+                    let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+                    Err(ErrorKind::Msg(msg).into())
+                },
+                x => Ok(x as i32)
+            }
+        }
+    }
+}
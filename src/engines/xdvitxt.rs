@@ -0,0 +1,69 @@
+// src/engines/xdvitxt.rs -- Rustic interface to the XDV text extractor.
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use errors::{ErrorKind, Result};
+use io::IoStack;
+use status::StatusBackend;
+use super::{was_cancelled, CancellationToken, IoEventBackend, ExecutionState, TectonicBridgeApi,
+            XdvisvgConfig};
+
+
+/// Extracts the Unicode text of XDV files, for spell-checking, indexing,
+/// and comparing the content of documents. The text of a file named
+/// "doc.xdv" ends up in "doc.txt", in the order that TeX typeset it, with
+/// each page ending in a form feed. Spaces and line breaks are guessed from
+/// the positions of the glyphs. This uses the same machinery as the SVG
+/// converter, so it has the same limitations.
+#[derive(Clone,Debug,Default)]
+pub struct XdvitxtEngine {
+    cancel: Option<CancellationToken>,
+}
+
+
+impl XdvitxtEngine {
+    pub fn new () -> XdvitxtEngine {
+        XdvitxtEngine::default()
+    }
+
+    /// Have the engine stop with an error if `token` is triggered while it
+    /// is running.
+    pub fn cancellation_token (&mut self, token: &CancellationToken) -> &mut Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    /// Extract the text of `dvi`. The output file is named `stem` plus
+    /// ".txt", where `stem` may include a directory.
+    pub fn process (&mut self, io: &mut IoStack,
+                    events: &mut IoEventBackend,
+                    status: &mut StatusBackend, dvi: &str, stem: &str) -> Result<i32> {
+        let cdvi = CString::new(dvi)?;
+        let cstem = CString::new(stem)?;
+
+        let config = XdvisvgConfig {
+            paperspec: ptr::null(),
+            woff_fonts: 0,
+            png_dpi: 0,
+            text_output: 1,
+        };
+
+        let /*mut*/ state = ExecutionState::new(io, events, status, self.cancel.clone());
+        let bridge = TectonicBridgeApi::new(&state);
+
+        unsafe {
+            match super::xdvisvg_simple_main(&bridge, &config, cdvi.as_ptr(), cstem.as_ptr()) {
+                99 if was_cancelled(&self.cancel) => Err(ErrorKind::Cancelled.into()),
+                99 => {
+                    let ptr = super::tt_get_error_message();
+                    let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+                    Err(ErrorKind::Msg(msg).into())
+                },
+                x => Ok(x as i32)
+            }
+        }
+    }
+}
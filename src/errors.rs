@@ -5,8 +5,8 @@
 use app_dirs;
 use flate2;
 use hyper;
+use serde_json;
 use std::{convert, ffi, io, num, str};
-use std::result::Result as StdResult;
 use std::io::Write;
 use toml;
 use zip::result::ZipError;
@@ -23,6 +23,7 @@ error_chain! {
         Io(io::Error);
         Nul(ffi::NulError);
         ParseInt(num::ParseIntError);
+        SerdeJson(serde_json::Error);
         TomlDe(toml::de::Error);
         Utf8(str::Utf8Error);
         Zip(ZipError);
@@ -34,6 +35,36 @@ error_chain! {
             display("expected length {}; found {}", expected, observed)
         }
 
+        BundleUnavailable {
+            description("the bundle of support files could not be set up")
+            display("couldn't set up the bundle of support files")
+        }
+
+        CacheCorrupted(name: String, path: String) {
+            description("a file in the local cache is corrupted")
+            display("the cached copy of \"{}\" at {} is corrupted", name, path)
+        }
+
+        Cancelled {
+            description("processing was cancelled")
+            display("processing was cancelled")
+        }
+
+        InvalidConfig(path: String, problems: Vec<String>) {
+            description("a configuration file has invalid settings")
+            display("invalid settings in configuration file \"{}\":\n  {}", path, problems.join("\n  "))
+        }
+
+        EngineFailed(engine: String) {
+            description("an engine failed to process the document")
+            display("{} couldn't process the document", engine)
+        }
+
+        NetworkDisabled(name: String, url: String) {
+            description("network access is disabled")
+            display("\"{}\" is not in the local cache of bundle {}, and network access is disabled", name, url)
+        }
+
         NotSeekable {
             description("this stream is not seekable")
             display("this stream is not seekable")
@@ -44,10 +75,21 @@ error_chain! {
             display("the size of this stream cannot be determined")
         }
 
+        PinnedDigestMismatch(expected: String, found: String) {
+            description("the bundle does not match its pinned digest")
+            display("the bundle is pinned to digest {} but its digest is {}; refusing to use a different version",
+                    expected, found)
+        }
+
         PathForbidden(path: String) {
             description("access to this file path is forbidden")
             display("access to the path {} is forbidden", path)
         }
+
+        ResourceLimitExceeded(resource: String, limit: u64) {
+            description("a resource limit was exceeded")
+            display("the limit on {} ({}) was exceeded", resource, limit)
+        }
     }
 }
 
@@ -66,7 +108,160 @@ impl convert::From<Error> for io::Error {
 }
 
 
+/// Every kind of error has a code, like "TT0030" for `PathForbidden`, which
+/// is shown along with the message and included in machine-readable output,
+/// so that programs can tell errors apart without matching their text and
+/// people can look them up. The codes never change meaning: a kind of error
+/// that goes away leaves its code unused, and new kinds get new codes.
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        self.code_and_name().0
+    }
+
+    /// The name of this kind of error, which is that of its variant, such as
+    /// "PathForbidden".
+    pub fn name(&self) -> &'static str {
+        self.code_and_name().1
+    }
+
+    fn code_and_name(&self) -> (&'static str, &'static str) {
+        match *self {
+            ErrorKind::Msg(_) => ("TT0001", "Msg"),
+            ErrorKind::AppDirs(_) => ("TT0002", "AppDirs"),
+            ErrorKind::Flate2(_) => ("TT0003", "Flate2"),
+            ErrorKind::Hyper(_) => ("TT0004", "Hyper"),
+            ErrorKind::Io(_) => ("TT0005", "Io"),
+            ErrorKind::Nul(_) => ("TT0006", "Nul"),
+            ErrorKind::ParseInt(_) => ("TT0007", "ParseInt"),
+            ErrorKind::SerdeJson(_) => ("TT0008", "SerdeJson"),
+            ErrorKind::TomlDe(_) => ("TT0009", "TomlDe"),
+            ErrorKind::Utf8(_) => ("TT0010", "Utf8"),
+            ErrorKind::Zip(_) => ("TT0011", "Zip"),
+            ErrorKind::BadLength(..) => ("TT0020", "BadLength"),
+            ErrorKind::BundleUnavailable => ("TT0021", "BundleUnavailable"),
+            ErrorKind::CacheCorrupted(..) => ("TT0022", "CacheCorrupted"),
+            ErrorKind::Cancelled => ("TT0023", "Cancelled"),
+            ErrorKind::EngineFailed(..) => ("TT0024", "EngineFailed"),
+            ErrorKind::InvalidConfig(..) => ("TT0025", "InvalidConfig"),
+            ErrorKind::NetworkDisabled(..) => ("TT0026", "NetworkDisabled"),
+            ErrorKind::NotSeekable => ("TT0027", "NotSeekable"),
+            ErrorKind::NotSizeable => ("TT0028", "NotSizeable"),
+            ErrorKind::PinnedDigestMismatch(..) => ("TT0029", "PinnedDigestMismatch"),
+            ErrorKind::PathForbidden(..) => ("TT0030", "PathForbidden"),
+            ErrorKind::ResourceLimitExceeded(..) => ("TT0031", "ResourceLimitExceeded"),
+        }
+    }
+}
+
+
+/// The form in which `Error::write_json()` writes an error.
+#[derive(Serialize)]
+struct JsonError<'a> {
+    kind: &'a str,
+    code: &'a str,
+    message: String,
+    causes: Vec<String>,
+    backtrace: bool,
+}
+
+
 impl Error {
+    /// Get the code of the most specific kind of error in the chain. Most
+    /// errors are wrapped in plain messages that say what we were doing at
+    /// the time, which aren't interesting, so this looks through them to the
+    /// first error of another kind. If there isn't one, the code is that of
+    /// a plain message.
+    pub fn code(&self) -> &'static str {
+        self.specific_code_and_name().0
+    }
+
+    /// Get the name of the most specific kind of error in the chain, in the
+    /// same way as `code()`.
+    pub fn kind_name(&self) -> &'static str {
+        self.specific_code_and_name().1
+    }
+
+    fn specific_code_and_name(&self) -> (&'static str, &'static str) {
+        match self.specific_kind() {
+            Some(kind) => kind.code_and_name(),
+            None if self.io_cause().is_some() => ("TT0005", "Io"), // as for ErrorKind::Io
+            None => self.kind().code_and_name(),
+        }
+    }
+
+    /// Get the first kind of error in the chain that isn't a plain message,
+    /// if there is one. This is the kind that says what really went wrong.
+    pub fn specific_kind(&self) -> Option<&ErrorKind> {
+        self.kinds().into_iter().find(|kind| match **kind {
+            ErrorKind::Msg(_) => false,
+            _ => true,
+        })
+    }
+
+    /// Get the I/O error at the end of the chain, if there is one that was
+    /// wrapped in a message without being converted to `ErrorKind::Io`, as
+    /// `ctry!` does.
+    pub fn io_cause(&self) -> Option<&io::Error> {
+        let mut err = self;
+
+        loop {
+            let next = match err.1.next_error {
+                Some(ref e) => e,
+                None => return None,
+            };
+
+            if let Some(inner) = next.downcast_ref::<Error>() {
+                err = inner;
+            } else {
+                return next.downcast_ref::<io::Error>();
+            }
+        }
+    }
+
+    /// Write this error to `stream` as a JSON object on a line of its own,
+    /// for programs that run Tectonic and want to know why it failed without
+    /// picking apart the output of `dump_uncolorized()`. The object looks
+    /// like this:
+    ///
+    /// ```json
+    /// {"kind":"PathForbidden","code":"TT0030","message":"...","causes":["..."],"backtrace":false}
+    /// ```
+    ///
+    /// The `kind` and `code` are those of the most specific kind of error
+    /// in the chain (see `code()`), the `causes` follow the `message` from
+    /// the outermost in, and `backtrace` says whether a backtrace was
+    /// captured, which happens if `RUST_BACKTRACE` is set.
+    pub fn write_json<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        let (code, kind) = self.specific_code_and_name();
+        let mut items = self.iter().map(|item| item.to_string());
+
+        let report = JsonError {
+            kind: kind,
+            code: code,
+            message: items.next().unwrap_or_default(),
+            causes: items.collect(),
+            backtrace: self.backtrace().is_some(),
+        };
+
+        let line = serde_json::to_string(&report).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        writeln!(stream, "{}", line)?;
+        stream.flush()
+    }
+
+    /// Get the kinds of this error and the errors that it was caused by,
+    /// from the outermost in, as far as they're our own errors.
+    pub fn kinds(&self) -> Vec<&ErrorKind> {
+        let mut kinds = vec![self.kind()];
+        let mut err = self;
+
+        while let Some(inner) = err.1.next_error.as_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            kinds.push(inner.kind());
+            err = inner;
+        }
+
+        kinds
+    }
+
     /// Write the information contained in this object to standard error in a
     /// somewhat user-friendly form.
     ///
@@ -78,12 +273,12 @@ impl Error {
     /// providing nice colorization if possible. This function should only be
     /// used if a `StatusBackend` is not yet available in the running program.
     pub fn dump_uncolorized(&self) {
-        let mut prefix = "error:";
+        let mut prefix = format!("error[{}]:", self.code());
         let mut s = io::stderr();
 
         for item in self.iter() {
             writeln!(s, "{} {}", prefix, item).expect("write to stderr failed");
-            prefix = "caused by:";
+            prefix = "caused by:".to_owned();
         }
 
         if let Some(backtrace) = self.backtrace() {
@@ -94,65 +289,102 @@ impl Error {
 }
 
 
-/// The DefinitelySame trait is a helper trait implemented because Errors do
-/// not generically implement PartialEq. This is a bit of a drag for testing
-/// since it's nice to be able to check if an error matches the one that's
-/// expected. DefinitelySame addresses this by providing a weak equivalence
-/// test: definitely_same() returns true if the two values definitely are
-/// equivalent, and false otherwise. This can happen if the value are known to
-/// be different, but also if we can't tell. It doesn't cover all cases, but
-/// it does cover the ones that come up in our test suite.
+/// Errors can be compared, mostly so that tests can check that they got the
+/// error that they expected. Two errors of our own kinds are equal if their
+/// kinds and payloads are. Errors from other crates are compared directly
+/// where they allow it; otherwise, I/O errors are compared by their kind
+/// and message, and the rest just by their messages.
+impl PartialEq for ErrorKind {
+    fn eq(&self, other: &ErrorKind) -> bool {
+        match (self, other) {
+            (&ErrorKind::Msg(ref a), &ErrorKind::Msg(ref b)) => a == b,
+            (&ErrorKind::AppDirs(ref a), &ErrorKind::AppDirs(ref b)) => a.to_string() == b.to_string(),
+            (&ErrorKind::Flate2(ref a), &ErrorKind::Flate2(ref b)) => a.to_string() == b.to_string(),
+            (&ErrorKind::Hyper(ref a), &ErrorKind::Hyper(ref b)) => a.to_string() == b.to_string(),
+            (&ErrorKind::Io(ref a), &ErrorKind::Io(ref b)) => a.kind() == b.kind() && a.to_string() == b.to_string(),
+            (&ErrorKind::Nul(ref a), &ErrorKind::Nul(ref b)) => a == b,
+            (&ErrorKind::ParseInt(ref a), &ErrorKind::ParseInt(ref b)) => a == b,
+            (&ErrorKind::SerdeJson(ref a), &ErrorKind::SerdeJson(ref b)) => a.to_string() == b.to_string(),
+            (&ErrorKind::TomlDe(ref a), &ErrorKind::TomlDe(ref b)) => a.to_string() == b.to_string(),
+            (&ErrorKind::Utf8(ref a), &ErrorKind::Utf8(ref b)) => a == b,
+            (&ErrorKind::Zip(ref a), &ErrorKind::Zip(ref b)) => a.to_string() == b.to_string(),
+            (&ErrorKind::BadLength(e1, o1), &ErrorKind::BadLength(e2, o2)) => e1 == e2 && o1 == o2,
+            (&ErrorKind::BundleUnavailable, &ErrorKind::BundleUnavailable) => true,
+            (&ErrorKind::CacheCorrupted(ref n1, ref p1), &ErrorKind::CacheCorrupted(ref n2, ref p2)) =>
+                n1 == n2 && p1 == p2,
+            (&ErrorKind::Cancelled, &ErrorKind::Cancelled) => true,
+            (&ErrorKind::EngineFailed(ref a), &ErrorKind::EngineFailed(ref b)) => a == b,
+            (&ErrorKind::InvalidConfig(ref p1, ref l1), &ErrorKind::InvalidConfig(ref p2, ref l2)) =>
+                p1 == p2 && l1 == l2,
+            (&ErrorKind::NetworkDisabled(ref n1, ref u1), &ErrorKind::NetworkDisabled(ref n2, ref u2)) =>
+                n1 == n2 && u1 == u2,
+            (&ErrorKind::NotSeekable, &ErrorKind::NotSeekable) => true,
+            (&ErrorKind::NotSizeable, &ErrorKind::NotSizeable) => true,
+            (&ErrorKind::PinnedDigestMismatch(ref e1, ref f1), &ErrorKind::PinnedDigestMismatch(ref e2, ref f2)) =>
+                e1 == e2 && f1 == f2,
+            (&ErrorKind::PathForbidden(ref a), &ErrorKind::PathForbidden(ref b)) => a == b,
+            (&ErrorKind::ResourceLimitExceeded(ref r1, l1), &ErrorKind::ResourceLimitExceeded(ref r2, l2)) =>
+                r1 == r2 && l1 == l2,
+            _ => false,
+        }
+    }
+}
 
-pub trait DefinitelySame {
-    fn definitely_same(&self, other: &Self) -> bool;
+/// Errors are equal if their kinds are. Their causes and backtraces aren't
+/// compared; use `error_matches!` to look through the causes.
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        self.kind() == other.kind()
+    }
 }
 
-// Rust currently thinks that this impl conflicts with the one that we
-// provide for Result ... I am pretty sure that's not the case since the
-// Result PartialEq impl requires that T and E be PartialEq too, whereas
-// our definition works for subtypes that are DefinitelySame but
-// not PartialEq too.
-//
-//impl<T: PartialEq> DefinitelySame for T {
-//    fn definitely_same(&self, other: &T) -> bool {
-//        self == other
-//    }
-//}
-
-impl DefinitelySame for ErrorKind {
-    fn definitely_same(&self, other: &Self) -> bool {
-        if let ErrorKind::Msg(ref s) = *self {
-            return if let ErrorKind::Msg(ref o) = *other {
-                s == o
-            } else {
-                false
-            }
-        }
 
-        false
+/// Check whether an `Error`, or any of the errors that it was caused by, is
+/// of a kind that matches a pattern, so that tests can check for specific
+/// kinds of errors, and what they're about, without comparing their
+/// messages. For instance:
+///
+/// ```ignore
+/// assert!(error_matches!(err, ErrorKind::PathForbidden(ref p) if p.ends_with("secret.tex")));
+/// ```
+#[macro_export]
+macro_rules! error_matches {
+    ($err:expr, $($pattern:tt)+) => {
+        $err.kinds().into_iter().any(|kind| match *kind {
+            $($pattern)+ => true,
+            _ => false,
+        })
     }
 }
 
-impl DefinitelySame for Error {
-    /// Here we abuse DefinitelySame a bit and ignore the backtrace etc.
-    fn definitely_same(&self, other: &Self) -> bool {
-        self.kind().definitely_same(other.kind())
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparing() {
+        let forbidden: Result<()> = Err(ErrorKind::PathForbidden("/etc/passwd".to_owned()).into());
+        assert_eq!(forbidden, Err(ErrorKind::PathForbidden("/etc/passwd".to_owned()).into()));
+        assert!(forbidden != Err(ErrorKind::PathForbidden("/etc/shadow".to_owned()).into()));
+        assert!(forbidden != Ok(()));
+
+        let err = forbidden.chain_err(|| "couldn't open the input").unwrap_err();
+        assert_eq!(err, Error::from(ErrorKind::Msg("couldn't open the input".to_owned())));
+        assert!(error_matches!(err, ErrorKind::PathForbidden(ref p) if p == "/etc/passwd"));
+        assert!(!error_matches!(err, ErrorKind::Cancelled));
     }
-}
 
-impl<T: DefinitelySame, E: DefinitelySame> DefinitelySame for StdResult<T,E> {
-    fn definitely_same(&self, other: &Self) -> bool {
-        match *self {
-            Ok(ref st) => if let Ok(ref ot) = *other {
-                st.definitely_same(ot)
-            } else {
-                false
-            },
-            Err(ref se) => if let Err(ref oe) = *other {
-                se.definitely_same(oe)
-            } else {
-                false
-            }
-        }
+    #[test]
+    fn specific_kinds() {
+        let err = Err::<(), _>(Error::from(ErrorKind::Cancelled)).chain_err(|| "couldn't build").unwrap_err();
+        assert_eq!(err.specific_kind(), Some(&ErrorKind::Cancelled));
+        assert_eq!(err.code(), "TT0023");
+
+        let io_err = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let err = Err::<(), _>(io_err).chain_err(|| "couldn't open the input").unwrap_err();
+        assert_eq!(err.specific_kind(), None);
+        assert!(err.io_cause().is_some());
+        assert_eq!(err.code(), "TT0005");
     }
 }
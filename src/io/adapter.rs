@@ -0,0 +1,129 @@
+// src/io/adapter.rs -- exposing arbitrary readers and writers as files
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An I/O provider that makes arbitrary `Read + Seek` and `Write` objects
+//! available as named input and output files. Programs embedding Tectonic
+//! can use this to feed the engine data from sockets, databases, archives,
+//! and so on without having to go through temporary files.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+use errors::Result;
+use status::StatusBackend;
+use super::{InputFeatures, InputHandle, InputOrigin, IoProvider, OpenResult, OutputHandle};
+
+
+/// A helper trait so that we can box up things that are both readable and
+/// seekable.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+
+pub struct AdapterIo {
+    inputs: HashMap<OsString, Rc<RefCell<Box<ReadSeek>>>>,
+    outputs: HashMap<OsString, Rc<RefCell<Box<Write>>>>,
+}
+
+
+impl AdapterIo {
+    pub fn new() -> AdapterIo {
+        AdapterIo {
+            inputs: HashMap::new(),
+            outputs: HashMap::new(),
+        }
+    }
+
+    /// Make `reader` available as the input file `name`. TeX often opens
+    /// files several times, so each time the file is opened, reading starts
+    /// again from the beginning of `reader`.
+    pub fn add_input<R: 'static + Read + Seek>(&mut self, name: &OsStr, reader: R) -> &mut Self {
+        self.inputs.insert(name.to_os_string(), Rc::new(RefCell::new(Box::new(reader))));
+        self
+    }
+
+    /// Send the output file `name` to `writer`. Generic writers can't be
+    /// truncated, so if the engine opens the file more than once (e.g., the
+    /// `.aux` file in a multi-pass build), the data from each opening are
+    /// written one after another.
+    pub fn add_output<W: 'static + Write>(&mut self, name: &OsStr, writer: W) -> &mut Self {
+        self.outputs.insert(name.to_os_string(), Rc::new(RefCell::new(Box::new(writer))));
+        self
+    }
+}
+
+
+impl IoProvider for AdapterIo {
+    fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
+        match self.outputs.get(name) {
+            Some(w) => OpenResult::Ok(OutputHandle::new(name, SharedWriter { inner: w.clone() })),
+            None => OpenResult::NotAvailable,
+        }
+    }
+
+    fn input_open_name(&mut self, name: &OsStr, _status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        match self.inputs.get(name) {
+            Some(r) => OpenResult::Ok(InputHandle::new(name, SharedReader { inner: r.clone(), pos: 0 },
+                                                       InputOrigin::Other)),
+            None => OpenResult::NotAvailable,
+        }
+    }
+}
+
+
+/// A handle onto a reader that may have several handles open at once. Each
+/// handle tracks its own position and seeks the reader before every read.
+struct SharedReader {
+    inner: Rc<RefCell<Box<ReadSeek>>>,
+    pos: u64,
+}
+
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        inner.seek(SeekFrom::Start(self.pos))?;
+        let n = inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl InputFeatures for SharedReader {
+    fn get_size(&mut self) -> Result<usize> {
+        Ok(self.inner.borrow_mut().seek(SeekFrom::End(0))? as usize)
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let mut inner = self.inner.borrow_mut();
+
+        self.pos = match pos {
+            SeekFrom::Current(n) => {
+                inner.seek(SeekFrom::Start(self.pos))?;
+                inner.seek(SeekFrom::Current(n))?
+            },
+            other => inner.seek(other)?,
+        };
+
+        Ok(self.pos)
+    }
+}
+
+
+struct SharedWriter {
+    inner: Rc<RefCell<Box<Write>>>,
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.borrow_mut().flush()
+    }
+}
@@ -0,0 +1,169 @@
+// src/io/allowlist.rs -- only allow reads of specific files
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A guard for services that compile untrusted documents. AllowlistIo wraps
+//! another provider and refuses to open any input that hasn't been
+//! explicitly allowed, either by its exact name or by its extension. This
+//! stops things like `\input{/etc/passwd}` at the I/O layer, without needing
+//! to patch the engines.
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use errors::ErrorKind;
+use status::StatusBackend;
+use super::{InputHandle, IoProvider, OpenResult, OutputHandle};
+
+
+pub struct AllowlistIo<P: IoProvider> {
+    inner: P,
+    names: HashSet<OsString>,
+    extensions: HashSet<OsString>,
+}
+
+
+impl<P: IoProvider> AllowlistIo<P> {
+    /// Wrap `inner`. Until something is added to the allowlist, no inputs
+    /// can be opened at all.
+    pub fn new(inner: P) -> AllowlistIo<P> {
+        AllowlistIo {
+            inner: inner,
+            names: HashSet::new(),
+            extensions: HashSet::new(),
+        }
+    }
+
+    /// Allow the input with exactly this name to be read. This is the only
+    /// way to allow absolute paths or paths containing `..`.
+    pub fn allow_name<S: AsRef<OsStr>>(&mut self, name: S) -> &mut Self {
+        self.names.insert(name.as_ref().to_os_string());
+        self
+    }
+
+    /// Allow inputs whose names end with this extension (given without the
+    /// leading dot, e.g. `"sty"`) to be read, as long as their names are
+    /// relative paths that don't contain `..` components. The empty
+    /// extension allows names without one, except for hidden files such as
+    /// `.env`.
+    pub fn allow_extension<S: AsRef<OsStr>>(&mut self, ext: S) -> &mut Self {
+        self.extensions.insert(ext.as_ref().to_os_string());
+        self
+    }
+
+    fn is_allowed(&self, name: &OsStr) -> bool {
+        if self.names.contains(name) {
+            return true;
+        }
+
+        let path = Path::new(name);
+
+        if path.components().any(|c| match c {
+            Component::Normal(_) | Component::CurDir => false,
+            _ => true,
+        }) {
+            return false;
+        }
+
+        match path.extension() {
+            Some(ext) => self.extensions.contains(ext),
+            None => match path.file_name() {
+                Some(base) if !base.to_string_lossy().starts_with('.') => self.extensions.contains(OsStr::new("")),
+                _ => false,
+            },
+        }
+    }
+}
+
+
+impl<P: IoProvider> IoProvider for AllowlistIo<P> {
+    fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
+        self.inner.output_open_name(name)
+    }
+
+    fn output_open_stdout(&mut self) -> OpenResult<OutputHandle> {
+        self.inner.output_open_stdout()
+    }
+
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        if !self.is_allowed(name) {
+            return OpenResult::Err(ErrorKind::PathForbidden(name.to_string_lossy().into_owned()).into());
+        }
+
+        self.inner.input_open_name(name, status)
+    }
+
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        if !self.is_allowed(name) {
+            return OpenResult::Err(ErrorKind::PathForbidden(name.to_string_lossy().into_owned()).into());
+        }
+
+        self.inner.input_open_name_with_abspath(name, status)
+    }
+
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        if !self.is_allowed(name) {
+            return OpenResult::Err(ErrorKind::PathForbidden(name.to_string_lossy().into_owned()).into());
+        }
+
+        self.inner.input_mtime(name)
+    }
+
+    fn input_open_primary(&mut self, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.inner.input_open_primary(status)
+    }
+
+    fn input_open_primary_with_abspath(&mut self, status: &mut StatusBackend)
+                                       -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        self.inner.input_open_primary_with_abspath(status)
+    }
+
+    fn input_open_format(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.inner.input_open_format(name, status)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use io::memory::MemoryIo;
+    use status::NoopStatusBackend;
+    use super::*;
+
+    #[test]
+    fn allowlist() {
+        let mut mem = MemoryIo::new(false);
+        mem.create_entry(OsStr::new("article.cls"), b"cls".to_vec());
+        mem.create_entry(OsStr::new("secret.txt"), b"secret".to_vec());
+
+        let mut al = AllowlistIo::new(mem);
+        al.allow_extension("cls").allow_extension("txt").allow_name("/etc/fonts.conf");
+        let mut sb = NoopStatusBackend::new();
+
+        al.input_open_name(OsStr::new("article.cls"), &mut sb).unwrap();
+        al.input_open_name(OsStr::new("secret.txt"), &mut sb).unwrap();
+        assert!(al.input_open_name(OsStr::new("missing.cls"), &mut sb).is_not_available());
+        assert!(al.input_open_name(OsStr::new("/etc/fonts.conf"), &mut sb).is_not_available());
+
+        for bad in &["/etc/passwd.txt", "../secret.txt", "article.sty", "noext"] {
+            match al.input_open_name(OsStr::new(bad), &mut sb) {
+                OpenResult::Err(e) => match *e.kind() {
+                    ErrorKind::PathForbidden(_) => {},
+                    ref k => panic!("unexpected error for {}: {}", bad, k),
+                },
+                _ => panic!("{} should have been forbidden", bad),
+            }
+        }
+
+        al.allow_extension("");
+        assert!(al.input_open_name(OsStr::new("noext"), &mut sb).is_not_available());
+
+        match al.input_open_name(OsStr::new(".env"), &mut sb) {
+            OpenResult::Err(_) => {},
+            _ => panic!(".env should have been forbidden"),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// src/io/audit.rs -- recording a trail of I/O accesses
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An I/O audit log records every file open that goes through an IoStack:
+//! what was asked for, which provider in the stack satisfied the request (if
+//! any), when it happened, and how many bytes were ultimately transferred.
+//! It's meant for answering "why did it read *that*?" questions and for
+//! tools that need to know exactly what a build touched.
+
+use serde_json;
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::io::{self, Read, SeekFrom, Write};
+use std::rc::Rc;
+use std::time::Instant;
+
+use errors::Result;
+use super::{InputFeatures, InputHandle, OpenResult, OutputHandle};
+
+
+/// The kind of I/O operation that an AuditRecord describes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum AuditOperation {
+    InputOpen,
+    PrimaryInputOpen,
+    FormatOpen,
+    OutputOpen,
+    StdoutOpen,
+}
+
+/// What happened when a file was opened.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum AuditOutcome {
+    Opened,
+    NotAvailable,
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuditRecord {
+    pub operation: AuditOperation,
+    pub name: String,
+
+    /// The position in the IoStack of the provider that handled the request.
+    pub provider: Option<usize>,
+
+    /// The name of that provider, if the IoStack knows it.
+    pub provider_name: Option<String>,
+
+    pub outcome: AuditOutcome,
+
+    /// When the open happened, in seconds since the log was created.
+    pub time: f64,
+
+    /// The number of bytes read from or written to the file.
+    pub bytes: u64,
+}
+
+
+struct AuditData {
+    start: Instant,
+    records: Vec<AuditRecord>,
+}
+
+/// A shareable handle to an I/O audit log. Clones refer to the same log, so
+/// that one log can be attached to the successive IoStacks used in a
+/// processing session.
+#[derive(Clone)]
+pub struct IoAuditLog(Rc<RefCell<AuditData>>);
+
+
+impl IoAuditLog {
+    pub fn new() -> IoAuditLog {
+        IoAuditLog(Rc::new(RefCell::new(AuditData {
+            start: Instant::now(),
+            records: Vec::new(),
+        })))
+    }
+
+    /// Get a copy of all of the records logged so far.
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.0.borrow().records.clone()
+    }
+
+    /// Write out the log as a JSON array of records.
+    pub fn write_json<W: Write>(&self, dest: &mut W) -> Result<()> {
+        serde_json::to_writer_pretty(dest, &self.0.borrow().records)?;
+        Ok(())
+    }
+
+    fn push(&self, operation: AuditOperation, name: &OsStr, provider: Option<usize>,
+            provider_name: Option<&str>, outcome: AuditOutcome) -> usize {
+        let mut data = self.0.borrow_mut();
+        let elapsed = data.start.elapsed();
+
+        data.records.push(AuditRecord {
+            operation: operation,
+            name: name.to_string_lossy().into_owned(),
+            provider: provider,
+            provider_name: provider_name.map(|s| s.to_owned()),
+            outcome: outcome,
+            time: elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9,
+            bytes: 0,
+        });
+
+        data.records.len() - 1
+    }
+
+    fn add_bytes(&self, index: usize, n: usize) {
+        self.0.borrow_mut().records[index].bytes += n as u64;
+    }
+
+    /// Log the result of an attempt to open an input, wrapping the handle so
+    /// that the bytes read from it are counted.
+    pub fn log_input(&self, operation: AuditOperation, name: &OsStr, provider: Option<usize>,
+                     provider_name: Option<&str>, r: OpenResult<InputHandle>) -> OpenResult<InputHandle> {
+        match r {
+            OpenResult::Ok(ih) => {
+                let index = self.push(operation, name, provider, provider_name, AuditOutcome::Opened);
+                let wrapped_name = ih.name().to_owned();
+                let origin = ih.origin();
+
+                OpenResult::Ok(InputHandle::new(&wrapped_name, AuditedInput {
+                    inner: ih,
+                    log: self.clone(),
+                    index: index,
+                }, origin))
+            },
+            OpenResult::NotAvailable => {
+                self.push(operation, name, None, None, AuditOutcome::NotAvailable);
+                OpenResult::NotAvailable
+            },
+            OpenResult::Err(e) => {
+                self.push(operation, name, provider, provider_name, AuditOutcome::Failed);
+                OpenResult::Err(e)
+            },
+        }
+    }
+
+    /// Log the result of an attempt to open an output, wrapping the handle
+    /// so that the bytes written to it are counted.
+    pub fn log_output(&self, operation: AuditOperation, name: &OsStr, provider: Option<usize>,
+                      provider_name: Option<&str>, r: OpenResult<OutputHandle>) -> OpenResult<OutputHandle> {
+        match r {
+            OpenResult::Ok(oh) => {
+                let index = self.push(operation, name, provider, provider_name, AuditOutcome::Opened);
+                let wrapped_name = oh.name().to_owned();
+
+                OpenResult::Ok(OutputHandle::new(&wrapped_name, AuditedOutput {
+                    inner: oh,
+                    log: self.clone(),
+                    index: index,
+                }))
+            },
+            OpenResult::NotAvailable => {
+                self.push(operation, name, None, None, AuditOutcome::NotAvailable);
+                OpenResult::NotAvailable
+            },
+            OpenResult::Err(e) => {
+                self.push(operation, name, provider, provider_name, AuditOutcome::Failed);
+                OpenResult::Err(e)
+            },
+        }
+    }
+}
+
+
+struct AuditedInput {
+    inner: InputHandle,
+    log: IoAuditLog,
+    index: usize,
+}
+
+impl Read for AuditedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.log.add_bytes(self.index, n);
+        Ok(n)
+    }
+}
+
+impl InputFeatures for AuditedInput {
+    fn get_size(&mut self) -> Result<usize> {
+        self.inner.get_size()
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.inner.try_seek(pos)
+    }
+}
+
+
+struct AuditedOutput {
+    inner: OutputHandle,
+    log: IoAuditLog,
+    index: usize,
+}
+
+impl Write for AuditedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.log.add_bytes(self.index, n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,146 @@
+// src/io/casefold.rs -- case-insensitive fallback for file lookups
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Documents written on case-insensitive filesystems (the defaults on
+//! Windows and macOS) often refer to files with the wrong capitalization,
+//! e.g. `\includegraphics{Figure1.PNG}` for `figure1.png`. Such documents
+//! break on case-sensitive systems. CaseFoldIo wraps another provider and,
+//! if a lookup fails, tries again with the name that differs only in case,
+//! using a prebuilt index of the names that the wrapped provider knows.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use errors::Result;
+use status::StatusBackend;
+use super::{InputHandle, IoProvider, OpenResult};
+
+
+pub struct CaseFoldIo<P: IoProvider> {
+    inner: P,
+    index: HashMap<String, OsString>,
+}
+
+
+fn fold(name: &OsStr) -> String {
+    name.to_string_lossy().to_lowercase()
+}
+
+
+impl<P: IoProvider> CaseFoldIo<P> {
+    /// Wrap `inner`, given the names of all of the files it can provide. If
+    /// several names differ only in case, the first one wins.
+    pub fn new<I: IntoIterator<Item = OsString>>(inner: P, names: I) -> CaseFoldIo<P> {
+        let mut index = HashMap::new();
+
+        for name in names {
+            index.entry(fold(&name)).or_insert(name);
+        }
+
+        CaseFoldIo {
+            inner: inner,
+            index: index,
+        }
+    }
+
+    /// Wrap `inner`, which should provide the files in the directory `root`.
+    /// The index is built by listing all of the files beneath `root`, named
+    /// by their paths relative to it.
+    pub fn for_directory(inner: P, root: &Path) -> Result<CaseFoldIo<P>> {
+        let mut names = Vec::new();
+        list_dir(root, "", &mut names)?;
+        Ok(Self::new(inner, names))
+    }
+}
+
+
+fn list_dir(dir: &Path, prefix: &str, names: &mut Vec<OsString>) -> Result<()> {
+    // An empty root path means the current directory.
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+
+    let mut items = fs::read_dir(dir)?.collect::<::std::io::Result<Vec<_>>>()?;
+    items.sort_by_key(|e| e.file_name());
+
+    for item in items {
+        let fname = item.file_name();
+        let fname = match fname.to_str() {
+            Some(s) => s,
+            None => continue, // can't be named from TeX anyway
+        };
+
+        let relname = format!("{}{}", prefix, fname);
+
+        if item.file_type()?.is_dir() {
+            list_dir(&item.path(), &format!("{}/", relname), names)?;
+        } else {
+            names.push(OsString::from(relname));
+        }
+    }
+
+    Ok(())
+}
+
+
+impl<P: IoProvider> IoProvider for CaseFoldIo<P> {
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.input_open_name_with_abspath(name, status).map(|(h, _)| h)
+    }
+
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        match self.inner.input_open_name_with_abspath(name, status) {
+            OpenResult::NotAvailable => {},
+            r => return r,
+        }
+
+        let real_name = match self.index.get(&fold(name)) {
+            Some(n) if n.as_os_str() != name => n.clone(),
+            _ => return OpenResult::NotAvailable,
+        };
+
+        tt_warning!(status, "\"{}\" does not exist; using \"{}\", which differs only in case",
+                    name.to_string_lossy(), real_name.to_string_lossy());
+        self.inner.input_open_name_with_abspath(&real_name, status)
+    }
+
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        match self.inner.input_mtime(name) {
+            OpenResult::NotAvailable => {},
+            r => return r,
+        }
+
+        let real_name = match self.index.get(&fold(name)) {
+            Some(n) if n.as_os_str() != name => n.clone(),
+            _ => return OpenResult::NotAvailable,
+        };
+
+        self.inner.input_mtime(&real_name)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use io::memory::MemoryIo;
+    use status::NoopStatusBackend;
+    use std::io::Read;
+    use super::*;
+
+    #[test]
+    fn fallback() {
+        let mut mem = MemoryIo::new(false);
+        mem.create_entry(OsStr::new("figures/figure1.png"), b"png".to_vec());
+
+        let mut cf = CaseFoldIo::new(mem, vec![OsString::from("figures/figure1.png")]);
+        let mut sb = NoopStatusBackend::new();
+        let mut s = String::new();
+
+        cf.input_open_name(OsStr::new("Figures/Figure1.PNG"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "png");
+        assert!(cf.input_open_name(OsStr::new("figure2.png"), &mut sb).is_not_available());
+    }
+}
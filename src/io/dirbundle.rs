@@ -0,0 +1,84 @@
+// src/io/dirbundle.rs -- I/O on files in an unpacked "bundle" directory
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+
+use digest;
+use errors::{ErrorKind, Result};
+use super::{try_open_file, InputHandle, InputOrigin, IoProvider, OpenResult};
+use status::StatusBackend;
+
+
+/// A DirBundle is a bundle whose files live in a plain directory on disk,
+/// such as you would get by unpacking a Zip bundle. As with other bundles,
+/// the directory must contain a `SHA256SUM` file identifying its contents.
+///
+/// Unlike FilesystemIo, a DirBundle will only ever serve up files that live
+/// inside its directory: absolute paths and paths that climb out of it with
+/// `..` are treated as unavailable.
+
+pub struct DirBundle {
+    dir: PathBuf,
+}
+
+
+impl DirBundle {
+    pub fn new(dir: &Path) -> Result<DirBundle> {
+        let mut digest_path = dir.to_owned();
+        digest_path.push(digest::DIGEST_NAME);
+
+        if !digest_path.is_file() {
+            return Err(ErrorKind::Msg(format!("bundle directory \"{}\" does not contain a {} file",
+                                              dir.display(), digest::DIGEST_NAME)).into());
+        }
+
+        Ok(DirBundle {
+            dir: dir.to_owned(),
+        })
+    }
+}
+
+
+impl IoProvider for DirBundle {
+    fn input_open_name(&mut self, name: &OsStr, _status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        let path = Path::new(name);
+
+        for c in path.components() {
+            match c {
+                Component::Normal(_) | Component::CurDir => {},
+                _ => return OpenResult::NotAvailable,
+            }
+        }
+
+        let mut full_path = self.dir.clone();
+        full_path.push(path);
+
+        if !full_path.is_file() {
+            return OpenResult::NotAvailable;
+        }
+
+        match try_open_file(&full_path) {
+            OpenResult::Ok(f) => OpenResult::Ok(InputHandle::new(name, BufReader::new(f), InputOrigin::Other)),
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    /// Bundles are flat, so this only lists the top level of the directory.
+    fn file_names(&mut self, _status: &mut StatusBackend) -> Vec<OsString> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(e) => e,
+            Err(_) => return Vec::new(),
+        };
+
+        entries.filter_map(|e| e.ok())
+            .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|e| e.file_name())
+            .collect()
+    }
+}
+
@@ -3,11 +3,14 @@
 // Licensed under the MIT License.
 
 use libc;
+use memmap::{Mmap, Protection};
+use std::cmp;
 use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{self, BufReader, Seek, SeekFrom};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
 
 use errors::{ErrorKind, Result};
 use status::StatusBackend;
@@ -38,28 +41,37 @@ impl FilesystemPrimaryInputIo {
 
 
 impl IoProvider for FilesystemPrimaryInputIo {
-    fn input_open_primary(&mut self, _status: &mut StatusBackend) -> OpenResult<InputHandle> {
+    fn input_open_primary(&mut self, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.input_open_primary_with_abspath(status).map(|(h, _)| h)
+    }
+
+    fn input_open_primary_with_abspath(&mut self, _status: &mut StatusBackend)
+                                       -> OpenResult<(InputHandle, Option<PathBuf>)> {
         let f = match try_open_file(&self.path) {
             OpenResult::Ok(f) => f,
             OpenResult::NotAvailable => return OpenResult::NotAvailable,
             OpenResult::Err(e) => return OpenResult::Err(e),
         };
 
-        OpenResult::Ok(InputHandle::new(OsStr::new(""), BufReader::new(f), InputOrigin::Filesystem))
+        OpenResult::Ok((InputHandle::new(OsStr::new(""), BufReader::new(f), InputOrigin::Filesystem),
+                        self.path.canonicalize().ok()))
     }
 }
 
 
 /// FilesystemIo is an I/O provider that reads, and optionally writes, files
-/// from a given root directory. NOTE: no effort is made to contain I/O within
-/// the specified root!! We have an option to disallow absolute paths, but we
-/// don't do anything about "../../../...." paths.
+/// from a given root directory. NOTE: by default, no effort is made to
+/// contain I/O within the specified root!! We have an option to disallow
+/// absolute paths, but we don't do anything about "../../../...." paths
+/// unless `confine_to_root()` is turned on.
 
 pub struct FilesystemIo {
     root: PathBuf,
     writes_allowed: bool,
     absolute_allowed: bool,
     hidden_input_paths: HashSet<PathBuf>,
+    mmap_threshold: Option<u64>,
+    confined: bool,
 }
 
 impl FilesystemIo {
@@ -70,9 +82,36 @@ impl FilesystemIo {
             writes_allowed: writes_allowed,
             absolute_allowed: absolute_allowed,
             hidden_input_paths: hidden_input_paths,
+            mmap_threshold: None,
+            confined: false,
         }
     }
 
+    /// Refuse to access any file that does not live inside the root
+    /// directory. Absolute paths, `..` components, and symbolic links that
+    /// lead outside of the root all result in `ErrorKind::PathForbidden`.
+    /// This is needed to safely process untrusted documents.
+    pub fn confine_to_root(&mut self, setting: bool) -> &mut Self {
+        self.confined = setting;
+        self
+    }
+
+    /// Memory-map input files that are at least `threshold` bytes in size,
+    /// rather than reading them through a buffer. This can substantially
+    /// reduce memory usage when large images or data files are read. Note
+    /// that if a mapped file is modified by another program while we're
+    /// reading it, the results are unpredictable.
+    pub fn mmap_threshold(&mut self, threshold: Option<u64>) -> &mut Self {
+        self.mmap_threshold = threshold;
+        self
+    }
+
+    /// Hidden paths are checked before confinement resolves the path, since
+    /// the hidden paths aren't resolved themselves.
+    fn is_hidden(&self, name: &OsStr) -> bool {
+        self.hidden_input_paths.contains(&self.root.join(name))
+    }
+
     fn construct_path(&mut self, name: &OsStr) -> Result<PathBuf> {
         let path = Path::new(name);
 
@@ -83,8 +122,65 @@ impl FilesystemIo {
 
         let mut combined = PathBuf::from(&self.root);
         combined.push(path);
+
+        if self.confined {
+            return self.confine_path(path, combined);
+        }
+
         Ok(combined)
     }
+
+    /// Check that `combined`, which was created by joining the relative path
+    /// `path` onto our root, doesn't lead outside of the root. Returns the
+    /// resolved version of the path.
+    fn confine_path(&self, path: &Path, combined: PathBuf) -> Result<PathBuf> {
+        let forbidden = || ErrorKind::PathForbidden(path.to_string_lossy().into_owned()).into();
+
+        // First, a lexical check, which covers paths that don't (yet) exist.
+
+        let mut depth = 0;
+
+        for c in path.components() {
+            match c {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {},
+                Component::ParentDir => {
+                    if depth == 0 {
+                        return Err(forbidden());
+                    }
+                    depth -= 1;
+                },
+                Component::RootDir | Component::Prefix(_) => return Err(forbidden()),
+            }
+        }
+
+        // Now resolve symlinks. If the file doesn't exist, its parent
+        // directory might, and it might be a symlink to somewhere else.
+
+        let root = if self.root.as_os_str().is_empty() { Path::new(".") } else { self.root.as_path() };
+        let canon_root = root.canonicalize()?;
+
+        let resolved = match combined.canonicalize() {
+            Ok(p) => p,
+            Err(_) => {
+                let parent = match combined.parent() {
+                    Some(p) if !p.as_os_str().is_empty() => p,
+                    _ => root,
+                };
+
+                match (parent.canonicalize(), combined.file_name()) {
+                    (Ok(p), Some(f)) => p.join(f),
+                    _ => return Ok(combined), // the parent doesn't exist, so neither does the file
+                }
+            },
+        };
+
+        if !resolved.starts_with(&canon_root) {
+            return Err(forbidden());
+        }
+
+        Ok(resolved)
+    }
 }
 
 
@@ -112,17 +208,39 @@ impl IoProvider for FilesystemIo {
         OpenResult::NotAvailable
     }
 
-    fn input_open_name(&mut self, name: &OsStr, _status: &mut StatusBackend) -> OpenResult<InputHandle> {
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.input_open_name_with_abspath(name, status).map(|(h, _)| h)
+    }
+
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        if self.is_hidden(name) {
+            return OpenResult::NotAvailable;
+        }
+
         let path = match self.construct_path(name) {
             Ok(p) => p,
             Err(e) => return OpenResult::Err(e.into())
         };
 
-        if self.hidden_input_paths.contains(&path) {
+        match path.metadata().and_then(|md| md.modified()) {
+            Ok(t) => OpenResult::Ok(t),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => OpenResult::NotAvailable,
+            Err(e) => OpenResult::Err(e.into()),
+        }
+    }
+
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, _status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        if self.is_hidden(name) {
             return OpenResult::NotAvailable;
         }
 
-        let f = match File::open (path) {
+        let path = match self.construct_path(name) {
+            Ok(p) => p,
+            Err(e) => return OpenResult::Err(e.into())
+        };
+
+        let f = match File::open (&path) {
             Ok(f) => f,
             Err(e) => return if e.kind() == io::ErrorKind::NotFound {
                 OpenResult::NotAvailable
@@ -137,7 +255,67 @@ impl IoProvider for FilesystemIo {
             }
         };
 
-        OpenResult::Ok(InputHandle::new(name, BufReader::new(f), InputOrigin::Filesystem))
+        let abspath = path.canonicalize().ok();
+
+        if let Some(threshold) = self.mmap_threshold {
+            // Empty files can't be mapped, so make sure that the threshold is
+            // at least 1.
+            let size = match f.metadata() {
+                Ok(md) => md.len(),
+                Err(e) => return OpenResult::Err(e.into()),
+            };
+
+            if size >= cmp::max(threshold, 1) {
+                return match Mmap::open(&f, Protection::Read) {
+                    Ok(map) => OpenResult::Ok((InputHandle::new(name, MmapInput { map: map, pos: 0 },
+                                                                InputOrigin::Filesystem), abspath)),
+                    Err(e) => OpenResult::Err(e.into()),
+                };
+            }
+        }
+
+        OpenResult::Ok((InputHandle::new(name, BufReader::new(f), InputOrigin::Filesystem), abspath))
+    }
+}
+
+
+/// An input file that has been memory-mapped.
+struct MmapInput {
+    map: Mmap,
+    pos: usize,
+}
+
+impl Read for MmapInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Safe as long as no one modifies the file while it is mapped; see
+        // FilesystemIo::mmap_threshold().
+        let data = unsafe { self.map.as_slice() };
+        let start = cmp::min(self.pos, data.len());
+        let n = cmp::min(buf.len(), data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos = start + n;
+        Ok(n)
+    }
+}
+
+impl InputFeatures for MmapInput {
+    fn get_size(&mut self) -> Result<usize> {
+        Ok(self.map.len())
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.map.len() as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+
+        if new_pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position").into());
+        }
+
+        self.pos = new_pos as usize;
+        Ok(new_pos as u64)
     }
 }
 
@@ -162,3 +340,51 @@ impl InputFeatures for BufReader<File> {
         Ok(self.seek(pos)?)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use status::NoopStatusBackend;
+    use self::tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn confinement() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new("fsconfine").unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir(&root).unwrap();
+        File::create(root.join("ok.tex")).unwrap();
+        File::create(tmp.path().join("secret.txt")).unwrap();
+        symlink(tmp.path().join("secret.txt"), root.join("link.txt")).unwrap();
+
+        let mut fsio = FilesystemIo::new(&root, false, true, HashSet::new());
+        fsio.confine_to_root(true);
+        let mut sb = NoopStatusBackend::new();
+
+        fsio.input_open_name(OsStr::new("ok.tex"), &mut sb).unwrap();
+        assert!(fsio.input_open_name(OsStr::new("missing.tex"), &mut sb).is_not_available());
+
+        let mut hidden = HashSet::new();
+        hidden.insert(root.join("ok.tex"));
+        let mut hiding = FilesystemIo::new(&root, false, true, hidden);
+        hiding.confine_to_root(true);
+        assert!(hiding.input_open_name(OsStr::new("ok.tex"), &mut sb).is_not_available());
+        assert!(hiding.input_mtime(OsStr::new("ok.tex")).is_not_available());
+
+        for bad in &["../secret.txt", "link.txt", "sub/../../secret.txt"] {
+            match fsio.input_open_name(OsStr::new(bad), &mut sb) {
+                OpenResult::Err(e) => match *e.kind() {
+                    ErrorKind::PathForbidden(_) => {},
+                    ref k => panic!("unexpected error for {}: {}", bad, k),
+                },
+                _ => panic!("{} should have been forbidden", bad),
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// src/io/format_cache.rs -- on-disk caching of generated format files
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Generating a format file takes a while, so we save them on disk. A
+//! format depends on the support files that it was generated from and on the
+//! engine that generated it, so cached formats are named by the digest of
+//! their bundle, the format serial number, and the engine version. If any of
+//! these change, the old format is never looked at again.
+//!
+//! Bundles fetched through `LocalCache` have their formats saved alongside
+//! the rest of the cache. `FormatCache` provides the same service for other
+//! bundles, such as Zip files and directories on local disk.
+
+use mkstemp;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use digest::DigestData;
+use errors::{ErrorKind, Result};
+use status::StatusBackend;
+use super::{get_bundle_digest, try_open_file, InputHandle, InputOrigin, IoProvider, OpenResult};
+
+
+/// Get the name of the cache file for the format `name` generated from the
+/// bundle identified by `bundle_digest`.
+pub fn format_cache_name(bundle_digest: &DigestData, name: &OsStr) -> Result<String> {
+    // Remove all extensions from the format name. PathBuf.file_stem() doesn't
+    // do what we want since it only strips one extension, so here we go:
+
+    let stem = match name.to_str().and_then(|s| s.splitn(2, ".").next()) {
+        Some(s) => s,
+        None => {
+            return Err(ErrorKind::Msg(format!("incomprehensible format file name \"{}\"",
+                                              name.to_string_lossy())).into());
+        }
+    };
+
+    Ok(format!("{}-{}-{}-{}.fmt.gz", bundle_digest.to_string(), stem, ::FORMAT_SERIAL,
+               env!("CARGO_PKG_VERSION")))
+}
+
+
+/// Save a format file in the directory `formats_base`. The file is written
+/// under a temporary name and then moved into place, so that concurrent
+/// processes never see a partial file.
+pub fn save_format(formats_base: &Path, final_name: &str, data: &[u8]) -> Result<()> {
+    let mut templ = formats_base.to_owned();
+    templ.push("format_XXXXXX");
+
+    let temp_path = {
+        let mut temp_dest = mkstemp::TempFile::new(&templ.to_string_lossy(), false)?;
+        temp_dest.write_all(data)?;
+        temp_dest.path().to_owned()
+    };
+
+    fs::rename(&temp_path, formats_base.join(final_name)).map_err(|e| e.into())
+}
+
+
+pub struct FormatCache {
+    bundle: Box<IoProvider>,
+    formats_base: PathBuf,
+
+    /// The bundle's digest, once we've looked it up. The inner Option is
+    /// None if the bundle doesn't provide one, in which case we can't cache
+    /// its formats.
+    bundle_digest: Option<Option<DigestData>>,
+}
+
+
+impl FormatCache {
+    pub fn new(bundle: Box<IoProvider>, formats_base: &Path) -> FormatCache {
+        FormatCache {
+            bundle: bundle,
+            formats_base: formats_base.to_owned(),
+            bundle_digest: None,
+        }
+    }
+
+    fn cache_name_for_format(&mut self, name: &OsStr, status: &mut StatusBackend) -> Result<Option<String>> {
+        if self.bundle_digest.is_none() {
+            self.bundle_digest = Some(match get_bundle_digest(&mut *self.bundle, status) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    tt_warning!(status, "format files for this bundle cannot be cached"; e);
+                    None
+                }
+            });
+        }
+
+        match self.bundle_digest {
+            Some(Some(ref d)) => Ok(Some(format_cache_name(d, name)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+
+impl IoProvider for FormatCache {
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.bundle.input_open_name(name, status)
+    }
+
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        self.bundle.input_open_name_with_abspath(name, status)
+    }
+
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        self.bundle.input_mtime(name)
+    }
+
+    fn input_open_format(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        let path = match self.cache_name_for_format(name, status) {
+            Ok(Some(n)) => self.formats_base.join(n),
+            Ok(None) => return self.bundle.input_open_format(name, status),
+            Err(e) => return OpenResult::Err(e),
+        };
+
+        match try_open_file(&path) {
+            OpenResult::Ok(f) => OpenResult::Ok(InputHandle::new(name, BufReader::new(f), InputOrigin::Other)),
+            OpenResult::NotAvailable => self.bundle.input_open_format(name, status),
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    fn write_format(&mut self, name: &str, data: &[u8], status: &mut StatusBackend) -> Result<()> {
+        match self.cache_name_for_format(OsStr::new(name), status)? {
+            Some(n) => save_format(&self.formats_base, &n, data),
+            None => self.bundle.write_format(name, data, status),
+        }
+    }
+
+    fn file_names(&mut self, status: &mut StatusBackend) -> Vec<OsString> {
+        self.bundle.file_names(status)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use io::memory::MemoryIo;
+    use status::NoopStatusBackend;
+    use std::io::Read;
+    use self::tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn keyed_on_digest() {
+        let dir = TempDir::new("tectonic_format_cache").unwrap();
+        let mut sb = NoopStatusBackend::new();
+        let digest_text = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+        let mut mem = MemoryIo::new(false);
+        mem.create_entry(OsStr::new("SHA256SUM"), digest_text.as_bytes().to_vec());
+        let mut fc = FormatCache::new(Box::new(mem), dir.path());
+
+        assert!(fc.input_open_format(OsStr::new("latex.fmt"), &mut sb).is_not_available());
+        fc.write_format("latex.fmt", b"dump", &mut sb).unwrap();
+
+        let mut s = String::new();
+        fc.input_open_format(OsStr::new("latex.fmt"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "dump");
+
+        let expected = format_cache_name(&digest_text.parse().unwrap(), OsStr::new("latex")).unwrap();
+        assert!(dir.path().join(expected).is_file());
+
+        // A different version of the bundle doesn't see the old format.
+        let mut mem = MemoryIo::new(false);
+        mem.create_entry(OsStr::new("SHA256SUM"), digest_text.replace("0", "1").into_bytes());
+        let mut fc = FormatCache::new(Box::new(mem), dir.path());
+        assert!(fc.input_open_format(OsStr::new("latex.fmt"), &mut sb).is_not_available());
+    }
+}
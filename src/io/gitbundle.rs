@@ -0,0 +1,219 @@
+// src/io/gitbundle.rs -- I/O on files stored in a git repository
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A bundle whose files are stored in a git repository, pinned at a specific
+//! commit. This lets a group keep its custom TeX files under version control
+//! and have Tectonic read them straight out of the repository. Files can be
+//! found either by their full path within the repository or by their base
+//! name, since TeX trees are usually organized into subdirectories but
+//! documents refer to files by base name only.
+//!
+//! We drive the `git` command-line program rather than linking with a git
+//! library, so `git` must be installed to use this provider.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str;
+
+use errors::{ErrorKind, Result};
+use super::{InputHandle, InputOrigin, IoProvider, OpenResult};
+use status::StatusBackend;
+
+
+pub struct GitBundle {
+    git_dir: PathBuf,
+    commit: String,
+    index: HashMap<OsString, String>,
+}
+
+
+/// Run git with the specified arguments and return its standard output.
+fn run_git(git_dir: Option<&Path>, args: &[&OsStr]) -> Result<Vec<u8>> {
+    let mut cmd = Command::new("git");
+
+    if let Some(d) = git_dir {
+        cmd.arg("--git-dir").arg(d);
+    }
+
+    cmd.args(args);
+
+    let output = match cmd.output() {
+        Ok(o) => o,
+        Err(e) => return Err(ErrorKind::Msg(format!("failed to run \"git\": {}", e)).into()),
+    };
+
+    if !output.status.success() {
+        return Err(ErrorKind::Msg(format!("\"git {}\" failed: {}",
+                                          args.iter().map(|a| a.to_string_lossy()).collect::<Vec<_>>().join(" "),
+                                          String::from_utf8_lossy(&output.stderr).trim())).into());
+    }
+
+    Ok(output.stdout)
+}
+
+
+impl GitBundle {
+    /// Open a bundle from a repository on the local filesystem. `git_dir`
+    /// may be either a bare repository or the `.git` directory of a working
+    /// tree. `commit` may be anything that git can resolve to a commit; it is
+    /// resolved once, when the bundle is opened.
+    pub fn open(git_dir: &Path, commit: &str) -> Result<GitBundle> {
+        let spec = format!("{}^{{commit}}", commit);
+        let sha = run_git(Some(git_dir), &[OsStr::new("rev-parse"), OsStr::new("--verify"), OsStr::new(&spec)])?;
+        let sha = str::from_utf8(&sha)?.trim().to_owned();
+
+        let listing = run_git(Some(git_dir), &[OsStr::new("ls-tree"), OsStr::new("-r"), OsStr::new("-z"),
+                                               OsStr::new("--name-only"), OsStr::new(&sha)])?;
+        let mut index = HashMap::new();
+
+        for item in listing.split(|b| *b == 0) {
+            let path = match str::from_utf8(item) {
+                Ok(p) if p.len() > 0 => p,
+                _ => continue,
+            };
+
+            // Full paths always win over base names; among base names, the
+            // first one listed (i.e., alphabetically by path) wins.
+            index.insert(OsString::from(path), path.to_owned());
+
+            if let Some(base) = path.rsplit('/').next() {
+                index.entry(OsString::from(base)).or_insert_with(|| path.to_owned());
+            }
+        }
+
+        Ok(GitBundle {
+            git_dir: git_dir.to_owned(),
+            commit: sha,
+            index: index,
+        })
+    }
+
+    /// Open a bundle from a remote repository. A bare clone of the
+    /// repository is kept in `clone_dir`; if the clone already exists and
+    /// doesn't contain `commit`, it is updated from the remote first.
+    pub fn open_remote(url: &str, clone_dir: &Path, commit: &str,
+                       status: &mut StatusBackend) -> Result<GitBundle> {
+        if !clone_dir.join("HEAD").exists() {
+            tt_note!(status, "cloning {}", url);
+            run_git(None, &[OsStr::new("clone"), OsStr::new("--bare"), OsStr::new("--quiet"),
+                            OsStr::new(url), clone_dir.as_os_str()])?;
+        } else {
+            let spec = format!("{}^{{commit}}", commit);
+            let have_it = run_git(Some(clone_dir), &[OsStr::new("rev-parse"), OsStr::new("--verify"),
+                                                     OsStr::new("--quiet"), OsStr::new(&spec)]).is_ok();
+
+            if !have_it {
+                tt_note!(status, "updating clone of {}", url);
+                run_git(Some(clone_dir), &[OsStr::new("fetch"), OsStr::new("--quiet"), OsStr::new(url),
+                                           OsStr::new("+refs/heads/*:refs/heads/*"),
+                                           OsStr::new("+refs/tags/*:refs/tags/*")])?;
+            }
+        }
+
+        Self::open(clone_dir, commit)
+    }
+}
+
+
+impl IoProvider for GitBundle {
+    fn input_open_name(&mut self, name: &OsStr, _status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        let path = match self.index.get(name) {
+            Some(p) => p,
+            None => return OpenResult::NotAvailable,
+        };
+
+        let spec = format!("{}:{}", self.commit, path);
+
+        match run_git(Some(&self.git_dir), &[OsStr::new("cat-file"), OsStr::new("blob"), OsStr::new(&spec)]) {
+            Ok(data) => OpenResult::Ok(InputHandle::new(name, Cursor::new(data), InputOrigin::Other)),
+            Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    fn file_names(&mut self, _status: &mut StatusBackend) -> Vec<OsString> {
+        self.index.keys().cloned().collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::io::{Read, Write};
+    use std::process::Stdio;
+    use status::NoopStatusBackend;
+    use self::tempdir::TempDir;
+    use super::*;
+
+    /// Run a git plumbing command in `git_dir`, feeding it `input`, and
+    /// return its trimmed output.
+    fn git(git_dir: &Path, args: &[&str], input: &[u8]) -> String {
+        let mut child = Command::new("git")
+            .arg("--git-dir").arg(git_dir)
+            .args(&["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// Commit a tree holding `top.sty` and `sub/thesis.cls` with the given
+    /// contents, returning the commit's hash.
+    fn commit(git_dir: &Path, sty: &[u8], cls: &[u8], parent: Option<&str>) -> String {
+        let sty = git(git_dir, &["hash-object", "-w", "--stdin"], sty);
+        let cls = git(git_dir, &["hash-object", "-w", "--stdin"], cls);
+        let sub = git(git_dir, &["mktree"], format!("100644 blob {}\tthesis.cls\n", cls).as_bytes());
+        let root = git(git_dir, &["mktree"],
+                       format!("100644 blob {}\ttop.sty\n040000 tree {}\tsub\n", sty, sub).as_bytes());
+
+        match parent {
+            Some(p) => git(git_dir, &["commit-tree", &root, "-p", p, "-m", "update"], b""),
+            None => git(git_dir, &["commit-tree", &root, "-m", "initial"], b""),
+        }
+    }
+
+    #[test]
+    fn read_at_commit() {
+        let tmp = TempDir::new("gitbundle").unwrap();
+        let git_dir = tmp.path().join("texmf.git");
+        let status = Command::new("git").args(&["init", "--bare", "--quiet"]).arg(&git_dir).status().unwrap();
+        assert!(status.success());
+
+        let first = commit(&git_dir, b"old sty", b"cls", None);
+        let second = commit(&git_dir, b"new sty", b"cls", Some(&first));
+
+        let mut bundle = GitBundle::open(&git_dir, &first).unwrap();
+        let mut sb = NoopStatusBackend::new();
+        let mut s = String::new();
+
+        bundle.input_open_name(OsStr::new("top.sty"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "old sty");
+
+        s.clear();
+        bundle.input_open_name(OsStr::new("thesis.cls"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "cls");
+
+        s.clear();
+        bundle.input_open_name(OsStr::new("sub/thesis.cls"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "cls");
+
+        assert!(bundle.input_open_name(OsStr::new("missing.sty"), &mut sb).is_not_available());
+
+        let mut bundle = GitBundle::open(&git_dir, &second).unwrap();
+        s.clear();
+        bundle.input_open_name(OsStr::new("top.sty"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "new sty");
+
+        assert!(GitBundle::open(&git_dir, "0000000000000000000000000000000000000000").is_err());
+    }
+}
@@ -0,0 +1,362 @@
+// src/io/gzip.rs -- seekable reading of gzipped inputs
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Gzip streams can't generally be seeked, which trips up engine code paths
+//! that need to jump around in their inputs. This module provides readers
+//! that decompress gzipped inputs while still supporting seeks:
+//!
+//! - Files in the BGZF format produced by `bgzip` consist of a series of
+//!   small, independent gzip members, each of which records its compressed
+//!   size in a header field. We index the members when the file is opened,
+//!   and then only need to decompress a single member to seek anywhere in
+//!   the file.
+//! - Other gzip files can be seeked if the underlying input can: forward
+//!   seeks are done by decompressing and discarding data, and backward seeks
+//!   by starting over from the beginning of the file. This is slow but
+//!   correct.
+//!
+//! If the underlying input can't be seeked either, we fall back to a plain
+//! streaming decoder.
+
+use flate2::read::GzDecoder;
+use std::cmp;
+use std::io::{self, Cursor, Read, SeekFrom};
+
+use errors::{ErrorKind, Result};
+use super::InputFeatures;
+
+
+/// Open a gzipped input for reading, supporting seeks if at all possible.
+pub fn open_gzip(mut inner: Box<InputFeatures>) -> Result<Box<InputFeatures>> {
+    if inner.try_seek(SeekFrom::Current(0)).is_err() {
+        return Ok(Box::new(GzDecoder::new(inner)?));
+    }
+
+    if let Some(blocks) = index_bgzf(&mut *inner)? {
+        return Ok(Box::new(BgzfReader::new(inner, blocks)));
+    }
+
+    inner.try_seek(SeekFrom::Start(0))?;
+    Ok(Box::new(RestartingGzDecoder {
+        decoder: Some(GzDecoder::new(inner)?),
+        pos: 0,
+    }))
+}
+
+
+/// Information about one member of a BGZF file.
+struct BgzfBlock {
+    /// The offset of the member in the compressed file.
+    coffset: u64,
+
+    /// The total size of the member in the compressed file.
+    csize: usize,
+
+    /// The offset of the member's data in the uncompressed stream.
+    uoffset: u64,
+
+    /// The size of the member's data when uncompressed.
+    usize: usize,
+}
+
+
+/// If `inner` is in the BGZF format, build an index of its members.
+/// Otherwise return None. Either way, the input is left at an undefined
+/// position.
+fn index_bgzf(inner: &mut InputFeatures) -> Result<Option<Vec<BgzfBlock>>> {
+    let total = match inner.get_size() {
+        Ok(s) => s as u64,
+        Err(_) => return Ok(None),
+    };
+
+    let mut blocks = Vec::new();
+    let mut coffset = 0;
+    let mut uoffset = 0;
+
+    while coffset < total {
+        inner.try_seek(SeekFrom::Start(coffset))?;
+
+        let mut header = [0u8; 18];
+
+        if inner.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+
+        // ID1, ID2, CM = deflate, FLG = FEXTRA, XLEN = 6, SI1 = 'B', SI2 =
+        // 'C', SLEN = 2. Other extra subfields are allowed by the spec but
+        // bgzip never writes them, so we don't bother with them.
+        if header[0] != 0x1f || header[1] != 0x8b || header[2] != 8 || header[3] & 4 == 0 ||
+            header[10] != 6 || header[11] != 0 || header[12] != b'B' || header[13] != b'C' ||
+            header[14] != 2 || header[15] != 0 {
+            return Ok(None);
+        }
+
+        let csize = (header[16] as usize | (header[17] as usize) << 8) + 1;
+
+        if coffset + (csize as u64) > total || csize < 26 {
+            return Ok(None);
+        }
+
+        // The uncompressed size is in the last four bytes of the member.
+        inner.try_seek(SeekFrom::Start(coffset + csize as u64 - 4))?;
+        let mut isize = [0u8; 4];
+        inner.read_exact(&mut isize)?;
+        let usize = isize[0] as usize | (isize[1] as usize) << 8 | (isize[2] as usize) << 16 |
+                    (isize[3] as usize) << 24;
+
+        // Empty members, notably the EOF marker, don't need to be indexed.
+        if usize > 0 {
+            blocks.push(BgzfBlock {
+                coffset: coffset,
+                csize: csize,
+                uoffset: uoffset,
+                usize: usize,
+            });
+        }
+
+        coffset += csize as u64;
+        uoffset += usize as u64;
+    }
+
+    Ok(Some(blocks))
+}
+
+
+struct BgzfReader {
+    inner: Box<InputFeatures>,
+    blocks: Vec<BgzfBlock>,
+    size: u64,
+    pos: u64,
+
+    /// The index and decompressed data of the most recently used member.
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl BgzfReader {
+    fn new(inner: Box<InputFeatures>, blocks: Vec<BgzfBlock>) -> BgzfReader {
+        let size = blocks.last().map(|b| b.uoffset + b.usize as u64).unwrap_or(0);
+
+        BgzfReader {
+            inner: inner,
+            blocks: blocks,
+            size: size,
+            pos: 0,
+            current: None,
+        }
+    }
+
+    fn load_block(&mut self, index: usize) -> Result<()> {
+        if let Some((i, _)) = self.current {
+            if i == index {
+                return Ok(());
+            }
+        }
+
+        let block = &self.blocks[index];
+        let mut compressed = vec![0u8; block.csize];
+        self.inner.try_seek(SeekFrom::Start(block.coffset))?;
+        self.inner.read_exact(&mut compressed)?;
+
+        let mut data = Vec::with_capacity(block.usize);
+        GzDecoder::new(Cursor::new(compressed))?.read_to_end(&mut data)?;
+
+        if data.len() != block.usize {
+            return Err(ErrorKind::BadLength(block.usize, data.len()).into());
+        }
+
+        self.current = Some((index, data));
+        Ok(())
+    }
+}
+
+impl Read for BgzfReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.len() == 0 {
+            return Ok(0);
+        }
+
+        let pos = self.pos;
+        let index = match self.blocks.binary_search_by(|b| b.uoffset.cmp(&pos)) {
+            Ok(i) => i,
+            Err(i) => i - 1, // i > 0 since the first block starts at zero
+        };
+
+        self.load_block(index)?;
+
+        let start = (self.pos - self.blocks[index].uoffset) as usize;
+        let data = &self.current.as_ref().unwrap().1;
+        let n = cmp::min(buf.len(), data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl InputFeatures for BgzfReader {
+    fn get_size(&mut self) -> Result<usize> {
+        Ok(self.size as usize)
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.size as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+
+        if new_pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position").into());
+        }
+
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
+
+
+/// A decoder for ordinary gzip files on seekable inputs.
+struct RestartingGzDecoder {
+    // This is only None if restarting failed after we had given up the
+    // underlying input, in which case the decoder is unusable.
+    decoder: Option<GzDecoder<Box<InputFeatures>>>,
+    pos: u64,
+}
+
+impl RestartingGzDecoder {
+    fn restart(&mut self) -> Result<()> {
+        // Seek before taking the decoder apart, so that if the seek fails
+        // we still have a decoder to leave in place.
+        match self.decoder {
+            Some(ref mut d) => d.get_mut().try_seek(SeekFrom::Start(0))?,
+            None => return Err(ErrorKind::NotSeekable.into()),
+        };
+
+        let inner = self.decoder.take().unwrap().into_inner();
+        self.decoder = Some(GzDecoder::new(inner)?);
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn skip(&mut self, mut amount: u64) -> Result<u64> {
+        let mut buf = [0u8; 4096];
+
+        while amount > 0 {
+            let n = self.read(&mut buf[..cmp::min(amount, 4096) as usize])?;
+
+            if n == 0 {
+                break; // seeking past EOF is allowed
+            }
+
+            amount -= n as u64;
+        }
+
+        Ok(self.pos)
+    }
+}
+
+impl Read for RestartingGzDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.decoder {
+            Some(ref mut d) => d.read(buf)?,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "gzip decoder failed to restart")),
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl InputFeatures for RestartingGzDecoder {
+    fn get_size(&mut self) -> Result<usize> {
+        Err(ErrorKind::NotSizeable.into())
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n,
+            SeekFrom::Current(n) if n >= 0 => self.pos + n as u64,
+            SeekFrom::Current(n) if (-n) as u64 <= self.pos => self.pos - (-n) as u64,
+            _ => return Err(ErrorKind::NotSeekable.into()),
+        };
+
+        if target < self.pos {
+            self.restart()?;
+        }
+
+        let pos = self.pos;
+        self.skip(target - pos)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use flate2::{Compression, GzBuilder};
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::Default);
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    /// Compress `data` in the BGZF format, with members of at most `chunk`
+    /// bytes of uncompressed data, followed by the usual empty EOF member.
+    fn bgzip(data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        for piece in data.chunks(chunk).chain(Some(&b""[..])) {
+            let mut enc = GzBuilder::new().extra(vec![b'B', b'C', 2, 0, 0, 0]).write(Vec::new(), Compression::Default);
+            enc.write_all(piece).unwrap();
+            let mut member = enc.finish().unwrap();
+
+            // Fill in the member size now that we know it.
+            let bsize = member.len() - 1;
+            member[16] = (bsize & 0xFF) as u8;
+            member[17] = (bsize >> 8) as u8;
+            out.extend_from_slice(&member);
+        }
+
+        out
+    }
+
+    #[test]
+    fn bgzf() {
+        let data: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+        let mut r = open_gzip(Box::new(Cursor::new(bgzip(&data, 7000)))).unwrap();
+        let mut buf = [0u8; 10];
+
+        // Only the BGZF reader knows the size up front.
+        assert_eq!(r.get_size().unwrap(), 20000);
+
+        r.try_seek(SeekFrom::Start(13995)).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[13995..14005]);
+
+        r.try_seek(SeekFrom::Start(10)).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[10..20]);
+
+        r.try_seek(SeekFrom::End(-5)).unwrap();
+        assert_eq!(r.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], &data[19995..]);
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn restarting() {
+        let data: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+        let mut r = open_gzip(Box::new(Cursor::new(gzip(&data)))).unwrap();
+        let mut buf = [0u8; 3];
+
+        r.try_seek(SeekFrom::Start(15000)).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[15000..15003]);
+
+        r.try_seek(SeekFrom::Start(10)).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[10..13]);
+    }
+}
@@ -0,0 +1,259 @@
+// src/io/http.rs -- helpers for making HTTP requests
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Creation of the HTTP clients used by the network-based bundle backends.
+//! Requests are sent through a proxy if one is configured explicitly or
+//! through the conventional `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY`
+//! environment variables. How long to wait for servers, and how often to
+//! retry, is set by `NetworkSettings`.
+
+use hyper::{self, Client, Url};
+use hyper::client::ProxyConfig;
+use hyper::net::{HttpConnector, HttpStream, HttpsConnector, NetworkConnector};
+use hyper_native_tls::NativeTlsClient;
+use std::env;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use errors::{ErrorKind, Result};
+
+
+/// How patient to be with the network. These can be set in the `[network]`
+/// section of the configuration file:
+///
+/// ```toml
+/// [network]
+/// connect_timeout_secs = 5
+/// read_timeout_secs = 120
+/// max_attempts = 6
+/// retry_delay_ms = 1000
+/// ```
+///
+/// A timeout of zero means waiting forever.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NetworkSettings {
+    /// Give up on connecting to a server after this many seconds, so that
+    /// we can move on to the next mirror.
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// Give up on a request if the server sends nothing for this many
+    /// seconds.
+    #[serde(default = "default_read_timeout_secs")]
+    pub read_timeout_secs: u64,
+
+    /// How many times to try a download before giving up.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: usize,
+
+    /// How long to wait before the first retry of a failed download, in
+    /// milliseconds. The delay doubles with each subsequent retry.
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+}
+
+fn default_connect_timeout_secs() -> u64 { 15 }
+fn default_read_timeout_secs() -> u64 { 60 }
+fn default_max_attempts() -> usize { 4 }
+fn default_retry_delay_ms() -> u64 { 500 }
+
+impl Default for NetworkSettings {
+    fn default() -> NetworkSettings {
+        NetworkSettings {
+            connect_timeout_secs: default_connect_timeout_secs(),
+            read_timeout_secs: default_read_timeout_secs(),
+            max_attempts: default_max_attempts(),
+            retry_delay_ms: default_retry_delay_ms(),
+        }
+    }
+}
+
+impl NetworkSettings {
+    fn timeout(secs: u64) -> Option<Duration> {
+        if secs == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(secs))
+        }
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        NetworkSettings::timeout(self.connect_timeout_secs)
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        NetworkSettings::timeout(self.read_timeout_secs)
+    }
+
+    /// How many times to try a download. This is always at least one.
+    pub fn attempts(&self) -> usize {
+        if self.max_attempts == 0 { 1 } else { self.max_attempts }
+    }
+
+    /// Sleep before making retry number `attempt` (counting from 1) of a
+    /// network request, backing off exponentially. Nothing happens for
+    /// attempt 0, the first try.
+    pub fn retry_backoff(&self, attempt: usize) {
+        if attempt > 0 {
+            // Don't let silly settings overflow the shift.
+            let shift = if attempt > 16 { 16 } else { attempt - 1 };
+            thread::sleep(Duration::from_millis(self.retry_delay_ms << shift));
+        }
+    }
+}
+
+
+/// Makes plain TCP connections like hyper's `HttpConnector`, but gives up on
+/// each address of the server after a timeout.
+struct TimeoutConnector {
+    timeout: Option<Duration>,
+}
+
+impl NetworkConnector for TimeoutConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        let timeout = match self.timeout {
+            Some(t) => t,
+            None => return HttpConnector.connect(host, port, scheme),
+        };
+
+        if scheme != "http" && scheme != "https" {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid scheme for HTTP").into());
+        }
+
+        let mut last_error = None;
+
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(s) => return Ok(HttpStream(s)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, format!("no addresses found for host \"{}\"", host))
+        }).into())
+    }
+}
+
+
+/// Create an HTTP client suitable for fetching `url`, using the timeouts of
+/// `settings`. If `proxy` is given, it is used in preference to any proxy
+/// specified in the environment.
+pub fn create_client(url: &str, proxy: Option<&str>, settings: &NetworkSettings) -> Result<Client> {
+    let mut client = make_client(url, proxy, settings)?;
+    client.set_read_timeout(settings.read_timeout());
+    client.set_write_timeout(settings.read_timeout());
+    Ok(client)
+}
+
+
+fn make_client(url: &str, proxy: Option<&str>, settings: &NetworkSettings) -> Result<Client> {
+    let tcp = TimeoutConnector { timeout: settings.connect_timeout() };
+
+    let proxy_url = match find_proxy(url, proxy)? {
+        Some(u) => u,
+        None => {
+            let ssl = NativeTlsClient::new().unwrap();
+            let connector = HttpsConnector::with_connector(ssl, tcp);
+            return Ok(Client::with_connector(connector));
+        }
+    };
+
+    let host = match proxy_url.host_str() {
+        Some(h) => h.to_owned(),
+        None => return Err(ErrorKind::Msg(format!("no host in proxy URL \"{}\"", proxy_url)).into()),
+    };
+
+    let port = proxy_url.port_or_known_default().unwrap_or(80);
+    let ssl = NativeTlsClient::new().unwrap();
+    let config = ProxyConfig::new(proxy_url.scheme(), host, port, tcp, ssl);
+    Ok(Client::with_proxy_config(config))
+}
+
+
+/// Figure out which proxy, if any, should be used to fetch `url`.
+fn find_proxy(url: &str, explicit: Option<&str>) -> Result<Option<Url>> {
+    let target = match Url::parse(url) {
+        Ok(u) => u,
+        Err(e) => return Err(ErrorKind::Msg(format!("invalid URL \"{}\": {}", url, e)).into()),
+    };
+
+    if let Some(host) = target.host_str() {
+        let no_proxy = env::var("no_proxy").or_else(|_| env::var("NO_PROXY")).unwrap_or_default();
+
+        if host_excluded(host, &no_proxy) {
+            return Ok(None);
+        }
+    }
+
+    let proxy_text = match explicit {
+        Some(p) => p.to_owned(),
+        None => {
+            let names: &[&str] = if target.scheme() == "https" {
+                &["https_proxy", "HTTPS_PROXY"]
+            } else {
+                &["http_proxy", "HTTP_PROXY"]
+            };
+
+            match names.iter().filter_map(|n| env::var(n).ok()).find(|v| v.len() > 0) {
+                Some(p) => p,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    // People often leave off the scheme, e.g. `proxy.example.com:3128`.
+    let proxy_text = if proxy_text.contains("://") {
+        proxy_text
+    } else {
+        format!("http://{}", proxy_text)
+    };
+
+    match Url::parse(&proxy_text) {
+        Ok(u) => Ok(Some(u)),
+        Err(e) => Err(ErrorKind::Msg(format!("invalid proxy URL \"{}\": {}", proxy_text, e)).into()),
+    }
+}
+
+
+/// Check whether `host` matches the comma-separated list of exclusions in
+/// `no_proxy`. An entry matches the host itself and any of its subdomains;
+/// the special entry `*` matches everything.
+fn host_excluded(host: &str, no_proxy: &str) -> bool {
+    let host = host.to_lowercase();
+
+    for entry in no_proxy.split(',') {
+        let entry = entry.trim().split(':').next().unwrap_or("").trim_left_matches('.').to_lowercase();
+
+        if entry.len() == 0 {
+            continue;
+        }
+
+        if entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)) {
+            return true;
+        }
+    }
+
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_excluded() {
+        assert!(host_excluded("localhost", "localhost,127.0.0.1"));
+        assert!(host_excluded("www.example.com", " .example.com"));
+        assert!(host_excluded("example.com", "example.com:8080"));
+        assert!(host_excluded("anything.org", "*"));
+        assert!(!host_excluded("notexample.com", "example.com"));
+        assert!(!host_excluded("example.com", ""));
+    }
+}
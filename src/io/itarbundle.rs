@@ -4,26 +4,38 @@
 
 use flate2::read::GzDecoder;
 use hyper::{self, Client};
-use hyper::net::HttpsConnector;
 use hyper::client::Response;
 use hyper::header::{Headers, Range};
 use hyper::status::StatusCode;
-use hyper_native_tls::NativeTlsClient;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
-use std::io::{BufRead, BufReader, Cursor, Read};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
 
 use errors::{Error, ErrorKind, Result, ResultExt};
 use super::{InputHandle, InputOrigin, IoProvider, OpenResult};
-use status::StatusBackend;
+use super::http::{create_client, NetworkSettings};
+use status::{ProgressUnit, StatusBackend};
 
 
-const MAX_HTTP_ATTEMPTS: usize = 4;
+/// The maximum number of files that we will speculatively download
+/// alongside a file that the engine actually asked for.
+const MAX_PREFETCH: usize = 8;
 
+/// When a file is fetched, files with the same stem and one of these
+/// extensions are prefetched too, since packages commonly load them.
+const PREFETCH_SIBLING_EXTENSIONS: &'static [&'static str] = &["cfg", "def"];
 
-// A simple way to read chunks out of a big seekable byte stream. You could
-// implement this for io::File pretty trivially but that's not currently
-// needed.
+/// The most files that the prefetch hints remember as having followed any
+/// one file. Beyond that the oldest hints are forgotten, which keeps the
+/// hints file from growing without bound.
+const MAX_HINT_FOLLOWERS: usize = MAX_PREFETCH;
+
+
+// A simple way to read chunks out of a big seekable byte stream.
 
 pub trait RangeRead {
     type InnerRead: Read;
@@ -32,22 +44,68 @@ pub trait RangeRead {
 }
 
 
+/// The result of trying a request against one mirror of a bundle. If the
+/// mirror seems to be down -- we can't connect to it, or it gives a 5xx
+/// error -- it's worth trying the request against the next mirror.
+enum MirrorAttempt<T> {
+    Done(T),
+    FailOver(Error),
+}
+
+
 pub struct HttpRangeReader {
-    url: String,
+    urls: Vec<String>,
+    current: usize,
+    proxy: Option<String>,
+    network: NetworkSettings,
     client: Client,
 }
 
 
 impl HttpRangeReader {
-    pub fn new(url: &str) -> HttpRangeReader {
-        let ssl = NativeTlsClient::new().unwrap();
-        let connector = HttpsConnector::new(ssl);
-        let client = Client::with_connector(connector);
+    /// Create a reader that fetches data from the first of `urls`, failing
+    /// over to the others in order if it becomes unavailable. All of the
+    /// URLs should provide identical data.
+    pub fn new(urls: Vec<String>, proxy: Option<&str>, network: &NetworkSettings) -> Result<HttpRangeReader> {
+        if urls.len() == 0 {
+            return Err(ErrorKind::Msg("no URLs given for HTTP range reader".to_owned()).into());
+        }
 
-        HttpRangeReader {
-            url: url.to_owned(),
+        let client = create_client(&urls[0], proxy, network)?;
+
+        Ok(HttpRangeReader {
+            urls: urls,
+            current: 0,
+            proxy: proxy.map(|s| s.to_owned()),
+            network: network.clone(),
             client: client,
+        })
+    }
+
+    fn try_read_range(&mut self, offset: u64, length: usize) -> Result<MirrorAttempt<Response>> {
+        let end_inclusive = offset + length as u64 - 1;
+
+        let mut headers = Headers::new();
+        headers.set(Range::bytes(offset, end_inclusive));
+
+        let req = self.client.get(&self.urls[self.current]).headers(headers);
+
+        let res = match req.send() {
+            Ok(r) => r,
+            Err(e) => return Ok(MirrorAttempt::FailOver(e.into())),
+        };
+
+        if res.status.is_server_error() {
+            return Ok(MirrorAttempt::FailOver(ErrorKind::Msg(format!(
+                "couldn\'t fetch {} (HTTP status {})", self.urls[self.current], res.status)).into()));
         }
+
+        if res.status != StatusCode::PartialContent {
+            // FIXME: this loses the actual status code! Should report it.
+            return Err(hyper::Error::Status.into());
+        }
+
+        Ok(MirrorAttempt::Done(res))
     }
 }
 
@@ -56,20 +114,43 @@ impl RangeRead for HttpRangeReader {
     type InnerRead = Response;
 
     fn read_range(&mut self, offset: u64, length: usize) -> Result<Response> {
-        let end_inclusive = offset + length as u64 - 1;
+        let mut n_tried = 0;
 
-        let mut headers = Headers::new();
-        headers.set(Range::bytes(offset, end_inclusive));
+        loop {
+            match self.try_read_range(offset, length)? {
+                MirrorAttempt::Done(r) => return Ok(r),
+                MirrorAttempt::FailOver(e) => {
+                    n_tried += 1;
 
-        let req = self.client.get(&self.url).headers(headers);
-        let res = req.send()?;
+                    if n_tried >= self.urls.len() {
+                        return Err(e);
+                    }
 
-        if res.status != StatusCode::PartialContent {
-            // FIXME: this loses the actual status code! Should report it.
-            return Err(hyper::Error::Status.into());
+                    self.current = (self.current + 1) % self.urls.len();
+                    self.client = create_client(&self.urls[self.current], self.proxy.as_ref().map(|s| s.as_str()),
+                                                &self.network)?;
+                },
+            }
         }
+    }
+}
+
+
+/// Reads ranges out of an indexed tar file on the local filesystem. Each
+/// read opens the file afresh so that readers don't need to coordinate
+/// their seeks.
+pub struct FileRangeReader {
+    path: PathBuf,
+}
+
+
+impl RangeRead for FileRangeReader {
+    type InnerRead = io::Take<File>;
 
-        Ok(res)
+    fn read_range(&mut self, offset: u64, length: usize) -> Result<io::Take<File>> {
+        let mut f = File::open(&self.path)?;
+        f.seek(SeekFrom::Start(offset))?;
+        Ok(f.take(length as u64))
     }
 }
 
@@ -79,7 +160,7 @@ impl RangeRead for HttpRangeReader {
 
 pub trait ITarIoFactory {
     type IndexReader: Read;
-    type DataReader: RangeRead;
+    type DataReader: RangeRead + Send + 'static;
 
     fn get_index(&mut self, status: &mut StatusBackend) -> Result<Self::IndexReader>;
     fn get_data(&self) -> Result<Self::DataReader>;
@@ -91,29 +172,272 @@ struct FileInfo {
     length: u64
 }
 
+/// An ITarBundle fetches files out of an indexed tar file, usually over
+/// HTTP. To cut down on network round trips, when the engine asks for a file
+/// we also download, in parallel, other files that it is likely to ask for
+/// next. These predictions come from files with related names and from
+/// "prefetch hints": a record of which files were fetched right after which
+/// in previous sessions, which is persisted to disk if a hints path has been
+/// specified. Prefetches finish in the background: opening a file only waits
+/// for a prefetch if it's of that very file.
+
 pub struct ITarBundle<F: ITarIoFactory> {
     factory: F,
     data: Option<F::DataReader>,
     index: HashMap<OsString,FileInfo>,
+    prefetched: HashMap<OsString, Vec<u8>>,
+    in_flight: HashMap<OsString, thread::JoinHandle<()>>,
+    prefetch_tx: mpsc::Sender<(OsString, Result<Vec<u8>>)>,
+    prefetch_rx: mpsc::Receiver<(OsString, Result<Vec<u8>>)>,
+    hints: HashMap<OsString, Vec<OsString>>,
+    hints_path: Option<PathBuf>,
+    last_fetched: Option<OsString>,
+    network: NetworkSettings,
 }
 
 
 impl<F: ITarIoFactory> ITarBundle<F> {
     fn construct (factory: F) -> ITarBundle<F> {
+        let (tx, rx) = mpsc::channel();
+
         ITarBundle {
             factory: factory,
             data: None,
             index: HashMap::new(),
+            prefetched: HashMap::new(),
+            in_flight: HashMap::new(),
+            prefetch_tx: tx,
+            prefetch_rx: rx,
+            hints: HashMap::new(),
+            hints_path: None,
+            last_fetched: None,
+            network: NetworkSettings::default(),
         }
     }
 
-    fn ensure_loaded(&mut self, status: &mut StatusBackend) -> Result<()> {
-        if self.data.is_some() {
-            return Ok(());
+    /// Load and save prefetch hints from a file at the specified path. The
+    /// file need not exist yet.
+    pub fn prefetch_hints_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.hints_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    fn load_hints(&mut self, status: &mut StatusBackend) {
+        let path = match self.hints_path {
+            Some(ref p) => p.clone(),
+            None => return,
+        };
+
+        let f = match File::open(&path) {
+            Ok(f) => f,
+            Err(_) => return, // no hints yet -- fine
+        };
+
+        // Hints files written by older versions could grow without bound,
+        // so if we had to throw anything away, we write back what we kept.
+        let mut compact = false;
+
+        for res in BufReader::new(f).lines() {
+            let line = match res {
+                Ok(l) => l,
+                Err(e) => {
+                    tt_warning!(status, "error reading prefetch hints from \"{}\"", path.display(); e.into());
+                    return;
+                },
+            };
+
+            let bits = line.split_whitespace().collect::<Vec<_>>();
+
+            if bits.len() != 2 {
+                continue;
+            }
+
+            match self.add_hint(OsString::from(bits[0]), OsString::from(bits[1])) {
+                Some(false) => {},
+                _ => compact = true, // a duplicate, or too many followers
+            }
         }
 
-        // We need to initialize. First, the index ...
+        if compact {
+            self.save_hints(status);
+        }
+    }
+
+    /// Remember that `name` was fetched right after `prev`, forgetting the
+    /// oldest file known to follow `prev` if there are too many. Returns
+    /// None if this was already known, and otherwise whether anything was
+    /// forgotten.
+    fn add_hint(&mut self, prev: OsString, name: OsString) -> Option<bool> {
+        let followers = self.hints.entry(prev).or_insert_with(Vec::new);
+
+        if followers.contains(&name) {
+            return None;
+        }
+
+        followers.push(name);
+
+        if followers.len() > MAX_HINT_FOLLOWERS {
+            followers.remove(0);
+            return Some(true);
+        }
+
+        Some(false)
+    }
+
+    /// Rewrite the hints file with all of the hints that we have.
+    fn save_hints(&self, status: &mut StatusBackend) {
+        let path = match self.hints_path {
+            Some(ref p) => p,
+            None => return,
+        };
+
+        let res = File::create(path).and_then(|mut f| {
+            for (prev, followers) in &self.hints {
+                for name in followers {
+                    if let (Some(p), Some(n)) = (prev.to_str(), name.to_str()) {
+                        writeln!(f, "{} {}", p, n)?;
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        if let Err(e) = res {
+            tt_warning!(status, "couldn't save prefetch hints to \"{}\"", path.display(); e.into());
+        }
+    }
 
+    /// Note that a file was fetched, updating the prefetch hints if this is
+    /// a new pairing. Usually we can just add the pairing to the end of the
+    /// hints file, but if an old hint was forgotten, we rewrite it.
+    fn record_fetch(&mut self, name: &OsStr, status: &mut StatusBackend) {
+        let prev = self.last_fetched.take();
+        self.last_fetched = Some(name.to_owned());
+
+        let prev = match prev {
+            Some(p) => p,
+            None => return,
+        };
+
+        match self.add_hint(prev.clone(), name.to_owned()) {
+            None => return,
+            Some(true) => {
+                self.save_hints(status);
+                return;
+            },
+            Some(false) => {},
+        }
+
+        let path = match self.hints_path {
+            Some(ref p) => p,
+            None => return,
+        };
+
+        if let (Some(p), Some(n)) = (prev.to_str(), name.to_str()) {
+            let res = OpenOptions::new().append(true).create(true).open(path)
+                .and_then(|mut f| writeln!(f, "{} {}", p, n));
+
+            if let Err(e) = res {
+                tt_warning!(status, "couldn't save prefetch hints to \"{}\"", path.display(); e.into());
+            }
+        }
+    }
+
+    /// Figure out which files to prefetch along with `name`: first, those
+    /// that have been seen to follow it (transitively), then those with
+    /// sibling names.
+    fn prefetch_candidates(&self, name: &OsStr) -> Vec<OsString> {
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = vec![name.to_owned()];
+        seen.insert(name.to_owned());
+
+        let mut i = 0;
+
+        while i < queue.len() && result.len() < MAX_PREFETCH {
+            if let Some(followers) = self.hints.get(&queue[i]) {
+                for f in followers {
+                    if seen.insert(f.clone()) {
+                        queue.push(f.clone());
+                        result.push(f.clone());
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        if let Some(stem) = Path::new(name).file_stem() {
+            for ext in PREFETCH_SIBLING_EXTENSIONS {
+                let mut sib = Path::new(stem).to_owned();
+                sib.set_extension(ext);
+                let sib = sib.into_os_string();
+
+                if seen.insert(sib.clone()) {
+                    result.push(sib);
+                }
+            }
+        }
+
+        result.into_iter()
+            .filter(|n| self.index.contains_key(n) && !self.prefetched.contains_key(n) &&
+                    !self.in_flight.contains_key(n))
+            .take(MAX_PREFETCH)
+            .collect()
+    }
+
+    /// Start downloading the prefetch candidates for `name` in background
+    /// threads. Each download gets its own data reader so that the requests
+    /// can proceed concurrently. The threads send back what they got when
+    /// they're done.
+    fn start_prefetch(&mut self, name: &OsStr) {
+        for cand in self.prefetch_candidates(name) {
+            let (offset, length) = {
+                let info = &self.index[&cand];
+                (info.offset, info.length as usize)
+            };
+
+            let mut reader = match self.factory.get_data() {
+                Ok(r) => r,
+                Err(_) => break, // prefetching is just an optimization
+            };
+
+            let tx = self.prefetch_tx.clone();
+            let thread_name = cand.clone();
+
+            let handle = thread::spawn(move || {
+                let result = reader.read_range(offset, length).and_then(|mut r| {
+                    let mut buf = Vec::with_capacity(length);
+                    r.read_to_end(&mut buf)?;
+                    Ok(buf)
+                });
+                let _ = tx.send((thread_name, result)); // the bundle may be gone
+            });
+
+            self.in_flight.insert(cand, handle);
+        }
+    }
+
+    /// Collect the results of the prefetches that have finished. If `wanted`
+    /// is being prefetched, we wait for it, but never for the others.
+    /// Failures are ignored, since the file will just be fetched normally if
+    /// it's ever needed.
+    fn collect_prefetches(&mut self, wanted: &OsStr) {
+        if let Some(handle) = self.in_flight.remove(wanted) {
+            let _ = handle.join();
+        }
+
+        while let Ok((name, result)) = self.prefetch_rx.try_recv() {
+            self.in_flight.remove(&name);
+
+            if let Ok(buf) = result {
+                self.prefetched.insert(name, buf);
+            }
+        }
+    }
+
+    fn load_index(&mut self, status: &mut StatusBackend) -> Result<()> {
         let index = self.factory.get_index(status)?;
         let br = BufReader::new(index);
 
@@ -131,9 +455,43 @@ impl<F: ITarIoFactory> ITarBundle<F> {
             self.index.insert(name, FileInfo { offset: offset, length: length });
         }
 
-        // ... then, the data reader.
+        Ok(())
+    }
+
+    fn ensure_loaded(&mut self, status: &mut StatusBackend) -> Result<()> {
+        if self.data.is_some() {
+            return Ok(());
+        }
+
+        // We need to initialize. First, the index. If the download gets
+        // interrupted, we start over, since it's a compressed stream.
+
+        let mut attempt = 0;
+
+        loop {
+            match self.load_index(status) {
+                Ok(_) => break,
+                Err(e) => {
+                    attempt += 1;
+
+                    if attempt >= self.network.attempts() {
+                        return Err(e);
+                    }
+
+                    tt_warning!(status, "failure downloading the bundle index; will retry"; e);
+                    self.index.clear();
+                    self.network.retry_backoff(attempt);
+                },
+            }
+        }
+
+        // ... then, the data reader ...
 
         self.data = Some(self.factory.get_data()?);
+
+        // ... and finally, the prefetch hints.
+
+        self.load_hints(status);
         Ok(())
     }
 }
@@ -150,25 +508,45 @@ impl<F: ITarIoFactory> IoProvider for ITarBundle<F> {
         // lifetime-related issues. So for now we just slurp the whole thing
         // into RAM.
 
-        let info = match self.index.get(name) {
-            Some(i) => i,
+        self.collect_prefetches(name);
+
+        if let Some(buf) = self.prefetched.remove(name) {
+            self.record_fetch(name, status);
+            return OpenResult::Ok(InputHandle::new(name, Cursor::new(buf), InputOrigin::Other));
+        }
+
+        let (offset, length) = match self.index.get(name) {
+            Some(i) => (i.offset, i.length),
             None => return OpenResult::NotAvailable,
         };
 
         self.factory.report_fetch(name, status);
+        self.start_prefetch(name);
 
         // When fetching a bunch of resource files (i.e., on the first
         // invocation), bintray will sometimes drop connections. The error
         // manifests itself in a way that has a not-so-nice user experience.
-        // Our solution: retry the HTTP a few times in case it was a transient
-        // problem.
+        // Our solution: retry the HTTP a few times, with exponential
+        // backoff, in case it was a transient problem. If a download gets
+        // interrupted partway through, we keep what we got and only request
+        // the remainder of the file on the next try.
 
-        let mut buf = Vec::with_capacity(info.length as usize);
+        let task = name.to_string_lossy().into_owned();
+        let mut buf = Vec::with_capacity(length as usize);
         let mut overall_failed = true;
         let mut any_failed = false;
 
-        for _ in 0..MAX_HTTP_ATTEMPTS {
-            let mut stream = match self.data.as_mut().unwrap().read_range(info.offset, info.length as usize) {
+        for attempt in 0..self.network.attempts() {
+            self.network.retry_backoff(attempt);
+
+            let done = buf.len() as u64;
+
+            if done > 0 {
+                tt_note!(status, "resuming download of \"{}\" ({} of {} bytes retrieved)",
+                         name.to_string_lossy(), done, length);
+            }
+
+            let stream = match self.data.as_mut().unwrap().read_range(offset + done, (length - done) as usize) {
                 Ok(r) => r,
                 Err(e) => {
                     tt_warning!(status, "failure requesting \"{}\" from network", name.to_string_lossy(); e.into());
@@ -177,16 +555,24 @@ impl<F: ITarIoFactory> IoProvider for ITarBundle<F> {
                 },
             };
 
-            if let Err(e) = stream.read_to_end(&mut buf) {
+            if let Err(e) = read_with_progress(stream.take(length - done), &mut buf, length, &task, status) {
                 tt_warning!(status, "failure downloading \"{}\" from network", name.to_string_lossy(); e.into());
                 any_failed = true;
                 continue;
             }
 
+            if (buf.len() as u64) < length {
+                tt_warning!(status, "download of \"{}\" ended prematurely", name.to_string_lossy());
+                any_failed = true;
+                continue;
+            }
+
             overall_failed = false;
             break;
         }
 
+        status.end_progress(&task);
+
         if overall_failed {
             // Note: can't save & reuse the hyper errors since they're not cloneable
             return OpenResult::Err(ErrorKind::Msg(format!("failed to retrieve \"{}\" from the network; \
@@ -197,65 +583,150 @@ impl<F: ITarIoFactory> IoProvider for ITarBundle<F> {
             tt_note!(status, "download succeeded after retry");
         }
 
+        self.record_fetch(name, status);
         OpenResult::Ok(InputHandle::new(name, Cursor::new(buf), InputOrigin::Other))
     }
+
+    fn file_names(&mut self, status: &mut StatusBackend) -> Vec<OsString> {
+        if let Err(e) = self.ensure_loaded(status) {
+            tt_warning!(status, "couldn't get the list of files in the bundle"; e);
+            return Vec::new();
+        }
+
+        self.index.keys().cloned().collect()
+    }
+}
+
+
+/// Read all of `stream` onto the end of `buf`, reporting progress toward
+/// the `total` size of the file as we go.
+fn read_with_progress<R: Read>(mut stream: R, buf: &mut Vec<u8>, total: u64, task: &str,
+                               status: &mut StatusBackend) -> io::Result<()> {
+    let mut chunk = [0u8; 65536];
+
+    loop {
+        let n = match stream.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        buf.extend_from_slice(&chunk[..n]);
+        status.report_progress(task, buf.len() as u64, Some(total), ProgressUnit::Bytes);
+    }
 }
 
 
 pub struct HttpITarIoFactory {
     url: String,
-}
+    mirrors: Vec<String>,
+    proxy: Option<String>,
+    network: NetworkSettings,
 
-impl ITarIoFactory for HttpITarIoFactory {
-    type IndexReader = GzDecoder<Response>;
-    type DataReader = HttpRangeReader;
+    /// The mirrors other than the one that we resolved `url` from, to fall
+    /// back on when fetching data.
+    fallbacks: Vec<String>,
+}
 
-    fn get_index(&mut self, status: &mut StatusBackend) -> Result<GzDecoder<Response>> {
-        tt_note!(status, "indexing {}", self.url);
+impl HttpITarIoFactory {
+    fn try_get_index(&mut self, url: &str, status: &mut StatusBackend) -> Result<MirrorAttempt<GzDecoder<Response>>> {
+        tt_note!(status, "indexing {}", url);
 
-        let ssl = NativeTlsClient::new().unwrap();
-        let connector = HttpsConnector::new(ssl);
-        let client = Client::with_connector(connector);
+        let client = create_client(url, self.proxy.as_ref().map(|s| s.as_str()), &self.network)?;
 
         // First, we actually do a HEAD request on the URL for the data file.
         // If it's redirected, we update our URL to follow the redirects. If
         // we didn't do this separately, the index file would have to be the
         // one with the redirect setup, which would be confusing and annoying.
 
-        let req = client.head(&self.url);
-        let res = req.send()?;
+        let req = client.head(url);
+
+        let res = match req.send() {
+            Ok(r) => r,
+            Err(e) => return Ok(MirrorAttempt::FailOver(e.into())),
+        };
+
+        if res.status.is_server_error() {
+            return Ok(MirrorAttempt::FailOver(ErrorKind::Msg(format!(
+                "couldn\'t probe {} (HTTP status {})", url, res.status)).into()));
+        }
 
         if !res.status.is_success() {
             return Err(Error::from(hyper::Error::Status)).chain_err(
-                || format!("couldn\'t probe {}", self.url)
+                || format!("couldn\'t probe {}", url)
             );
         }
 
         let final_url = res.url.clone().into_string();
 
-        if final_url != self.url {
+        if final_url != url {
             tt_note!(status, "resolved to {}", final_url);
-            self.url = final_url;
         }
 
         // Now let's actually go for the index.
 
-        let mut index_url = self.url.clone();
+        let mut index_url = final_url.clone();
         index_url.push_str(".index.gz");
 
         let req = client.get(&index_url);
-        let res = req.send()?;
+
+        let res = match req.send() {
+            Ok(r) => r,
+            Err(e) => return Ok(MirrorAttempt::FailOver(e.into())),
+        };
+
+        if res.status.is_server_error() {
+            return Ok(MirrorAttempt::FailOver(ErrorKind::Msg(format!(
+                "couldn\'t fetch {} (HTTP status {})", index_url, res.status)).into()));
+        }
+
         if !res.status.is_success() {
             return Err(Error::from(hyper::Error::Status)).chain_err(
                 || format!("couldn\'t fetch {}", index_url)
             );
         }
 
-        Ok(GzDecoder::new(res)?) // <- needed to convert Error types
+        self.url = final_url;
+        Ok(MirrorAttempt::Done(GzDecoder::new(res)?)) // <- needed to convert Error types
+    }
+}
+
+impl ITarIoFactory for HttpITarIoFactory {
+    type IndexReader = GzDecoder<Response>;
+    type DataReader = HttpRangeReader;
+
+    fn get_index(&mut self, status: &mut StatusBackend) -> Result<GzDecoder<Response>> {
+        let mut candidates = vec![self.url.clone()];
+        candidates.extend(self.mirrors.iter().cloned());
+
+        for i in 0..candidates.len() {
+            match self.try_get_index(&candidates[i], status)? {
+                MirrorAttempt::Done(r) => {
+                    self.fallbacks = candidates.iter().enumerate()
+                        .filter(|&(j, _)| j != i)
+                        .map(|(_, u)| u.clone())
+                        .collect();
+                    return Ok(r);
+                },
+                MirrorAttempt::FailOver(e) => {
+                    if i + 1 == candidates.len() {
+                        return Err(e);
+                    }
+
+                    tt_warning!(status, "bundle mirror {} is unavailable; trying {}",
+                                candidates[i], candidates[i + 1]; e);
+                },
+            }
+        }
+
+        unreachable!();
     }
 
     fn get_data(&self) -> Result<HttpRangeReader> {
-        Ok(HttpRangeReader::new(&self.url))
+        let mut urls = vec![self.url.clone()];
+        urls.extend(self.fallbacks.iter().cloned());
+        HttpRangeReader::new(urls, self.proxy.as_ref().map(|s| s.as_str()), &self.network)
     }
 
     fn report_fetch(&self, name: &OsStr, status: &mut StatusBackend) {
@@ -265,6 +736,66 @@ impl ITarIoFactory for HttpITarIoFactory {
 
 impl ITarBundle<HttpITarIoFactory> {
     pub fn new (url: &str) -> ITarBundle<HttpITarIoFactory> {
-        Self::construct(HttpITarIoFactory { url: url.to_owned() })
+        Self::construct(HttpITarIoFactory {
+            url: url.to_owned(),
+            mirrors: Vec::new(),
+            proxy: None,
+            network: NetworkSettings::default(),
+            fallbacks: Vec::new(),
+        })
+    }
+
+    /// Specify alternative URLs that provide the same bundle. If the main
+    /// URL can't be reached, or gives a server error, these are tried in
+    /// order.
+    pub fn mirrors(&mut self, mirrors: &[String]) -> &mut Self {
+        self.factory.mirrors = mirrors.to_vec();
+        self
+    }
+
+    /// Send requests through the specified proxy, overriding any proxy
+    /// settings in the environment.
+    pub fn proxy(&mut self, proxy: Option<&str>) -> &mut Self {
+        self.factory.proxy = proxy.map(|s| s.to_owned());
+        self
+    }
+
+    /// Use these timeouts and retry policy rather than the defaults.
+    pub fn network(&mut self, settings: &NetworkSettings) -> &mut Self {
+        self.network = settings.clone();
+        self.factory.network = settings.clone();
+        self
+    }
+}
+
+
+/// An ITarIoFactory for indexed tar files on the local filesystem, for use
+/// with `file://` bundle URLs. The index is expected to live alongside the
+/// tar file, with `.index.gz` appended to its name.
+pub struct FileITarIoFactory {
+    path: PathBuf,
+}
+
+impl ITarIoFactory for FileITarIoFactory {
+    type IndexReader = GzDecoder<File>;
+    type DataReader = FileRangeReader;
+
+    fn get_index(&mut self, _status: &mut StatusBackend) -> Result<GzDecoder<File>> {
+        let mut index_path = self.path.clone().into_os_string();
+        index_path.push(".index.gz");
+        let f = ctry!(File::open(&index_path); "couldn\'t open {}", Path::new(&index_path).display());
+        Ok(GzDecoder::new(f)?)
+    }
+
+    fn get_data(&self) -> Result<FileRangeReader> {
+        Ok(FileRangeReader { path: self.path.clone() })
+    }
+
+    fn report_fetch(&self, _name: &OsStr, _status: &mut StatusBackend) {}
+}
+
+impl ITarBundle<FileITarIoFactory> {
+    pub fn open(path: &Path) -> ITarBundle<FileITarIoFactory> {
+        Self::construct(FileITarIoFactory { path: path.to_owned() })
     }
 }
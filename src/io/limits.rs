@@ -0,0 +1,278 @@
+// src/io/limits.rs -- capping the I/O done in a session
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An I/O provider that enforces limits on the total number of bytes read
+//! and written, and the number of files opened, by everything that goes
+//! through it. Services that process arbitrary documents can use this to
+//! keep a pathological input from, e.g., filling up the disk. Once a limit
+//! is exceeded, the offending operation fails with
+//! `ErrorKind::ResourceLimitExceeded`.
+
+use std::cell::Cell;
+use std::ffi::OsStr;
+use std::io::{self, Read, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::rc::Rc;
+
+use errors::{ErrorKind, Result};
+use status::StatusBackend;
+use super::{InputFeatures, InputHandle, IoProvider, OpenResult, OutputHandle};
+
+
+/// A running total along with its optional cap.
+struct Counter {
+    resource: &'static str,
+    count: Cell<u64>,
+    limit: Cell<Option<u64>>,
+}
+
+impl Counter {
+    fn new(resource: &'static str) -> Rc<Counter> {
+        Rc::new(Counter {
+            resource: resource,
+            count: Cell::new(0),
+            limit: Cell::new(None),
+        })
+    }
+
+    fn add(&self, amount: u64) -> Result<()> {
+        let total = self.count.get() + amount;
+        self.count.set(total);
+
+        match self.limit.get() {
+            Some(limit) if total > limit =>
+                Err(ErrorKind::ResourceLimitExceeded(self.resource.to_owned(), limit).into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+
+pub struct LimitedIo<P: IoProvider> {
+    inner: P,
+    read: Rc<Counter>,
+    written: Rc<Counter>,
+    opened: Rc<Counter>,
+}
+
+
+impl<P: IoProvider> LimitedIo<P> {
+    /// Wrap `inner`. No limits are enforced until they are set.
+    pub fn new(inner: P) -> LimitedIo<P> {
+        LimitedIo {
+            inner: inner,
+            read: Counter::new("bytes read"),
+            written: Counter::new("bytes written"),
+            opened: Counter::new("files opened"),
+        }
+    }
+
+    pub fn max_bytes_read(&mut self, limit: Option<u64>) -> &mut Self {
+        self.read.limit.set(limit);
+        self
+    }
+
+    pub fn max_bytes_written(&mut self, limit: Option<u64>) -> &mut Self {
+        self.written.limit.set(limit);
+        self
+    }
+
+    /// Limit the number of files, inputs and outputs combined, that may be
+    /// opened. Attempts to open files that don't exist aren't counted.
+    pub fn max_files_opened(&mut self, limit: Option<u64>) -> &mut Self {
+        self.opened.limit.set(limit);
+        self
+    }
+
+    /// Wrap another provider, sharing this one's limits and running totals,
+    /// so that the limits apply to both of them combined.
+    pub fn share<Q: IoProvider>(&self, inner: Q) -> LimitedIo<Q> {
+        LimitedIo {
+            inner: inner,
+            read: self.read.clone(),
+            written: self.written.clone(),
+            opened: self.opened.clone(),
+        }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.read.count.get()
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.written.count.get()
+    }
+
+    pub fn files_opened(&self) -> u64 {
+        self.opened.count.get()
+    }
+
+    fn wrap_input(&self, r: OpenResult<InputHandle>) -> OpenResult<InputHandle> {
+        let handle = match r {
+            OpenResult::Ok(h) => h,
+            r => return r,
+        };
+
+        if let Err(e) = self.opened.add(1) {
+            return OpenResult::Err(e);
+        }
+
+        let name = handle.name().to_os_string();
+        let origin = handle.origin();
+        let counted = CountedInput { inner: handle, counter: self.read.clone() };
+        OpenResult::Ok(InputHandle::new(&name, counted, origin))
+    }
+
+    fn wrap_output(&self, r: OpenResult<OutputHandle>) -> OpenResult<OutputHandle> {
+        let handle = match r {
+            OpenResult::Ok(h) => h,
+            r => return r,
+        };
+
+        if let Err(e) = self.opened.add(1) {
+            return OpenResult::Err(e);
+        }
+
+        let name = handle.name().to_os_string();
+        OpenResult::Ok(OutputHandle::new(&name, CountedOutput { inner: handle, counter: self.written.clone() }))
+    }
+}
+
+
+impl<P: IoProvider> IoProvider for LimitedIo<P> {
+    fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
+        let r = self.inner.output_open_name(name);
+        self.wrap_output(r)
+    }
+
+    fn output_open_stdout(&mut self) -> OpenResult<OutputHandle> {
+        let r = self.inner.output_open_stdout();
+        self.wrap_output(r)
+    }
+
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        let r = self.inner.input_open_name(name, status);
+        self.wrap_input(r)
+    }
+
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        match self.inner.input_open_name_with_abspath(name, status) {
+            OpenResult::Ok((h, p)) => self.wrap_input(OpenResult::Ok(h)).map(|h| (h, p)),
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        self.inner.input_mtime(name)
+    }
+
+    fn input_open_primary(&mut self, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        let r = self.inner.input_open_primary(status);
+        self.wrap_input(r)
+    }
+
+    fn input_open_primary_with_abspath(&mut self, status: &mut StatusBackend)
+                                       -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        match self.inner.input_open_primary_with_abspath(status) {
+            OpenResult::Ok((h, p)) => self.wrap_input(OpenResult::Ok(h)).map(|h| (h, p)),
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    fn input_open_format(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        let r = self.inner.input_open_format(name, status);
+        self.wrap_input(r)
+    }
+
+    fn write_format(&mut self, name: &str, data: &[u8], status: &mut StatusBackend) -> Result<()> {
+        self.written.add(data.len() as u64)?;
+        self.inner.write_format(name, data, status)
+    }
+}
+
+
+struct CountedInput {
+    inner: InputHandle,
+    counter: Rc<Counter>,
+}
+
+impl Read for CountedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counter.add(n as u64).map_err(io::Error::from)?;
+        Ok(n)
+    }
+}
+
+impl InputFeatures for CountedInput {
+    fn get_size(&mut self) -> Result<usize> {
+        self.inner.get_size()
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.inner.try_seek(pos)
+    }
+}
+
+
+struct CountedOutput {
+    inner: OutputHandle,
+    counter: Rc<Counter>,
+}
+
+impl Write for CountedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Check before writing, so that we never exceed the limit on disk.
+        self.counter.add(buf.len() as u64).map_err(io::Error::from)?;
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use io::memory::MemoryIo;
+    use status::NoopStatusBackend;
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let mut mem = MemoryIo::new(false);
+        mem.create_entry(OsStr::new("in.tex"), b"0123456789".to_vec());
+
+        let mut lim = LimitedIo::new(mem);
+        lim.max_bytes_read(Some(15)).max_bytes_written(Some(4)).max_files_opened(Some(3));
+        let mut sb = NoopStatusBackend::new();
+        let mut s = String::new();
+
+        lim.input_open_name(OsStr::new("in.tex"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(lim.bytes_read(), 10);
+        assert!(lim.input_open_name(OsStr::new("in.tex"), &mut sb).unwrap().read_to_string(&mut s).is_err());
+
+        {
+            let mut h = lim.output_open_name(OsStr::new("out.pdf")).unwrap();
+            h.write_all(b"%PDF").unwrap();
+            assert!(h.write_all(b"-").is_err());
+        }
+
+        assert!(lim.input_open_name(OsStr::new("missing.tex"), &mut sb).is_not_available());
+
+        match lim.input_open_name(OsStr::new("in.tex"), &mut sb) {
+            OpenResult::Err(e) => match *e.kind() {
+                ErrorKind::ResourceLimitExceeded(..) => {},
+                ref k => panic!("unexpected error: {}", k),
+            },
+            _ => panic!("file limit should have been exceeded"),
+        }
+    }
+}
@@ -4,22 +4,24 @@
 
 use fs2::FileExt;
 use mkstemp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Read, Write};
 use std::io::ErrorKind as IoErrorKind;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use digest::{self, Digest, DigestData};
 use errors::{ErrorKind, Result, ResultExt};
 use super::{try_open_file, InputHandle, InputOrigin, IoProvider, OpenResult};
+use super::format_cache::{format_cache_name, save_format};
 use status::StatusBackend;
 
 
 struct LocalCacheItem {
-    _length: u64,
+    length: u64,
     digest: Option<DigestData>, // None => negative cache: this file is not in the bundle
 }
 
@@ -28,16 +30,41 @@ pub struct LocalCache<B: IoProvider> {
     digest_path: PathBuf,
     cached_digest: DigestData,
     checked_digest: bool,
+    pinned: bool,
     manifest_path: PathBuf,
     formats_base: PathBuf,
     data_path: PathBuf,
     contents: HashMap<OsString,LocalCacheItem>,
+    verified: HashSet<OsString>,
+    redownload_corrupt: bool,
+    max_size: Option<u64>,
+    access_log_path: PathBuf,
+    used: HashSet<String>,
+
+    /// The total size of the cached files, once we've had to measure it.
+    cache_size: Option<u64>,
 }
 
 
 impl<B: IoProvider> LocalCache<B> {
-    pub fn new(mut backend: B, digest: &Path, manifest_base: &Path, formats_base: &Path,
+    pub fn new(backend: B, digest: &Path, manifest_base: &Path, formats_base: &Path,
                data: &Path, status: &mut StatusBackend) -> Result<LocalCache<B>> {
+        Self::open(backend, None, digest, manifest_base, formats_base, data, status)
+    }
+
+
+    /// Create a cache for a bundle that is pinned to a specific version,
+    /// identified by its digest. Only files belonging to that version will be
+    /// used, and if the backend turns out to provide a different version, we
+    /// error out rather than switching to it.
+    pub fn new_pinned(backend: B, pinned_digest: &DigestData, digest: &Path, manifest_base: &Path,
+                      formats_base: &Path, data: &Path, status: &mut StatusBackend) -> Result<LocalCache<B>> {
+        Self::open(backend, Some(pinned_digest), digest, manifest_base, formats_base, data, status)
+    }
+
+
+    fn open(mut backend: B, pinned_digest: Option<&DigestData>, digest: &Path, manifest_base: &Path,
+            formats_base: &Path, data: &Path, status: &mut StatusBackend) -> Result<LocalCache<B>> {
         // If the `digest` file exists, we assume that it is valid; this is
         // *essential* so that we can use a URL as our default IoProvider
         // without requiring a network connection to run. If it does not
@@ -45,36 +72,42 @@ impl<B: IoProvider> LocalCache<B> {
 
         let mut checked_digest = false;
 
-        let digest_text = match File::open(digest) {
-            Ok(f) => {
-                let mut text = String::new();
-                f.take(64).read_to_string(&mut text)?;
-                text
-            },
-            Err(e) => {
-                if e.kind() != IoErrorKind::NotFound {
-                    // Unexpected error reading the digest cache file. Ruh roh!
-                    return Err(e.into());
-                }
+        let digest_text = match pinned_digest {
+            // We know exactly what we want, so don't bother with the digest
+            // file. The backend will be checked if we need to talk to it.
+            Some(d) => d.to_string(),
 
-                // OK, digest file just doesn't exist. We need to query the backend for it.
-                match backend.input_open_name(OsStr::new(digest::DIGEST_NAME), status) {
-                    OpenResult::Ok(h) => {
-                        // Phew, the backend has the info we need.
-                        let mut text = String::new();
-                        h.take(64).read_to_string(&mut text)?;
-                        checked_digest = true;
-                        text
-                    },
-                    OpenResult::NotAvailable => {
-                        // Broken or un-cacheable backend.
-                        return Err(ErrorKind::Msg("backend does not provide needed SHA256SUM file".to_owned()).into());
-                    },
-                    OpenResult::Err(e) => {
+            None => match File::open(digest) {
+                Ok(f) => {
+                    let mut text = String::new();
+                    f.take(64).read_to_string(&mut text)?;
+                    text
+                },
+                Err(e) => {
+                    if e.kind() != IoErrorKind::NotFound {
+                        // Unexpected error reading the digest cache file. Ruh roh!
                         return Err(e.into());
                     }
+
+                    // OK, digest file just doesn't exist. We need to query the backend for it.
+                    match backend.input_open_name(OsStr::new(digest::DIGEST_NAME), status) {
+                        OpenResult::Ok(h) => {
+                            // Phew, the backend has the info we need.
+                            let mut text = String::new();
+                            h.take(64).read_to_string(&mut text)?;
+                            checked_digest = true;
+                            text
+                        },
+                        OpenResult::NotAvailable => {
+                            // Broken or un-cacheable backend.
+                            return Err(ErrorKind::Msg("backend does not provide needed SHA256SUM file".to_owned()).into());
+                        },
+                        OpenResult::Err(e) => {
+                            return Err(e.into());
+                        }
+                    }
                 }
-            }
+            },
         };
 
         let cached_digest = ctry!(DigestData::from_str(&digest_text); "corrupted SHA256 digest cache");
@@ -142,7 +175,7 @@ impl<B: IoProvider> LocalCache<B> {
                         }
                     };
 
-                    contents.insert(name, LocalCacheItem { _length: length, digest: digest });
+                    contents.insert(name, LocalCacheItem { length: length, digest: digest });
                 }
             }
         }
@@ -154,14 +187,41 @@ impl<B: IoProvider> LocalCache<B> {
             digest_path: digest.to_owned(),
             cached_digest: cached_digest,
             checked_digest: checked_digest,
+            pinned: pinned_digest.is_some(),
             manifest_path: manifest_path,
             formats_base: formats_base.to_owned(),
             data_path: data.to_owned(),
-            contents: contents
+            contents: contents,
+            verified: HashSet::new(),
+            redownload_corrupt: false,
+            max_size: None,
+            access_log_path: data.join("access.txt"),
+            used: HashSet::new(),
+            cache_size: None,
         })
     }
 
 
+    /// Set whether cached files that fail verification should be fetched
+    /// again from the backend. If false (the default), a corrupted file
+    /// results in a CacheCorrupted error.
+    pub fn redownload_corrupt(&mut self, setting: bool) -> &mut Self {
+        self.redownload_corrupt = setting;
+        self
+    }
+
+
+    /// Set the maximum total size of the cached files, in bytes. When a
+    /// download pushes the cache over this size, the least recently used
+    /// files are deleted. Files used in the current session are never
+    /// deleted, so the limit may be exceeded if a single document needs more
+    /// than this much data.
+    pub fn max_size(&mut self, max_size: Option<u64>) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
+
     fn record_cache_result(&mut self, name: &OsStr, length: u64, digest: Option<DigestData>) -> Result<()> {
         let digest_text = match digest {
             Some(ref d) => d.to_string(),
@@ -181,7 +241,7 @@ impl<B: IoProvider> LocalCache<B> {
                 writeln!(man, "{} {} {}", name_utf8, length, digest_text)?;
             }
         }
-        self.contents.insert(name.to_owned(), LocalCacheItem { _length: length, digest: digest });
+        self.contents.insert(name.to_owned(), LocalCacheItem { length: length, digest: digest });
         Ok(())
     }
 
@@ -212,6 +272,11 @@ impl<B: IoProvider> LocalCache<B> {
 
         let current_digest = ctry!(DigestData::from_str(&dtext); "bad SHA256 digest from backend");
 
+        if self.pinned && self.cached_digest != current_digest {
+            return Err(ErrorKind::PinnedDigestMismatch(self.cached_digest.to_string(),
+                                                       current_digest.to_string()).into());
+        }
+
         if self.cached_digest != current_digest {
             // Crap! The backend isn't what we thought it was. Rewrite the
             // digest file so that next time we'll start afresh.
@@ -230,16 +295,63 @@ impl<B: IoProvider> LocalCache<B> {
 
 
     fn path_for_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<PathBuf> {
-        if let Some(info) = self.contents.get(name) {
-            return match info.digest {
-                None => OpenResult::NotAvailable,
-                Some(ref d) => match d.create_two_part_path(&self.data_path) {
-                    Ok(p) => OpenResult::Ok(p),
-                    Err(e) => OpenResult::Err(e.into()),
-                },
+        let cached = self.contents.get(name).map(|info| (info.length, info.digest));
+
+        if let Some((length, digest)) = cached {
+            let digest = match digest {
+                None => return OpenResult::NotAvailable,
+                Some(d) => d,
             };
+
+            let path = match digest.create_two_part_path(&self.data_path) {
+                Ok(p) => p,
+                Err(e) => return OpenResult::Err(e.into()),
+            };
+
+            // Files can get truncated if we're killed while writing them, or
+            // damaged in other ways, so check that the file is intact the
+            // first time that it's used in each session.
+
+            if self.verified.contains(name) {
+                return OpenResult::Ok(path);
+            }
+
+            if !path.is_file() {
+                // The file has been evicted from the cache to save space.
+                // Just fetch it again.
+                self.contents.remove(name);
+                return self.fetch_path_for_name(name, status);
+            }
+
+            match verify_cached_file(&path, length, &digest) {
+                Ok(true) => {
+                    self.verified.insert(name.to_owned());
+                    self.note_use(&digest, status);
+                    return OpenResult::Ok(path);
+                },
+                Ok(false) => {},
+                Err(e) => return OpenResult::Err(e),
+            }
+
+            if !self.redownload_corrupt {
+                return OpenResult::Err(ErrorKind::CacheCorrupted(name.to_string_lossy().into_owned(),
+                                                                 path.display().to_string()).into());
+            }
+
+            tt_warning!(status, "the cached copy of \"{}\" is corrupted; fetching it again",
+                        name.to_string_lossy());
+
+            // The file is readonly, but that doesn't stop us from removing it
+            // on Unix; elsewhere, the rename below will fail informatively.
+            let _ = fs::remove_file(&path);
+            self.contents.remove(name);
         }
 
+        self.fetch_path_for_name(name, status)
+    }
+
+
+    fn fetch_path_for_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<PathBuf> {
         // Bummer, we haven't seen this file before. We need to (try to) fetch
         // the item from the backend, saving it to disk and calculating its
         // digest ourselves, then enter it in the cache and in our manifest.
@@ -283,10 +395,17 @@ impl<B: IoProvider> LocalCache<B> {
 
             let mut buf = [0u8; 8192];
 
-            while let Ok(nbytes) = stream.read(&mut buf) {
-                if nbytes == 0 {
-                    break;
-                }
+            loop {
+                // A read error here used to be treated as EOF, which caused
+                // truncated files to be entered into the cache.
+                let nbytes = match stream.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) => {
+                        let _ = fs::remove_file(temp_dest.path());
+                        return OpenResult::Err(e.into());
+                    },
+                };
 
                 length += nbytes;
                 let chunk = &buf[..nbytes];
@@ -333,10 +452,161 @@ impl<B: IoProvider> LocalCache<B> {
             return OpenResult::Err(e.into());
         }
 
+        self.verified.insert(name.to_owned());
+        self.note_use(&digest, status);
+
+        if let Err(e) = self.enforce_size_limit(length as u64, status) {
+            tt_warning!(status, "failed to trim the local cache to its maximum size"; e);
+        }
+
         OpenResult::Ok(final_path)
     }
 
 
+    /// Record that a cached file has been used, for the purposes of deciding
+    /// what to evict when the cache gets too big. We only bother to do this
+    /// once per file per session.
+    fn note_use(&mut self, digest: &DigestData, status: &mut StatusBackend) {
+        let key = digest.to_string();
+
+        if self.used.contains(&key) {
+            return;
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let result = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.access_log_path)
+            .and_then(|mut f| writeln!(f, "{} {}", key, now));
+
+        if let Err(e) = result {
+            tt_warning!(status, "failed to update cache access log \"{}\"",
+                        self.access_log_path.display(); e.into());
+        }
+
+        self.used.insert(key);
+    }
+
+
+    /// If the cache has grown beyond its maximum size, delete the least
+    /// recently used files until it fits again. `added` is the size of the
+    /// file that was just downloaded. We keep a running total so that we
+    /// only have to scan the cache the first time and when the limit is
+    /// crossed.
+    fn enforce_size_limit(&mut self, added: u64, status: &mut StatusBackend) -> Result<()> {
+        let max_size = match self.max_size {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        if let Some(ref mut size) = self.cache_size {
+            *size += added;
+
+            if *size <= max_size {
+                return Ok(());
+            }
+        }
+
+        // Figure out when each file was last used. If a file isn't in the
+        // access log, fall back to its modification time, which is when it
+        // was downloaded.
+
+        let mut last_used = HashMap::new();
+
+        if let OpenResult::Ok(f) = try_open_file(&self.access_log_path) {
+            for res in BufReader::new(f).lines() {
+                let line = res?;
+                let mut bits = line.split(' ');
+
+                if let (Some(key), Some(Ok(when))) = (bits.next(), bits.next().map(|t| t.parse::<u64>())) {
+                    let entry = last_used.entry(key.to_owned()).or_insert(when);
+
+                    if when > *entry {
+                        *entry = when;
+                    }
+                }
+            }
+        }
+
+        let mut entries = Vec::new();
+        let mut total_size = 0;
+
+        for dent in fs::read_dir(&self.data_path)? {
+            let dent = dent?;
+
+            if !dent.file_type()?.is_dir() {
+                continue;
+            }
+
+            let prefix = dent.file_name().to_string_lossy().into_owned();
+
+            for fent in fs::read_dir(dent.path())? {
+                let fent = fent?;
+                let md = fent.metadata()?;
+
+                if !md.is_file() {
+                    continue;
+                }
+
+                let key = format!("{}{}", prefix, fent.file_name().to_string_lossy());
+                let when = match last_used.get(&key) {
+                    Some(t) => *t,
+                    None => md.modified().ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0),
+                };
+
+                total_size += md.len();
+                entries.push((when, key, fent.path(), md.len()));
+            }
+        }
+
+        self.cache_size = Some(total_size);
+
+        if total_size <= max_size {
+            return Ok(());
+        }
+
+        entries.sort();
+        let mut n_evicted = 0;
+        let mut survivors = Vec::new();
+
+        for (when, key, path, size) in entries {
+            if total_size <= max_size || self.used.contains(&key) {
+                survivors.push((key, when));
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(_) => {
+                    total_size -= size;
+                    n_evicted += 1;
+                },
+                Err(e) => {
+                    tt_warning!(status, "failed to remove \"{}\" from the local cache", path.display(); e.into());
+                    survivors.push((key, when));
+                },
+            }
+        }
+
+        self.cache_size = Some(total_size);
+        tt_note!(status, "removed {} least-recently-used files from the local cache", n_evicted);
+
+        // Rewrite the access log so that it doesn't grow without bound.
+
+        let mut f = File::create(&self.access_log_path)?;
+
+        for (key, when) in survivors {
+            writeln!(f, "{} {}", key, when)?;
+        }
+
+        Ok(())
+    }
+
+
     /// Get an on-disk path name for a given format file. Unlike
     /// path_for_name(), we do *not* do any fetching or caching; this simply
     /// produces a path that may or may not exist. We rely on the cached
@@ -344,21 +614,37 @@ impl<B: IoProvider> LocalCache<B> {
     /// open up the backend whenever we wanted to open a format file, breaking
     /// network-free operation.
     fn path_for_format(&mut self, name: &OsStr) -> Result<PathBuf> {
-        // Remove all extensions from the format name. PathBuf.file_stem() doesn't
-        // do what we want since it only strips one extension, so here we go:
+        Ok(self.formats_base.join(format_cache_name(&self.cached_digest, name)?))
+    }
+}
 
-        let stem = match name.to_str().and_then(|s| s.splitn(2, ".").next()) {
-            Some(s) => s,
-            None => {
-                return Err(ErrorKind::Msg(format!("incomprehensible format file name \"{}\"",
-                                                  name.to_string_lossy())).into());
-            }
+
+/// Check that a file in the cache has the expected length and digest.
+/// Returns Ok(false) if it doesn't, or if it has gone missing.
+fn verify_cached_file(path: &Path, length: u64, digest: &DigestData) -> Result<bool> {
+    let mut f = match try_open_file(path) {
+        OpenResult::Ok(f) => f,
+        OpenResult::NotAvailable => return Ok(false),
+        OpenResult::Err(e) => return Err(e),
+    };
+
+    if f.metadata()?.len() != length {
+        return Ok(false);
+    }
+
+    let mut digest_builder = digest::create();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let nbytes = match f.read(&mut buf)? {
+            0 => break,
+            n => n,
         };
 
-        let mut p = self.formats_base.clone();
-        p.push(format!("{}-{}-{}.fmt.gz", self.cached_digest.to_string(), stem, ::FORMAT_SERIAL));
-        Ok(p)
+        digest_builder.input(&buf[..nbytes]);
     }
+
+    Ok(DigestData::from(digest_builder) == *digest)
 }
 
 
@@ -396,17 +682,19 @@ impl<B: IoProvider> IoProvider for LocalCache<B> {
 
 
     fn write_format(&mut self, name: &str, data: &[u8], _status: &mut StatusBackend) -> Result<()> {
-        let final_path = self.path_for_format(OsStr::new(name))?;
+        let final_name = format_cache_name(&self.cached_digest, OsStr::new(name))?;
+        save_format(&self.formats_base, &final_name, data)
+    }
 
-        let mut templ = self.formats_base.clone();
-        templ.push("format_XXXXXX");
+    /// If the backend can't list its files, as when we're not allowed to
+    /// use the network, we can at least list the ones that we've cached.
+    fn file_names(&mut self, status: &mut StatusBackend) -> Vec<OsString> {
+        let names = self.backend.file_names(status);
 
-        let temp_path = {
-            let mut temp_dest = mkstemp::TempFile::new(&templ.to_string_lossy(), false)?;
-            temp_dest.write_all(data)?;
-            temp_dest.path().to_owned()
-        };
+        if !names.is_empty() {
+            return names;
+        }
 
-        fs::rename(&temp_path, &final_path).map_err(|e| e.into())
+        self.contents.keys().cloned().collect()
     }
 }
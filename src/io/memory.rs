@@ -2,6 +2,28 @@
 // Copyright 2016-2017 the Tectonic Project
 // Licensed under the MIT License.
 
+//! MemoryIo keeps "files" in in-memory buffers. Besides catching the
+//! outputs of the engines, it can serve as a complete virtual filesystem for
+//! programs that embed Tectonic and don't want to touch the real one: seed
+//! it with the main `.tex` file and any images, bibliographies, etc., run
+//! the engines, and then collect the outputs as byte vectors. Something
+//! like:
+//!
+//! ```ignore
+//! let mut mem = MemoryIo::new(true);
+//! mem.create_entry(OsStr::new("paper.tex"), tex_source)
+//!    .create_entry(OsStr::new("figure.png"), png_data)
+//!    .primary_input(OsStr::new("paper.tex"));
+//!
+//! {
+//!     let mut io = IoStack::new(vec![&mut mem, &mut bundle]);
+//!     TexEngine::new().process(&mut io, &mut events, &mut status, "latex.fmt", "paper.tex")?;
+//! }
+//!
+//! let xdv = mem.take_file(OsStr::new("paper.xdv"));
+//! let log = mem.file(OsStr::new("paper.log"));
+//! ```
+
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
@@ -94,19 +116,69 @@ impl Drop for MemoryIoItem {
 pub struct MemoryIo {
     pub files: Rc<RefCell<HashMap<OsString, Vec<u8>>>>,
     stdout_allowed: bool,
+    primary_input: Option<OsString>,
 }
 
 impl MemoryIo {
+    /// Create a new, empty MemoryIo. If `stdout_allowed` is true, writes to
+    /// standard output are captured in the file named by `stdout_key()`.
     pub fn new(stdout_allowed: bool) -> MemoryIo {
         MemoryIo {
             files: Rc::new(RefCell::new(HashMap::new())),
             stdout_allowed: stdout_allowed,
+            primary_input: None,
         }
     }
 
-    pub fn create_entry(&mut self, name: &OsStr, data: Vec<u8>) {
-        let mut mfiles = self.files.borrow_mut();
-        mfiles.insert(name.to_os_string(), data);
+    /// Create a file with the given contents, replacing any existing file
+    /// of the same name.
+    pub fn create_entry(&mut self, name: &OsStr, data: Vec<u8>) -> &mut Self {
+        self.files.borrow_mut().insert(name.to_os_string(), data);
+        self
+    }
+
+    /// Serve the file `name` as the engine's "primary input". The file
+    /// should be created with `create_entry()` before the engine runs.
+    pub fn primary_input(&mut self, name: &OsStr) -> &mut Self {
+        self.primary_input = Some(name.to_os_string());
+        self
+    }
+
+    /// Get a copy of the contents of the file `name`, if it exists.
+    pub fn file(&self, name: &OsStr) -> Option<Vec<u8>> {
+        self.files.borrow().get(name).cloned()
+    }
+
+    /// Remove the file `name`, returning its contents, without copying
+    /// them, if it exists.
+    pub fn take_file(&mut self, name: &OsStr) -> Option<Vec<u8>> {
+        self.files.borrow_mut().remove(name)
+    }
+
+    /// Get the names of all of the files that exist, in sorted order.
+    pub fn file_names(&self) -> Vec<OsString> {
+        let mut names: Vec<_> = self.files.borrow().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Consume this object and return all of the files that it contains.
+    pub fn into_files(self) -> HashMap<OsString, Vec<u8>> {
+        match Rc::try_unwrap(self.files) {
+            Ok(cell) => cell.into_inner(),
+            Err(rc) => rc.borrow().clone(), // some handle is still open
+        }
+    }
+
+    /// Get another MemoryIo that works on the same files as this one. This
+    /// is useful for putting one behind a wrapper such as `LimitedIo` while
+    /// keeping direct access to the files.
+    pub fn share(&self) -> MemoryIo {
+        MemoryIo {
+            files: self.files.clone(),
+            stdout_allowed: self.stdout_allowed,
+            primary_input: self.primary_input.clone(),
+        }
     }
 
     pub fn stdout_key(& self) -> &OsStr {
@@ -144,6 +216,15 @@ impl IoProvider for MemoryIo {
             OpenResult::NotAvailable
         }
     }
+
+    fn input_open_primary(&mut self, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        let name = match self.primary_input {
+            Some(ref n) => n.clone(),
+            None => return OpenResult::NotAvailable,
+        };
+
+        self.input_open_name(&name, status)
+    }
 }
 
 
@@ -200,4 +281,33 @@ mod tests {
             assert_eq!(s.len(), 0);
         }
     }
+
+    #[test]
+    fn virtual_filesystem() {
+        let mut mem = MemoryIo::new(false);
+        let mut sb = NoopStatusBackend::new();
+
+        assert!(mem.input_open_primary(&mut sb).is_not_available());
+
+        mem.create_entry(OsStr::new("main.tex"), b"hello".to_vec())
+            .primary_input(OsStr::new("main.tex"));
+
+        {
+            let mut s = String::new();
+            mem.input_open_primary(&mut sb).unwrap().read_to_string(&mut s).unwrap();
+            assert_eq!(s, "hello");
+        }
+
+        {
+            let mut h = mem.output_open_name(OsStr::new("main.log")).unwrap();
+            write!(h, "log").unwrap();
+        }
+
+        assert_eq!(mem.file_names(), vec![OsString::from("main.log"), OsString::from("main.tex")]);
+        assert_eq!(mem.file(OsStr::new("main.log")), Some(b"log".to_vec()));
+        assert_eq!(mem.take_file(OsStr::new("main.log")), Some(b"log".to_vec()));
+
+        let files = mem.into_files();
+        assert_eq!(files.len(), 1);
+    }
 }
@@ -3,24 +3,41 @@
 // Licensed under the MIT License.
 
 use flate2::read::GzDecoder;
+use hyper::Url;
 use std::borrow::Cow;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use digest::{self, Digest, DigestData};
-use errors::{Error, ErrorKind, Result};
+use errors::{Error, ErrorKind, Result, ResultExt};
 use status::StatusBackend;
 
+pub mod adapter;
+pub mod allowlist;
+pub mod audit;
+pub mod casefold;
+pub mod dirbundle;
 pub mod filesystem;
+pub mod format_cache;
+pub mod gitbundle;
+pub mod gzip;
+pub mod http;
 //pub mod hyper_seekable; -- Not currently used, but nice code to keep around.
 pub mod itarbundle;
+pub mod limits;
 pub mod local_cache;
 pub mod memory;
+pub mod offline;
+pub mod s3bundle;
+pub mod searchpath;
 pub mod stack;
 pub mod stdstreams;
+pub mod tee;
 pub mod zipbundle;
+pub mod zstbundle;
 
 
 
@@ -77,6 +94,7 @@ pub struct InputHandle {
     inner: Box<InputFeatures>,
     digest: digest::DigestComputer,
     origin: InputOrigin,
+    provider: Option<String>,
     ever_read: bool,
     did_unhandled_seek: bool,
     ungetc_char: Option<u8>,
@@ -90,6 +108,7 @@ impl InputHandle {
             inner: Box::new(inner),
             digest: Default::default(),
             origin: origin,
+            provider: None,
             ever_read: false,
             did_unhandled_seek: false,
             ungetc_char: None,
@@ -104,6 +123,16 @@ impl InputHandle {
         self.origin
     }
 
+    /// The name of the I/O provider that opened this handle, if known. This
+    /// is filled in by IoStacks whose providers have been given names.
+    pub fn provider(&self) -> Option<&str> {
+        self.provider.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn set_provider(&mut self, provider: Option<String>) {
+        self.provider = provider;
+    }
+
     /// Consumes the object and returns the underlying readable handle that
     /// it references.
     pub fn into_inner(self) -> Box<InputFeatures> {
@@ -269,6 +298,16 @@ impl<T> OpenResult<T> {
         }
     }
 
+    /// Transform the value of a successful open, leaving the other variants
+    /// untouched.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> OpenResult<U> {
+        match self {
+            OpenResult::Ok(t) => OpenResult::Ok(f(t)),
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+
     /// Returns true if this result is of the NotAvailable variant.
     pub fn is_not_available(&self) -> bool {
         if let &OpenResult::NotAvailable = self {
@@ -293,6 +332,25 @@ pub trait IoProvider {
         OpenResult::NotAvailable
     }
 
+    /// Open the named file for input, and also return the absolute path of
+    /// the file on the filesystem if it has one. Files that come from
+    /// bundles, memory, and so on have no such path. The engines need real
+    /// paths for things like SyncTeX output and dependency tracking.
+    /// Providers that read from the filesystem should implement this
+    /// function; the default implementation just calls `input_open_name()`.
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        self.input_open_name(name, status).map(|h| (h, None))
+    }
+
+    /// Get the modification time of the named input file. This is optional:
+    /// only providers whose files have meaningful modification times, i.e.
+    /// those that read from the filesystem, need to implement it. The
+    /// engines use this for primitives like `\filemoddate`.
+    fn input_mtime(&mut self, _name: &OsStr) -> OpenResult<SystemTime> {
+        OpenResult::NotAvailable
+    }
+
     /// Open the "primary" input file, which in the context of TeX is the main
     /// input that it's given. When the build is being done using the
     /// filesystem and the input is a file on the filesystem, this function
@@ -302,6 +360,12 @@ pub trait IoProvider {
         OpenResult::NotAvailable
     }
 
+    /// Like `input_open_name_with_abspath()`, for the primary input.
+    fn input_open_primary_with_abspath(&mut self, status: &mut StatusBackend)
+                                       -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        self.input_open_primary(status).map(|h| (h, None))
+    }
+
     /// Open a format file with the specified name. Format files have a
     /// specialized entry point because IOProviders may wish to handle them
     /// specially: namely, to munge the filename to one that includes the
@@ -317,6 +381,14 @@ pub trait IoProvider {
     fn write_format(&mut self, _name: &str, _data: &[u8], _status: &mut StatusBackend) -> Result<()> {
         Err(ErrorKind::Msg("this I/O layer cannot save format files".to_owned()).into())
     }
+
+    /// List the names of the files that this provider can open, so that we
+    /// can suggest alternatives when a file can't be found. This only makes
+    /// sense for bundles; other providers, and bundles whose contents can't
+    /// be listed cheaply, give an empty list.
+    fn file_names(&mut self, _status: &mut StatusBackend) -> Vec<OsString> {
+        Vec::new()
+    }
 }
 
 
@@ -333,6 +405,17 @@ impl<R: Read> InputFeatures for GzDecoder<R> {
 }
 
 
+impl InputFeatures for Box<InputFeatures> {
+    fn get_size(&mut self) -> Result<usize> {
+        (**self).get_size()
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        (**self).try_seek(pos)
+    }
+}
+
+
 impl InputFeatures for Cursor<Vec<u8>> {
     fn get_size(&mut self) -> Result<usize> {
         Ok(self.get_ref().len())
@@ -354,6 +437,70 @@ pub use self::stack::IoStack;
 
 // Helpful.
 
+/// Open a bundle that lives on the local filesystem. Directories are treated
+/// as unpacked bundles, files ending in `.zst` as Zstandard bundles, and
+/// anything else as a Zip bundle.
+pub fn open_local_bundle(path: &Path) -> Result<Box<IoProvider>> {
+    if path.is_dir() {
+        Ok(Box::new(dirbundle::DirBundle::new(path)?))
+    } else if path.extension() == Some(OsStr::new("zst")) {
+        Ok(Box::new(zstbundle::ZstBundle::<File>::open(path)?))
+    } else {
+        Ok(Box::new(zipbundle::ZipBundle::<File>::open(path)?))
+    }
+}
+
+
+/// Get the digest that a bundle provides for itself in its `SHA256SUM`
+/// file, which identifies the version of the bundle.
+pub fn get_bundle_digest(bundle: &mut IoProvider, status: &mut StatusBackend) -> Result<DigestData> {
+    let mut text = String::new();
+
+    match bundle.input_open_name(OsStr::new(digest::DIGEST_NAME), status) {
+        OpenResult::Ok(h) => { h.take(64).read_to_string(&mut text)?; },
+        OpenResult::NotAvailable => {
+            return Err(ErrorKind::Msg("bundle does not provide the SHA256SUM file needed to check its version".to_owned()).into());
+        },
+        OpenResult::Err(e) => return Err(e),
+    }
+
+    Ok(ctry!(text.parse::<DigestData>(); "bad SHA256 digest in bundle"))
+}
+
+
+/// Check that a bundle is the version identified by `expected`, by
+/// comparing against the digest that the bundle provides for itself. This
+/// is used to pin bundles that don't go through the local cache; pinning of
+/// cached bundles is handled by `LocalCache::new_pinned()`.
+pub fn check_bundle_digest(bundle: &mut IoProvider, expected: &DigestData,
+                           status: &mut StatusBackend) -> Result<()> {
+    let found = get_bundle_digest(bundle, status)?;
+
+    if found != *expected {
+        return Err(ErrorKind::PinnedDigestMismatch(expected.to_string(), found.to_string()).into());
+    }
+
+    Ok(())
+}
+
+
+/// Open a bundle specified by a `file://` URL. Paths ending in `.tar` are
+/// treated as indexed tar files, like those served over HTTP; anything else
+/// is handled by `open_local_bundle()`. No network access is involved.
+pub fn open_file_url_bundle(url: &str) -> Result<Box<IoProvider>> {
+    let path = match Url::parse(url).ok().and_then(|u| u.to_file_path().ok()) {
+        Some(p) => p,
+        None => return Err(ErrorKind::Msg(format!("cannot understand bundle URL \"{}\"", url)).into()),
+    };
+
+    if path.extension() == Some(OsStr::new("tar")) {
+        Ok(Box::new(itarbundle::ITarBundle::<itarbundle::FileITarIoFactory>::open(&path)))
+    } else {
+        open_local_bundle(&path)
+    }
+}
+
+
 pub fn try_open_file<P: AsRef<Path>>(path: P) -> OpenResult<File> {
     use std::io::ErrorKind::NotFound;
 
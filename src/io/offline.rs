@@ -0,0 +1,38 @@
+// src/io/offline.rs -- a stand-in for network I/O when the network is off-limits
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+use std::ffi::OsStr;
+
+use errors::ErrorKind;
+use status::StatusBackend;
+use super::{InputHandle, IoProvider, OpenResult};
+
+
+/// OfflineGuardIo takes the place of a network-based bundle when Tectonic is
+/// operating in "only cached" mode. It is meant to be used as the backend of
+/// a LocalCache: files already in the cache are served as usual, but any
+/// request that would have gone to the network yields a NetworkDisabled
+/// error rather than a connection attempt.
+
+pub struct OfflineGuardIo {
+    url: String,
+}
+
+
+impl OfflineGuardIo {
+    /// Create a guard standing in for the bundle at `url`, which is used
+    /// in error messages.
+    pub fn new(url: &str) -> OfflineGuardIo {
+        OfflineGuardIo {
+            url: url.to_owned(),
+        }
+    }
+}
+
+
+impl IoProvider for OfflineGuardIo {
+    fn input_open_name(&mut self, name: &OsStr, _status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        OpenResult::Err(ErrorKind::NetworkDisabled(name.to_string_lossy().into_owned(), self.url.clone()).into())
+    }
+}
@@ -0,0 +1,326 @@
+// src/io/s3bundle.rs -- I/O on files stored in an S3 object store
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Bundle files served out of an Amazon S3 bucket, or any object store that
+//! speaks the S3 protocol. Each file in the bundle is stored as its own
+//! object under a common key prefix, alongside the `SHA256SUM` file that the
+//! local cache needs. Bundles are specified with URLs of the form
+//! `s3://bucket/prefix`.
+//!
+//! If the standard `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment
+//! variables are set, requests are signed using AWS Signature Version 4, so
+//! that private buckets can be used. Otherwise requests are made anonymously.
+
+use hyper::{Client, Url};
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use std::env;
+use std::ffi::OsStr;
+use std::io::{Cursor, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use digest::{self, bytes_to_hex, Digest};
+use errors::{ErrorKind, Result};
+use super::{InputHandle, InputOrigin, IoProvider, OpenResult};
+use super::http::{create_client, NetworkSettings};
+use status::StatusBackend;
+
+
+const DEFAULT_REGION: &'static str = "us-east-1";
+
+
+/// Credentials used to sign requests to the object store.
+struct S3Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Credentials {
+    fn from_env() -> Option<S3Credentials> {
+        let access_key_id = match env::var("AWS_ACCESS_KEY_ID") {
+            Ok(s) => s,
+            Err(_) => return None,
+        };
+
+        let secret_access_key = match env::var("AWS_SECRET_ACCESS_KEY") {
+            Ok(s) => s,
+            Err(_) => return None,
+        };
+
+        Some(S3Credentials {
+            access_key_id: access_key_id,
+            secret_access_key: secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+
+pub struct S3Bundle {
+    client: Client,
+    proxy: Option<String>,
+    network: NetworkSettings,
+    endpoint: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    credentials: Option<S3Credentials>,
+}
+
+
+impl S3Bundle {
+    /// Create a new S3 bundle from an `s3://bucket/prefix` URL. If
+    /// `endpoint` is None, the standard AWS endpoint for the region is used;
+    /// otherwise it should be the base URL of an S3-compatible service, e.g.
+    /// `https://minio.example.com`. Objects are always addressed path-style.
+    pub fn new(url: &str, endpoint: Option<&str>, region: Option<&str>) -> Result<S3Bundle> {
+        let rest = if url.starts_with("s3://") {
+            &url[5..]
+        } else {
+            return Err(ErrorKind::Msg(format!("not an S3 URL: \"{}\"", url)).into());
+        };
+
+        let mut pieces = rest.splitn(2, '/');
+        let bucket = pieces.next().unwrap_or("");
+
+        if bucket.len() == 0 {
+            return Err(ErrorKind::Msg(format!("no bucket name in S3 URL \"{}\"", url)).into());
+        }
+
+        let mut prefix = pieces.next().unwrap_or("").trim_matches('/').to_owned();
+
+        if prefix.len() > 0 {
+            prefix.push('/');
+        }
+
+        let region = region.map(|s| s.to_owned())
+            .or_else(|| env::var("AWS_REGION").ok())
+            .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+            .unwrap_or_else(|| DEFAULT_REGION.to_owned());
+
+        let endpoint = match endpoint {
+            Some(e) => e.trim_right_matches('/').to_owned(),
+            None => format!("https://s3.{}.amazonaws.com", region),
+        };
+
+        let network = NetworkSettings::default();
+        let client = create_client(&endpoint, None, &network)?;
+
+        Ok(S3Bundle {
+            client: client,
+            proxy: None,
+            network: network,
+            endpoint: endpoint,
+            bucket: bucket.to_owned(),
+            prefix: prefix,
+            region: region,
+            credentials: S3Credentials::from_env(),
+        })
+    }
+
+    /// Send requests through the specified proxy, overriding any proxy
+    /// settings in the environment.
+    pub fn proxy(&mut self, proxy: Option<&str>) -> Result<&mut Self> {
+        self.proxy = proxy.map(|s| s.to_owned());
+        self.client = create_client(&self.endpoint, proxy, &self.network)?;
+        Ok(self)
+    }
+
+    /// Use these timeouts rather than the defaults.
+    pub fn network(&mut self, settings: &NetworkSettings) -> Result<&mut Self> {
+        self.network = settings.clone();
+        self.client = create_client(&self.endpoint, self.proxy.as_ref().map(|s| s.as_str()), &self.network)?;
+        Ok(self)
+    }
+
+    fn object_path(&self, name: &str) -> String {
+        format!("/{}/{}{}", uri_encode(&self.bucket), uri_encode(&self.prefix), uri_encode(name))
+    }
+
+    /// Fill in the headers needed to authenticate a GET request with AWS
+    /// Signature Version 4. See
+    /// <https://docs.aws.amazon.com/general/latest/gr/sigv4_signing.html>.
+    fn sign_request(&self, url: &Url, path: &str, headers: &mut Headers) -> Result<()> {
+        let creds = match self.credentials {
+            Some(ref c) => c,
+            None => return Ok(()),
+        };
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(h), Some(p)) => format!("{}:{}", h, p),
+            (Some(h), None) => h.to_owned(),
+            (None, _) => return Err(ErrorKind::Msg(format!("no host in S3 endpoint \"{}\"", self.endpoint)).into()),
+        };
+
+        let (amz_date, date_stamp) = amz_timestamps(SystemTime::now());
+        let payload_hash = bytes_to_hex(digest::create().result().as_slice());
+
+        let mut canonical_headers = format!("host:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n",
+                                            host, payload_hash, amz_date);
+        let mut signed_headers = "host;x-amz-content-sha256;x-amz-date".to_owned();
+
+        if let Some(ref token) = creds.session_token {
+            canonical_headers.push_str(&format!("x-amz-security-token:{}\n", token));
+            signed_headers.push_str(";x-amz-security-token");
+        }
+
+        let canonical_request = format!("GET\n{}\n\n{}\n{}\n{}", path, canonical_headers,
+                                        signed_headers, payload_hash);
+
+        let mut dc = digest::create();
+        dc.input(canonical_request.as_bytes());
+        let scope = format!("{}/{}/s3/aws4_request", date_stamp, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope,
+                                     bytes_to_hex(dc.result().as_slice()));
+
+        let k_date = digest::hmac(format!("AWS4{}", creds.secret_access_key).as_bytes(), date_stamp.as_bytes());
+        let k_region = digest::hmac(&k_date, self.region.as_bytes());
+        let k_service = digest::hmac(&k_region, b"s3");
+        let k_signing = digest::hmac(&k_service, b"aws4_request");
+        let signature = bytes_to_hex(&digest::hmac(&k_signing, string_to_sign.as_bytes()));
+
+        let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                                    creds.access_key_id, scope, signed_headers, signature);
+
+        headers.set_raw("x-amz-content-sha256", vec![payload_hash.into_bytes()]);
+        headers.set_raw("x-amz-date", vec![amz_date.into_bytes()]);
+        headers.set_raw("Authorization", vec![authorization.into_bytes()]);
+
+        if let Some(ref token) = creds.session_token {
+            headers.set_raw("x-amz-security-token", vec![token.clone().into_bytes()]);
+        }
+
+        Ok(())
+    }
+
+    fn fetch(&mut self, name: &str) -> OpenResult<Vec<u8>> {
+        let path = self.object_path(name);
+        let url_text = format!("{}{}", self.endpoint, path);
+
+        let url = match Url::parse(&url_text) {
+            Ok(u) => u,
+            Err(e) => return OpenResult::Err(ErrorKind::Msg(format!("invalid S3 URL \"{}\": {}", url_text, e)).into()),
+        };
+
+        let mut headers = Headers::new();
+
+        if let Err(e) = self.sign_request(&url, &path, &mut headers) {
+            return OpenResult::Err(e);
+        }
+
+        let mut res = match self.client.get(url).headers(headers).send() {
+            Ok(r) => r,
+            Err(e) => return OpenResult::Err(e.into()),
+        };
+
+        match res.status {
+            StatusCode::Ok => {},
+            StatusCode::NotFound => return OpenResult::NotAvailable,
+            // S3 reports missing keys as 403 rather than 404 if the
+            // requester isn't allowed to list the bucket, which is the usual
+            // situation for anonymous access to a public bundle.
+            StatusCode::Forbidden if self.credentials.is_none() => return OpenResult::NotAvailable,
+            s => {
+                return OpenResult::Err(ErrorKind::Msg(format!("couldn\'t fetch {} (HTTP status {})",
+                                                              url_text, s)).into());
+            },
+        }
+
+        let mut buf = Vec::new();
+
+        if let Err(e) = res.read_to_end(&mut buf) {
+            return OpenResult::Err(e.into());
+        }
+
+        OpenResult::Ok(buf)
+    }
+}
+
+
+impl IoProvider for S3Bundle {
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        // Object keys must be Unicode, so non-Unicode names can't be in the
+        // bundle.
+
+        let namestr = match name.to_str() {
+            Some(s) => s,
+            None => return OpenResult::NotAvailable
+        };
+
+        tt_note!(status, "downloading {}", namestr);
+
+        match self.fetch(namestr) {
+            OpenResult::Ok(buf) => OpenResult::Ok(InputHandle::new(name, Cursor::new(buf), InputOrigin::Other)),
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+}
+
+
+/// Percent-encode a string in the way that AWS expects for canonical URIs:
+/// everything but the RFC 3986 unreserved characters is escaped, except that
+/// slashes are left alone.
+fn uri_encode(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for b in text.bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                result.push(b as char);
+            },
+            _ => {
+                result.push_str(&format!("%{:02X}", b));
+            },
+        }
+    }
+
+    result
+}
+
+
+/// Compute the `YYYYMMDDTHHMMSSZ` and `YYYYMMDD` timestamps used in AWS
+/// request signing.
+fn amz_timestamps(when: SystemTime) -> (String, String) {
+    let secs = when.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Convert days since the epoch to a civil date; see
+    // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let date_stamp = format!("{:04}{:02}{:02}", year, month, day);
+    let amz_date = format!("{}T{:02}{:02}{:02}Z", date_stamp, rem / 3600, (rem % 3600) / 60, rem % 60);
+    (amz_date, date_stamp)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn test_amz_timestamps() {
+        let t = UNIX_EPOCH + Duration::from_secs(1440938160);
+        assert_eq!(amz_timestamps(t), ("20150830T123600Z".to_owned(), "20150830".to_owned()));
+        assert_eq!(amz_timestamps(UNIX_EPOCH), ("19700101T000000Z".to_owned(), "19700101".to_owned()));
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("tectonic/latex.ltx"), "tectonic/latex.ltx");
+        assert_eq!(uri_encode("a b+c.tex"), "a%20b%2Bc.tex");
+    }
+}
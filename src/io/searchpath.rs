@@ -0,0 +1,244 @@
+// src/io/searchpath.rs -- I/O on files found along a kpathsea-style search path
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An I/O provider that looks for input files in a list of directories, in
+//! the manner of the `TEXINPUTS` environment variable understood by
+//! kpathsea. A directory whose name ends with `//` is searched recursively:
+//! a file is found if it lives anywhere beneath that directory. This lets
+//! documents use shared directories of class and style files without
+//! copying them around.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use errors::Result;
+use super::{InputHandle, InputOrigin, IoProvider, OpenResult};
+use status::StatusBackend;
+
+
+struct SearchPathEntry {
+    dir: PathBuf,
+    recursive: bool,
+
+    /// For recursive entries, a map from file base names to all of the
+    /// files with that name beneath `dir`. Built the first time it's needed.
+    index: Option<HashMap<OsString, Vec<PathBuf>>>,
+}
+
+
+pub struct SearchPathIo {
+    entries: Vec<SearchPathEntry>,
+}
+
+
+impl SearchPathIo {
+    pub fn new() -> SearchPathIo {
+        SearchPathIo { entries: Vec::new() }
+    }
+
+    /// Add a directory to the end of the search path. If its name ends with
+    /// `//`, it is searched recursively. Relative paths are interpreted
+    /// relative to `base`.
+    pub fn add_dir(&mut self, spec: &str, base: &Path) -> &mut Self {
+        let (dir, recursive) = if spec.ends_with("//") {
+            (spec.trim_right_matches('/'), true)
+        } else {
+            (spec, false)
+        };
+
+        // Don't let "//" (i.e., the root directory, recursively) turn into "".
+        let dir = if dir.len() == 0 && spec.starts_with('/') { "/" } else { dir };
+
+        self.entries.push(SearchPathEntry {
+            dir: base.join(dir),
+            recursive: recursive,
+            index: None,
+        });
+        self
+    }
+
+    /// Add all of the directories listed in a search path specification such
+    /// as the value of `TEXINPUTS`, using the platform's path separator.
+    /// Empty elements, which kpathsea uses to mean "the default path", are
+    /// ignored since the rest of the I/O stack provides the defaults.
+    pub fn add_path_spec(&mut self, spec: &OsStr, base: &Path) -> &mut Self {
+        for p in env::split_paths(spec) {
+            let text = p.to_string_lossy().into_owned();
+
+            if text.len() > 0 {
+                self.add_dir(&text, base);
+            }
+        }
+
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+
+impl SearchPathEntry {
+    fn find(&mut self, name: &OsStr) -> Result<Option<PathBuf>> {
+        let direct = self.dir.join(name);
+
+        if direct.is_file() {
+            return Ok(Some(direct));
+        }
+
+        if !self.recursive {
+            return Ok(None);
+        }
+
+        if self.index.is_none() {
+            let mut index = HashMap::new();
+            index_dir(&self.dir, &mut index)?;
+            self.index = Some(index);
+        }
+
+        let path = Path::new(name);
+
+        let base = match path.file_name() {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+
+        let candidates = match self.index.as_ref().unwrap().get(base) {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        // If the name has directory components, they need to match too.
+        Ok(candidates.iter().find(|c| c.ends_with(path)).cloned())
+    }
+}
+
+
+/// Recursively record all of the files beneath `dir`. Subdirectories are
+/// visited in sorted order so that lookups give consistent results.
+fn index_dir(dir: &Path, index: &mut HashMap<OsString, Vec<PathBuf>>) -> Result<()> {
+    let mut items = match fs::read_dir(dir) {
+        Ok(rd) => rd.collect::<::std::io::Result<Vec<_>>>()?,
+        Err(_) => return Ok(()), // nonexistent directories are just skipped
+    };
+
+    items.sort_by_key(|e| e.file_name());
+
+    for item in items {
+        let ft = item.file_type()?;
+        let path = item.path();
+
+        if ft.is_dir() || (ft.is_symlink() && path.is_dir()) {
+            index_dir(&path, index)?;
+        } else {
+            index.entry(item.file_name()).or_insert_with(Vec::new).push(path);
+        }
+    }
+
+    Ok(())
+}
+
+
+impl SearchPathIo {
+    fn find(&mut self, name: &OsStr) -> Result<Option<PathBuf>> {
+        // Only look for names that stay inside the search directories.
+        if Path::new(name).components().any(|c| match c {
+            Component::Normal(_) | Component::CurDir => false,
+            _ => true,
+        }) {
+            return Ok(None);
+        }
+
+        for entry in self.entries.iter_mut() {
+            if let Some(p) = entry.find(name)? {
+                return Ok(Some(p));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+
+impl IoProvider for SearchPathIo {
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.input_open_name_with_abspath(name, status).map(|(h, _)| h)
+    }
+
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, _status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        let path = match self.find(name) {
+            Ok(Some(p)) => p,
+            Ok(None) => return OpenResult::NotAvailable,
+            Err(e) => return OpenResult::Err(e),
+        };
+
+        match File::open(&path) {
+            Ok(f) => OpenResult::Ok((InputHandle::new(name, BufReader::new(f), InputOrigin::Filesystem),
+                                     path.canonicalize().ok())),
+            Err(e) => OpenResult::Err(e.into()),
+        }
+    }
+
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        let path = match self.find(name) {
+            Ok(Some(p)) => p,
+            Ok(None) => return OpenResult::NotAvailable,
+            Err(e) => return OpenResult::Err(e),
+        };
+
+        match path.metadata().and_then(|md| md.modified()) {
+            Ok(t) => OpenResult::Ok(t),
+            Err(e) => OpenResult::Err(e.into()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::io::{Read, Write};
+    use status::NoopStatusBackend;
+    use self::tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn recursive_search() {
+        let tmp = TempDir::new("searchpath").unwrap();
+        fs::create_dir_all(tmp.path().join("shared/classes/sub")).unwrap();
+        File::create(tmp.path().join("shared/classes/sub/thesis.cls")).unwrap().write_all(b"cls").unwrap();
+        File::create(tmp.path().join("top.sty")).unwrap().write_all(b"sty").unwrap();
+
+        let mut sp = SearchPathIo::new();
+        sp.add_dir(".", tmp.path());
+        sp.add_dir("shared//", tmp.path());
+
+        let mut sb = NoopStatusBackend::new();
+        let mut s = String::new();
+
+        sp.input_open_name(OsStr::new("thesis.cls"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "cls");
+
+        s.clear();
+        sp.input_open_name(OsStr::new("sub/thesis.cls"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "cls");
+
+        s.clear();
+        sp.input_open_name(OsStr::new("top.sty"), &mut sb).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "sty");
+
+        assert!(sp.input_open_name(OsStr::new("other/thesis.cls"), &mut sb).is_not_available());
+        assert!(sp.input_open_name(OsStr::new("missing.sty"), &mut sb).is_not_available());
+        assert!(sp.input_open_name(OsStr::new("../top.sty"), &mut sb).is_not_available());
+        assert!(sp.input_open_name(OsStr::new("classes/../../top.sty"), &mut sb).is_not_available());
+    }
+}
@@ -2,26 +2,126 @@
 // Copyright 2016-2017 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::OsStr;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::time::SystemTime;
 
-use status::StatusBackend;
+use status::{ChatterLevel, StatusBackend};
 use super::{InputHandle, IoProvider, OpenResult, OutputHandle};
+use super::audit::{AuditOperation, IoAuditLog};
 
 
 /// An IoStack is an IoProvider that delegates to an ordered list of
 /// subordinate IoProviders. It also checks the order in which files are read
 /// and written to detect "circular" access patterns that indicate whether we
 /// need to run multiple passes of the TeX engine.
+///
+/// If the providers are given names, input handles opened through the stack
+/// are labeled with the name of the provider that supplied them, so that
+/// callers can tell when (e.g.) a file on disk shadows one in the bundle. If
+/// an IoAuditLog is attached to the stack, every open request is recorded in
+/// it, along with the provider that handled it.
+///
+/// The engines probe for lots of files that don't exist, often repeatedly,
+/// and some providers (notably network bundles) have to do real work to
+/// answer each probe. So the stack remembers the names of inputs that no
+/// provider could supply, and doesn't ask again unless a file of that name
+/// is subsequently written. Stacks are created anew for each engine pass, so
+/// this memory only lasts for one pass.
 
 pub struct IoStack<'a> {
     items: Vec<&'a mut IoProvider>,
+    names: Vec<Option<String>>,
+    audit: Option<IoAuditLog>,
+    not_available: HashSet<OsString>,
 }
 
 
 impl<'a> IoStack<'a> {
     pub fn new(items: Vec<&'a mut IoProvider>) -> IoStack<'a> {
+        let names = items.iter().map(|_| None).collect();
+
         IoStack {
             items: items,
+            names: names,
+            audit: None,
+            not_available: HashSet::new(),
+        }
+    }
+
+    /// Create a stack whose providers are labeled with names.
+    pub fn new_named(named_items: Vec<(&str, &'a mut IoProvider)>) -> IoStack<'a> {
+        let mut items = Vec::with_capacity(named_items.len());
+        let mut names = Vec::with_capacity(named_items.len());
+
+        for (name, item) in named_items {
+            names.push(Some(name.to_owned()));
+            items.push(item);
+        }
+
+        IoStack {
+            items: items,
+            names: names,
+            audit: None,
+            not_available: HashSet::new(),
+        }
+    }
+
+    /// Get the name of the provider at the specified position in the stack,
+    /// if it has one.
+    pub fn provider_name(&self, index: usize) -> Option<&str> {
+        match self.names.get(index) {
+            Some(&Some(ref n)) => Some(n.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Record all of the I/O performed through this stack in the specified
+    /// log.
+    pub fn audit(&mut self, log: IoAuditLog) -> &mut Self {
+        self.audit = Some(log);
+        self
+    }
+
+    fn log_input(&self, op: AuditOperation, name: &OsStr, provider: Option<usize>,
+                 r: OpenResult<InputHandle>) -> OpenResult<InputHandle> {
+        let provider_name = provider.and_then(|i| self.provider_name(i));
+
+        let r = match self.audit {
+            Some(ref log) => log.log_input(op, name, provider, provider_name, r),
+            None => r,
+        };
+
+        match r {
+            OpenResult::Ok(mut ih) => {
+                ih.set_provider(provider_name.map(|s| s.to_owned()));
+                OpenResult::Ok(ih)
+            },
+            r => r,
+        }
+    }
+
+    /// Tell the user where an input was found, if they want to know.
+    fn note_resolution(&self, name: &OsStr, provider: Option<usize>, status: &mut StatusBackend) {
+        if status.chatter() < ChatterLevel::Debug {
+            return;
+        }
+
+        match provider {
+            Some(i) => tt_note!(status, "found \"{}\" in {}", name.to_string_lossy(),
+                                self.provider_name(i).unwrap_or("an unnamed provider")),
+            None => tt_note!(status, "\"{}\" was not found", name.to_string_lossy()),
+        }
+    }
+
+    fn log_output(&self, op: AuditOperation, name: &OsStr, provider: Option<usize>,
+                  r: OpenResult<OutputHandle>) -> OpenResult<OutputHandle> {
+        let provider_name = provider.and_then(|i| self.provider_name(i));
+
+        match self.audit {
+            Some(ref log) => log.log_output(op, name, provider, provider_name, r),
+            None => r,
         }
     }
 }
@@ -29,67 +129,113 @@ impl<'a> IoStack<'a> {
 
 impl<'a> IoProvider for IoStack<'a> {
     fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
-        for item in self.items.iter_mut() {
-            let r = item.output_open_name(name);
+        // Once this file is written, it will exist even if it didn't before.
+        self.not_available.remove(name);
+
+        for i in 0..self.items.len() {
+            let r = self.items[i].output_open_name(name);
 
             match r {
                 OpenResult::NotAvailable => continue,
-                _ => return r
+                _ => return self.log_output(AuditOperation::OutputOpen, name, Some(i), r)
             };
         }
 
-        OpenResult::NotAvailable
+        self.log_output(AuditOperation::OutputOpen, name, None, OpenResult::NotAvailable)
     }
 
     fn output_open_stdout(&mut self) -> OpenResult<OutputHandle> {
-        for item in self.items.iter_mut() {
-            let r = item.output_open_stdout();
+        let name = OsStr::new("<stdout>");
+
+        for i in 0..self.items.len() {
+            let r = self.items[i].output_open_stdout();
 
             match r {
                 OpenResult::NotAvailable => continue,
-                _ => return r
+                _ => return self.log_output(AuditOperation::StdoutOpen, name, Some(i), r)
             };
         }
 
-        OpenResult::NotAvailable
+        self.log_output(AuditOperation::StdoutOpen, name, None, OpenResult::NotAvailable)
     }
 
     fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
-        for item in self.items.iter_mut() {
-            let r = item.input_open_name(name, status);
+        self.input_open_name_with_abspath(name, status).map(|(h, _)| h)
+    }
 
-            match r {
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        if self.not_available.contains(name) {
+            return self.log_input(AuditOperation::InputOpen, name, None, OpenResult::NotAvailable).map(|h| (h, None));
+        }
+
+        for i in 0..self.items.len() {
+            match self.items[i].input_open_name_with_abspath(name, status) {
                 OpenResult::NotAvailable => continue,
-                _ => return r
+                OpenResult::Ok((h, p)) => {
+                    self.note_resolution(name, Some(i), status);
+                    return self.log_input(AuditOperation::InputOpen, name, Some(i), OpenResult::Ok(h)).map(|h| (h, p));
+                },
+                OpenResult::Err(e) =>
+                    return self.log_input(AuditOperation::InputOpen, name, Some(i), OpenResult::Err(e)).map(|h| (h, None)),
             };
         }
 
+        self.note_resolution(name, None, status);
+        self.not_available.insert(name.to_os_string());
+        self.log_input(AuditOperation::InputOpen, name, None, OpenResult::NotAvailable).map(|h| (h, None))
+    }
+
+    /// Providers whose files don't have modification times can't tell us
+    /// whether they have a file or not, so the first provider that knows a
+    /// modification time for the name wins.
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        if self.not_available.contains(name) {
+            return OpenResult::NotAvailable;
+        }
+
+        for item in self.items.iter_mut() {
+            match item.input_mtime(name) {
+                OpenResult::NotAvailable => continue,
+                r => return r,
+            }
+        }
+
         OpenResult::NotAvailable
     }
 
     fn input_open_primary(&mut self, status: &mut StatusBackend) -> OpenResult<InputHandle> {
-        for item in self.items.iter_mut() {
-            let r = item.input_open_primary(status);
+        self.input_open_primary_with_abspath(status).map(|(h, _)| h)
+    }
 
-            match r {
+    fn input_open_primary_with_abspath(&mut self, status: &mut StatusBackend)
+                                       -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        let name = OsStr::new("<primary input>");
+        let op = AuditOperation::PrimaryInputOpen;
+
+        for i in 0..self.items.len() {
+            match self.items[i].input_open_primary_with_abspath(status) {
                 OpenResult::NotAvailable => continue,
-                _ => return r
+                OpenResult::Ok((h, p)) =>
+                    return self.log_input(op, name, Some(i), OpenResult::Ok(h)).map(|h| (h, p)),
+                OpenResult::Err(e) =>
+                    return self.log_input(op, name, Some(i), OpenResult::Err(e)).map(|h| (h, None)),
             };
         }
 
-        OpenResult::NotAvailable
+        self.log_input(op, name, None, OpenResult::NotAvailable).map(|h| (h, None))
     }
 
     fn input_open_format(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
-        for item in self.items.iter_mut() {
-            let r = item.input_open_format(name, status);
+        for i in 0..self.items.len() {
+            let r = self.items[i].input_open_format(name, status);
 
             match r {
                 OpenResult::NotAvailable => continue,
-                _ => return r
+                _ => return self.log_input(AuditOperation::FormatOpen, name, Some(i), r)
             };
         }
 
-        OpenResult::NotAvailable
+        self.log_input(AuditOperation::FormatOpen, name, None, OpenResult::NotAvailable)
     }
 }
@@ -106,6 +106,12 @@ impl BufferedPrimaryIo {
         Self::from_stream(&mut stdin())
     }
 
+    pub fn from_buffer(data: Vec<u8>) -> Self {
+        BufferedPrimaryIo {
+            buffer: SharedByteBuffer::new(data)
+        }
+    }
+
     pub fn from_text<T: AsRef<str>>(text: T) -> Self {
         BufferedPrimaryIo {
             buffer: SharedByteBuffer::new(text.as_ref().as_bytes().to_owned())
@@ -0,0 +1,150 @@
+// src/io/tee.rs -- duplicating outputs into two providers
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An I/O provider that sends every output file to two other providers. For
+//! instance, a tool can tee a FilesystemIo and a MemoryIo to save its
+//! outputs to disk while also getting at them in-process, without having to
+//! read the files back in afterwards.
+
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use status::StatusBackend;
+use super::{InputHandle, IoProvider, OpenResult, OutputHandle};
+
+
+/// TeeIo duplicates outputs into two providers. An output is only opened in
+/// the secondary provider if the primary one accepted it; if the secondary
+/// provider declines, the output goes to the primary provider only. Inputs
+/// are read from the primary provider.
+pub struct TeeIo<A: IoProvider, B: IoProvider> {
+    primary: A,
+    secondary: B,
+}
+
+
+impl<A: IoProvider, B: IoProvider> TeeIo<A, B> {
+    pub fn new(primary: A, secondary: B) -> TeeIo<A, B> {
+        TeeIo {
+            primary: primary,
+            secondary: secondary,
+        }
+    }
+
+    /// Consumes the object and returns the two wrapped providers.
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.secondary)
+    }
+
+    fn tee(primary: OpenResult<OutputHandle>, secondary: OpenResult<OutputHandle>) -> OpenResult<OutputHandle> {
+        let first = match primary {
+            OpenResult::Ok(h) => h,
+            r => return r,
+        };
+
+        match secondary {
+            OpenResult::Ok(second) => {
+                let name = first.name().to_os_string();
+                OpenResult::Ok(OutputHandle::new(&name, TeeWriter { first: first, second: second }))
+            },
+            OpenResult::NotAvailable => OpenResult::Ok(first),
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+}
+
+
+impl<A: IoProvider, B: IoProvider> IoProvider for TeeIo<A, B> {
+    fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
+        let primary = self.primary.output_open_name(name);
+
+        if let OpenResult::Ok(_) = primary {
+            let secondary = self.secondary.output_open_name(name);
+            Self::tee(primary, secondary)
+        } else {
+            primary
+        }
+    }
+
+    fn output_open_stdout(&mut self) -> OpenResult<OutputHandle> {
+        let primary = self.primary.output_open_stdout();
+
+        if let OpenResult::Ok(_) = primary {
+            let secondary = self.secondary.output_open_stdout();
+            Self::tee(primary, secondary)
+        } else {
+            primary
+        }
+    }
+
+    fn input_open_name(&mut self, name: &OsStr, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.primary.input_open_name(name, status)
+    }
+
+    fn input_open_name_with_abspath(&mut self, name: &OsStr, status: &mut StatusBackend)
+                                    -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        self.primary.input_open_name_with_abspath(name, status)
+    }
+
+    fn input_mtime(&mut self, name: &OsStr) -> OpenResult<SystemTime> {
+        self.primary.input_mtime(name)
+    }
+
+    fn input_open_primary(&mut self, status: &mut StatusBackend) -> OpenResult<InputHandle> {
+        self.primary.input_open_primary(status)
+    }
+
+    fn input_open_primary_with_abspath(&mut self, status: &mut StatusBackend)
+                                       -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        self.primary.input_open_primary_with_abspath(status)
+    }
+}
+
+
+struct TeeWriter {
+    first: OutputHandle,
+    second: OutputHandle,
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.first.write(buf)?;
+        self.second.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use io::memory::MemoryIo;
+    use super::*;
+
+    #[test]
+    fn duplicated_output() {
+        let a = MemoryIo::new(false);
+        let b = MemoryIo::new(true);
+        let a_files = a.files.clone();
+        let b_files = b.files.clone();
+        let mut tee = TeeIo::new(a, b);
+
+        {
+            let mut h = tee.output_open_name(OsStr::new("out.pdf")).unwrap();
+            h.write_all(b"%PDF").unwrap();
+        }
+
+        assert_eq!(a_files.borrow().get(OsStr::new("out.pdf")).unwrap(), b"%PDF");
+        assert_eq!(b_files.borrow().get(OsStr::new("out.pdf")).unwrap(), b"%PDF");
+
+        // The primary provider doesn't allow stdout, so neither should we.
+        assert!(tee.output_open_stdout().is_not_available());
+    }
+}
@@ -2,7 +2,7 @@
 // Copyright 2016 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::{Cursor, Read, Seek};
 use std::path::Path;
@@ -68,4 +68,16 @@ impl<R: Read + Seek> IoProvider for ZipBundle<R> {
 
         OpenResult::Ok(InputHandle::new(name, Cursor::new(buf), InputOrigin::Other))
     }
+
+    fn file_names(&mut self, _status: &mut StatusBackend) -> Vec<OsString> {
+        let mut names = Vec::with_capacity(self.zip.len());
+
+        for i in 0..self.zip.len() {
+            if let Ok(item) = self.zip.by_index(i) {
+                names.push(OsString::from(item.name()));
+            }
+        }
+
+        names
+    }
 }
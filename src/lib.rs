@@ -27,10 +27,11 @@
 //! - The frontend is just a thin shim over the Tectonic Rust crate, so that
 //!   the full engine can be embedded anywhere you can run Rust code.
 //!
-//! Rust API documentation for Tectonic is currently very incomplete. As a
-//! stopgap, please see [the source to the CLI
+//! Rust API documentation for Tectonic is currently very incomplete. The
+//! easiest way to process a document the way the `tectonic` program does is
+//! with a `driver::SessionBuilder`; see [the source to the CLI
 //! frontend](https://github.com/tectonic-typesetting/tectonic/blob/master/src/cli_driver.rs)
-//! for a demonstration of how to run the engine.
+//! for a demonstration of how to set one up.
 
 extern crate app_dirs;
 #[macro_use] extern crate error_chain;
@@ -39,27 +40,43 @@ extern crate fs2;
 extern crate hyper;
 extern crate hyper_native_tls;
 extern crate libc;
+#[macro_use] extern crate log;
 extern crate md_5 as md5;
+extern crate memmap;
 extern crate mkstemp;
 #[macro_use] extern crate serde_derive;
 extern crate serde;
+extern crate serde_json;
 extern crate sha2;
+extern crate tempdir;
 extern crate termcolor;
 extern crate toml;
 extern crate zip;
+extern crate zstd;
 
 #[macro_use] pub mod status;
 #[macro_use] pub mod errors;
 pub mod config;
+pub mod config_schema;
+pub mod diagnostics;
 pub mod digest;
+pub mod driver;
 pub mod engines;
 pub mod io;
+pub mod manifest;
+pub mod preview;
 
-pub use engines::tex::{TexEngine, TexResult};
-pub use engines::xdvipdfmx::XdvipdfmxEngine;
+pub use driver::{OutputFormat, PassSetting, ProcessingSession, SessionBuilder};
+pub use engines::CancellationToken;
+pub use engines::tex::{InteractionMode, TexEngine, TexHooks, TexResult};
+pub use engines::xdvipdfmx::{PdfAttachment, PdfEncryption, PdfMetadata, XdvipdfmxEngine};
+pub use engines::xdvisvg::{SvgFontMode, XdvisvgEngine};
+pub use engines::xdvipng::XdvipngEngine;
+pub use engines::xdvitxt::XdvitxtEngine;
 pub use engines::bibtex::BibtexEngine;
+pub use engines::makeindex::MakeindexEngine;
 pub use errors::{Error, ErrorKind, Result};
 
 const APP_INFO: app_dirs::AppInfo = app_dirs::AppInfo {name: "Tectonic", author: "TectonicProject"};
 
-const FORMAT_SERIAL: u32 = 25; // keep synchronized with tectonic/constants.h!!
+const FORMAT_SERIAL: u32 = 26; // keep synchronized with tectonic/constants.h!!
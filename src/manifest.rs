@@ -0,0 +1,274 @@
+// src/manifest.rs -- project manifests
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A project manifest, a file named `Tectonic.toml`, lists the documents
+//! that make up a project, such as a thesis along with the slides and
+//! poster that go with it, and how to process each one. The `tectonic
+//! build` command processes the documents of the manifest that it finds in
+//! the current directory or one of its parents. A manifest looks like:
+//!
+//! ```toml
+//! [[bundles]]
+//! url = "https://example.com/tectonic-2017.04.tar"
+//! digest = "..."
+//!
+//! [[documents]]
+//! name = "thesis"
+//! input = "thesis/main.tex"
+//! outputs = ["pdf"]
+//! synctex = true
+//!
+//! [[documents]]
+//! name = "slides"
+//! input = "slides.tex"
+//! outputs = ["pdf", "png"]
+//! before_build = ["python make-figures.py"]
+//! after_build = ["cp slides.pdf ~/public_html/"]
+//! ```
+//!
+//! The `bundles` are given as in the persistent configuration, and are used
+//! instead of the default bundles, so that the project can pin the exact
+//! support files that it's built with. A manifest can also define
+//! `[profiles.NAME]` tables, as described in `config::BuildProfile`. Paths are relative to the directory
+//! containing the manifest.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml;
+
+use config::{BuildProfile, BundleInfo};
+use driver::{CommandHooks, OutputFormat, SessionBuilder};
+use engines::tex::InteractionMode;
+use errors::{ErrorKind, Result, ResultExt};
+
+
+/// The name of the manifest file in the root directory of a project.
+pub const MANIFEST_FILE_NAME: &'static str = "Tectonic.toml";
+
+
+#[derive(Deserialize)]
+struct ManifestData {
+    #[serde(default)]
+    bundles: Vec<BundleInfo>,
+
+    #[serde(default)]
+    documents: Vec<DocumentInfo>,
+
+    #[serde(default)]
+    profiles: HashMap<String, BuildProfile>,
+}
+
+
+/// How to process one of the documents of a project.
+#[derive(Clone,Debug,Deserialize)]
+pub struct DocumentInfo {
+    /// The name used to refer to the document on the command line.
+    pub name: String,
+
+    /// The path of the main TeX file, relative to the project directory.
+    pub input: String,
+
+    /// The kinds of output to create, using the names accepted by the
+    /// `--outfmt` option. Each one takes a separate processing session.
+    #[serde(default = "default_outputs")]
+    pub outputs: Vec<String>,
+
+    /// The name of the format file to use.
+    #[serde(default = "default_format")]
+    pub format: String,
+
+    /// Extra directories to search for input files, relative to the
+    /// project directory.
+    #[serde(default)]
+    pub search_path: Vec<String>,
+
+    #[serde(default)]
+    pub synctex: bool,
+
+    #[serde(default)]
+    pub keep_intermediates: bool,
+
+    #[serde(default)]
+    pub keep_logs: bool,
+
+    /// The TeX interaction mode, by one of the names accepted by the
+    /// `--interaction` option, such as "nonstopmode".
+    #[serde(default)]
+    pub interaction: Option<String>,
+
+    /// Rerun TeX exactly this many times, rather than deciding
+    /// automatically.
+    #[serde(default)]
+    pub reruns: Option<usize>,
+
+    /// Shell commands to run in the project directory before processing the
+    /// document, and afterwards. See `driver::CommandHooks`.
+    #[serde(default)]
+    pub before_build: Vec<String>,
+
+    #[serde(default)]
+    pub after_build: Vec<String>,
+}
+
+fn default_outputs() -> Vec<String> {
+    vec!["pdf".to_owned()]
+}
+
+fn default_format() -> String {
+    "latex".to_owned()
+}
+
+
+/// A project manifest that has been loaded from disk.
+pub struct ProjectManifest {
+    dir: PathBuf,
+    bundles: Vec<BundleInfo>,
+    documents: Vec<DocumentInfo>,
+    profiles: HashMap<String, BuildProfile>,
+}
+
+
+impl ProjectManifest {
+    /// Load the manifest at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ProjectManifest> {
+        let path = path.as_ref();
+        let mut buf = Vec::new();
+        let mut f = ctry!(File::open(path); "couldn't open project manifest \"{}\"", path.display());
+        ctry!(f.read_to_end(&mut buf); "couldn't read project manifest \"{}\"", path.display());
+        let data: ManifestData = ctry!(toml::from_slice(&buf); "couldn't parse project manifest \"{}\"",
+                                       path.display());
+
+        let dir = match path.parent() {
+            Some(p) => p.to_owned(),
+            None => PathBuf::new(),
+        };
+
+        if data.documents.is_empty() {
+            return Err(ErrorKind::Msg(format!("project manifest \"{}\" doesn't list any documents",
+                                              path.display())).into());
+        }
+
+        for (i, doc) in data.documents.iter().enumerate() {
+            if data.documents[..i].iter().any(|d| d.name == doc.name) {
+                return Err(ErrorKind::Msg(format!("project manifest \"{}\" lists more than one document named \"{}\"",
+                                                  path.display(), doc.name)).into());
+            }
+
+            for name in &doc.outputs {
+                ctry!(OutputFormat::from_str(name); "bad output for document \"{}\" in \"{}\"",
+                      doc.name, path.display());
+            }
+        }
+
+        let mut bundles = data.bundles;
+
+        for b in &mut bundles {
+            b.make_relative_to(&dir);
+        }
+
+        Ok(ProjectManifest {
+            dir: dir,
+            bundles: bundles,
+            documents: data.documents,
+            profiles: data.profiles,
+        })
+    }
+
+    /// Find the manifest of the project containing the directory `dir`, by
+    /// looking there and in its parents. Returns `None` if there isn't one.
+    pub fn find<P: AsRef<Path>>(dir: P) -> Result<Option<ProjectManifest>> {
+        let mut dir = Some(dir.as_ref());
+
+        while let Some(d) = dir {
+            let path = d.join(MANIFEST_FILE_NAME);
+
+            if path.is_file() {
+                return Ok(Some(ProjectManifest::open(&path)?));
+            }
+
+            dir = d.parent();
+        }
+
+        Ok(None)
+    }
+
+    /// The directory containing the manifest, which the paths in it are
+    /// relative to.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The bundles that the project is built with. If this is empty, the
+    /// default bundles should be used.
+    pub fn bundles(&self) -> &[BundleInfo] {
+        &self.bundles
+    }
+
+    pub fn documents(&self) -> &[DocumentInfo] {
+        &self.documents
+    }
+
+    /// Look up a document by name.
+    pub fn document(&self, name: &str) -> Option<&DocumentInfo> {
+        self.documents.iter().find(|d| d.name == name)
+    }
+
+    /// Get the names of the profiles defined in the manifest.
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.keys().map(|s| s.as_str()).collect()
+    }
+
+    /// Look up a profile defined in the manifest. Profiles defined here take
+    /// precedence over those of the persistent configuration.
+    pub fn profile(&self, name: &str) -> Option<&BuildProfile> {
+        self.profiles.get(name)
+    }
+
+    /// Set up a session builder to create the output `output` of `doc`.
+    /// Bundles and everything else that doesn't come from the manifest are
+    /// left for the caller to configure.
+    pub fn session_builder(&self, doc: &DocumentInfo, output: &str) -> Result<SessionBuilder> {
+        let mut sb = SessionBuilder::new();
+
+        sb.primary_input_path(self.dir.join(&doc.input))
+          .output_format(OutputFormat::from_str(output)?)
+          .format_name(&doc.format)
+          .synctex(doc.synctex)
+          .keep_intermediates(doc.keep_intermediates)
+          .keep_logs(doc.keep_logs);
+
+        if let Some(ref mode) = doc.interaction {
+            sb.interaction(ctry!(InteractionMode::from_str(mode); "invalid interaction mode for document \"{}\"",
+                                 doc.name));
+        }
+
+        if let Some(n) = doc.reruns {
+            sb.reruns(n);
+        }
+
+        for dir in &doc.search_path {
+            sb.search_dir(&self.dir.join(dir).to_string_lossy());
+        }
+
+        if !doc.before_build.is_empty() || !doc.after_build.is_empty() {
+            let mut hooks = CommandHooks::new();
+            hooks.dir(&self.dir);
+
+            for c in &doc.before_build {
+                hooks.before(c);
+            }
+
+            for c in &doc.after_build {
+                hooks.after(c);
+            }
+
+            sb.hooks(Box::new(hooks));
+        }
+
+        Ok(sb)
+    }
+}
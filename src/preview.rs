@@ -0,0 +1,287 @@
+// src/preview.rs -- serving live previews of documents over HTTP
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A tiny HTTP server for previewing a document in a web browser while
+//! editing it. The server shows the output files that it was last given —
+//! a PDF, or the pages of SVG or PNG output — on a page that reloads itself
+//! whenever it's given new ones. The reloading uses [server-sent
+//! events](https://html.spec.whatwg.org/multipage/server-sent-events.html),
+//! which every browser understands without any help.
+//!
+//! This is meant to be used on the local machine by one person, not exposed
+//! to the world: it only understands enough HTTP to talk to browsers, and it
+//! uses a thread for each connection.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use errors::Result;
+
+
+/// The state shared between the server and its connections.
+#[derive(Default)]
+struct Shared {
+    /// The files to show, by the names that they're served under.
+    files: Vec<(String, PathBuf)>,
+
+    /// Counts the updates, so that browsers don't show stale cached files.
+    generation: u64,
+
+    /// One channel for each browser waiting to be told about updates.
+    listeners: Vec<mpsc::Sender<()>>,
+}
+
+
+/// A running preview server. The server stops when the program exits;
+/// there's no way to stop it sooner.
+pub struct PreviewServer {
+    addr: SocketAddr,
+    shared: Arc<Mutex<Shared>>,
+}
+
+
+impl PreviewServer {
+    /// Start serving on `addr` in the background. Until `update()` is
+    /// called, the page has nothing to show.
+    pub fn start<A: ToSocketAddrs>(addr: A) -> Result<PreviewServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let conn_shared = shared.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+
+                let shared = conn_shared.clone();
+
+                thread::spawn(move || {
+                    // A browser going away mid-request isn't worth reporting.
+                    let _ = handle_connection(stream, &shared);
+                });
+            }
+        });
+
+        Ok(PreviewServer {
+            addr: addr,
+            shared: shared,
+        })
+    }
+
+    /// The address that the server is listening on. This is the place to
+    /// find out the port if it was chosen by the system.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Show the output files at `paths`, and tell the browsers to reload.
+    /// Files that can't be shown, like SyncTeX data, are ignored.
+    pub fn update<P: AsRef<Path>>(&self, paths: &[P]) {
+        let mut shared = self.shared.lock().unwrap();
+
+        shared.files = paths.iter().filter_map(|p| {
+            let p = p.as_ref();
+
+            match p.file_name() {
+                Some(name) if content_type(p).is_some() => Some((name.to_string_lossy().into_owned(), p.to_owned())),
+                _ => None,
+            }
+        }).collect();
+
+        shared.generation += 1;
+
+        // Browsers that have gone away have dropped their receivers.
+        shared.listeners.retain(|tx| tx.send(()).is_ok());
+    }
+}
+
+
+/// The MIME type to send a file as, if it's something that we show.
+fn content_type(path: &Path) -> Option<&'static str> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("pdf") => Some("application/pdf"),
+        Some("svg") => Some("image/svg+xml"),
+        Some("png") => Some("image/png"),
+        Some("woff") => Some("font/woff"),
+        Some("txt") => Some("text/plain; charset=utf-8"),
+        _ => None,
+    }
+}
+
+
+fn handle_connection(stream: TcpStream, shared: &Arc<Mutex<Shared>>) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // We don't care about any of the headers, but they have to be read.
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut pieces = request_line.split_whitespace();
+    let method = pieces.next().unwrap_or("");
+    let target = pieces.next().unwrap_or("");
+
+    // Drop any query string, which is only there to defeat caching.
+    let path = match target.find('?') {
+        Some(i) => &target[..i],
+        None => target,
+    };
+
+    let mut stream = stream;
+
+    if method != "GET" {
+        return send(&mut stream, "405 Method Not Allowed", "text/plain", b"method not allowed\n");
+    }
+
+    if path == "/" {
+        let page = {
+            let shared = shared.lock().unwrap();
+            index_page(&shared)
+        };
+        send(&mut stream, "200 OK", "text/html; charset=utf-8", page.as_bytes())
+    } else if path == "/events" {
+        send_events(stream, shared)
+    } else if path.starts_with("/files/") {
+        let wanted = percent_decode(&path[7..]);
+
+        let file = {
+            let shared = shared.lock().unwrap();
+            shared.files.iter().find(|&&(ref name, _)| name == &wanted).map(|&(_, ref p)| p.clone())
+        };
+
+        let file = match file {
+            Some(p) => p,
+            None => return send(&mut stream, "404 Not Found", "text/plain", b"not found\n"),
+        };
+
+        let mut data = Vec::new();
+
+        if File::open(&file).and_then(|mut f| f.read_to_end(&mut data)).is_err() {
+            return send(&mut stream, "404 Not Found", "text/plain", b"not found\n");
+        }
+
+        send(&mut stream, "200 OK", content_type(&file).unwrap(), &data)
+    } else {
+        send(&mut stream, "404 Not Found", "text/plain", b"not found\n")
+    }
+}
+
+
+fn send(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\n\
+                    Connection: close\r\n\r\n", status, content_type, body.len())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+
+/// Keep the connection open, sending an event every time that there's an
+/// update, until the browser goes away.
+fn send_events(mut stream: TcpStream, shared: &Arc<Mutex<Shared>>) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    shared.lock().unwrap().listeners.push(tx);
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
+    stream.flush()?;
+
+    while rx.recv().is_ok() {
+        write!(stream, "data: reload\n\n")?;
+        stream.flush()?;
+    }
+
+    Ok(())
+}
+
+
+fn index_page(shared: &Shared) -> String {
+    let mut files: HashMap<&str, Vec<&str>> = HashMap::new();
+
+    for &(ref name, ref path) in &shared.files {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        files.entry(ext).or_insert_with(Vec::new).push(name);
+    }
+
+    let g = shared.generation;
+
+    let body = if let Some(names) = files.get("pdf") {
+        format!("<iframe src=\"/files/{}?g={}\"></iframe>", html_escape(names[0]), g)
+    } else if let Some(names) = files.get("svg").or_else(|| files.get("png")) {
+        names.iter()
+            .map(|n| format!("<img src=\"/files/{}?g={}\">", html_escape(n), g))
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else if let Some(names) = files.get("txt") {
+        format!("<iframe src=\"/files/{}?g={}\"></iframe>", html_escape(names[0]), g)
+    } else {
+        "<p>Nothing to show yet.</p>".to_owned()
+    };
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Tectonic preview</title>
+<style>
+html, body {{ margin: 0; height: 100%; background: #888; }}
+iframe {{ border: none; width: 100%; height: 100%; }}
+img {{ display: block; margin: 1em auto; background: white; box-shadow: 0 0 0.5em #444; }}
+p {{ text-align: center; font-family: sans-serif; }}
+</style>
+</head>
+<body>
+{}
+<script>
+new EventSource("/events").onmessage = function () {{ location.reload(); }};
+</script>
+</body>
+</html>
+"#, body)
+}
+
+
+/// Undo the escaping of special characters in a URL path, such as the
+/// spaces in file names.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
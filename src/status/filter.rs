@@ -0,0 +1,224 @@
+// src/status/filter.rs -- hiding warnings that the user doesn't care about
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Some documents produce the same harmless warnings on every run, which
+//! bury the ones that matter. A `FilteringStatusBackend` sits in front of
+//! another backend and drops the warnings that a `WarningFilter` says to
+//! hide, keeping count of them so that the user can be told how many there
+//! were.
+
+use std::collections::BTreeMap;
+use std::fmt::Arguments;
+
+use diagnostics::{Diagnostic, DiagnosticKind};
+use errors::Error;
+use super::{ChatterLevel, MessageKind, ProgressUnit, StatusBackend};
+
+
+/// Which warnings to hide. This can be read from the `[warnings]` section
+/// of the configuration file:
+///
+/// ```toml
+/// [warnings]
+/// hide = ["font-substitution"]
+/// min_overfull_pt = 5.0
+/// hide_matching = ["Package hyperref Warning"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WarningFilter {
+    /// Kinds of diagnostics to hide entirely, by the names given by
+    /// `DiagnosticKind::name()`, such as "badbox" or "font-substitution".
+    #[serde(default)]
+    pub hide: Vec<String>,
+
+    /// Hide overfull boxes that stick out by less than this many points.
+    #[serde(default)]
+    pub min_overfull_pt: Option<f64>,
+
+    /// Hide warnings whose text contains any of these strings.
+    #[serde(default)]
+    pub hide_matching: Vec<String>,
+}
+
+
+impl WarningFilter {
+    fn hides_message(&self, message: &str) -> bool {
+        self.hide_matching.iter().any(|m| message.contains(&m[..]))
+    }
+
+    /// Whether `diagnostic` should be hidden. Errors are never hidden.
+    pub fn hides(&self, diagnostic: &Diagnostic) -> bool {
+        if diagnostic.kind == DiagnosticKind::Error {
+            return false;
+        }
+
+        if self.hide.iter().any(|k| k == diagnostic.kind.name()) {
+            return true;
+        }
+
+        if let (&DiagnosticKind::BadBox, Some(min)) = (&diagnostic.kind, self.min_overfull_pt) {
+            if let Some(size) = overfull_size(&diagnostic.message) {
+                if size < min {
+                    return true;
+                }
+            }
+        }
+
+        self.hides_message(&diagnostic.message)
+    }
+}
+
+
+/// Get how far an overfull box sticks out, in points, from TeX's message
+/// about it, such as "Overfull \hbox (12.3pt too wide) in paragraph".
+fn overfull_size(message: &str) -> Option<f64> {
+    if !message.starts_with("Overfull ") {
+        return None;
+    }
+
+    let start = match message.find('(') {
+        Some(i) => i + 1,
+        None => return None,
+    };
+
+    let end = match message[start..].find("pt too ") {
+        Some(i) => start + i,
+        None => return None,
+    };
+
+    message[start..end].parse().ok()
+}
+
+
+/// A status backend that passes messages on to another one, except for the
+/// warnings that its filter hides.
+pub struct FilteringStatusBackend {
+    inner: Box<StatusBackend>,
+    filter: WarningFilter,
+
+    /// How many warnings were hidden, by kind. Warnings that weren't
+    /// diagnostics are counted as "warning".
+    hidden: BTreeMap<String, usize>,
+}
+
+
+impl FilteringStatusBackend {
+    pub fn new(inner: Box<StatusBackend>, filter: WarningFilter) -> FilteringStatusBackend {
+        FilteringStatusBackend {
+            inner: inner,
+            filter: filter,
+            hidden: BTreeMap::new(),
+        }
+    }
+
+    fn hide(&mut self, kind: &str) {
+        *self.hidden.entry(kind.to_owned()).or_insert(0) += 1;
+    }
+
+    /// The number of warnings hidden so far.
+    pub fn n_hidden(&self) -> usize {
+        self.hidden.values().sum()
+    }
+
+    /// Say how many warnings were hidden, if any, e.g. "hid 4 warnings
+    /// (3 badbox, 1 font-substitution)".
+    pub fn report_hidden(&mut self) {
+        let n = self.n_hidden();
+
+        if n == 0 {
+            return;
+        }
+
+        let counts: Vec<String> = self.hidden.iter().map(|(k, n)| format!("{} {}", n, k)).collect();
+        tt_note!(self.inner, "hid {} warning{} ({})", n, if n == 1 { "" } else { "s" }, counts.join(", "));
+    }
+}
+
+
+impl StatusBackend for FilteringStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        if kind == MessageKind::Warning && !self.filter.hide_matching.is_empty() {
+            let message = args.to_string();
+
+            if self.filter.hides_message(&message) {
+                self.hide("warning");
+                return;
+            }
+        }
+
+        self.inner.report(kind, args, err);
+    }
+
+    fn chatter(&self) -> ChatterLevel {
+        self.inner.chatter()
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        self.inner.report_error(err);
+    }
+
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        if self.filter.hides(diagnostic) {
+            self.hide(diagnostic.kind.name());
+        } else {
+            self.inner.report_diagnostic(diagnostic);
+        }
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        self.inner.note_highlighted(before, highlighted, after);
+    }
+
+    fn engine_log(&mut self, data: &[u8]) {
+        self.inner.engine_log(data);
+    }
+
+    fn report_progress(&mut self, task: &str, done: u64, total: Option<u64>, unit: ProgressUnit) {
+        self.inner.report_progress(task, done, total, unit);
+    }
+
+    fn end_progress(&mut self, task: &str) {
+        self.inner.end_progress(task);
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        self.inner.dump_error_logs(output);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(kind: DiagnosticKind, message: &str) -> Diagnostic {
+        Diagnostic {
+            kind: kind,
+            message: message.to_owned(),
+            file: None,
+            line: None,
+            context: None,
+        }
+    }
+
+    #[test]
+    fn filtering() {
+        let filter = WarningFilter {
+            hide: vec!["font-substitution".to_owned()],
+            min_overfull_pt: Some(5.0),
+            hide_matching: vec!["hyperref".to_owned()],
+        };
+
+        assert!(filter.hides(&Diagnostic::font_substitution("lmroman10-bold", "lmroman10-regular")));
+        assert!(filter.hides(&diagnostic(DiagnosticKind::BadBox,
+                                         "Overfull \\hbox (1.5pt too wide) in paragraph at lines 3--4")));
+        assert!(!filter.hides(&diagnostic(DiagnosticKind::BadBox,
+                                          "Overfull \\hbox (12.25pt too wide) in paragraph at lines 3--4")));
+        assert!(!filter.hides(&diagnostic(DiagnosticKind::BadBox,
+                                          "Underfull \\hbox (badness 10000) in paragraph at lines 3--4")));
+        assert!(filter.hides(&diagnostic(DiagnosticKind::Warning, "Package hyperref Warning: Token not allowed")));
+        assert!(!filter.hides(&diagnostic(DiagnosticKind::Error, "Package hyperref Error: oops")));
+        assert!(!filter.hides(&diagnostic(DiagnosticKind::Warning, "LaTeX Warning: Reference `x' undefined")));
+    }
+}
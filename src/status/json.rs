@@ -0,0 +1,137 @@
+// src/status/json.rs -- machine-readable status backend
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A status backend for programs that drive Tectonic, like editors and CI
+//! systems, rather than for people. Every message is written as a JSON
+//! object on a line of its own, so that it can be picked apart without
+//! guessing at the layout of prose meant for a terminal. The objects look
+//! like this:
+//!
+//! ```json
+//! {"kind":"warning","message":"...","code":null,"causes":[],"diagnostic":null,"file":null,"line":null}
+//! ```
+//!
+//! The `kind` is "note", "warning", "error", "diagnostic", or
+//! "engine-output". For diagnostics, `diagnostic` is the kind of diagnostic
+//! (see `DiagnosticKind::name()`) and `file` and `line` say where it came
+//! from, if known. Messages about errors have the error's `code` (see
+//! `Error::code()`), and the `causes` of the error, listed from the
+//! outermost in.
+
+use std::fmt::Arguments;
+use std::io::Write;
+
+use serde_json;
+
+use diagnostics::{Diagnostic, DiagnosticKind};
+use errors::Error;
+use super::{ChatterLevel, MessageKind, StatusBackend};
+
+
+#[derive(Serialize)]
+struct Message<'a> {
+    kind: &'a str,
+    message: String,
+    code: Option<&'static str>,
+    causes: Vec<String>,
+    diagnostic: Option<&'a str>,
+    file: Option<&'a str>,
+    line: Option<u32>,
+}
+
+impl<'a> Message<'a> {
+    fn new(kind: &'a str, message: String) -> Message<'a> {
+        Message {
+            kind: kind,
+            message: message,
+            code: None,
+            causes: Vec::new(),
+            diagnostic: None,
+            file: None,
+            line: None,
+        }
+    }
+}
+
+
+pub struct JsonStatusBackend<W: Write> {
+    chatter: ChatterLevel,
+    stream: W,
+}
+
+
+impl<W: Write> JsonStatusBackend<W> {
+    /// Create a backend that writes its messages to `stream`. As with the
+    /// terminal backend, notes are dropped if `chatter` is `Minimal`.
+    pub fn new(chatter: ChatterLevel, stream: W) -> JsonStatusBackend<W> {
+        JsonStatusBackend {
+            chatter: chatter,
+            stream: stream,
+        }
+    }
+
+    fn emit(&mut self, message: &Message) {
+        if message.kind == "note" && self.chatter <= ChatterLevel::Minimal {
+            return;
+        }
+
+        let line = serde_json::to_string(message).expect("failed to serialize message");
+        writeln!(self.stream, "{}", line).expect("failed to write status message");
+        self.stream.flush().expect("failed to write status message");
+    }
+}
+
+
+impl<W: Write> StatusBackend for JsonStatusBackend<W> {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        let kind = match kind {
+            MessageKind::Note => "note",
+            MessageKind::Warning => "warning",
+            MessageKind::Error => "error",
+        };
+
+        let mut message = Message::new(kind, args.to_string());
+
+        if let Some(e) = err {
+            message.code = Some(e.code());
+            message.causes = e.iter().map(|item| item.to_string()).collect();
+        }
+
+        self.emit(&message);
+    }
+
+    fn chatter(&self) -> ChatterLevel {
+        self.chatter
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        let mut items = err.iter().map(|item| item.to_string());
+        let mut message = Message::new("error", items.next().unwrap_or_default());
+        message.code = Some(err.code());
+        message.causes = items.collect();
+        self.emit(&message);
+    }
+
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        // The files that the engine opens aren't problems, and there are
+        // a lot of them.
+        if diagnostic.kind == DiagnosticKind::FileOpened {
+            return;
+        }
+
+        let mut message = Message::new("diagnostic", diagnostic.message.clone());
+        message.diagnostic = Some(diagnostic.kind.name());
+        message.file = diagnostic.file.as_ref().map(|f| &f[..]);
+        message.line = diagnostic.line;
+        self.emit(&message);
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        self.emit(&Message::new("note", format!("{}{}{}", before, highlighted, after)));
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        self.emit(&Message::new("engine-output", String::from_utf8_lossy(output).into_owned()));
+    }
+}
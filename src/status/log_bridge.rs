@@ -0,0 +1,108 @@
+// src/status/log_bridge.rs -- sending status messages to the `log` crate
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Programs that embed Tectonic usually have their own way of dealing with
+//! messages, set up through the `log` crate and a logger such as
+//! `env_logger`. A `LogStatusBackend` hands Tectonic's messages to that
+//! logger. They're logged under a few targets, so that the logger can treat
+//! them separately:
+//!
+//! - `tectonic::status`: notes (at the `Info` level), warnings, and errors.
+//! - `tectonic::diagnostics`: the problems that the engines find in the
+//!   document, as warnings and errors.
+//! - `tectonic::tex_log`: each line of the TeX log, at the `Trace` level.
+//!
+//! Since notes that only matter at the higher chatter levels aren't even
+//! formatted unless they're wanted, the chatter level is worked out from the
+//! level that the logger lets through for `tectonic::status`: `Debug` and
+//! `Trace` mean `ChatterLevel::Debug`, and `Info` means `Normal`.
+
+use std::fmt::Arguments;
+
+use log::LogLevel;
+
+use diagnostics::{Diagnostic, DiagnosticKind};
+use errors::Error;
+use super::{ChatterLevel, MessageKind, StatusBackend};
+
+
+pub const STATUS_TARGET: &'static str = "tectonic::status";
+pub const DIAGNOSTICS_TARGET: &'static str = "tectonic::diagnostics";
+pub const TEX_LOG_TARGET: &'static str = "tectonic::tex_log";
+
+
+pub struct LogStatusBackend {
+    /// The part of the TeX log that we've been given after its last newline.
+    partial_log_line: Vec<u8>,
+}
+
+
+impl LogStatusBackend {
+    pub fn new() -> LogStatusBackend {
+        LogStatusBackend {
+            partial_log_line: Vec::new(),
+        }
+    }
+}
+
+
+impl StatusBackend for LogStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        let level = match kind {
+            MessageKind::Note => LogLevel::Info,
+            MessageKind::Warning => LogLevel::Warn,
+            MessageKind::Error => LogLevel::Error,
+        };
+
+        log!(target: STATUS_TARGET, level, "{}", args);
+
+        if let Some(e) = err {
+            for item in e.iter() {
+                log!(target: STATUS_TARGET, level, "caused by: {}", item);
+            }
+        }
+    }
+
+    fn chatter(&self) -> ChatterLevel {
+        if log_enabled!(target: STATUS_TARGET, LogLevel::Debug) {
+            ChatterLevel::Debug
+        } else if log_enabled!(target: STATUS_TARGET, LogLevel::Info) {
+            ChatterLevel::Normal
+        } else {
+            ChatterLevel::Minimal
+        }
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        let mut prefix = format!("[{}] ", err.code());
+
+        for item in err.iter() {
+            error!(target: STATUS_TARGET, "{}{}", prefix, item);
+            prefix = "caused by: ".to_owned();
+        }
+    }
+
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        let level = match diagnostic.kind {
+            DiagnosticKind::Error => LogLevel::Error,
+            DiagnosticKind::FileOpened => LogLevel::Trace,
+            _ => LogLevel::Warn,
+        };
+
+        log!(target: DIAGNOSTICS_TARGET, level, "{}", diagnostic);
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        error!(target: STATUS_TARGET, "the engine's output follows:\n{}", String::from_utf8_lossy(output));
+    }
+
+    fn engine_log(&mut self, data: &[u8]) {
+        self.partial_log_line.extend_from_slice(data);
+
+        while let Some(i) = self.partial_log_line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial_log_line.drain(..i + 1).collect();
+            trace!(target: TEX_LOG_TARGET, "{}", String::from_utf8_lossy(&line[..i]).trim_right_matches('\r'));
+        }
+    }
+}
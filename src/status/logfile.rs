@@ -0,0 +1,161 @@
+// src/status/logfile.rs -- keeping a record of a run in a file
+// Copyright 2017 the Tectonic Project
+// Licensed under the MIT License.
+
+//! When a build goes wrong on a machine that you can't get at, like a CI
+//! server, it helps to have a complete record of what happened. A
+//! `LogFileStatusBackend` passes everything on to another backend, so that
+//! the user sees the usual messages, and also writes it all to a file: every
+//! message, whatever the chatter level, and the whole of the TeX log. Each
+//! line of the file starts with the number of seconds since the backend was
+//! created.
+
+use std::fmt::Arguments;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use diagnostics::Diagnostic;
+use errors::{Error, Result};
+use super::{ChatterLevel, MessageKind, ProgressUnit, StatusBackend};
+
+
+pub struct LogFileStatusBackend {
+    inner: Box<StatusBackend>,
+
+    /// The log file. If writing to it fails, we say so and stop trying.
+    file: Option<BufWriter<File>>,
+    start: Instant,
+
+    /// The part of the TeX log that we've been given after its last newline.
+    partial_log_line: Vec<u8>,
+}
+
+
+impl LogFileStatusBackend {
+    /// Start logging to the file at `path`. If `append` is true, the log
+    /// is added to the end of the file; otherwise, any file that's already
+    /// there is replaced. Appending is safe to do from several processes at
+    /// once, since each line is written in one go.
+    pub fn open<P: AsRef<Path>>(path: P, append: bool, inner: Box<StatusBackend>) -> Result<LogFileStatusBackend> {
+        let path = path.as_ref();
+        let mut options = OpenOptions::new();
+
+        if append {
+            options.append(true).create(true);
+        } else {
+            options.write(true).create(true).truncate(true);
+        }
+
+        let file = ctry!(options.open(path); "couldn't open log file \"{}\"", path.display());
+
+        let mut backend = LogFileStatusBackend {
+            inner: inner,
+            file: Some(BufWriter::new(file)),
+            start: Instant::now(),
+            partial_log_line: Vec::new(),
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        backend.write_line("log", format_args!("started at {} seconds since the Unix epoch", now));
+        Ok(backend)
+    }
+
+    fn write_line(&mut self, label: &str, args: Arguments) {
+        let elapsed = self.start.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+
+        let result = match self.file {
+            Some(ref mut f) => writeln!(f, "[{:9.3}] {}: {}", secs, label, args).and_then(|_| f.flush()),
+            None => return,
+        };
+
+        self.check(result);
+    }
+
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            self.file = None;
+            tt_warning!(self.inner, "couldn't write to the log file; not logging any more"; e.into());
+        }
+    }
+
+    fn write_error(&mut self, label: &str, err: &Error) {
+        for item in err.iter() {
+            self.write_line(label, format_args!("caused by: {}", item));
+        }
+    }
+}
+
+
+impl StatusBackend for LogFileStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        let label = match kind {
+            MessageKind::Note => "note",
+            MessageKind::Warning => "warning",
+            MessageKind::Error => "error",
+        };
+
+        self.write_line(label, args);
+
+        if let Some(e) = err {
+            self.write_error(label, e);
+        }
+
+        self.inner.report(kind, args, err);
+    }
+
+    fn chatter(&self) -> ChatterLevel {
+        self.inner.chatter()
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        let mut prefix = format!("[{}] ", err.code());
+
+        for item in err.iter() {
+            self.write_line("error", format_args!("{}{}", prefix, item));
+            prefix = "caused by: ".to_owned();
+        }
+
+        self.inner.report_error(err);
+    }
+
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.write_line(diagnostic.kind.name(), format_args!("{}", diagnostic));
+        self.inner.report_diagnostic(diagnostic);
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        self.write_line("note", format_args!("{}{}{}", before, highlighted, after));
+        self.inner.note_highlighted(before, highlighted, after);
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        for line in String::from_utf8_lossy(output).lines() {
+            self.write_line("engine output", format_args!("{}", line));
+        }
+
+        self.inner.dump_error_logs(output);
+    }
+
+    fn report_progress(&mut self, task: &str, done: u64, total: Option<u64>, unit: ProgressUnit) {
+        self.inner.report_progress(task, done, total, unit);
+    }
+
+    fn end_progress(&mut self, task: &str) {
+        self.inner.end_progress(task);
+    }
+
+    fn engine_log(&mut self, data: &[u8]) {
+        self.partial_log_line.extend_from_slice(data);
+
+        while let Some(i) = self.partial_log_line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial_log_line.drain(..i + 1).collect();
+            let text = String::from_utf8_lossy(&line[..i]).into_owned();
+            self.write_line("tex log", format_args!("{}", text.trim_right_matches('\r')));
+        }
+
+        self.inner.engine_log(data);
+    }
+}
@@ -3,18 +3,35 @@
 // Licensed under the MIT License.
 
 #[macro_use] pub mod termcolor;
+pub mod filter;
+pub mod json;
+pub mod log_bridge;
+pub mod logfile;
 
 use std::cmp;
 use std::fmt::Arguments;
 
+use diagnostics::{Diagnostic, DiagnosticKind};
 use errors::Error;
 
 
+/// How much to tell the user. Each level includes everything reported at
+/// the levels below it.
 #[repr(usize)]
 #[derive(Clone, Copy, Eq, Debug)]
 pub enum ChatterLevel {
+    /// Only warnings and errors.
     Minimal = 0,
+
+    /// Notes about what's going on, too.
     Normal,
+
+    /// How long each engine pass took, and other details.
+    Verbose,
+
+    /// Where each file that the engines asked for was found, and other
+    /// information for tracking down problems.
+    Debug,
 }
 
 impl PartialEq for ChatterLevel {
@@ -47,8 +64,73 @@ pub enum MessageKind {
 }
 
 
+/// What the numbers in a progress report count.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProgressUnit {
+    Bytes,
+    Pages,
+    Passes,
+}
+
+
 pub trait StatusBackend {
     fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>);
+
+    /// How much the user wants to be told. Notes that are only interesting
+    /// at the higher levels should be checked against this, with the
+    /// `tt_verbose!` macro, to avoid the work of formatting them for
+    /// nothing. By default, the level is `Normal`.
+    fn chatter(&self) -> ChatterLevel {
+        ChatterLevel::Normal
+    }
+
+    /// Report an error that stopped the processing, followed by its causes.
+    /// The error's code (see `Error::code()`) goes before its message.
+    fn report_error(&mut self, err: &Error) {
+        let mut prefix = format!("[{}] ", err.code());
+
+        for item in err.iter() {
+            self.report(MessageKind::Error, format_args!("{}{}", prefix, item), None);
+            prefix = "caused by: ".to_owned();
+        }
+    }
+
+    /// Report a problem that an engine noticed and described in structured
+    /// form. By default, the diagnostic is just reported as an error or a
+    /// warning, prefixed with its location in the "file:line:" form that
+    /// editors know how to jump to.
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        let kind = if diagnostic.kind == DiagnosticKind::Error {
+            MessageKind::Error
+        } else {
+            MessageKind::Warning
+        };
+
+        self.report(kind, format_args!("{}", diagnostic), None);
+    }
+
+    /// Report the progress of processing, with the most important part of
+    /// the message picked out. By default, this is just reported as a note.
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        self.report(MessageKind::Note, format_args!("{}{}{}", before, highlighted, after), None);
+    }
+
+    /// Receive the text of the TeX log as the engine writes it. By default,
+    /// it's ignored; the log can be saved with the other outputs instead.
+    fn engine_log(&mut self, _data: &[u8]) {}
+
+    /// Report how far along a long-running task is: `done` units of work
+    /// out of `total`, if the total is known. Each task is identified by
+    /// its name, which is meant to be shown to the user, and may be
+    /// reported on many times. By default, progress isn't shown at all.
+    fn report_progress(&mut self, _task: &str, _done: u64, _total: Option<u64>, _unit: ProgressUnit) {}
+
+    /// Say that a task whose progress was reported has finished.
+    fn end_progress(&mut self, _task: &str) {}
+
+    /// Show the raw output of an engine that failed, which usually explains
+    /// what went wrong. By default, the output is dropped.
+    fn dump_error_logs(&mut self, _output: &[u8]) {}
 }
 
 #[macro_export]
@@ -61,6 +143,16 @@ macro_rules! tt_note {
     };
 }
 
+/// Report a note, but only if the chatter level is at least `$level`.
+#[macro_export]
+macro_rules! tt_verbose {
+    ($dest:expr, $level:expr, $( $fmt_args:expr ),*) => {
+        if $dest.chatter() >= $level {
+            $dest.report($crate::status::MessageKind::Note, format_args!($( $fmt_args ),*), None)
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! tt_warning {
     ($dest:expr, $( $fmt_args:expr ),*) => {
@@ -92,4 +184,8 @@ impl NoopStatusBackend {
 
 impl StatusBackend for NoopStatusBackend {
     fn report(&mut self, _kind: MessageKind, _args: Arguments, _err: Option<&Error>) {}
+
+    fn chatter(&self) -> ChatterLevel {
+        ChatterLevel::Minimal
+    }
 }
@@ -5,28 +5,145 @@
 // TODO: make this module a feature that can be disabled if the user doesn't want to
 // link with termcolor
 
+use std::{cmp, env};
 use std::fmt::Arguments;
 use std::io::Write;
+use std::iter::repeat;
+
+#[cfg(unix)]
+use libc;
 
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 use errors::Error;
-use super::{ChatterLevel, MessageKind, StatusBackend};
+use super::{ChatterLevel, MessageKind, ProgressUnit, StatusBackend};
+
+
+/// The widest that the progress line can get, so that it doesn't wrap.
+const MAX_PROGRESS_WIDTH: usize = 79;
+
+/// The width of a progress bar, not counting the brackets.
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+
+/// A task whose progress is being shown.
+struct Progress {
+    task: String,
+    done: u64,
+    total: Option<u64>,
+    unit: ProgressUnit,
+}
+
+impl Progress {
+    fn render(&self) -> String {
+        let done = format_amount(self.done, self.unit);
+
+        match self.total {
+            Some(total) if total > 0 => {
+                let filled = cmp::min(self.done, total) as usize * PROGRESS_BAR_WIDTH / total as usize;
+                format!("{} [{}{}] {}/{}", self.task, repeat('=').take(filled).collect::<String>(),
+                        repeat(' ').take(PROGRESS_BAR_WIDTH - filled).collect::<String>(),
+                        done, format_amount(total, self.unit))
+            },
+            _ => format!("{} {}", self.task, done),
+        }
+    }
+}
+
+fn format_amount(n: u64, unit: ProgressUnit) -> String {
+    match unit {
+        ProgressUnit::Bytes if n >= 1024 * 1024 => format!("{:.1} MiB", n as f64 / (1024. * 1024.)),
+        ProgressUnit::Bytes => format!("{} KiB", n / 1024),
+        ProgressUnit::Pages | ProgressUnit::Passes => format!("{}", n),
+    }
+}
+
+/// When to use colors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Use colors on streams that go to a terminal, unless the `NO_COLOR`
+    /// environment variable is set.
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[cfg(unix)]
+fn is_terminal(stream: Stream) -> bool {
+    let fd = match stream {
+        Stream::Stdout => libc::STDOUT_FILENO,
+        Stream::Stderr => libc::STDERR_FILENO,
+    };
+
+    unsafe { libc::isatty(fd) != 0 }
+}
+
+/// Elsewhere, we can't easily tell, so we say yes and leave it to termcolor
+/// to find out whether there's a console to color.
+#[cfg(not(unix))]
+fn is_terminal(_stream: Stream) -> bool {
+    true
+}
+
+fn color_choice(mode: ColorMode, stream: Stream) -> ColorChoice {
+    match mode {
+        ColorMode::Never => ColorChoice::Never,
+
+        // Terminals like mintty on Windows understand ANSI escapes but
+        // aren't consoles, so termcolor can't color them the Windows way.
+        // They set TERM; the console doesn't.
+        ColorMode::Always if cfg!(windows) && env::var_os("TERM").is_some() => ColorChoice::AlwaysAnsi,
+        ColorMode::Always => ColorChoice::Always,
+
+        ColorMode::Auto => {
+            let no_color = env::var_os("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false);
+
+            if no_color || !is_terminal(stream) {
+                ColorChoice::Never
+            } else {
+                ColorChoice::Auto
+            }
+        },
+    }
+}
 
 
 pub struct TermcolorStatusBackend {
     chatter: ChatterLevel,
+
+    /// Where notes go. This is standard output unless `notes_to_stderr()`
+    /// has been called.
     stdout: StandardStream,
     stderr: StandardStream,
+    color: ColorMode,
     note_spec: ColorSpec,
     highlight_spec: ColorSpec,
     warning_spec: ColorSpec,
     error_spec: ColorSpec,
+
+    /// The tasks whose progress is being shown, on one line of standard
+    /// error that's redrawn as they go. Progress is only shown if standard
+    /// error is a terminal.
+    progress: Vec<Progress>,
+    show_progress: bool,
+
+    /// How long the progress line is on the screen, or zero if it isn't.
+    progress_width: usize,
 }
 
 
 impl TermcolorStatusBackend {
     pub fn new(chatter: ChatterLevel) -> TermcolorStatusBackend {
+        TermcolorStatusBackend::with_color(chatter, ColorMode::Auto)
+    }
+
+    pub fn with_color(chatter: ChatterLevel, color: ColorMode) -> TermcolorStatusBackend {
         let mut note_spec = ColorSpec::new();
         note_spec.set_fg(Some(Color::Green)).set_bold(true);
 
@@ -41,20 +158,66 @@ impl TermcolorStatusBackend {
 
         TermcolorStatusBackend {
             chatter: chatter,
-            stdout: StandardStream::stdout(ColorChoice::Auto),
-            stderr: StandardStream::stderr(ColorChoice::Auto),
+            stdout: StandardStream::stdout(color_choice(color, Stream::Stdout)),
+            stderr: StandardStream::stderr(color_choice(color, Stream::Stderr)),
+            color: color,
             note_spec: note_spec,
             highlight_spec: highlight_spec,
             warning_spec: warning_spec,
             error_spec: error_spec,
+            progress: Vec::new(),
+            // The progress line is redrawn with carriage returns, which
+            // would make a mess of a file, so we only show it where we can
+            // tell that it's going to a terminal.
+            show_progress: chatter > ChatterLevel::Minimal && cfg!(unix) && is_terminal(Stream::Stderr),
+            progress_width: 0,
         }
     }
 
+    /// Send notes to standard error rather than standard output, for when
+    /// standard output is carrying something else, like the output of the
+    /// processing.
+    pub fn notes_to_stderr(&mut self) {
+        self.stdout = StandardStream::stderr(color_choice(self.color, Stream::Stderr));
+    }
+
+    /// Draw the progress line over whatever was there before.
+    fn draw_progress(&mut self) {
+        let mut line = self.progress.iter().map(|p| p.render()).collect::<Vec<_>>().join("  ");
+
+        if line.chars().count() > MAX_PROGRESS_WIDTH {
+            line = line.chars().take(MAX_PROGRESS_WIDTH).collect();
+        }
+
+        // Blank out whatever's left of a longer line that was drawn before.
+        let width = line.chars().count();
+        let padding = self.progress_width.saturating_sub(width);
+        write!(self.stderr, "\r{}{}", line, repeat(' ').take(padding).collect::<String>())
+            .expect("write to stderr failed");
+        self.stderr.flush().expect("write to stderr failed");
+        self.progress_width = width + padding;
+    }
+
+    /// Take the progress line off the screen so that a message can be
+    /// printed. It comes back at the next progress report.
+    fn clear_progress(&mut self) {
+        if self.progress_width == 0 {
+            return;
+        }
+
+        write!(self.stderr, "\r{}\r", repeat(' ').take(self.progress_width).collect::<String>())
+            .expect("write to stderr failed");
+        self.stderr.flush().expect("write to stderr failed");
+        self.progress_width = 0;
+    }
+
     fn styled<F>(&mut self, kind: MessageKind, f: F) where F: FnOnce(&mut StandardStream) {
         if kind == MessageKind::Note && self.chatter <= ChatterLevel::Minimal {
             return;
         }
 
+        self.clear_progress();
+
         let (spec, stream) = match kind {
             MessageKind::Note => (&self.note_spec, &mut self.stdout),
             MessageKind::Warning => (&self.warning_spec, &mut self.stderr),
@@ -71,6 +234,8 @@ impl TermcolorStatusBackend {
             return;
         }
 
+        self.clear_progress();
+
         let stream = match kind {
             MessageKind::Note => &mut self.stdout,
             MessageKind::Warning => &mut self.stderr,
@@ -105,20 +270,11 @@ impl TermcolorStatusBackend {
 
     pub fn note_styled(&mut self, args: Arguments) {
         if self.chatter > ChatterLevel::Minimal {
+            self.clear_progress();
             writeln!(self.stdout, "{}", args).expect("write to stdout failed");
         }
     }
 
-    pub fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
-        if self.chatter > ChatterLevel::Minimal {
-            write!(self.stdout, "{}", before).expect("write to stdout failed");
-            self.stdout.set_color(&self.highlight_spec).expect("write to stdout failed");
-            write!(self.stdout, "{}", highlighted).expect("write to stdout failed");
-            self.stdout.reset().expect("write to stdout failed");
-            writeln!(self.stdout, "{}", after).expect("write to stdout failed");
-        }
-    }
-
     pub fn error_styled(&mut self, args: Arguments) {
         self.styled(MessageKind::Error, |s| {
             writeln!(s, "{}", args).expect("write to stderr failed");
@@ -126,11 +282,11 @@ impl TermcolorStatusBackend {
     }
 
     pub fn bare_error(&mut self, err: &Error) {
-        let mut prefix = "error:";
+        let mut prefix = format!("error[{}]:", err.code());
 
         for item in err.iter() {
-            self.generic_message(MessageKind::Error, Some(prefix), format_args!("{}", item));
-            prefix = "caused by:";
+            self.generic_message(MessageKind::Error, Some(&prefix), format_args!("{}", item));
+            prefix = "caused by:".to_owned();
         }
 
         if let Some(backtrace) = err.backtrace() {
@@ -142,6 +298,7 @@ impl TermcolorStatusBackend {
     }
 
     pub fn dump_to_stderr(&mut self, output: &[u8]) {
+        self.clear_progress();
         self.stderr.write_all(output).expect("write to stderr failed");
     }
 }
@@ -179,4 +336,66 @@ impl StatusBackend for TermcolorStatusBackend {
             }
         }
     }
+
+    fn chatter(&self) -> ChatterLevel {
+        self.chatter
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        self.bare_error(err);
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        if self.chatter > ChatterLevel::Minimal {
+            self.clear_progress();
+            write!(self.stdout, "{}", before).expect("write to stdout failed");
+            self.stdout.set_color(&self.highlight_spec).expect("write to stdout failed");
+            write!(self.stdout, "{}", highlighted).expect("write to stdout failed");
+            self.stdout.reset().expect("write to stdout failed");
+            writeln!(self.stdout, "{}", after).expect("write to stdout failed");
+        }
+    }
+
+    fn report_progress(&mut self, task: &str, done: u64, total: Option<u64>, unit: ProgressUnit) {
+        if !self.show_progress {
+            return;
+        }
+
+        match self.progress.iter().position(|p| p.task == task) {
+            Some(i) => {
+                self.progress[i].done = done;
+                self.progress[i].total = total;
+            },
+            None => self.progress.push(Progress {
+                task: task.to_owned(),
+                done: done,
+                total: total,
+                unit: unit,
+            }),
+        }
+
+        self.draw_progress();
+    }
+
+    fn end_progress(&mut self, task: &str) {
+        let n = self.progress.len();
+        self.progress.retain(|p| p.task != task);
+
+        if self.progress.len() == n {
+            return;
+        }
+
+        if self.progress.is_empty() {
+            self.clear_progress();
+        } else if self.progress_width > 0 {
+            self.draw_progress();
+        }
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        tt_error_styled!(self, "===============================================================================");
+        self.dump_to_stderr(output);
+        tt_error_styled!(self, "===============================================================================");
+        tt_error_styled!(self, "");
+    }
 }
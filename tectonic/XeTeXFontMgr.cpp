@@ -199,6 +199,7 @@ XeTeXFontMgr::findFont(const char* name, char* variant, double ptSize)
     sReqEngine = 0;
     bool reqBold = false;
     bool reqItal = false;
+    bool substituted = false;
     if (variant != NULL) {
         std::string varString;
         char* cp = variant;
@@ -281,6 +282,7 @@ XeTeXFontMgr::findFont(const char* name, char* variant, double ptSize)
 
         std::map<std::string,Font*>::iterator i;
         if (reqItal) {
+            Font* unstyled = font;
             Font* bestMatch = font;
             if (font->slant < parent->maxSlant)
                 // try for a face with more slant
@@ -326,6 +328,8 @@ XeTeXFontMgr::findFont(const char* name, char* variant, double ptSize)
             }
             if (bestMatch != NULL)
                 font = bestMatch;
+            if (font == unstyled)
+                substituted = true; // no italic face in the family
         }
 
         if (reqBold) {
@@ -357,10 +361,22 @@ XeTeXFontMgr::findFont(const char* name, char* variant, double ptSize)
                     }
                 }
             }
+            if (bestMatch == font && !font->isBold)
+                substituted = true; // no bold face in the family
             font = bestMatch;
         }
     }
 
+    if (substituted) {
+        std::string requested(nameStr);
+        requested.append("/");
+        if (reqBold)
+            requested.append("B");
+        if (reqItal)
+            requested.append("I");
+        font_substitution_warning(requested.c_str(), getFullName(font->fontRef));
+    }
+
     // if there's optical size info, try to apply it
     if (ptSize < 0.0)
         ptSize = dsize / 10.0;
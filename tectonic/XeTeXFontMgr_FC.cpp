@@ -92,6 +92,9 @@ XeTeXFontMgr_FC::readNames(FcPattern* pat)
     if (FcPatternGetInteger(pat, FC_INDEX, 0, &index) != FcResultMatch)
         return names;
 
+    if (readCachedRecord(pat, pathname, index, names))
+        return names;
+
     FT_Face face;
     if (FT_New_Face(gFreeTypeLibrary, pathname, index, &face) != 0)
         return names;
@@ -184,12 +187,142 @@ XeTeXFontMgr_FC::readNames(FcPattern* pat)
 
     FT_Done_Face(face);
 
+    // We'll save the names once we know the rest of the font's details.
+    m_uncachedNames[pat] = *names;
     return names;
 }
 
+// Records in the font cache are lines of the form "key value". The names are
+// listed in order of preference, one per line.
+
+static bool
+appendRecordLines(std::string& record, const char* key, const std::list<std::string>& values)
+{
+    for (std::list<std::string>::const_iterator i = values.begin(); i != values.end(); ++i) {
+        if (i->find('\n') != std::string::npos)
+            return false;
+        record += key;
+        record += " ";
+        record += *i;
+        record += "\n";
+    }
+    return true;
+}
+
+bool
+XeTeXFontMgr_FC::readCachedRecord(FcPattern* pat, const char* path, int index, NameCollection* names)
+{
+    const char* record = ttstub_font_cache_get(path, index);
+    if (record == NULL)
+        return false;
+
+    NameCollection cached;
+    CachedStyle style;
+    bool haveFlags = false;
+    bool haveOpSize = false;
+
+    while (*record) {
+        const char* eol = strchr(record, '\n');
+        if (eol == NULL)
+            eol = record + strlen(record);
+        std::string line(record, eol);
+        record = (*eol) ? eol + 1 : eol;
+
+        size_t space = line.find(' ');
+        if (space == std::string::npos)
+            continue;
+        std::string key(line, 0, space);
+        std::string value(line, space + 1);
+
+        if (key == "ps")
+            cached.m_psName = value;
+        else if (key == "full")
+            cached.m_fullNames.push_back(value);
+        else if (key == "family")
+            cached.m_familyNames.push_back(value);
+        else if (key == "style")
+            cached.m_styleNames.push_back(value);
+        else if (key == "flags") {
+            int weight, width, slant, isReg, isBold, isItalic;
+            if (sscanf(value.c_str(), "%d %d %d %d %d %d", &weight, &width, &slant, &isReg, &isBold, &isItalic) != 6)
+                return false;
+            style.weight = weight;
+            style.width = width;
+            style.slant = slant;
+            style.isReg = isReg != 0;
+            style.isBold = isBold != 0;
+            style.isItalic = isItalic != 0;
+            haveFlags = true;
+        } else if (key == "opsize") {
+            if (sscanf(value.c_str(), "%u %u %u %u %u", &style.opSizeInfo.designSize, &style.opSizeInfo.subFamilyID,
+                       &style.opSizeInfo.nameCode, &style.opSizeInfo.minSize, &style.opSizeInfo.maxSize) != 5)
+                return false;
+            haveOpSize = true;
+        }
+    }
+
+    if (cached.m_psName.length() == 0 || !haveFlags || !haveOpSize)
+        return false; // damaged or from an incompatible version; just ignore it
+
+    *names = cached;
+    m_cachedStyles[pat] = style;
+    return true;
+}
+
+void
+XeTeXFontMgr_FC::saveCachedRecord(const Font* theFont, const NameCollection& names)
+{
+    char* pathname;
+    if (FcPatternGetString(theFont->fontRef, FC_FILE, 0, (FcChar8**)&pathname) != FcResultMatch)
+        return;
+    int index;
+    if (FcPatternGetInteger(theFont->fontRef, FC_INDEX, 0, &index) != FcResultMatch)
+        return;
+
+    std::string record;
+    std::list<std::string> psNames;
+    psNames.push_back(names.m_psName);
+
+    if (!appendRecordLines(record, "ps", psNames)
+            || !appendRecordLines(record, "full", names.m_fullNames)
+            || !appendRecordLines(record, "family", names.m_familyNames)
+            || !appendRecordLines(record, "style", names.m_styleNames))
+        return; // can't represent this font's names; just don't cache it
+
+    char buf[128];
+    snprintf(buf, sizeof(buf), "flags %d %d %d %d %d %d\n", theFont->weight, theFont->width, theFont->slant,
+             theFont->isReg ? 1 : 0, theFont->isBold ? 1 : 0, theFont->isItalic ? 1 : 0);
+    record += buf;
+
+    // Only the design size is meaningful unless there's a subfamily.
+    const OpSizeRec& os = theFont->opSizeInfo;
+    if (os.subFamilyID != 0)
+        snprintf(buf, sizeof(buf), "opsize %u %u %u %u %u\n", os.designSize, os.subFamilyID, os.nameCode,
+                 os.minSize, os.maxSize);
+    else
+        snprintf(buf, sizeof(buf), "opsize %u 0 0 0 0\n", os.designSize);
+    record += buf;
+
+    ttstub_font_cache_put(pathname, index, record.c_str());
+}
+
 void
 XeTeXFontMgr_FC::getOpSizeRecAndStyleFlags(Font* theFont)
 {
+    std::map<FcPattern*,CachedStyle>::iterator cached = m_cachedStyles.find(theFont->fontRef);
+    if (cached != m_cachedStyles.end()) {
+        const CachedStyle& style = cached->second;
+        theFont->opSizeInfo = style.opSizeInfo;
+        theFont->weight = style.weight;
+        theFont->width = style.width;
+        theFont->slant = style.slant;
+        theFont->isReg = style.isReg;
+        theFont->isBold = style.isBold;
+        theFont->isItalic = style.isItalic;
+        m_cachedStyles.erase(cached);
+        return;
+    }
+
     XeTeXFontMgr::getOpSizeRecAndStyleFlags(theFont);
 
     if (theFont->weight == 0 && theFont->width == 0) {
@@ -203,6 +336,12 @@ XeTeXFontMgr_FC::getOpSizeRecAndStyleFlags(Font* theFont)
         if (FcPatternGetInteger(pat, FC_SLANT, 0, &value) == FcResultMatch)
             theFont->slant = value;
     }
+
+    std::map<FcPattern*,NameCollection>::iterator names = m_uncachedNames.find(theFont->fontRef);
+    if (names != m_uncachedNames.end()) {
+        saveCachedRecord(theFont, names->second);
+        m_uncachedNames.erase(names);
+    }
 }
 
 void
@@ -59,8 +59,27 @@ protected:
 
     void                            cacheFamilyMembers(const std::list<std::string>& familyNames);
 
+    // The driver can keep what we learn about each font across runs, since
+    // reading it from hundreds of font files is slow. See
+    // src/engines/font_cache.rs.
+    struct CachedStyle {
+        OpSizeRec   opSizeInfo;
+        uint16_t    weight;
+        uint16_t    width;
+        int16_t     slant;
+        bool        isReg;
+        bool        isBold;
+        bool        isItalic;
+    };
+
+    bool                            readCachedRecord(FcPattern* pat, const char* path, int index, NameCollection* names);
+    void                            saveCachedRecord(const Font* theFont, const NameCollection& names);
+
     FcFontSet*  allFonts;
     bool        cachedAll;
+
+    std::map<FcPattern*,CachedStyle>    m_cachedStyles;     // read from the cache, not yet applied to a Font
+    std::map<FcPattern*,NameCollection> m_uncachedNames;    // read from the font file, not yet saved in the cache
 };
 
 #endif  /* __XETEX_FONT_MGR_FC_H */
@@ -46,6 +46,7 @@ void print_char(int c);
 void begin_diagnostic(void);
 void end_diagnostic(int nl);
 int get_tracing_fonts_state(void);
+void font_substitution_warning(const char *requested, const char *substitute);
 
 extern Fixed loaded_font_design_size;
 extern void **font_layout_engine;
@@ -4915,7 +4915,10 @@ void execute_fn(hash_loc ex_fn_loc)
     integer r_pop_lt1, r_pop_lt2;
     stk_type r_pop_tp1, r_pop_tp2;
     wiz_fn_loc wiz_ptr;
-    ;
+    static unsigned int cancel_poll_count = 0;
+
+    if ((++cancel_poll_count & 0xFFFF) == 0)
+        ttstub_check_cancelled();
 
     switch ((fn_type[ex_fn_loc])) {
     case 0:
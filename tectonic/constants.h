@@ -647,6 +647,7 @@
 #define JOB_NAME_CODE 15
 #define PDF_STRCMP_CODE 43
 #define PDF_MDFIVE_SUM_CODE 44
+#define PDF_FILE_MOD_DATE_CODE 45
 
 /* args to IF_TEST */
 #define IF_CHAR_CODE 0
@@ -1003,7 +1004,7 @@
  * contents of the "format" files must be regenerated. KEEP SYNCHRONIZED
  * WITH src/lib.rs!!! */
 
-#define FORMAT_SERIAL 25
+#define FORMAT_SERIAL 26
 
 
 #endif /* not TECTONIC_CONSTANTS_H */
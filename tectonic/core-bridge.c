@@ -5,6 +5,7 @@
 
 #include <tectonic/core-bridge.h>
 #include <tectonic/dpx-dvipdfmx.h>
+#include <tectonic/xdvisvg.h>
 #include <tectonic/internals.h>
 
 #include <stdio.h> /*vsnprintf*/
@@ -68,7 +69,7 @@ tex_simple_main(tt_bridge_api_t *api, char *dump_name, char *input_file_name)
 
 
 int
-dvipdfmx_simple_main(tt_bridge_api_t *api, char *dviname, char *pdfname)
+dvipdfmx_simple_main(tt_bridge_api_t *api, const tt_xdvipdfmx_config_t *config, char *dviname, char *pdfname)
 {
     int rv;
 
@@ -79,7 +80,7 @@ dvipdfmx_simple_main(tt_bridge_api_t *api, char *dviname, char *pdfname)
         return 99;
     }
 
-    rv = dvipdfmx_main(pdfname, dviname, NULL, 0, false, false, 0);
+    rv = dvipdfmx_main(pdfname, dviname, NULL, 0, false, false, 0, config);
     tectonic_global_bridge = NULL;
 
     return rv;
@@ -105,6 +106,24 @@ bibtex_simple_main(tt_bridge_api_t *api, char *aux_file_name)
 }
 
 
+int
+xdvisvg_simple_main(tt_bridge_api_t *api, const tt_xdvisvg_config_t *config, char *dviname, char *svgstem)
+{
+    int rv;
+
+    tectonic_global_bridge = api;
+
+    if (setjmp(jump_buffer)) {
+        tectonic_global_bridge = NULL;
+        return 99;
+    }
+
+    rv = xdvisvg_main(dviname, svgstem, config);
+    tectonic_global_bridge = NULL;
+    return rv;
+}
+
+
 /* Global symbols that route through the global API */
 
 #define TGB tectonic_global_bridge
@@ -171,6 +190,12 @@ ttstub_get_data_md5(char const *data, size_t len, char *digest)
     return TGB->get_data_md5(TGB->context, data, len, digest);
 }
 
+int
+ttstub_get_file_mtime(char const *path, time_t *mtime)
+{
+    return TGB->get_file_mtime(TGB->context, path, mtime);
+}
+
 rust_output_handle_t
 ttstub_output_open(char const *path, int is_gz)
 {
@@ -254,3 +279,47 @@ ttstub_input_close(rust_input_handle_t handle)
 {
     return TGB->input_close(TGB->context, handle);
 }
+
+void
+ttstub_check_cancelled(void)
+{
+    if (TGB->check_cancelled(TGB->context))
+        _tt_abort("processing was cancelled");
+}
+
+void
+ttstub_page_shipped(int32_t const *counts)
+{
+    if (TGB->page_shipped(TGB->context, counts))
+        _tt_abort("too many pages were shipped out");
+}
+
+void
+ttstub_missing_char(char const *font_name, int32_t codepoint, char const *file_name, int line)
+{
+    TGB->missing_char(TGB->context, font_name, codepoint, file_name, line);
+}
+
+void
+ttstub_font_substituted(char const *requested, char const *substitute, char const *file_name, int line)
+{
+    TGB->font_substituted(TGB->context, requested, substitute, file_name, line);
+}
+
+int
+ttstub_special(char const *text, size_t len)
+{
+    return TGB->special(TGB->context, text, len);
+}
+
+char const *
+ttstub_font_cache_get(char const *path, int index)
+{
+    return TGB->font_cache_get(TGB->context, path, index);
+}
+
+void
+ttstub_font_cache_put(char const *path, int index, char const *record)
+{
+    TGB->font_cache_put(TGB->context, path, index, record);
+}
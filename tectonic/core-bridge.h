@@ -10,6 +10,7 @@
 
 #include <stddef.h> /* size_t */
 #include <sys/types.h> /* ssize_t */
+#include <time.h> /* time_t */
 
 /* OK maybe this isn't the best place to have this, but here we are. */
 
@@ -97,6 +98,7 @@ typedef struct tt_bridge_api_t {
 
     int (*get_file_md5)(void *context, char const *path, char *digest);
     int (*get_data_md5)(void *context, char const *data, size_t len, char *digest);
+    int (*get_file_mtime)(void *context, char const *path, time_t *mtime);
 
     rust_output_handle_t (*output_open)(void *context, char const *path, int is_gz);
     rust_output_handle_t (*output_open_stdout)(void *context);
@@ -113,9 +115,73 @@ typedef struct tt_bridge_api_t {
     int (*input_getc)(void *context, rust_input_handle_t handle);
     int (*input_ungetc)(void *context, rust_input_handle_t handle, int ch);
     int (*input_close)(void *context, rust_input_handle_t handle);
+
+    int (*check_cancelled)(void *context);
+    int (*page_shipped)(void *context, int32_t const *counts);
+
+    void (*missing_char)(void *context, char const *font_name, int32_t codepoint, char const *file_name, int line);
+    void (*font_substituted)(void *context, char const *requested, char const *substitute, char const *file_name, int line);
+
+    char const *(*font_cache_get)(void *context, char const *path, int index);
+    void (*font_cache_put)(void *context, char const *path, int index, char const *record);
+
+    int (*special)(void *context, char const *text, size_t len);
 } tt_bridge_api_t;
 
 
+/* A file to attach to the output PDF; the name is in UTF-8. */
+
+typedef struct tt_pdf_attachment_t {
+    char const *name;
+    char const *data;
+    size_t len;
+} tt_pdf_attachment_t;
+
+
+/* Settings for the xdvipdfmx engine. A NULL paperspec and negative numbers
+ * select the defaults. The PDF version is the minor version number, as with
+ * "xdvipdfmx -V". Keep synchronized with src/engines/mod.rs. */
+
+typedef struct tt_xdvipdfmx_config_t {
+    char const *paperspec;
+    int compression_level;
+    int pdf_version;
+    int enable_png_predictors;
+    int pdfa;
+    char const *title;
+    char const *author;
+    char const *subject;
+    char const *keywords;
+    char const *xmp;
+    size_t xmp_len;
+    int reproducible;
+    int linearize;
+    int encrypt;
+    char const *owner_password;
+    char const *user_password;
+    int no_print;
+    int no_copy;
+    tt_pdf_attachment_t const *attachments;
+    int num_attachments;
+} tt_xdvipdfmx_config_t;
+
+
+/* Settings for the xdvisvg engine. A NULL paperspec selects US letter. If
+ * woff_fonts is nonzero, glyphs of OpenType and TrueType fonts are drawn as
+ * text in WOFF versions of the fonts rather than as paths. If png_dpi is
+ * nonzero, the pages are instead rendered as PNG images at that resolution.
+ * If text_output is nonzero, the document's text is written to a single
+ * plain-text file and nothing is drawn. Keep synchronized with
+ * **src/engines/mod.rs**. */
+
+typedef struct tt_xdvisvg_config_t {
+    char const *paperspec;
+    int woff_fonts;
+    int png_dpi;
+    int text_output;
+} tt_xdvisvg_config_t;
+
+
 BEGIN_EXTERN_C
 
 /* These functions are not meant to be used in the C/C++ code. They define the
@@ -123,8 +189,9 @@ BEGIN_EXTERN_C
 
 const char *tt_get_error_message(void);
 int tex_simple_main(tt_bridge_api_t *api, char *dump_name, char *input_file_name);
-int dvipdfmx_simple_main(tt_bridge_api_t *api, char *dviname, char *pdfname);
+int dvipdfmx_simple_main(tt_bridge_api_t *api, const tt_xdvipdfmx_config_t *config, char *dviname, char *pdfname);
 int bibtex_simple_main(tt_bridge_api_t *api, char *aux_file_name);
+int xdvisvg_simple_main(tt_bridge_api_t *api, const tt_xdvisvg_config_t *config, char *dviname, char *svgstem);
 
 /* The internal, C/C++ interface: */
 
@@ -144,6 +211,7 @@ PRINTF_FUNC(2,3) int ttstub_fprintf(rust_output_handle_t handle, const char *for
 
 int ttstub_get_file_md5 (char const *path, char *digest);
 int ttstub_get_data_md5 (char const *data, size_t len, char *digest);
+int ttstub_get_file_mtime (char const *path, time_t *mtime);
 
 rust_output_handle_t ttstub_output_open (char const *path, int is_gz);
 rust_output_handle_t ttstub_output_open_stdout (void);
@@ -161,6 +229,33 @@ int ttstub_input_getc (rust_input_handle_t handle);
 int ttstub_input_ungetc (rust_input_handle_t handle, int ch);
 int ttstub_input_close (rust_input_handle_t handle);
 
+/* Abort if the driver has asked us to stop. Engines should call this
+ * periodically, but it is not free, so not too often. */
+void ttstub_check_cancelled (void);
+
+/* Tell the driver that the engine has shipped out a page, whose \count0
+ * through \count9 registers are given in `counts`. Aborts if the driver's
+ * limit on the number of pages has been exceeded. */
+void ttstub_page_shipped (int32_t const *counts);
+
+/* Offer the text of a \special to the driver as its page is shipped out.
+ * Returns nonzero if the driver dealt with it, in which case the special
+ * should not be written to the output. */
+int ttstub_special (char const *text, size_t len);
+
+/* Tell the driver about problems with fonts. The file name is that of the
+ * input file being read at the time, and may be NULL if it isn't known, in
+ * which case the line number is meaningless. */
+void ttstub_missing_char (char const *font_name, int32_t codepoint, char const *file_name, int line);
+void ttstub_font_substituted (char const *requested, char const *substitute, char const *file_name, int line);
+
+/* Look up and save the metadata record describing face number `index` of the
+ * font file `path`, which the driver may keep across runs. The lookup
+ * returns NULL if there is no record; otherwise, the record is valid until
+ * the next call into the cache. */
+char const *ttstub_font_cache_get (char const *path, int index);
+void ttstub_font_cache_put (char const *path, int index, char const *record);
+
 END_EXTERN_C
 
 #endif /* not TECTONIC_CORE_BRIDGE_H */
@@ -333,6 +333,7 @@ do_dvi_pages (PageRange *page_ranges, unsigned num_page_ranges)
           mediabox.ury = page_height;
           pdf_doc_set_mediabox(page_count+1, &mediabox);
         }
+        ttstub_check_cancelled();
         dvi_do_page(page_height, x_offset, y_offset);
         page_count++;
         dpx_message("]");
@@ -366,12 +367,14 @@ dvipdfmx_main (
   int opt_flags,
   bool translate,
   bool quiet,
-  unsigned verbose)
+  unsigned verbose,
+  const tt_xdvipdfmx_config_t *config)
 {
   bool enable_object_stream = true;
   double dvi2pts;
   unsigned num_page_ranges = 0;
   PageRange *page_ranges = NULL;
+  int i;
 
   assert(pdf_filename);
   assert(dvi_filename);
@@ -406,6 +409,7 @@ dvipdfmx_main (
   select_paper("letter");
   annot_grow = 0;
   bookmark_open = 0;
+  do_encryption = 0;
   key_bits = 40;
   permission = 0x003C;
   font_dpi = 600;
@@ -415,6 +419,44 @@ dvipdfmx_main (
   pdf_load_fontmap_file("kanjix.map", FONTMAP_RMODE_APPEND);
   pdf_load_fontmap_file("ckx.map", FONTMAP_RMODE_APPEND);
 
+  /* Settings from the caller take precedence over the defaults above. */
+  if (config) {
+    if (config->paperspec)
+      select_paper(config->paperspec);
+    if (config->compression_level >= 0)
+      pdf_set_compression(config->compression_level);
+    if (config->pdf_version >= 0)
+      pdf_set_version(config->pdf_version);
+    if (!config->enable_png_predictors)
+      opt_flags |= OPT_PDFOBJ_NO_PREDICTOR;
+  }
+
+  /* Encryption requested by the caller is always AES-256, which needs PDF
+   * 1.7 with Adobe's extension level 3. It takes precedence over any
+   * pdf:encrypt special in the document. */
+  if (config && config->encrypt) {
+    do_encryption = 1;
+    key_bits = 256;
+    permission = 0x0F3C;
+    if (config->no_print)
+      permission &= ~0x0804;
+    if (config->no_copy)
+      permission &= ~0x0010;
+    if (pdf_get_version() < 7)
+      pdf_set_version(7);
+  }
+
+  /* PDF/A-1 is based on PDF 1.4, which doesn't have object streams. */
+  pdf_set_pdfa(config && config->pdfa);
+  pdf_set_reproducible(config && config->reproducible);
+  pdf_set_linearize(config && config->linearize);
+  if (pdf_get_pdfa()) {
+    pdf_set_version(4);
+    opt_flags |= OPT_PDFOBJ_NO_OBJSTM;
+    if (config->num_attachments > 0)
+      _tt_abort("PDF/A-1 output can't have file attachments");
+  }
+
   if (pagespec) {
     select_pages(pagespec, &page_ranges, &num_page_ranges);
   }
@@ -433,11 +475,18 @@ dvipdfmx_main (
   dpx_delete_old_cache(image_cache_life);
 
   pdf_enc_compute_id_string(dvi_filename, pdf_filename);
+  if (pdf_get_reproducible())
+    pdf_enc_clear_id();
   if (do_encryption) {
     if (key_bits > 40 && pdf_get_version() < 4)
       _tt_abort("Chosen key length requires at least PDF 1.4. "
             "Use \"-V 4\" to change.");
-    pdf_enc_set_passwd(key_bits, permission, NULL, NULL);
+    if (config && config->encrypt)
+      pdf_enc_set_passwd(key_bits, permission,
+                         config->owner_password ? config->owner_password : "",
+                         config->user_password ? config->user_password : "");
+    else
+      pdf_enc_set_passwd(key_bits, permission, NULL, NULL);
   }
 
   {
@@ -448,7 +497,9 @@ dvipdfmx_main (
     if (dvi2pts == 0.0)
       _tt_abort("dvi_init() failed!");
 
-    pdf_doc_set_creator(dvi_comment());
+    /* The comment that TeX writes includes the time of the run. */
+    if (!pdf_get_reproducible())
+      pdf_doc_set_creator(dvi_comment());
 
     if (do_encryption) {
       /* command line takes precedence */
@@ -463,6 +514,10 @@ dvipdfmx_main (
       if (ver_minor >= PDF_VERSION_MIN && ver_minor <= PDF_VERSION_MAX) {
         pdf_set_version(ver_minor);
       }
+      if (config && config->encrypt && pdf_get_version() < 7) {
+        dpx_warning("Ignoring the PDF version requested by the document: AES-256 encryption needs PDF 1.7.");
+        pdf_set_version(7);
+      }
     } else {
       dvi_scan_specials(0,
                         &paper_width, &paper_height,
@@ -488,6 +543,33 @@ dvipdfmx_main (
     }
   }
 
+  if (config && config->encrypt) {
+    if (pdf_get_linearize())
+      _tt_abort("Linearized output can't be encrypted");
+    if (pdf_get_pdfa())
+      _tt_abort("PDF/A does not allow encryption");
+  }
+
+  if (pdf_get_linearize() && do_encryption)
+    _tt_abort("Linearized output can't be encrypted; remove the pdf:encrypt special from the document "
+              "or turn off linearization");
+
+  /* The ID was cleared above, before the specials were scanned, and
+   * encryption keys are derived from it, so we can't let a pdf:encrypt
+   * special turn encryption on behind our backs. */
+  if (pdf_get_reproducible() && do_encryption)
+    _tt_abort("Reproducible output can't be encrypted; remove the pdf:encrypt special from the document "
+              "or turn off reproducible output");
+
+  if (pdf_get_pdfa()) {
+    if (do_encryption)
+      _tt_abort("PDF/A does not allow encryption; remove the pdf:encrypt special from the document");
+    if (pdf_get_version() != 4) {
+      dpx_warning("Ignoring the PDF version requested by the document: PDF/A-1 output is always PDF 1.4.");
+      pdf_set_version(4);
+    }
+  }
+
   pdf_files_init();
 
   if (opt_flags & OPT_PDFOBJ_NO_OBJSTM)
@@ -501,6 +583,22 @@ dvipdfmx_main (
                     paper_width, paper_height, annot_grow, bookmark_open,
                     !(opt_flags & OPT_PDFDOC_NO_DEST_REMOVE));
 
+  if (config) {
+    if (config->title)
+      pdf_doc_set_docinfo_text("Title", config->title);
+    if (config->author)
+      pdf_doc_set_docinfo_text("Author", config->author);
+    if (config->subject)
+      pdf_doc_set_docinfo_text("Subject", config->subject);
+    if (config->keywords)
+      pdf_doc_set_docinfo_text("Keywords", config->keywords);
+    if (config->xmp)
+      pdf_doc_set_metadata(config->xmp, config->xmp_len);
+    for (i = 0; i < config->num_attachments; i++)
+      pdf_doc_add_attachment(config->attachments[i].name, config->attachments[i].data,
+                             config->attachments[i].len);
+  }
+
   /* Ignore_colors placed here since
    * they are considered as device's capacity.
    */
@@ -27,6 +27,8 @@
 
 #include <stdbool.h>
 
+#include <tectonic/core-bridge.h>
+
 #define DVIPDFMX_PROG_NAME "xdvipdfmx"
 
 extern int is_xdv;
@@ -40,6 +42,7 @@ int dvipdfmx_main(
   int opt_flags,
   bool translate,
   bool quiet,
-  unsigned verbose);
+  unsigned verbose,
+  const tt_xdvipdfmx_config_t *config);
 
 #endif /* _DVIPDFMX_H_ */
@@ -1960,3 +1960,33 @@ pdf_dev_end_actualtext (void)
 
   pdf_doc_add_page_content(" EMC", 4);
 }
+
+/* Marked content belonging to an element of the structure tree. */
+void
+pdf_dev_begin_marked_content (const char *tag, int mcid)
+{
+  int len = 0;
+
+  graphics_mode();
+
+  work_buffer[len++] = '\n';
+  work_buffer[len++] = '/';
+  for (; *tag && len < WORK_BUFFER_SIZE - 32; tag++) {
+    unsigned char c = *tag;
+
+    if (c < '!' || c > '~' || strchr("#()/<>[]{}%", c))
+      len += sprintf(work_buffer + len, "#%02X", c);
+    else
+      work_buffer[len++] = c;
+  }
+  len += sprintf(work_buffer + len, "<</MCID %d>>BDC", mcid);
+  pdf_doc_add_page_content(work_buffer, len);
+}
+
+void
+pdf_dev_end_marked_content (void)
+{
+  graphics_mode();
+
+  pdf_doc_add_page_content(" EMC", 4);
+}
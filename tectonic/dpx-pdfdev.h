@@ -183,4 +183,7 @@ void   pdf_dev_pop_coord(void);
 void   pdf_dev_begin_actualtext (uint16_t *unicodes, int len);
 void   pdf_dev_end_actualtext (void);
 
+void   pdf_dev_begin_marked_content (const char *tag, int mcid);
+void   pdf_dev_end_marked_content (void);
+
 #endif /* _PDFDEV_H_ */
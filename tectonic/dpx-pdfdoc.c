@@ -57,6 +57,8 @@
 #endif
 #include <tectonic/dpx-jpegimage.h>
 
+#include <tectonic/dpx-unicode.h>
+
 #include <tectonic/dpx-pdfdoc.h>
 
 #define PDFDOC_PAGES_ALLOC_SIZE   128u
@@ -161,6 +163,9 @@ typedef struct pdf_page
 
   pdf_obj  *annots;
   pdf_obj  *beads;
+
+  /* Structure elements owning the page's marked content, by MCID */
+  pdf_obj  *mcids;
 } pdf_page;
 
 typedef struct pdf_olitem
@@ -191,6 +196,14 @@ typedef struct pdf_article
   pdf_bead *beads;
 } pdf_article;
 
+struct struct_elem
+{
+  char    *tag;
+  pdf_obj *dict;
+  pdf_obj *kids;
+  int      page_no; /* page of the element's /Pg, or 0 if none yet */
+};
+
 struct name_dict
 {
   const char  *category;
@@ -245,6 +258,20 @@ typedef struct pdf_doc
 
   struct form_list_node *pending_forms;
 
+  struct {
+    pdf_obj *root;        /* NULL until the document uses tagging */
+    pdf_obj *kids;
+    pdf_obj *parent_tree; /* the ParentTree's /Nums array */
+    int      next_key;
+
+    int      depth;
+    int      max_depth;
+    struct struct_elem *stack;
+
+    int      mc_open;     /* marked content of the innermost element is open */
+    int      ignored;     /* tags begun inside form XObjects */
+  } structure;
+
 } pdf_doc;
 static pdf_doc pdoc;
 
@@ -340,6 +367,7 @@ doc_resize_page_entries (pdf_doc *p, int size)
       p->pages.entries[i].content_refs[3] = NULL; /* global eop */
       p->pages.entries[i].annots    = NULL;
       p->pages.entries[i].beads     = NULL;
+      p->pages.entries[i].mcids     = NULL;
     }
     p->pages.max_entries = size;
   }
@@ -378,6 +406,9 @@ static void pdf_doc_add_goto (pdf_obj *annot_dict);
 static void pdf_doc_init_docinfo  (pdf_doc *p);
 static void pdf_doc_close_docinfo (pdf_doc *p);
 
+static void doc_open_marked_content  (pdf_doc *p);
+static void doc_close_marked_content (pdf_doc *p);
+
 static void pdf_doc_init_articles    (pdf_doc *p);
 static void pdf_doc_close_articles   (pdf_doc *p);
 static void pdf_doc_init_bookmarks   (pdf_doc *p, int bm_open_depth);
@@ -491,6 +522,408 @@ asn_date (char *date_string)
   return strlen(date_string);
 }
 
+/*
+ * PDF/A
+ *
+ * PDF/A-1b requires an output intent describing the color space that the
+ * document's device colors refer to, and an XMP metadata stream that agrees
+ * with the DocInfo dictionary.
+ */
+#define PDFA_ICC_PROFILE   "sRGB.icc"
+#define PDFA_ICC_CONDITION "sRGB IEC61966-2.1"
+
+static void
+pdf_doc_init_pdfa (pdf_doc *p)
+{
+  rust_input_handle_t handle;
+  pdf_obj *profile, *intent, *intents;
+  char     buf[4096];
+  ssize_t  n;
+
+  handle = ttstub_input_open(PDFA_ICC_PROFILE, kpse_tex_format, 0);
+  if (!handle)
+    _tt_abort("PDF/A output requires the ICC color profile \"%s\", but it could not be found; "
+              "it is provided by the \"colorprofiles\" package", PDFA_ICC_PROFILE);
+
+  profile = pdf_new_stream(STREAM_COMPRESS);
+  while ((n = ttstub_input_read(handle, buf, sizeof(buf))) > 0)
+    pdf_add_stream(profile, buf, n);
+  ttstub_input_close(handle);
+
+  if (n < 0)
+    _tt_abort("error reading the ICC color profile \"%s\"", PDFA_ICC_PROFILE);
+
+  pdf_add_dict(pdf_stream_dict(profile), pdf_new_name("N"), pdf_new_number(3));
+
+  intent = pdf_new_dict();
+  pdf_add_dict(intent, pdf_new_name("Type"), pdf_new_name("OutputIntent"));
+  pdf_add_dict(intent, pdf_new_name("S"), pdf_new_name("GTS_PDFA1"));
+  pdf_add_dict(intent, pdf_new_name("OutputConditionIdentifier"),
+               pdf_new_string(PDFA_ICC_CONDITION, strlen(PDFA_ICC_CONDITION)));
+  pdf_add_dict(intent, pdf_new_name("Info"),
+               pdf_new_string(PDFA_ICC_CONDITION, strlen(PDFA_ICC_CONDITION)));
+  pdf_add_dict(intent, pdf_new_name("DestOutputProfile"), pdf_ref_obj(profile));
+  pdf_release_obj(profile);
+
+  intents = pdf_new_array();
+  pdf_add_array(intents, intent);
+  pdf_add_dict(p->root.dict, pdf_new_name("OutputIntents"), intents);
+}
+
+typedef struct xmp_buf
+{
+  char   *data;
+  size_t  len;
+  size_t  size;
+} xmp_buf;
+
+static void
+xmp_append (xmp_buf *b, const char *s, size_t len)
+{
+  if (b->len + len + 1 > b->size) {
+    b->size = (b->len + len + 1) * 2;
+    b->data = RENEW(b->data, b->size, char);
+  }
+
+  memcpy(b->data + b->len, s, len);
+  b->len += len;
+  b->data[b->len] = '\0';
+}
+
+static void
+xmp_puts (xmp_buf *b, const char *s)
+{
+  xmp_append(b, s, strlen(s));
+}
+
+/* Append a character to the packet as UTF-8, escaping it if needed. */
+static void
+xmp_put_unicode (xmp_buf *b, uint32_t c)
+{
+  char u[4];
+
+  switch (c) {
+  case '<': xmp_puts(b, "&lt;"); return;
+  case '>': xmp_puts(b, "&gt;"); return;
+  case '&': xmp_puts(b, "&amp;"); return;
+  case '"': xmp_puts(b, "&quot;"); return;
+  }
+
+  /* Most control characters can't appear in XML at all. */
+  if ((c < 0x20 && c != '\t' && c != '\n' && c != '\r') || (c >= 0xD800 && c < 0xE000) || c > 0x10FFFF)
+    return;
+
+  if (c < 0x80) {
+    u[0] = c;
+    xmp_append(b, u, 1);
+  } else if (c < 0x800) {
+    u[0] = 0xC0 | (c >> 6);
+    u[1] = 0x80 | (c & 0x3F);
+    xmp_append(b, u, 2);
+  } else if (c < 0x10000) {
+    u[0] = 0xE0 | (c >> 12);
+    u[1] = 0x80 | ((c >> 6) & 0x3F);
+    u[2] = 0x80 | (c & 0x3F);
+    xmp_append(b, u, 3);
+  } else {
+    u[0] = 0xF0 | (c >> 18);
+    u[1] = 0x80 | ((c >> 12) & 0x3F);
+    u[2] = 0x80 | ((c >> 6) & 0x3F);
+    u[3] = 0x80 | (c & 0x3F);
+    xmp_append(b, u, 4);
+  }
+}
+
+/* The characters of PDFDocEncoding that differ from Latin-1. */
+static const uint16_t pdfdoc_0x80[] = {
+  0x2022, 0x2020, 0x2021, 0x2026, 0x2014, 0x2013, 0x0192, 0x2044,
+  0x2039, 0x203A, 0x2212, 0x2030, 0x201E, 0x201C, 0x201D, 0x2018,
+  0x2019, 0x201A, 0x2122, 0xFB01, 0xFB02, 0x0141, 0x0152, 0x0160,
+  0x0178, 0x017D, 0x0131, 0x0142, 0x0153, 0x0161, 0x017E, 0x0000,
+  0x20AC
+};
+
+/* Append a PDF text string to the packet. Text strings are either UTF-16BE
+ * with a byte order mark, or PDFDocEncoding. */
+static void
+xmp_put_text (xmp_buf *b, pdf_obj *str)
+{
+  const unsigned char *s = pdf_string_value(str);
+  unsigned len = pdf_string_length(str);
+  unsigned i;
+
+  if (len >= 2 && s[0] == 0xFE && s[1] == 0xFF) {
+    for (i = 2; i + 1 < len; i += 2) {
+      uint32_t c = (s[i] << 8) | s[i+1];
+
+      if (c >= 0xD800 && c < 0xDC00 && i + 3 < len) {
+        uint32_t lo = (s[i+2] << 8) | s[i+3];
+
+        if (lo >= 0xDC00 && lo < 0xE000) {
+          c = 0x10000 + ((c - 0xD800) << 10) + (lo - 0xDC00);
+          i += 2;
+        }
+      }
+
+      xmp_put_unicode(b, c);
+    }
+  } else {
+    for (i = 0; i < len; i++) {
+      if (s[i] >= 0x80 && s[i] <= 0xA0)
+        xmp_put_unicode(b, pdfdoc_0x80[s[i] - 0x80]);
+      else
+        xmp_put_unicode(b, s[i]);
+    }
+  }
+}
+
+/* Append a PDF date string, "D:YYYYMMDDHHmmSSOHH'mm'", as an XMP date,
+ * "YYYY-MM-DDTHH:mm:SS+HH:mm". Fields left out of the PDF date take their
+ * default values. */
+static void
+xmp_put_date (xmp_buf *b, pdf_obj *str)
+{
+  const char *s = pdf_string_value(str);
+  unsigned len = pdf_string_length(str);
+  unsigned i, pos = 0;
+  int   f[6] = { 0, 1, 1, 0, 0, 0 };
+  const int width[6] = { 4, 2, 2, 2, 2, 2 };
+  char  out[32];
+
+  if (len >= 2 && s[0] == 'D' && s[1] == ':')
+    pos = 2;
+
+  for (i = 0; i < 6; i++) {
+    int j, v = 0;
+
+    if (pos + width[i] > len || !isdigit((unsigned char) s[pos]))
+      break;
+
+    for (j = 0; j < width[i]; j++) {
+      if (!isdigit((unsigned char) s[pos + j]))
+        return;
+      v = v * 10 + (s[pos + j] - '0');
+    }
+
+    f[i] = v;
+    pos += width[i];
+  }
+
+  if (i == 0)
+    return;
+
+  sprintf(out, "%04d-%02d-%02dT%02d:%02d:%02d", f[0], f[1], f[2], f[3], f[4], f[5]);
+  xmp_puts(b, out);
+
+  if (pos < len && s[pos] == 'Z') {
+    xmp_puts(b, "Z");
+  } else if (pos + 3 <= len && (s[pos] == '+' || s[pos] == '-') &&
+             isdigit((unsigned char) s[pos+1]) && isdigit((unsigned char) s[pos+2])) {
+    int tzm = 0;
+
+    if (pos + 6 <= len && s[pos+3] == '\'' &&
+        isdigit((unsigned char) s[pos+4]) && isdigit((unsigned char) s[pos+5]))
+      tzm = (s[pos+4] - '0') * 10 + (s[pos+5] - '0');
+
+    sprintf(out, "%c%c%c:%02d", s[pos], s[pos+1], s[pos+2], tzm);
+    xmp_puts(b, out);
+  }
+}
+
+static void
+xmp_put_property (xmp_buf *b, pdf_obj *docinfo, const char *key,
+                  const char *prop, const char *container, int is_date)
+{
+  pdf_obj *value = pdf_lookup_dict(docinfo, key);
+
+  if (!value)
+    return;
+
+  xmp_puts(b, "   <");
+  xmp_puts(b, prop);
+  xmp_puts(b, ">");
+
+  if (container) {
+    xmp_puts(b, "<rdf:");
+    xmp_puts(b, container);
+    xmp_puts(b, !strcmp(container, "Alt") ? "><rdf:li xml:lang=\"x-default\">" : "><rdf:li>");
+  }
+
+  if (is_date)
+    xmp_put_date(b, value);
+  else
+    xmp_put_text(b, value);
+
+  if (container) {
+    xmp_puts(b, "</rdf:li></rdf:");
+    xmp_puts(b, container);
+    xmp_puts(b, ">");
+  }
+
+  xmp_puts(b, "</");
+  xmp_puts(b, prop);
+  xmp_puts(b, ">\n");
+}
+
+/* Build an XMP packet mirroring the (finished) DocInfo dictionary and attach
+ * it to the catalog. */
+static void
+pdf_doc_add_pdfa_metadata (pdf_doc *p, pdf_obj *docinfo)
+{
+  xmp_buf  b = { NULL, 0, 0 };
+  pdf_obj *metadata;
+
+  xmp_puts(&b,
+           "<?xpacket begin=\"\xEF\xBB\xBF\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n"
+           "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n"
+           " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n"
+           "  <rdf:Description rdf:about=\"\" xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\n"
+           "   <pdfaid:part>1</pdfaid:part>\n"
+           "   <pdfaid:conformance>B</pdfaid:conformance>\n"
+           "  </rdf:Description>\n"
+           "  <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n"
+           "   <dc:format>application/pdf</dc:format>\n");
+  xmp_put_property(&b, docinfo, "Title", "dc:title", "Alt", 0);
+  xmp_put_property(&b, docinfo, "Author", "dc:creator", "Seq", 0);
+  xmp_put_property(&b, docinfo, "Subject", "dc:description", "Alt", 0);
+  xmp_puts(&b,
+           "  </rdf:Description>\n"
+           "  <rdf:Description rdf:about=\"\" xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\n");
+  xmp_put_property(&b, docinfo, "Producer", "pdf:Producer", NULL, 0);
+  xmp_put_property(&b, docinfo, "Keywords", "pdf:Keywords", NULL, 0);
+  xmp_puts(&b,
+           "  </rdf:Description>\n"
+           "  <rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n");
+  xmp_put_property(&b, docinfo, "Creator", "xmp:CreatorTool", NULL, 0);
+  xmp_put_property(&b, docinfo, "CreationDate", "xmp:CreateDate", NULL, 1);
+  xmp_put_property(&b, docinfo, "ModDate", "xmp:ModifyDate", NULL, 1);
+  xmp_puts(&b,
+           "  </rdf:Description>\n"
+           " </rdf:RDF>\n"
+           "</x:xmpmeta>\n"
+           "<?xpacket end=\"w\"?>");
+
+  metadata = pdf_new_stream(0);
+  pdf_add_dict(pdf_stream_dict(metadata), pdf_new_name("Type"), pdf_new_name("Metadata"));
+  pdf_add_dict(pdf_stream_dict(metadata), pdf_new_name("Subtype"), pdf_new_name("XML"));
+  pdf_add_stream(metadata, b.data, b.len);
+  free(b.data);
+
+  pdf_add_dict(p->root.dict, pdf_new_name("Metadata"), pdf_ref_obj(metadata));
+  pdf_release_obj(metadata);
+}
+
+/*
+ * Metadata given by the caller, rather than by the document, takes precedence
+ * over whatever the document sets.
+ */
+static pdf_obj *docinfo_overrides = NULL;
+static pdf_obj *metadata_packet = NULL;
+
+/* Make a PDF text string out of `value`, given in UTF-8. Returns NULL if
+ * it's not valid UTF-8. */
+static pdf_obj *
+utf8_text_string (const char *value)
+{
+  const unsigned char *s = (const unsigned char *) value;
+  const unsigned char *end = s + strlen(value);
+  const unsigned char *q;
+  unsigned char *buf, *dst, *buf_end;
+  pdf_obj *str;
+
+  if (!UC_UTF8_is_valid_string(s, end))
+    return NULL;
+
+  for (q = s; q < end && *q < 0x80; q++)
+    ;
+
+  if (q == end) {
+    str = pdf_new_string(value, strlen(value));
+  } else {
+    /* Anything beyond ASCII goes in UTF-16BE with a byte order mark. */
+    buf = NEW(2 + 4 * (end - s), unsigned char);
+    buf_end = buf + 2 + 4 * (end - s);
+    dst = buf;
+    *dst++ = 0xFE;
+    *dst++ = 0xFF;
+
+    while (s < end) {
+      int32_t c = UC_UTF8_decode_char(&s, end);
+      UC_UTF16BE_encode_char(c, &dst, buf_end);
+    }
+
+    str = pdf_new_string(buf, dst - buf);
+    free(buf);
+  }
+
+  return str;
+}
+
+/* Set DocInfo entry `key` to the text `value`, given in UTF-8. */
+void
+pdf_doc_set_docinfo_text (const char *key, const char *value)
+{
+  pdf_obj *str = utf8_text_string(value);
+
+  if (!str) {
+    dpx_warning("Ignoring \"%s\" for DocInfo: not valid UTF-8.", key);
+    return;
+  }
+
+  if (!docinfo_overrides)
+    docinfo_overrides = pdf_new_dict();
+  pdf_add_dict(docinfo_overrides, pdf_new_name(key), str);
+}
+
+/* Use `data` as the document's XMP metadata packet. */
+void
+pdf_doc_set_metadata (const void *data, size_t len)
+{
+  if (metadata_packet)
+    pdf_release_obj(metadata_packet);
+
+  metadata_packet = pdf_new_stream(0);
+  pdf_add_dict(pdf_stream_dict(metadata_packet), pdf_new_name("Type"), pdf_new_name("Metadata"));
+  pdf_add_dict(pdf_stream_dict(metadata_packet), pdf_new_name("Subtype"), pdf_new_name("XML"));
+  pdf_add_stream(metadata_packet, data, len);
+}
+
+/* Attach the file `name`, whose contents are `data`, to the document. The
+ * name is given in UTF-8. */
+void
+pdf_doc_add_attachment (const char *name, const void *data, size_t len)
+{
+  pdf_obj *stream, *params, *ef, *filespec, *uf;
+
+  uf = utf8_text_string(name);
+  if (!uf) {
+    dpx_warning("Not attaching \"%s\": its name is not valid UTF-8.", name);
+    return;
+  }
+
+  stream = pdf_new_stream(STREAM_COMPRESS);
+  pdf_add_dict(pdf_stream_dict(stream), pdf_new_name("Type"), pdf_new_name("EmbeddedFile"));
+  params = pdf_new_dict();
+  pdf_add_dict(params, pdf_new_name("Size"), pdf_new_number((double) len));
+  pdf_add_dict(pdf_stream_dict(stream), pdf_new_name("Params"), params);
+  pdf_add_stream(stream, data, len);
+
+  ef = pdf_new_dict();
+  pdf_add_dict(ef, pdf_new_name("F"), pdf_ref_obj(stream));
+  pdf_release_obj(stream);
+
+  filespec = pdf_new_dict();
+  pdf_add_dict(filespec, pdf_new_name("Type"), pdf_new_name("Filespec"));
+  pdf_add_dict(filespec, pdf_new_name("F"), pdf_new_string(name, strlen(name)));
+  pdf_add_dict(filespec, pdf_new_name("UF"), uf);
+  pdf_add_dict(filespec, pdf_new_name("EF"), ef);
+  pdf_add_dict(filespec, pdf_new_name("AFRelationship"), pdf_new_name("Source"));
+
+  if (pdf_doc_add_names("EmbeddedFiles", name, (int) strlen(name), pdf_ref_obj(filespec)) < 0)
+    dpx_warning("Failed to attach \"%s\".", name);
+  pdf_release_obj(filespec);
+}
+
 static void
 pdf_doc_init_docinfo (pdf_doc *p)
 {
@@ -526,6 +959,12 @@ pdf_doc_close_docinfo (pdf_doc *p)
   pdf_obj *value;
   int      i;
 
+  if (docinfo_overrides) {
+    pdf_merge_dict(docinfo, docinfo_overrides);
+    pdf_release_obj(docinfo_overrides);
+    docinfo_overrides = NULL;
+  }
+
   for (i = 0; keys[i] != NULL; i++) {
     value = pdf_lookup_dict(docinfo, keys[i]);
     if (value) {
@@ -540,7 +979,11 @@ pdf_doc_close_docinfo (pdf_doc *p)
     }
   }
 
-  if (!pdf_lookup_dict(docinfo, "Producer")) {
+  if (pdf_get_reproducible()) {
+    pdf_remove_dict(docinfo, "CreationDate");
+    pdf_remove_dict(docinfo, "ModDate");
+    pdf_remove_dict(docinfo, "Producer");
+  } else if (!pdf_lookup_dict(docinfo, "Producer")) {
     char banner[] = DVIPDFMX_PROG_NAME " (" DPX_VERSION ")";
 
     pdf_add_dict(docinfo,
@@ -548,7 +991,7 @@ pdf_doc_close_docinfo (pdf_doc *p)
                  pdf_new_string(banner, strlen(banner)));
   }
 
-  if (!pdf_lookup_dict(docinfo, "CreationDate")) {
+  if (!pdf_get_reproducible() && !pdf_lookup_dict(docinfo, "CreationDate")) {
     char now[32];
 
     asn_date(now);
@@ -557,6 +1000,16 @@ pdf_doc_close_docinfo (pdf_doc *p)
                  pdf_new_string(now, strlen(now)));
   }
 
+  if (metadata_packet) {
+    if (pdf_get_pdfa())
+      dpx_warning("Using the given XMP metadata for PDF/A output; it must identify the document as PDF/A-1b.");
+    pdf_add_dict(p->root.dict, pdf_new_name("Metadata"), pdf_ref_obj(metadata_packet));
+    pdf_release_obj(metadata_packet);
+    metadata_packet = NULL;
+  } else if (pdf_get_pdfa()) {
+    pdf_doc_add_pdfa_metadata(p, docinfo);
+  }
+
   pdf_release_obj(docinfo);
   p->info = NULL;
 
@@ -733,6 +1186,7 @@ build_page_tree (pdf_doc  *p,
    * references right.
    */
   self_ref = parent_ref ? pdf_ref_obj(self) : pdf_ref_obj(p->root.pages);
+  pdf_linearize_add_page_node(self_ref);
 
   pdf_add_dict(self, pdf_new_name("Type"),  pdf_new_name("Pages"));
   pdf_add_dict(self, pdf_new_name("Count"), pdf_new_number((double) num_pages));
@@ -749,6 +1203,7 @@ build_page_tree (pdf_doc  *p,
       if (!page->page_ref)
         page->page_ref = pdf_ref_obj(page->page_obj);
       pdf_add_array (kids, pdf_link_obj(page->page_ref));
+      pdf_linearize_add_page(page->page_ref);
       doc_flush_page(p, page, pdf_link_obj(self_ref));
     }
   } else if (num_pages > 0) {
@@ -771,6 +1226,7 @@ build_page_tree (pdf_doc  *p,
         if (!page->page_ref)
           page->page_ref = pdf_ref_obj(page->page_obj);
         pdf_add_array (kids, pdf_link_obj(page->page_ref));
+        pdf_linearize_add_page(page->page_ref);
         doc_flush_page(p, page, pdf_link_obj(self_ref));
       }
     }
@@ -2301,6 +2757,7 @@ pdf_doc_new_page (pdf_doc *p)
 
   currentpage->annots = NULL;
   currentpage->beads  = NULL;
+  currentpage->mcids  = NULL;
 
   return;
 }
@@ -2390,6 +2847,17 @@ pdf_doc_finish_page (pdf_doc *p)
     currentpage->resources = NULL;
   }
 
+  if (currentpage->mcids) {
+    int key = p->structure.next_key++;
+
+    pdf_add_dict(currentpage->page_obj,
+                 pdf_new_name("StructParents"), pdf_new_number(key));
+    pdf_add_array(p->structure.parent_tree, pdf_new_number(key));
+    pdf_add_array(p->structure.parent_tree, pdf_ref_obj(currentpage->mcids));
+    pdf_release_obj(currentpage->mcids);
+    currentpage->mcids = NULL;
+  }
+
   if (manual_thumb_enabled) {
     char    *thumb_filename;
     pdf_obj *thumb_ref;
@@ -2469,6 +2937,10 @@ pdf_doc_begin_page (double scale, double x_origin, double y_origin)
   pdf_doc_new_page(p);
   pdf_dev_bop(&M);
 
+  /* Structure elements that continue from the previous page. */
+  if (p->structure.depth > 0)
+    doc_open_marked_content(p);
+
   return;
 }
 
@@ -2477,6 +2949,7 @@ pdf_doc_end_page (void)
 {
   pdf_doc *p = &pdoc;
 
+  doc_close_marked_content(p);
   pdf_dev_eop();
   doc_fill_page_background(p);
 
@@ -2501,6 +2974,205 @@ pdf_doc_add_page_content (const char *buffer, unsigned length)
   return;
 }
 
+/*
+ * Structure tree
+ *
+ * Elements are begun and ended by specials, and may nest. The content drawn
+ * while an element is innermost is enclosed in a marked-content sequence
+ * that the element owns. Since marked content can't span pages or enclose
+ * the content of child elements, an element's content may be split into
+ * several sequences.
+ */
+static void
+doc_open_marked_content (pdf_doc *p)
+{
+  struct struct_elem *elem;
+  pdf_page *currentpage;
+  int       page_no, mcid;
+
+  elem = &p->structure.stack[p->structure.depth - 1];
+  currentpage = LASTPAGE(p);
+  page_no = pdf_doc_current_page_number();
+
+  if (!currentpage->mcids)
+    currentpage->mcids = pdf_new_array();
+  mcid = pdf_array_length(currentpage->mcids);
+  pdf_add_array(currentpage->mcids, pdf_ref_obj(elem->dict));
+
+  if (!elem->page_no) {
+    elem->page_no = page_no;
+    pdf_add_dict(elem->dict, pdf_new_name("Pg"), pdf_doc_ref_page(page_no));
+  }
+
+  if (elem->page_no == page_no) {
+    pdf_add_array(elem->kids, pdf_new_number(mcid));
+  } else {
+    pdf_obj *mcr = pdf_new_dict();
+
+    pdf_add_dict(mcr, pdf_new_name("Type"), pdf_new_name("MCR"));
+    pdf_add_dict(mcr, pdf_new_name("Pg"), pdf_doc_ref_page(page_no));
+    pdf_add_dict(mcr, pdf_new_name("MCID"), pdf_new_number(mcid));
+    pdf_add_array(elem->kids, mcr);
+  }
+
+  pdf_dev_begin_marked_content(elem->tag, mcid);
+  p->structure.mc_open = 1;
+}
+
+static void
+doc_close_marked_content (pdf_doc *p)
+{
+  if (p->structure.mc_open) {
+    pdf_dev_end_marked_content();
+    p->structure.mc_open = 0;
+  }
+}
+
+/* Begin an element of the structure type `tag`, such as "P" or "H1".
+ * Entries of `attrib`, like /Alt, are added to the element's dictionary.
+ */
+void
+pdf_doc_begin_struct (const char *tag, pdf_obj *attrib)
+{
+  pdf_doc *p = &pdoc;
+  struct struct_elem *elem;
+  pdf_obj *parent_kids;
+
+  /* The content of form XObjects would need to be tagged separately. */
+  if (p->pending_forms) {
+    dpx_warning("Structure tags inside a form XObject are not supported; ignoring \"%s\".", tag);
+    p->structure.ignored++;
+    if (attrib)
+      pdf_release_obj(attrib);
+    return;
+  }
+
+  if (!p->structure.root) {
+    p->structure.root = pdf_new_dict();
+    p->structure.kids = pdf_new_array();
+    pdf_add_dict(p->structure.root,
+                 pdf_new_name("Type"), pdf_new_name("StructTreeRoot"));
+    pdf_add_dict(p->structure.root,
+                 pdf_new_name("K"), p->structure.kids);
+  }
+
+  doc_close_marked_content(p);
+
+  if (p->structure.depth >= p->structure.max_depth) {
+    p->structure.max_depth += 16;
+    p->structure.stack = RENEW(p->structure.stack, p->structure.max_depth, struct struct_elem);
+  }
+
+  elem = &p->structure.stack[p->structure.depth];
+  elem->tag  = NEW(strlen(tag) + 1, char);
+  strcpy(elem->tag, tag);
+  elem->dict = pdf_new_dict();
+  elem->kids = pdf_new_array();
+  elem->page_no = 0;
+
+  pdf_add_dict(elem->dict, pdf_new_name("Type"), pdf_new_name("StructElem"));
+  pdf_add_dict(elem->dict, pdf_new_name("S"), pdf_new_name(tag));
+  if (p->structure.depth > 0) {
+    pdf_add_dict(elem->dict, pdf_new_name("P"), pdf_ref_obj(elem[-1].dict));
+    parent_kids = elem[-1].kids;
+  } else {
+    pdf_add_dict(elem->dict, pdf_new_name("P"), pdf_ref_obj(p->structure.root));
+    parent_kids = p->structure.kids;
+  }
+  if (attrib) {
+    pdf_merge_dict(elem->dict, attrib);
+    pdf_release_obj(attrib);
+  }
+  pdf_add_dict(elem->dict, pdf_new_name("K"), elem->kids);
+  pdf_add_array(parent_kids, pdf_ref_obj(elem->dict));
+
+  p->structure.depth++;
+  doc_open_marked_content(p);
+}
+
+/* Returns -1 if no element is open. */
+int
+pdf_doc_end_struct (void)
+{
+  pdf_doc *p = &pdoc;
+  struct struct_elem *elem;
+
+  if (p->structure.ignored > 0) {
+    p->structure.ignored--;
+    return 0;
+  }
+
+  if (p->structure.depth == 0)
+    return -1;
+
+  doc_close_marked_content(p);
+
+  elem = &p->structure.stack[--p->structure.depth];
+  pdf_release_obj(elem->dict);
+  free(elem->tag);
+
+  /* Whatever follows belongs to the parent again. */
+  if (p->structure.depth > 0)
+    doc_open_marked_content(p);
+
+  return 0;
+}
+
+static void
+pdf_doc_init_structure (pdf_doc *p)
+{
+  p->structure.root        = NULL;
+  p->structure.kids        = NULL;
+  p->structure.parent_tree = pdf_new_array();
+  p->structure.next_key    = 0;
+  p->structure.depth       = 0;
+  p->structure.max_depth   = 0;
+  p->structure.stack       = NULL;
+  p->structure.mc_open     = 0;
+  p->structure.ignored     = 0;
+}
+
+static void
+pdf_doc_close_structure (pdf_doc *p)
+{
+  pdf_obj *tmp;
+
+  if (p->structure.depth > 0)
+    dpx_warning("%d structure tag(s) were never ended.", p->structure.depth);
+
+  while (p->structure.depth > 0) {
+    struct struct_elem *elem = &p->structure.stack[--p->structure.depth];
+
+    pdf_release_obj(elem->dict);
+    free(elem->tag);
+  }
+
+  free(p->structure.stack);
+  p->structure.stack = NULL;
+
+  if (p->structure.root) {
+    tmp = pdf_new_dict();
+    pdf_add_dict(tmp, pdf_new_name("Nums"), pdf_link_obj(p->structure.parent_tree));
+    pdf_add_dict(p->structure.root, pdf_new_name("ParentTree"), pdf_ref_obj(tmp));
+    pdf_release_obj(tmp);
+    pdf_add_dict(p->structure.root,
+                 pdf_new_name("ParentTreeNextKey"), pdf_new_number(p->structure.next_key));
+
+    pdf_add_dict(p->root.dict,
+                 pdf_new_name("StructTreeRoot"), pdf_ref_obj(p->structure.root));
+    tmp = pdf_new_dict();
+    pdf_add_dict(tmp, pdf_new_name("Marked"), pdf_new_boolean(1));
+    pdf_add_dict(p->root.dict, pdf_new_name("MarkInfo"), tmp);
+
+    pdf_release_obj(p->structure.root);
+    p->structure.root = NULL;
+    p->structure.kids = NULL;
+  }
+
+  pdf_release_obj(p->structure.parent_tree);
+  p->structure.parent_tree = NULL;
+}
+
 static char *doccreator = NULL; /* Ugh */
 
 void
@@ -2514,6 +3186,14 @@ pdf_open_document (const char *filename,
 
   pdf_out_init(filename, enable_encrypt, enable_object_stream);
 
+  /* Left over if a previous run was aborted. */
+  if (docinfo_overrides)
+    pdf_release_obj(docinfo_overrides);
+  docinfo_overrides = NULL;
+  if (metadata_packet)
+    pdf_release_obj(metadata_packet);
+  metadata_packet = NULL;
+
   pdf_doc_init_catalog(p);
 
   p->opt.annot_grow = annot_grow_amount;
@@ -2525,6 +3205,9 @@ pdf_open_document (const char *filename,
   /* Thumbnail want this to be initialized... */
   pdf_init_images();
 
+  if (pdf_get_pdfa())
+    pdf_doc_init_pdfa(p);
+
   pdf_doc_init_docinfo(p);
   if (doccreator) {
     pdf_add_dict(p->info,
@@ -2537,6 +3220,7 @@ pdf_open_document (const char *filename,
   pdf_doc_init_articles (p);
   pdf_doc_init_names    (p, check_gotos);
   pdf_doc_init_page_tree(p, media_width, media_height);
+  pdf_doc_init_structure(p);
 
   pdf_doc_set_bgcolor(NULL);
 
@@ -2589,6 +3273,7 @@ pdf_close_document (void)
   pdf_doc_close_names    (p);
   pdf_doc_close_bookmarks(p);
   pdf_doc_close_page_tree(p);
+  pdf_doc_close_structure(p);
   pdf_doc_close_docinfo  (p);
 
   pdf_doc_close_catalog  (p);
@@ -44,6 +44,9 @@ void     pdf_close_document (void);
 
 /* PDF document metadata */
 void     pdf_doc_set_creator (const char *creator);
+void     pdf_doc_set_docinfo_text (const char *key, const char *value);
+void     pdf_doc_set_metadata     (const void *data, size_t len);
+void     pdf_doc_add_attachment   (const char *name, const void *data, size_t len);
 
 
 /* They just return PDF dictionary object.
@@ -122,6 +125,10 @@ void     pdf_doc_end_annot   (void);
 void     pdf_doc_break_annot (void);
 void     pdf_doc_expand_box  (const pdf_rect *rect);
 
+/* Structure tree for tagged PDF */
+void     pdf_doc_begin_struct (const char *tag, pdf_obj *attrib);
+int      pdf_doc_end_struct   (void);
+
 /* Manual thumbnail */
 void     pdf_doc_enable_manual_thumbnails (void);
 
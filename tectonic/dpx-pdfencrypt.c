@@ -765,6 +765,15 @@ pdf_encrypt_obj (void)
   return doc_encrypt;
 }
 
+/* Use an all-zero document ID, for output that doesn't depend on the time
+ * or the file names. */
+void pdf_enc_clear_id (void)
+{
+  struct pdf_sec *p = &sec_data;
+
+  memset(p->ID, 0, sizeof(p->ID));
+}
+
 pdf_obj *pdf_enc_id_array (void)
 {
   struct pdf_sec *p = &sec_data;
@@ -29,6 +29,7 @@
 
 void pdf_enc_set_verbose (void);
 pdf_obj *pdf_enc_id_array (void);
+void pdf_enc_clear_id (void);
 void pdf_enc_compute_id_string (const char *dviname, const char *pdfname);
 void pdf_enc_set_label (unsigned label);
 void pdf_enc_set_generation (unsigned generation);
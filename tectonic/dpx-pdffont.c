@@ -106,6 +106,10 @@ get_unique_time_if_given(void)
   return ret;
 }
 
+/* State of the generator used for subset tags in reproducible mode; the
+ * tags then depend only on the order in which fonts are used. */
+static uint32_t unique_tag_state = 0;
+
 void
 pdf_font_make_uniqueTag (char *tag)
 {
@@ -113,6 +117,15 @@ pdf_font_make_uniqueTag (char *tag)
   char   ch;
   static char first = 1;
 
+  if (pdf_get_reproducible()) {
+    for (i = 0; i < 6; i++) {
+      unique_tag_state = unique_tag_state * 1103515245u + 12345u;
+      tag[i] = (unique_tag_state >> 16) % 26 + 'A';
+    }
+    tag[6] = '\0';
+    return;
+  }
+
   if (first) {
     time_t current_time;
     current_time = get_unique_time_if_given();
@@ -295,6 +308,8 @@ pdf_init_fonts (void)
 {
   assert(font_cache.fonts == NULL);
 
+  unique_tag_state = 0;
+
   agl_init_map();
   otl_init_conf();
 
@@ -21,6 +21,7 @@
 */
 
 #include <ctype.h>
+#include <limits.h>
 #include <string.h>
 /* floor and abs */
 #include <math.h>
@@ -296,6 +297,100 @@ pdf_obj_set_verbose(void)
     verbose++;
 }
 
+/* PDF/A-1b output: we don't try to repair documents that use features that
+ * the standard forbids, but we do refuse to write them out. Since fonts,
+ * images, and graphics states can come from many places (including imported
+ * PDF files), the checks are done as dictionaries are written. */
+static int pdfa_mode = 0;
+
+void
+pdf_set_pdfa (int enable)
+{
+    pdfa_mode = enable ? 1 : 0;
+}
+
+int
+pdf_get_pdfa (void)
+{
+    return pdfa_mode;
+}
+
+/* Reproducible output: leave out everything that would differ between two
+ * runs on the same input, such as timestamps and random font subset tags. */
+static int reproducible_mode = 0;
+
+void
+pdf_set_reproducible (int enable)
+{
+    reproducible_mode = enable ? 1 : 0;
+}
+
+int
+pdf_get_reproducible (void)
+{
+    return reproducible_mode;
+}
+
+static const char *
+pdfa_name_or (pdf_obj *obj, const char *dflt)
+{
+    if (PDF_OBJ_NAMETYPE(obj))
+        return pdf_name_value(obj);
+    return dflt;
+}
+
+static void
+pdfa_check_dict (pdf_obj *dict)
+{
+    pdf_obj *tmp;
+    const char *type, *subtype;
+
+    tmp = pdf_lookup_dict(dict, "SMask");
+    if (tmp && !(PDF_OBJ_NAMETYPE(tmp) && !strcmp(pdf_name_value(tmp), "None")))
+        _tt_abort("PDF/A-1 does not allow transparency, but this document uses a soft mask; "
+                  "this usually comes from an image with an alpha channel, such as a PNG, "
+                  "which must be flattened before it can be included");
+
+    tmp = pdf_lookup_dict(dict, "CA");
+    if (PDF_OBJ_NUMBERTYPE(tmp) && pdf_number_value(tmp) < 1.0)
+        _tt_abort("PDF/A-1 does not allow transparency, but this document draws with a stroke "
+                  "opacity of %g; remove the opacity settings from the document", pdf_number_value(tmp));
+
+    tmp = pdf_lookup_dict(dict, "ca");
+    if (PDF_OBJ_NUMBERTYPE(tmp) && pdf_number_value(tmp) < 1.0)
+        _tt_abort("PDF/A-1 does not allow transparency, but this document draws with a fill "
+                  "opacity of %g; remove the opacity settings from the document", pdf_number_value(tmp));
+
+    tmp = pdf_lookup_dict(dict, "BM");
+    if (PDF_OBJ_NAMETYPE(tmp) && strcmp(pdf_name_value(tmp), "Normal") &&
+        strcmp(pdf_name_value(tmp), "Compatible"))
+        _tt_abort("PDF/A-1 does not allow transparency, but this document uses the \"%s\" "
+                  "blend mode", pdf_name_value(tmp));
+
+    type = pdfa_name_or(pdf_lookup_dict(dict, "Type"), "");
+    subtype = pdfa_name_or(pdf_lookup_dict(dict, "Subtype"), "");
+
+    tmp = pdf_lookup_dict(dict, "S");
+    if (PDF_OBJ_NAMETYPE(tmp) && !strcmp(pdf_name_value(tmp), "Transparency"))
+        _tt_abort("PDF/A-1 does not allow transparency, but this document contains a transparency "
+                  "group; it may come from an included PDF figure, which must be flattened first");
+
+    if (!strcmp(type, "FontDescriptor") &&
+        !pdf_lookup_dict(dict, "FontFile") &&
+        !pdf_lookup_dict(dict, "FontFile2") &&
+        !pdf_lookup_dict(dict, "FontFile3"))
+        _tt_abort("PDF/A requires all fonts to be embedded, but the font \"%s\" is not; "
+                  "check that its font file is available and that its license allows embedding",
+                  pdfa_name_or(pdf_lookup_dict(dict, "FontName"), "(unknown)"));
+
+    if (!strcmp(type, "Font") &&
+        (!strcmp(subtype, "Type1") || !strcmp(subtype, "MMType1") || !strcmp(subtype, "TrueType")) &&
+        !pdf_lookup_dict(dict, "FontDescriptor"))
+        _tt_abort("PDF/A requires all fonts to be embedded, but the font \"%s\" is one of the "
+                  "standard 14 fonts that are never embedded; use a font with an available font file instead",
+                  pdfa_name_or(pdf_lookup_dict(dict, "BaseFont"), "(unknown)"));
+}
+
 static pdf_obj *current_objstm = NULL;
 static int do_objstm;
 
@@ -314,6 +409,672 @@ add_xref_entry (unsigned label, unsigned char type, unsigned int field2, unsigne
     output_xref[label].indirect = NULL;
 }
 
+/*
+ * Linearized output
+ *
+ * A linearized ("fast web view") file starts with everything needed to show
+ * the first page, followed by the other pages in order, and has hint tables
+ * that tell a viewer which byte ranges hold each page. We write objects in
+ * whatever order they're released, which has little to do with that, so
+ * when linearizing we keep the serialization of each object in memory along
+ * with the positions of the indirect references that it contains. When the
+ * file is closed, we work out which objects each page uses, lay everything
+ * out, and renumber the objects to match. Object streams and encryption
+ * aren't supported in this mode.
+ */
+
+typedef struct lin_ref
+{
+    unsigned offset;  /* position of the reference in the object's data */
+    unsigned length;
+    unsigned label;   /* the object referred to */
+} lin_ref;
+
+typedef struct lin_obj
+{
+    unsigned char *data;   /* NULL if the object was never written */
+    unsigned       length;
+    lin_ref       *refs;
+    unsigned       num_refs;
+    unsigned       max_refs;
+
+    int            page;     /* page number for page objects */
+    int            is_node;  /* interior node of the page tree */
+    int            placed;
+    int            owner;    /* the only page using it, or -1 if shared */
+    int            visited;  /* page of the last traversal to reach it */
+    unsigned       shared_index;
+
+    unsigned       new_label;
+    unsigned char *out;      /* final serialization */
+    unsigned       out_length;
+    unsigned       offset;   /* in the file, not counting the hint stream */
+} lin_obj;
+
+static int       linearize_mode = 0;
+static lin_obj  *lin_objs = NULL;   /* indexed by label */
+static unsigned  lin_max_objs = 0;
+static lin_obj  *lin_current = NULL; /* object being serialized */
+static unsigned *lin_pages = NULL;   /* labels of the page objects, in order */
+static unsigned  lin_num_pages = 0;
+static unsigned  lin_max_pages = 0;
+
+void
+pdf_set_linearize (int enable)
+{
+    linearize_mode = enable ? 1 : 0;
+}
+
+int
+pdf_get_linearize (void)
+{
+    return linearize_mode;
+}
+
+static lin_obj *
+lin_entry (unsigned label)
+{
+    if (label >= lin_max_objs) {
+        unsigned size = (label / IND_OBJECTS_ALLOC_SIZE + 1) * IND_OBJECTS_ALLOC_SIZE;
+
+        lin_objs = RENEW(lin_objs, size, lin_obj);
+        memset(lin_objs + lin_max_objs, 0, (size - lin_max_objs) * sizeof(lin_obj));
+        lin_max_objs = size;
+    }
+
+    return &lin_objs[label];
+}
+
+static unsigned
+lin_ref_label (pdf_obj *ref)
+{
+    if (!PDF_OBJ_INDIRECTTYPE(ref))
+        return 0;
+    return ((pdf_indirect *) ref->data)->label;
+}
+
+/* The page tree has to be excluded when working out which objects a page
+ * uses, since every page refers to it and it refers to every page. */
+void
+pdf_linearize_add_page (pdf_obj *page_ref)
+{
+    if (!linearize_mode)
+        return;
+
+    if (lin_num_pages >= lin_max_pages) {
+        lin_max_pages += 256;
+        lin_pages = RENEW(lin_pages, lin_max_pages, unsigned);
+    }
+
+    lin_pages[lin_num_pages++] = lin_ref_label(page_ref);
+}
+
+void
+pdf_linearize_add_page_node (pdf_obj *node_ref)
+{
+    if (linearize_mode)
+        lin_entry(lin_ref_label(node_ref))->is_node = 1;
+}
+
+static void
+lin_init (void)
+{
+    unsigned i;
+
+    for (i = 0; i < lin_max_objs; i++) {
+        free(lin_objs[i].data);
+        free(lin_objs[i].refs);
+        free(lin_objs[i].out);
+    }
+
+    free(lin_objs);
+    lin_objs = NULL;
+    lin_max_objs = 0;
+    free(lin_pages);
+    lin_pages = NULL;
+    lin_num_pages = lin_max_pages = 0;
+    lin_current = NULL;
+}
+
+static void
+lin_note_ref (unsigned label, unsigned length)
+{
+    lin_obj *lo = lin_current;
+
+    if (lo->num_refs >= lo->max_refs) {
+        lo->max_refs += 16;
+        lo->refs = RENEW(lo->refs, lo->max_refs, lin_ref);
+    }
+
+    lo->refs[lo->num_refs].offset = pdf_stream_length(output_stream);
+    lo->refs[lo->num_refs].length = length;
+    lo->refs[lo->num_refs].label  = label;
+    lo->num_refs++;
+}
+
+/* Serialize `object` into `lo` rather than into the file. */
+static void
+lin_capture (lin_obj *lo, pdf_obj *object)
+{
+    pdf_obj *buffer = pdf_new_stream(0);
+
+    output_stream = buffer;
+    lin_current = lo;
+    enc_mode = false;
+    pdf_write_obj(object, pdf_output_handle);
+    lin_current = NULL;
+    output_stream = NULL;
+
+    lo->length = pdf_stream_length(buffer);
+    lo->data = NEW(lo->length + 1, unsigned char);
+    if (lo->length > 0)
+        memcpy(lo->data, pdf_stream_dataptr(buffer), lo->length);
+    pdf_release_obj(buffer);
+}
+
+static void
+lin_capture_obj (pdf_obj *object)
+{
+    lin_obj *lo = lin_entry(object->label);
+
+    if (lo->data)
+        _tt_abort("Object %u written twice.", object->label);
+
+    lin_capture(lo, object);
+}
+
+/* Produce the final serialization of `lo`, with its references renumbered,
+ * wrapped in "obj ... endobj" if `label` is nonzero. */
+static void
+lin_renumber (lin_obj *lo, unsigned label)
+{
+    unsigned char *out;
+    unsigned pos = 0, len = 0, i;
+
+    out = NEW(lo->length + 24 * lo->num_refs + 64, unsigned char);
+
+    if (label)
+        len += sprintf((char *) out, "%u 0 obj\n", label);
+
+    for (i = 0; i < lo->num_refs; i++) {
+        lin_ref *r = &lo->refs[i];
+
+        memcpy(out + len, lo->data + pos, r->offset - pos);
+        len += r->offset - pos;
+
+        if (r->label < lin_max_objs && lin_objs[r->label].data)
+            len += sprintf((char *) out + len, "%u 0 R", lin_objs[r->label].new_label);
+        else
+            len += sprintf((char *) out + len, "null");
+
+        pos = r->offset + r->length;
+    }
+
+    memcpy(out + len, lo->data + pos, lo->length - pos);
+    len += lo->length - pos;
+
+    if (label) {
+        memcpy(out + len, "\nendobj\n", 8);
+        len += 8;
+    }
+
+    lo->out = out;
+    lo->out_length = len;
+}
+
+/* Order in which objects appear in the file, as labels. */
+typedef struct lin_list
+{
+    unsigned *labels;
+    unsigned  count;
+    unsigned  max;
+} lin_list;
+
+static void
+lin_list_add (lin_list *l, unsigned label)
+{
+    if (l->count >= l->max) {
+        l->max += 256;
+        l->labels = RENEW(l->labels, l->max, unsigned);
+    }
+
+    l->labels[l->count++] = label;
+}
+
+/* Find the objects used by page `page_no`, in depth-first order starting
+ * with the page object itself, without entering the page tree, other pages,
+ * or objects already placed. */
+static void
+lin_collect_page (int page_no, lin_list *dest)
+{
+    lin_list stack = { NULL, 0, 0 };
+
+    lin_list_add(&stack, lin_pages[page_no - 1]);
+
+    while (stack.count > 0) {
+        unsigned label = stack.labels[--stack.count];
+        lin_obj *lo;
+        int      i;
+
+        if (label >= lin_max_objs)
+            continue;
+
+        lo = &lin_objs[label];
+        if (!lo->data || lo->is_node || lo->placed || lo->visited == page_no ||
+            (lo->page && lo->page != page_no))
+            continue;
+
+        lo->visited = page_no;
+        lin_list_add(dest, label);
+
+        for (i = (int) lo->num_refs - 1; i >= 0; i--)
+            lin_list_add(&stack, lo->refs[i].label);
+    }
+
+    free(stack.labels);
+}
+
+typedef struct lin_bits
+{
+    unsigned char *data;
+    unsigned       length;
+    unsigned       max;
+    int            bit;   /* bits used in the last byte, or 0 */
+} lin_bits;
+
+static void
+lin_put_bits (lin_bits *b, unsigned value, int nbits)
+{
+    while (nbits-- > 0) {
+        if (b->bit == 0) {
+            if (b->length >= b->max) {
+                b->max += 1024;
+                b->data = RENEW(b->data, b->max, unsigned char);
+            }
+            b->data[b->length++] = 0;
+        }
+
+        if ((value >> nbits) & 1)
+            b->data[b->length - 1] |= 0x80 >> b->bit;
+
+        b->bit = (b->bit + 1) % 8;
+    }
+}
+
+static void
+lin_align (lin_bits *b)
+{
+    b->bit = 0;
+}
+
+static int
+lin_nbits (unsigned value)
+{
+    int n = 0;
+
+    while (value) {
+        n++;
+        value >>= 1;
+    }
+
+    return n;
+}
+
+static void
+lin_write_file (void)
+{
+    lin_list  first = { NULL, 0, 0 };   /* first page section, in order */
+    lin_list  rest = { NULL, 0, 0 };    /* everything after it */
+    lin_list  shared = { NULL, 0, 0 };
+    lin_list  used = { NULL, 0, 0 };    /* objects used by each later page */
+    unsigned *used_start, *page_count, *private_start;
+    unsigned  catalog, first_label, num_first, num_rest, num_pages = lin_num_pages;
+    unsigned  header_length = pdf_output_file_position;
+    unsigned  i, p;
+    lin_obj   trailer = { NULL };
+    lin_bits  hint = { NULL, 0, 0, 0 };
+    unsigned  shared_table_offset;
+    char      lin_dict[256], xref_head[64], hint_head[64];
+    unsigned  lin_dict_len, first_xref_len, first_trailer_len, hint_head_len;
+    unsigned  hint_offset, hint_length, first_xref_offset, end_of_first_page;
+    unsigned  main_xref_offset, main_xref_head_len, file_length, pos;
+    int       length;
+
+    if (num_pages == 0)
+        _tt_abort("Cannot linearize a document with no pages.");
+
+    catalog = lin_ref_label(pdf_lookup_dict(trailer_dict, "Root"));
+    if (!catalog || catalog >= lin_max_objs || !lin_objs[catalog].data)
+        _tt_abort("Cannot linearize a document without a catalog.");
+
+    for (p = 0; p < num_pages; p++)
+        lin_entry(lin_pages[p])->page = p + 1;
+
+    /* The catalog goes before the first page, and isn't part of it. */
+    lin_objs[catalog].placed = 1;
+
+    lin_collect_page(1, &first);
+    for (i = 0; i < first.count; i++)
+        lin_objs[first.labels[i]].placed = 1;
+
+    /* Work out which of the remaining objects belong to a single page. */
+    used_start = NEW(num_pages + 1, unsigned);
+    for (p = 2; p <= num_pages; p++) {
+        used_start[p - 1] = used.count;
+        lin_collect_page(p, &used);
+    }
+    used_start[num_pages] = used.count;
+    used_start[0] = 0;
+
+    for (i = 0; i < used.count; i++) {
+        lin_obj *lo = &lin_objs[used.labels[i]];
+        lo->owner = 0;
+    }
+    for (p = 2; p <= num_pages; p++) {
+        for (i = used_start[p - 1]; i < used_start[p]; i++) {
+            lin_obj *lo = &lin_objs[used.labels[i]];
+
+            if (lo->owner == 0)
+                lo->owner = p;
+            else if (lo->owner != (int) p)
+                lo->owner = -1;
+        }
+    }
+
+    /* Each later page's own objects, then the shared ones, then the rest. */
+    page_count = NEW(num_pages + 1, unsigned);
+    private_start = NEW(num_pages + 1, unsigned);
+    page_count[1] = first.count;
+    private_start[1] = 0;
+    for (p = 2; p <= num_pages; p++) {
+        private_start[p] = rest.count;
+        for (i = used_start[p - 1]; i < used_start[p]; i++) {
+            lin_obj *lo = &lin_objs[used.labels[i]];
+
+            if (lo->owner == (int) p && !lo->placed) {
+                lo->placed = 1;
+                lin_list_add(&rest, used.labels[i]);
+            }
+        }
+        page_count[p] = rest.count - private_start[p];
+    }
+
+    for (i = 0; i < used.count; i++) {
+        lin_obj *lo = &lin_objs[used.labels[i]];
+
+        if (lo->owner == -1 && !lo->placed) {
+            lo->placed = 1;
+            lo->shared_index = first.count + shared.count;
+            lin_list_add(&shared, used.labels[i]);
+            lin_list_add(&rest, used.labels[i]);
+        }
+    }
+
+    for (i = 1; i < lin_max_objs; i++) {
+        if (lin_objs[i].data && !lin_objs[i].placed) {
+            lin_objs[i].placed = 1;
+            lin_list_add(&rest, i);
+        }
+    }
+
+    /* Objects after the first page come first in numbering; the first
+     * page section is the linearization dictionary, the catalog, the hint
+     * stream, and the first page's objects. */
+    num_rest = rest.count;
+    first_label = num_rest + 1;
+    num_first = 3 + first.count;
+
+    for (i = 0; i < num_rest; i++)
+        lin_objs[rest.labels[i]].new_label = i + 1;
+    lin_objs[catalog].new_label = first_label + 1;
+    for (i = 0; i < first.count; i++)
+        lin_objs[first.labels[i]].new_label = first_label + 3 + i;
+
+    lin_renumber(&lin_objs[catalog], first_label + 1);
+    for (i = 0; i < first.count; i++)
+        lin_renumber(&lin_objs[first.labels[i]], first_label + 3 + i);
+    for (i = 0; i < num_rest; i++)
+        lin_renumber(&lin_objs[rest.labels[i]], i + 1);
+
+    /* The first page trailer; its /Prev is filled in below. */
+    pdf_add_dict(trailer_dict, pdf_new_name("Size"),
+                 pdf_new_number(first_label + num_first));
+    lin_capture(&trailer, trailer_dict);
+    lin_renumber(&trailer, 0);
+
+    /* Lay out the file, leaving out the hint stream for now since the
+     * offsets in the hint tables don't count it. Every value that we don't
+     * know yet is written with a fixed width. */
+#define LIN_DICT_FORMAT "%u 0 obj\n<</Linearized 1/L %010u/H[%010u %010u]/O %u/E %010u/N %u/T %010u>>\nendobj\n"
+    lin_dict_len = sprintf(lin_dict, LIN_DICT_FORMAT, first_label, 0, 0, 0,
+                           lin_objs[lin_pages[0]].new_label, 0, num_pages, 0);
+    first_xref_len = sprintf(xref_head, "xref\n%u %u\n", first_label, num_first) + 20 * num_first;
+    first_trailer_len = strlen("trailer\n") + trailer.out_length + strlen("/Prev 0123456789") +
+        strlen("\nstartxref\n0\n%%EOF\n");
+
+    pos = header_length + lin_dict_len;
+    first_xref_offset = pos;
+    pos += first_xref_len + first_trailer_len;
+
+    lin_objs[catalog].offset = pos;
+    pos += lin_objs[catalog].out_length;
+    hint_offset = pos;
+
+    for (i = 0; i < first.count; i++) {
+        lin_objs[first.labels[i]].offset = pos;
+        pos += lin_objs[first.labels[i]].out_length;
+    }
+    end_of_first_page = pos;
+
+    for (i = 0; i < num_rest; i++) {
+        lin_objs[rest.labels[i]].offset = pos;
+        pos += lin_objs[rest.labels[i]].out_length;
+    }
+    main_xref_offset = pos;
+
+    /* Page offset hint table. */
+    {
+        unsigned least_count = UINT_MAX, most_count = 0;
+        unsigned least_length = UINT_MAX, most_length = 0;
+        unsigned most_shared = 0;
+        unsigned *page_length = NEW(num_pages + 1, unsigned);
+        unsigned *page_shared = NEW(num_pages + 1, unsigned);
+        int count_bits, length_bits, shared_bits, ident_bits;
+
+        page_length[1] = end_of_first_page - lin_objs[first.labels[0]].offset;
+        page_shared[1] = 0;
+
+        for (p = 2; p <= num_pages; p++) {
+            page_length[p] = 0;
+            for (i = 0; i < page_count[p]; i++)
+                page_length[p] += lin_objs[rest.labels[private_start[p] + i]].out_length;
+
+            page_shared[p] = 0;
+            for (i = used_start[p - 1]; i < used_start[p]; i++) {
+                if (lin_objs[used.labels[i]].owner == -1)
+                    page_shared[p]++;
+            }
+        }
+
+        for (p = 1; p <= num_pages; p++) {
+            if (page_count[p] < least_count)
+                least_count = page_count[p];
+            if (page_count[p] > most_count)
+                most_count = page_count[p];
+            if (page_length[p] < least_length)
+                least_length = page_length[p];
+            if (page_length[p] > most_length)
+                most_length = page_length[p];
+            if (page_shared[p] > most_shared)
+                most_shared = page_shared[p];
+        }
+
+        count_bits  = lin_nbits(most_count - least_count);
+        length_bits = lin_nbits(most_length - least_length);
+        shared_bits = lin_nbits(most_shared);
+        ident_bits  = lin_nbits(first.count + shared.count);
+
+        lin_put_bits(&hint, least_count, 32);
+        lin_put_bits(&hint, lin_objs[first.labels[0]].offset, 32);
+        lin_put_bits(&hint, count_bits, 16);
+        lin_put_bits(&hint, least_length, 32);
+        lin_put_bits(&hint, length_bits, 16);
+        /* We don't track where content streams are, so we describe each
+         * page's content as the whole page. */
+        lin_put_bits(&hint, 0, 32);
+        lin_put_bits(&hint, 0, 16);
+        lin_put_bits(&hint, least_length, 32);
+        lin_put_bits(&hint, length_bits, 16);
+        lin_put_bits(&hint, shared_bits, 16);
+        lin_put_bits(&hint, ident_bits, 16);
+        lin_put_bits(&hint, 0, 16);
+        lin_put_bits(&hint, 1, 16);
+
+        for (p = 1; p <= num_pages; p++)
+            lin_put_bits(&hint, page_count[p] - least_count, count_bits);
+        lin_align(&hint);
+        for (p = 1; p <= num_pages; p++)
+            lin_put_bits(&hint, page_length[p] - least_length, length_bits);
+        lin_align(&hint);
+        for (p = 1; p <= num_pages; p++)
+            lin_put_bits(&hint, page_shared[p], shared_bits);
+        lin_align(&hint);
+        for (p = 2; p <= num_pages; p++) {
+            for (i = used_start[p - 1]; i < used_start[p]; i++) {
+                if (lin_objs[used.labels[i]].owner == -1)
+                    lin_put_bits(&hint, lin_objs[used.labels[i]].shared_index, ident_bits);
+            }
+        }
+        lin_align(&hint);
+        /* The numerators take no bits, so item 5 is empty. */
+        for (p = 1; p <= num_pages; p++)
+            lin_put_bits(&hint, page_length[p] - least_length, length_bits);
+        lin_align(&hint);
+
+        free(page_length);
+        free(page_shared);
+    }
+
+    /* Shared object hint table, with one object per group. */
+    shared_table_offset = hint.length;
+    {
+        unsigned least_length = UINT_MAX, most_length = 0;
+        unsigned num_entries = first.count + shared.count;
+        int length_bits;
+
+        for (i = 0; i < num_entries; i++) {
+            unsigned label = i < first.count ? first.labels[i] : shared.labels[i - first.count];
+
+            if (lin_objs[label].out_length < least_length)
+                least_length = lin_objs[label].out_length;
+            if (lin_objs[label].out_length > most_length)
+                most_length = lin_objs[label].out_length;
+        }
+
+        length_bits = lin_nbits(most_length - least_length);
+
+        lin_put_bits(&hint, shared.count ? lin_objs[shared.labels[0]].new_label : 0, 32);
+        lin_put_bits(&hint, shared.count ? lin_objs[shared.labels[0]].offset : 0, 32);
+        lin_put_bits(&hint, first.count, 32);
+        lin_put_bits(&hint, num_entries, 32);
+        lin_put_bits(&hint, 0, 16);
+        lin_put_bits(&hint, least_length, 32);
+        lin_put_bits(&hint, length_bits, 16);
+
+        for (i = 0; i < num_entries; i++) {
+            unsigned label = i < first.count ? first.labels[i] : shared.labels[i - first.count];
+            lin_put_bits(&hint, lin_objs[label].out_length - least_length, length_bits);
+        }
+        lin_align(&hint);
+        for (i = 0; i < num_entries; i++)
+            lin_put_bits(&hint, 0, 1);
+        lin_align(&hint);
+    }
+
+    hint_head_len = sprintf(hint_head, "%u 0 obj\n<</Length %u/S %u>>\nstream\n",
+                            first_label + 2, hint.length, shared_table_offset);
+    hint_length = hint_head_len + hint.length + strlen("\nendstream\nendobj\n");
+
+    /* Now the real offsets. */
+    for (i = 0; i < first.count; i++)
+        lin_objs[first.labels[i]].offset += hint_length;
+    for (i = 0; i < num_rest; i++)
+        lin_objs[rest.labels[i]].offset += hint_length;
+    end_of_first_page += hint_length;
+    main_xref_offset += hint_length;
+
+    main_xref_head_len = sprintf(xref_head, "xref\n0 %u\n", first_label);
+    file_length = main_xref_offset + main_xref_head_len + 20 * first_label +
+        sprintf(format_buffer, "trailer\n<</Size %u>>\nstartxref\n%u\n%%%%EOF\n",
+                first_label, first_xref_offset);
+
+    /* Write it all out. */
+    length = sprintf(lin_dict, LIN_DICT_FORMAT, first_label, file_length,
+                     hint_offset, hint_length, lin_objs[lin_pages[0]].new_label,
+                     end_of_first_page, num_pages, main_xref_offset + main_xref_head_len - 1);
+    pdf_out(pdf_output_handle, lin_dict, length);
+
+    length = sprintf(format_buffer, "xref\n%u %u\n", first_label, num_first);
+    pdf_out(pdf_output_handle, format_buffer, length);
+    length = sprintf(format_buffer, "%010u %05hu n \n", header_length, 0);
+    pdf_out(pdf_output_handle, format_buffer, length);
+    length = sprintf(format_buffer, "%010u %05hu n \n", lin_objs[catalog].offset, 0);
+    pdf_out(pdf_output_handle, format_buffer, length);
+    length = sprintf(format_buffer, "%010u %05hu n \n", hint_offset, 0);
+    pdf_out(pdf_output_handle, format_buffer, length);
+    for (i = 0; i < first.count; i++) {
+        length = sprintf(format_buffer, "%010u %05hu n \n", lin_objs[first.labels[i]].offset, 0);
+        pdf_out(pdf_output_handle, format_buffer, length);
+    }
+
+    /* The trailer's dictionary starts with "<<"; put /Prev right after. */
+    pdf_out(pdf_output_handle, "trailer\n", 8);
+    pdf_out(pdf_output_handle, trailer.out, 2);
+    length = sprintf(format_buffer, "/Prev %010u", main_xref_offset);
+    pdf_out(pdf_output_handle, format_buffer, length);
+    pdf_out(pdf_output_handle, trailer.out + 2, trailer.out_length - 2);
+    pdf_out(pdf_output_handle, "\nstartxref\n0\n%%EOF\n", strlen("\nstartxref\n0\n%%EOF\n"));
+
+    pdf_out(pdf_output_handle, lin_objs[catalog].out, lin_objs[catalog].out_length);
+
+    pdf_out(pdf_output_handle, hint_head, hint_head_len);
+    pdf_out(pdf_output_handle, hint.data, hint.length);
+    pdf_out(pdf_output_handle, "\nendstream\nendobj\n", strlen("\nendstream\nendobj\n"));
+
+    for (i = 0; i < first.count; i++)
+        pdf_out(pdf_output_handle, lin_objs[first.labels[i]].out, lin_objs[first.labels[i]].out_length);
+    for (i = 0; i < num_rest; i++)
+        pdf_out(pdf_output_handle, lin_objs[rest.labels[i]].out, lin_objs[rest.labels[i]].out_length);
+
+    pdf_out(pdf_output_handle, xref_head, main_xref_head_len);
+    pdf_out(pdf_output_handle, "0000000000 65535 f \n", 20);
+    for (i = 0; i < num_rest; i++) {
+        length = sprintf(format_buffer, "%010u %05hu n \n", lin_objs[rest.labels[i]].offset, 0);
+        pdf_out(pdf_output_handle, format_buffer, length);
+    }
+
+    length = sprintf(format_buffer, "trailer\n<</Size %u>>\nstartxref\n%u\n%%%%EOF\n",
+                     first_label, first_xref_offset);
+    pdf_out(pdf_output_handle, format_buffer, length);
+
+    if (pdf_output_file_position != file_length)
+        _tt_abort("Internal error: linearized file length is %u, not %u.",
+                  pdf_output_file_position, file_length);
+
+    free(first.labels);
+    free(rest.labels);
+    free(shared.labels);
+    free(used.labels);
+    free(used_start);
+    free(page_count);
+    free(private_start);
+    free(trailer.data);
+    free(trailer.refs);
+    free(trailer.out);
+    free(hint.data);
+    lin_init();
+}
+
 #define BINARY_MARKER "%\344\360\355\370\n"
 void
 pdf_out_init (const char *filename, bool do_encryption, bool enable_object_stream)
@@ -325,7 +1086,12 @@ pdf_out_init (const char *filename, bool do_encryption, bool enable_object_strea
     add_xref_entry(0, 0, 0, 0xffff);
     next_label = 1;
 
-    if (pdf_version >= 5) {
+    if (linearize_mode) {
+        lin_init();
+        xref_stream = NULL;
+        trailer_dict = pdf_new_dict();
+        do_objstm = 0;
+    } else if (pdf_version >= 5) {
         if (enable_object_stream) {
             xref_stream = pdf_new_stream(STREAM_COMPRESS);
             xref_stream->flags |= OBJ_NO_ENCRYPT;
@@ -452,7 +1218,11 @@ dump_xref_stream (void)
 void
 pdf_out_flush (void)
 {
-    if (pdf_output_handle) {
+    if (pdf_output_handle && linearize_mode) {
+        lin_write_file();
+        ttstub_output_close(pdf_output_handle);
+        pdf_output_handle = NULL;
+    } else if (pdf_output_handle) {
         int length;
 
         /* Flush current object stream */
@@ -733,6 +1503,8 @@ write_indirect (pdf_indirect *indirect, rust_output_handle_t handle)
     assert(!indirect->pf);
 
     length = sprintf(format_buffer, "%u %hu R", indirect->label, indirect->generation);
+    if (lin_current && handle == pdf_output_handle)
+        lin_note_ref(indirect->label, length);
     pdf_out(handle, format_buffer, length);
 }
 
@@ -2464,6 +3236,8 @@ pdf_write_obj (pdf_obj *object, rust_output_handle_t handle)
         write_array(object->data, handle);
         break;
     case PDF_DICT:
+        if (pdfa_mode)
+            pdfa_check_dict(object);
         write_dict (object->data, handle);
         break;
     case PDF_STREAM:
@@ -2484,6 +3258,11 @@ pdf_flush_obj (pdf_obj *object, rust_output_handle_t handle)
 {
     int length;
 
+    if (linearize_mode) {
+        lin_capture_obj(object);
+        return;
+    }
+
     /*
      * Record file position
      */
@@ -62,6 +62,14 @@ void     pdf_out_init      (const char *filename,
 void     pdf_out_flush     (void);
 void     pdf_set_version   (unsigned version);
 unsigned pdf_get_version   (void);
+void     pdf_set_pdfa      (int enable);
+int      pdf_get_pdfa      (void);
+void     pdf_set_reproducible (int enable);
+int      pdf_get_reproducible (void);
+void     pdf_set_linearize (int enable);
+int      pdf_get_linearize (void);
+void     pdf_linearize_add_page      (pdf_obj *page_ref);
+void     pdf_linearize_add_page_node (pdf_obj *node_ref);
 
 void     pdf_release_obj (pdf_obj *object);
 int      pdf_obj_typeof  (pdf_obj *object);
@@ -130,7 +130,8 @@ spc_handler_pdfm__init (void *dp)
   static const char *default_taintkeys[] = {
     "Title",   "Author",   "Subject", "Keywords",
     "Creator", "Producer", "Contents", "Subj",
-    "TU",      "T",        "TM",        NULL /* EOD */
+    "TU",      "T",        "TM",      "Alt",
+    "ActualText", "E",     NULL /* EOD */
   };
   int  i;
 
@@ -671,6 +672,62 @@ spc_handler_pdfm_eann (struct spc_env *spe, struct spc_arg *args)
 }
 
 
+/* Tagged PDF: "pdf:btag /Type <<attributes>>" begins an element of the
+ * structure tree and "pdf:etag" ends it. Attributes may include /Alt text
+ * for figures, /ActualText, /Lang, and so on.
+ */
+static int
+spc_handler_pdfm_btag (struct spc_env *spe, struct spc_arg *args)
+{
+  struct spc_pdf_ *sd = &_pdf_stat;
+  pdf_obj *tag, *attrib = NULL;
+
+  skip_white(&args->curptr, args->endptr);
+
+  tag = parse_pdf_object(&args->curptr, args->endptr, NULL);
+  if (!tag) {
+    spc_warn(spe, "Missing structure type for pdf:btag.");
+    return  -1;
+  } else if (!PDF_OBJ_NAMETYPE(tag)) {
+    spc_warn(spe, "Invalid structure type for pdf:btag: not a name object.");
+    pdf_release_obj(tag);
+    return  -1;
+  }
+
+  skip_white(&args->curptr, args->endptr);
+
+  if (args->curptr < args->endptr) {
+    attrib = parse_pdf_dict_with_tounicode(&args->curptr, args->endptr, &sd->cd);
+    if (!attrib) {
+      spc_warn(spe, "Ignoring structure element with invalid attribute dictionary.");
+      pdf_release_obj(tag);
+      return  -1;
+    } else if (!PDF_OBJ_DICTTYPE(attrib)) {
+      spc_warn(spe, "Invalid type: not a dictionary object.");
+      pdf_release_obj(attrib);
+      pdf_release_obj(tag);
+      return  -1;
+    }
+  }
+
+  pdf_doc_begin_struct(pdf_name_value(tag), attrib);
+  pdf_release_obj(tag);
+
+  return  0;
+}
+
+static int
+spc_handler_pdfm_etag (struct spc_env *spe, struct spc_arg *args)
+{
+  if (pdf_doc_end_struct() < 0) {
+    spc_warn(spe, "Tried to end a structure element without starting one!");
+    return  -1;
+  }
+
+  return  0;
+}
+
+
 /* Color:.... */
 static int
 spc_handler_pdfm_bcolor (struct spc_env *spe, struct spc_arg *ap)
@@ -1937,6 +1994,12 @@ static struct spc_handler pdfm_handlers[] = {
   {"endann",      spc_handler_pdfm_eann},
   {"eann",        spc_handler_pdfm_eann},
 
+  {"begintag",    spc_handler_pdfm_btag},
+  {"btag",        spc_handler_pdfm_btag},
+
+  {"endtag",      spc_handler_pdfm_etag},
+  {"etag",        spc_handler_pdfm_etag},
+
   {"btrans",         spc_handler_pdfm_btrans},
   {"begintransform", spc_handler_pdfm_btrans},
   {"begintrans",     spc_handler_pdfm_btrans},
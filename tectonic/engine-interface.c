@@ -15,8 +15,15 @@ tt_set_int_variable (char *var_name, int value)
 {
     if (STREQ (var_name, "halt_on_error_p"))
         halt_on_error_p = value;
+    else if (STREQ (var_name, "interaction_mode")) {
+        if (value < BATCH_MODE || value > ERROR_STOP_MODE)
+            return 1;
+        default_interaction_mode = value;
+    }
     else if (STREQ (var_name, "in_initex_mode"))
         in_initex_mode = (value != 0);
+    else if (STREQ (var_name, "initex_preload_format"))
+        initex_preload_format = (value != 0);
     else if (STREQ (var_name, "synctex_enabled"))
         synctex_enabled = (value != 0);
     else
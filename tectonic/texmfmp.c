@@ -10,6 +10,7 @@
 #include <tectonic/core-bridge.h>
 #include <tectonic/xetexd.h>
 #include <tectonic/XeTeX_ext.h>
+#include <tectonic/dpx-pdfobj.h> /* get_unique_time_if_given */
 
 #include <time.h> /* For `struct tm'.  Moved here for Visual Studio 2005.  */
 
@@ -23,8 +24,18 @@ get_date_and_time (integer *minutes,  integer *day,
 {
   struct tm *tmptr;
 
-  time_t myclock = time ((time_t *) 0);
-  tmptr = localtime (&myclock);
+  /* As in xdvipdfmx, honor SOURCE_DATE_EPOCH for reproducible builds. It
+   * is interpreted as UTC, not local time, so that the results don't depend
+   * on the time zone either. */
+  time_t myclock = get_unique_time_if_given ();
+
+  if (myclock == INVALID_EPOCH_VALUE) {
+    myclock = time ((time_t *) 0);
+    tmptr = localtime (&myclock);
+  } else {
+    tmptr = gmtime (&myclock);
+  }
+
   *minutes = tmptr->tm_hour * 60 + tmptr->tm_min;
   *day = tmptr->tm_mday;
   *month = tmptr->tm_mon + 1;
@@ -239,3 +250,40 @@ void getmd5sum(str_number s, bool file)
     for (i = 0; i < 2 * DIGEST_SIZE; i++)
         str_pool[pool_ptr++] = (uint16_t)outbuf[i];
 }
+
+/* The modification time of a file, as a PDF date string, for \filemoddate.
+ * As in pdfTeX, nothing is produced if the file can't be found. The time is
+ * given in UTC, so that the result doesn't depend on the time zone. */
+
+#define PDF_DATE_SIZE 17 /* "D:YYYYMMDDHHMMSSZ" */
+
+void getfilemoddate(str_number s)
+{
+    char outbuf[PDF_DATE_SIZE + 1];
+    char *xname;
+    time_t mtime;
+    struct tm *tmptr;
+    int ret, i, len;
+
+    xname = gettexstring (s);
+    ret = ttstub_get_file_mtime (xname, &mtime);
+    xfree (xname);
+    if (ret)
+        return;
+
+    tmptr = gmtime (&mtime);
+    if (tmptr == NULL)
+        return;
+
+    len = strftime (outbuf, sizeof (outbuf), "D:%Y%m%d%H%M%SZ", tmptr);
+    if (len <= 0)
+        return;
+
+    if (pool_ptr + len >= pool_size) {
+        /* error by str_toks that calls str_room(1) */
+        return;
+    }
+
+    for (i = 0; i < len; i++)
+        str_pool[pool_ptr++] = (uint16_t)outbuf[i];
+}
@@ -0,0 +1,2009 @@
+/* tectonic/xdvisvg.c: converting XDV files to SVG
+   Copyright 2017 the Tectonic Project
+   Licensed under the MIT License.
+*/
+
+/* This turns each page of an XDV (or plain DVI) file into a standalone SVG
+ * document. Glyphs are drawn from their outlines, which we get from
+ * FreeType: each page defines the glyphs that it uses as paths and places
+ * them with <use> elements. Alternatively, glyphs from OpenType and
+ * TrueType fonts can be drawn as <text>, with the fonts converted to WOFF
+ * files that the pages refer to.
+ *
+ * The same machinery can instead rasterize each page into a PNG image,
+ * again with FreeType, for previews and thumbnails, or pull out the
+ * document's text as plain Unicode.
+ *
+ * Traditional TeX fonts are handled through pdftex.map, just like
+ * xdvipdfmx does, so they need Type 1 versions. Virtual fonts, images, and
+ * most specials aren't supported; we only know about colors and the paper
+ * size.
+ */
+
+#include <tectonic/core-bridge.h>
+#include <tectonic/dpx-agl.h>
+#include <tectonic/dpx-dpxconf.h>
+#include <tectonic/dpx-dpxfile.h>
+#include <tectonic/dpx-dvicodes.h>
+#include <tectonic/dpx-error.h>
+#include <tectonic/dpx-fontmap.h>
+#include <tectonic/dpx-mem.h>
+#include <tectonic/dpx-numbers.h>
+#include <tectonic/dpx-pdfencoding.h>
+#include <tectonic/dpx-tfm.h>
+#include <tectonic/xdvisvg.h>
+
+#include <ctype.h>
+#include <stdarg.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <zlib.h>
+
+#include <ft2build.h>
+#include FT_FREETYPE_H
+#include FT_OUTLINE_H
+
+
+#define XDV_FLAG_VERTICAL  0x0100
+#define XDV_FLAG_COLORED   0x0200
+#define XDV_FLAG_EXTEND    0x1000
+#define XDV_FLAG_SLANT     0x2000
+#define XDV_FLAG_EMBOLDEN  0x4000
+
+#define DVI_STACK_SIZE   256
+#define COLOR_STACK_SIZE 128
+#define COLOR_LEN        8 /* "#rrggbb" */
+#define MAX_RASTER_SIZE  30000 /* pixels along either side of a PNG page */
+
+
+/* Growable text buffers */
+
+typedef struct svg_buf {
+    char   *data;
+    size_t  len;
+    size_t  cap;
+} svg_buf;
+
+static void
+buf_append (svg_buf *b, const char *s, size_t n)
+{
+    if (b->len + n + 1 > b->cap) {
+        b->cap = (b->len + n + 1) * 2;
+        b->data = RENEW(b->data, b->cap, char);
+    }
+
+    memcpy(b->data + b->len, s, n);
+    b->len += n;
+    b->data[b->len] = '\0';
+}
+
+static void
+buf_puts (svg_buf *b, const char *s)
+{
+    buf_append(b, s, strlen(s));
+}
+
+PRINTF_FUNC(2,3) static void
+buf_printf (svg_buf *b, const char *format, ...)
+{
+    char tmp[512];
+    va_list ap;
+    int n;
+
+    va_start(ap, format);
+    n = vsnprintf(tmp, sizeof(tmp), format, ap);
+    va_end(ap);
+
+    if (n < 0)
+        return;
+
+    if ((size_t) n >= sizeof(tmp)) {
+        char *big = NEW(n + 1, char);
+
+        va_start(ap, format);
+        vsnprintf(big, n + 1, format, ap);
+        va_end(ap);
+        buf_append(b, big, n);
+        free(big);
+    } else {
+        buf_append(b, tmp, n);
+    }
+}
+
+/* Numbers are written with at most three decimals, which is plenty at the
+ * scale of big points, and without trailing zeros. */
+static void
+buf_num (svg_buf *b, double v)
+{
+    char tmp[64];
+    char *p;
+
+    snprintf(tmp, sizeof(tmp), "%.3f", v);
+    p = tmp + strlen(tmp) - 1;
+
+    while (*p == '0')
+        *p-- = '\0';
+    if (*p == '.')
+        *p = '\0';
+
+    buf_puts(b, strcmp(tmp, "-0") ? tmp : "0");
+}
+
+static void
+buf_utf8 (svg_buf *b, uint32_t c)
+{
+    char tmp[4];
+
+    switch (c) {
+    case '&': buf_puts(b, "&amp;"); return;
+    case '<': buf_puts(b, "&lt;"); return;
+    case '>': buf_puts(b, "&gt;"); return;
+    }
+
+    if (c < 0x80) {
+        tmp[0] = (char) c;
+        buf_append(b, tmp, 1);
+    } else if (c < 0x800) {
+        tmp[0] = (char) (0xC0 | (c >> 6));
+        tmp[1] = (char) (0x80 | (c & 0x3F));
+        buf_append(b, tmp, 2);
+    } else if (c < 0x10000) {
+        tmp[0] = (char) (0xE0 | (c >> 12));
+        tmp[1] = (char) (0x80 | ((c >> 6) & 0x3F));
+        tmp[2] = (char) (0x80 | (c & 0x3F));
+        buf_append(b, tmp, 3);
+    } else {
+        tmp[0] = (char) (0xF0 | (c >> 18));
+        tmp[1] = (char) (0x80 | ((c >> 12) & 0x3F));
+        tmp[2] = (char) (0x80 | ((c >> 6) & 0x3F));
+        tmp[3] = (char) (0x80 | (c & 0x3F));
+        buf_append(b, tmp, 4);
+    }
+}
+
+
+/* Fonts */
+
+typedef struct svg_font {
+    int32_t         tex_id;
+    int             native;
+    char           *name;     /* the TFM name, or the font file for native fonts */
+    int32_t         size;     /* in DVI units */
+    int             tfm_id;   /* TFM fonts only */
+    int             enc_id;   /* TFM fonts only; -1 for the font's own encoding */
+    uint32_t        rgba;     /* native fonts only */
+    double          extend;
+    double          slant;
+    FT_Face         face;     /* NULL if we have no outlines */
+    unsigned char  *data;     /* the font file, which backs `face` */
+    size_t          data_len;
+    int             is_sfnt;
+    int             woff_written;
+    FT_ULong       *unicodes; /* maps glyphs to characters, for <text> */
+    char          **paths;    /* the outline of each glyph, once computed */
+    unsigned char  *used;     /* the glyphs used on the current page */
+    int             text_used;
+} svg_font;
+
+static struct {
+    const unsigned char *dvi;
+    size_t               dvi_len;
+    size_t               pos;
+
+    double               conv;   /* big points per DVI unit */
+    double               paper_width;
+    double               paper_height;
+    const char          *stem;
+    int                  woff_fonts;
+    int                  png_dpi; /* nonzero if we're making PNGs */
+    int                  skimming; /* looking for the paper size; see do_page() */
+    int                  text_output; /* nonzero if we're extracting text */
+
+    FT_Library           ft;
+    svg_font            *fonts;
+    int                  num_fonts;
+    int                  max_fonts;
+    int                  cur_font;
+
+    int32_t              h, v, w, x, y, z;
+    int32_t              stack[DVI_STACK_SIZE][6];
+    int                  stack_depth;
+
+    char                 colors[COLOR_STACK_SIZE][COLOR_LEN];
+    int                  color_depth;
+
+    svg_buf              body;
+    unsigned char       *raster; /* RGB pixels of the current PNG page */
+    int                  raster_width;
+    int                  raster_height;
+    int                  page_no;
+
+    svg_buf              text;   /* the extracted text of all pages */
+    int                  text_on_page;
+    int32_t              text_h; /* where the last piece of text ended */
+    int32_t              text_v;
+    int32_t              text_size;
+} svg;
+
+
+static unsigned char
+dvi_byte (void)
+{
+    if (svg.pos >= svg.dvi_len)
+        _tt_abort("XDV file ended prematurely");
+    return svg.dvi[svg.pos++];
+}
+
+static uint32_t
+dvi_unsigned (int n)
+{
+    uint32_t v = 0;
+
+    while (n-- > 0)
+        v = (v << 8) | dvi_byte();
+    return v;
+}
+
+static int32_t
+dvi_signed (int n)
+{
+    uint32_t v = dvi_unsigned(n);
+
+    if (n < 4 && (v & (1u << (8 * n - 1))))
+        v |= ~0u << (8 * n);
+    return (int32_t) v;
+}
+
+static void
+dvi_skip (size_t n)
+{
+    if (svg.pos + n > svg.dvi_len)
+        _tt_abort("XDV file ended prematurely");
+    svg.pos += n;
+}
+
+
+static svg_font *
+find_font (int32_t tex_id)
+{
+    int i;
+
+    for (i = 0; i < svg.num_fonts; i++) {
+        if (svg.fonts[i].tex_id == tex_id)
+            return &svg.fonts[i];
+    }
+
+    return NULL;
+}
+
+static svg_font *
+new_font (int32_t tex_id)
+{
+    svg_font *font;
+
+    if (svg.num_fonts >= svg.max_fonts) {
+        svg.max_fonts += 16;
+        svg.fonts = RENEW(svg.fonts, svg.max_fonts, svg_font);
+    }
+
+    font = &svg.fonts[svg.num_fonts++];
+    memset(font, 0, sizeof(svg_font));
+    font->tex_id = tex_id;
+    font->tfm_id = -1;
+    font->enc_id = -1;
+    font->rgba = 0xffffffff;
+    font->extend = 1.0;
+    font->slant = 0.0;
+    return font;
+}
+
+/* Read a whole font file into memory and give it to FreeType. */
+static int
+load_face (svg_font *font, rust_input_handle_t handle, int index)
+{
+    size_t len = ttstub_input_get_size(handle);
+
+    font->data = NEW(len, unsigned char);
+    ttstub_input_seek(handle, 0, SEEK_SET);
+
+    if (ttstub_input_read(handle, (char *) font->data, len) != (ssize_t) len) {
+        ttstub_input_close(handle);
+        return -1;
+    }
+
+    ttstub_input_close(handle);
+    font->data_len = len;
+
+    if (FT_New_Memory_Face(svg.ft, font->data, (FT_Long) len, index, &font->face)) {
+        font->face = NULL;
+        return -1;
+    }
+
+    font->is_sfnt = FT_IS_SFNT(font->face) && len >= 4 && memcmp(font->data, "ttcf", 4);
+    font->paths = NEW(font->face->num_glyphs, char *);
+    memset(font->paths, 0, font->face->num_glyphs * sizeof(char *));
+    font->used = NEW(font->face->num_glyphs, unsigned char);
+    memset(font->used, 0, font->face->num_glyphs);
+    return 0;
+}
+
+static void
+define_tfm_font (int32_t tex_id)
+{
+    svg_font *font;
+    fontmap_rec *mrec;
+    rust_input_handle_t handle = NULL;
+    const char *file;
+    int32_t size;
+    int len;
+    size_t start;
+
+    dvi_unsigned(4); /* checksum */
+    size = dvi_signed(4);
+    dvi_signed(4); /* design size */
+    len = dvi_byte();
+    len += dvi_byte();
+
+    if (find_font(tex_id)) {
+        dvi_skip(len);
+        return;
+    }
+
+    start = svg.pos;
+    dvi_skip(len); /* checks that the name is all there */
+    font = new_font(tex_id);
+    font->size = size;
+    font->name = NEW(len + 1, char);
+    memcpy(font->name, svg.dvi + start, len);
+    font->name[len] = '\0';
+
+    font->tfm_id = tfm_open(font->name, 1);
+    mrec = pdf_lookup_fontmap_record(font->name);
+
+    if (mrec && mrec->charmap.sfd_name) {
+        dpx_warning("Subfont \"%s\" isn't supported in SVG output; its characters are left out.", font->name);
+        return;
+    }
+
+    file = (mrec && mrec->font_name) ? mrec->font_name : font->name;
+
+    if ((handle = dpx_open_type1_file(file)) == NULL &&
+        (handle = dpx_open_truetype_file(file)) == NULL &&
+        (handle = dpx_open_opentype_file(file)) == NULL) {
+        dpx_warning("No outline font found for \"%s\"; its characters are left out of the SVG output.",
+                    font->name);
+        return;
+    }
+
+    if (load_face(font, handle, mrec ? mrec->opt.index : 0) < 0) {
+        dpx_warning("Failed to load the font \"%s\"; its characters are left out of the SVG output.", file);
+        return;
+    }
+
+    if (mrec) {
+        font->extend = mrec->opt.extend;
+        font->slant = mrec->opt.slant;
+
+        if (mrec->enc_name)
+            font->enc_id = pdf_encoding_findresource(mrec->enc_name);
+    }
+
+    if (font->enc_id < 0 && FT_Select_Charmap(font->face, FT_ENCODING_ADOBE_CUSTOM) != 0)
+        FT_Select_Charmap(font->face, FT_ENCODING_ADOBE_STANDARD);
+}
+
+static void
+define_native_font (int32_t tex_id)
+{
+    svg_font *font;
+    rust_input_handle_t handle;
+    unsigned int flags;
+    uint32_t index;
+    int32_t size;
+    char *name;
+    int len;
+    size_t start;
+
+    size = dvi_signed(4);
+    flags = dvi_unsigned(2);
+    len = dvi_byte();
+    start = svg.pos;
+    dvi_skip(len); /* checks that the name is all there */
+    name = NEW(len + 1, char);
+    memcpy(name, svg.dvi + start, len);
+    name[len] = '\0';
+    index = dvi_unsigned(4);
+
+    if (find_font(tex_id)) {
+        free(name);
+        if (flags & XDV_FLAG_COLORED)
+            dvi_skip(4);
+        if (flags & XDV_FLAG_EXTEND)
+            dvi_skip(4);
+        if (flags & XDV_FLAG_SLANT)
+            dvi_skip(4);
+        if (flags & XDV_FLAG_EMBOLDEN)
+            dvi_skip(4);
+        return;
+    }
+
+    font = new_font(tex_id);
+    font->native = 1;
+    font->name = name;
+    font->size = size;
+
+    if (flags & XDV_FLAG_COLORED)
+        font->rgba = dvi_unsigned(4);
+    if (flags & XDV_FLAG_EXTEND)
+        font->extend = dvi_signed(4) / 65536.0;
+    if (flags & XDV_FLAG_SLANT)
+        font->slant = dvi_signed(4) / 65536.0;
+    if (flags & XDV_FLAG_EMBOLDEN)
+        dvi_signed(4); /* not supported */
+
+    if ((handle = dpx_open_opentype_file(name)) == NULL &&
+        (handle = dpx_open_truetype_file(name)) == NULL &&
+        (handle = dpx_open_type1_file(name)) == NULL)
+        _tt_abort("Cannot proceed without the font: %s", name);
+
+    if (load_face(font, handle, index) < 0)
+        _tt_abort("Failed to load the font: %s", name);
+
+    /* For drawing text, we need to know which character each glyph stands
+     * for. We take the first one that the font maps to it. */
+    if ((svg.text_output || (svg.woff_fonts && font->is_sfnt)) &&
+        FT_Select_Charmap(font->face, FT_ENCODING_UNICODE) == 0) {
+        FT_ULong c;
+        FT_UInt gid;
+
+        font->unicodes = NEW(font->face->num_glyphs, FT_ULong);
+        memset(font->unicodes, 0, font->face->num_glyphs * sizeof(FT_ULong));
+
+        for (c = FT_Get_First_Char(font->face, &gid); gid != 0; c = FT_Get_Next_Char(font->face, c, &gid)) {
+            if (gid < (FT_UInt) font->face->num_glyphs && font->unicodes[gid] == 0)
+                font->unicodes[gid] = c;
+        }
+    }
+}
+
+static void
+free_fonts (void)
+{
+    int i;
+    FT_Long j;
+
+    for (i = 0; i < svg.num_fonts; i++) {
+        svg_font *font = &svg.fonts[i];
+
+        if (font->face) {
+            for (j = 0; j < font->face->num_glyphs; j++)
+                free(font->paths[j]);
+            FT_Done_Face(font->face);
+        }
+
+        free(font->paths);
+        free(font->used);
+        free(font->unicodes);
+        free(font->data);
+        free(font->name);
+    }
+
+    free(svg.fonts);
+    svg.fonts = NULL;
+    svg.num_fonts = svg.max_fonts = 0;
+}
+
+
+/* Glyph outlines */
+
+typedef struct path_ctx {
+    svg_buf *b;
+    double   xx, xy, yy; /* font units to big points */
+    int      started;
+} path_ctx;
+
+static void
+path_point (path_ctx *ctx, const FT_Vector *p)
+{
+    buf_num(ctx->b, ctx->xx * p->x + ctx->xy * p->y);
+    buf_puts(ctx->b, " ");
+    buf_num(ctx->b, -ctx->yy * p->y);
+}
+
+static int
+path_move_to (const FT_Vector *to, void *user)
+{
+    path_ctx *ctx = user;
+
+    buf_puts(ctx->b, ctx->started ? "ZM" : "M");
+    path_point(ctx, to);
+    ctx->started = 1;
+    return 0;
+}
+
+static int
+path_line_to (const FT_Vector *to, void *user)
+{
+    path_ctx *ctx = user;
+
+    buf_puts(ctx->b, "L");
+    path_point(ctx, to);
+    return 0;
+}
+
+static int
+path_conic_to (const FT_Vector *control, const FT_Vector *to, void *user)
+{
+    path_ctx *ctx = user;
+
+    buf_puts(ctx->b, "Q");
+    path_point(ctx, control);
+    buf_puts(ctx->b, " ");
+    path_point(ctx, to);
+    return 0;
+}
+
+static int
+path_cubic_to (const FT_Vector *control1, const FT_Vector *control2, const FT_Vector *to, void *user)
+{
+    path_ctx *ctx = user;
+
+    buf_puts(ctx->b, "C");
+    path_point(ctx, control1);
+    buf_puts(ctx->b, " ");
+    path_point(ctx, control2);
+    buf_puts(ctx->b, " ");
+    path_point(ctx, to);
+    return 0;
+}
+
+static const FT_Outline_Funcs path_funcs = {
+    path_move_to,
+    path_line_to,
+    path_conic_to,
+    path_cubic_to,
+    0,
+    0
+};
+
+/* Get the outline of a glyph as SVG path data, relative to its origin. */
+static const char *
+glyph_path (svg_font *font, FT_UInt gid)
+{
+    svg_buf b = { NULL, 0, 0 };
+    path_ctx ctx;
+    double scale;
+
+    if (font->paths[gid])
+        return font->paths[gid];
+
+    if (FT_Load_Glyph(font->face, gid, FT_LOAD_NO_SCALE) != 0 ||
+        font->face->glyph->format != FT_GLYPH_FORMAT_OUTLINE) {
+        font->paths[gid] = NEW(1, char);
+        font->paths[gid][0] = '\0';
+        return font->paths[gid];
+    }
+
+    scale = font->size * svg.conv / (font->face->units_per_EM ? font->face->units_per_EM : 1000);
+    ctx.b = &b;
+    ctx.xx = scale * font->extend;
+    ctx.xy = scale * font->slant;
+    ctx.yy = scale;
+    ctx.started = 0;
+
+    buf_puts(&b, "");
+    FT_Outline_Decompose(&font->face->glyph->outline, &path_funcs, &ctx);
+    if (ctx.started)
+        buf_puts(&b, "Z");
+
+    font->paths[gid] = b.data;
+    return b.data;
+}
+
+
+/* WOFF conversion, for fonts that are referenced rather than turned into
+ * paths. A WOFF file is just the tables of the sfnt, compressed one by
+ * one. */
+
+static uint32_t
+sfnt_ulong (const unsigned char *p)
+{
+    return ((uint32_t) p[0] << 24) | ((uint32_t) p[1] << 16) | ((uint32_t) p[2] << 8) | p[3];
+}
+
+static void
+put_ulong (unsigned char *p, uint32_t v)
+{
+    p[0] = (v >> 24) & 0xff;
+    p[1] = (v >> 16) & 0xff;
+    p[2] = (v >> 8) & 0xff;
+    p[3] = v & 0xff;
+}
+
+static int
+compare_tags (const void *a, const void *b)
+{
+    return memcmp(*(const unsigned char * const *) a, *(const unsigned char * const *) b, 4);
+}
+
+static void
+font_woff_name (svg_font *font, char *buf, size_t len, int basename)
+{
+    const char *stem = svg.stem;
+    const char *slash;
+
+    if (basename && (slash = strrchr(stem, '/')) != NULL)
+        stem = slash + 1;
+
+    snprintf(buf, len, "%s-f%d.woff", stem, (int) (font - svg.fonts));
+}
+
+static int
+write_woff (svg_font *font)
+{
+    const unsigned char *sfnt = font->data;
+    const unsigned char **entries;
+    unsigned char header[44], entry[20];
+    svg_buf tables = { NULL, 0, 0 };
+    svg_buf dir = { NULL, 0, 0 };
+    rust_output_handle_t handle;
+    uint32_t total_sfnt_size;
+    char name[1024];
+    int num_tables, i;
+
+    if (font->data_len < 12)
+        return -1;
+
+    num_tables = (sfnt[4] << 8) | sfnt[5];
+    if (font->data_len < 12 + 16 * (size_t) num_tables)
+        return -1;
+
+    entries = NEW(num_tables, const unsigned char *);
+    for (i = 0; i < num_tables; i++)
+        entries[i] = sfnt + 12 + 16 * i;
+    qsort(entries, num_tables, sizeof(const unsigned char *), compare_tags);
+
+    total_sfnt_size = 12 + 16 * num_tables;
+    buf_puts(&tables, "");
+
+    for (i = 0; i < num_tables; i++) {
+        uint32_t offset = sfnt_ulong(entries[i] + 8);
+        uint32_t length = sfnt_ulong(entries[i] + 12);
+        uLongf comp_len;
+        unsigned char *comp;
+
+        if ((size_t) offset + length > font->data_len) {
+            free(entries);
+            free(tables.data);
+            free(dir.data);
+            return -1;
+        }
+
+        comp_len = compressBound(length);
+        comp = NEW(comp_len, unsigned char);
+
+        if (compress2(comp, &comp_len, sfnt + offset, length, Z_BEST_COMPRESSION) != Z_OK || comp_len >= length) {
+            memcpy(comp, sfnt + offset, length);
+            comp_len = length;
+        }
+
+        memcpy(entry, entries[i], 4);
+        put_ulong(entry + 4, 44 + 20 * num_tables + tables.len);
+        put_ulong(entry + 8, comp_len);
+        put_ulong(entry + 12, length);
+        memcpy(entry + 16, entries[i] + 4, 4);
+        buf_append(&dir, (char *) entry, 20);
+
+        buf_append(&tables, (char *) comp, comp_len);
+        while (tables.len % 4)
+            buf_append(&tables, "\0", 1);
+        free(comp);
+
+        total_sfnt_size += (length + 3) & ~3u;
+    }
+
+    memset(header, 0, sizeof(header));
+    memcpy(header, "wOFF", 4);
+    memcpy(header + 4, sfnt, 4);
+    put_ulong(header + 8, 44 + dir.len + tables.len);
+    header[12] = (num_tables >> 8) & 0xff;
+    header[13] = num_tables & 0xff;
+    put_ulong(header + 16, total_sfnt_size);
+
+    font_woff_name(font, name, sizeof(name), 0);
+    handle = ttstub_output_open(name, 0);
+    if (handle == NULL)
+        _tt_abort("Unable to open \"%s\".", name);
+
+    ttstub_output_write(handle, (char *) header, sizeof(header));
+    ttstub_output_write(handle, dir.data, dir.len);
+    ttstub_output_write(handle, tables.data, tables.len);
+    ttstub_output_close(handle);
+
+    free(entries);
+    free(tables.data);
+    free(dir.data);
+    return 0;
+}
+
+/* Whether glyphs of this font can be drawn as text. */
+static int
+font_uses_text (svg_font *font)
+{
+    if (!font->unicodes || font->extend != 1.0 || font->slant != 0.0)
+        return 0;
+
+    if (!font->woff_written) {
+        if (write_woff(font) < 0) {
+            dpx_warning("Failed to convert \"%s\" to WOFF; drawing its glyphs as paths.", font->name);
+            free(font->unicodes);
+            font->unicodes = NULL;
+            return 0;
+        }
+
+        font->woff_written = 1;
+    }
+
+    return 1;
+}
+
+
+/* Colors */
+
+static const char *
+cur_color (void)
+{
+    return svg.color_depth > 0 ? svg.colors[svg.color_depth - 1] : "#000000";
+}
+
+static void
+put_fill (void)
+{
+    const char *color = cur_color();
+
+    if (strcmp(color, "#000000"))
+        buf_printf(&svg.body, " fill=\"%s\"", color);
+}
+
+static int
+color_component (double v)
+{
+    if (v < 0.0)
+        v = 0.0;
+    if (v > 1.0)
+        v = 1.0;
+    return (int) (v * 255.0 + 0.5);
+}
+
+static void
+format_color (char *dest, double r, double g, double b)
+{
+    snprintf(dest, COLOR_LEN, "#%02x%02x%02x", color_component(r), color_component(g), color_component(b));
+}
+
+/* Parse up to `max` numbers, stopping at anything else. */
+static int
+read_numbers (const char **pp, const char *end, double *vals, int max)
+{
+    const char *p = *pp;
+    int n = 0;
+
+    while (n < max) {
+        char *q;
+        double v;
+
+        while (p < end && (isspace((unsigned char) *p) || *p == '['))
+            p++;
+        if (p >= end)
+            break;
+
+        v = strtod(p, &q);
+        if (q == p || q > end)
+            break;
+
+        vals[n++] = v;
+        p = q;
+    }
+
+    *pp = p;
+    return n;
+}
+
+/* Parse a color given as numbers, whose count determines the color space,
+ * as in `pdf:bc [1 0 0]`. */
+static int
+parse_color_numbers (const char *p, const char *end, char *dest)
+{
+    double vals[4];
+
+    switch (read_numbers(&p, end, vals, 4)) {
+    case 1:
+        format_color(dest, vals[0], vals[0], vals[0]);
+        return 0;
+    case 3:
+        format_color(dest, vals[0], vals[1], vals[2]);
+        return 0;
+    case 4:
+        format_color(dest, (1 - vals[0]) * (1 - vals[3]), (1 - vals[1]) * (1 - vals[3]),
+                     (1 - vals[2]) * (1 - vals[3]));
+        return 0;
+    }
+
+    return -1;
+}
+
+/* Parse a dvips-style color, as in `color push rgb 1 0 0`. */
+static int
+parse_color_named (const char *p, const char *end, char *dest)
+{
+    static const struct {
+        const char *name;
+        const char *value;
+    } named[] = {
+        { "Black", "#000000" },
+        { "White", "#ffffff" },
+        { "Red", "#ff0000" },
+        { "Green", "#00ff00" },
+        { "Blue", "#0000ff" },
+        { "Cyan", "#00ffff" },
+        { "Magenta", "#ff00ff" },
+        { "Yellow", "#ffff00" },
+        { NULL, NULL }
+    };
+    size_t len;
+    int i;
+
+    while (p < end && isspace((unsigned char) *p))
+        p++;
+    for (len = 0; p + len < end && isalpha((unsigned char) p[len]); len++)
+        ;
+
+    if (len == 3 && !strncmp(p, "rgb", 3))
+        return parse_color_numbers(p + 3, end, dest);
+    if (len == 4 && !strncmp(p, "gray", 4))
+        return parse_color_numbers(p + 4, end, dest);
+    if (len == 4 && !strncmp(p, "cmyk", 4))
+        return parse_color_numbers(p + 4, end, dest);
+
+    for (i = 0; named[i].name; i++) {
+        if (strlen(named[i].name) == len && !strncmp(p, named[i].name, len)) {
+            strcpy(dest, named[i].value);
+            return 0;
+        }
+    }
+
+    return -1;
+}
+
+static void
+push_color (const char *color)
+{
+    if (svg.color_depth >= COLOR_STACK_SIZE) {
+        dpx_warning("Color stack overflow; ignoring color change.");
+        return;
+    }
+
+    strcpy(svg.colors[svg.color_depth++], color);
+}
+
+static void
+pop_color (void)
+{
+    if (svg.color_depth > 0)
+        svg.color_depth--;
+}
+
+
+/* Paper sizes */
+
+static int
+read_length (const char **pp, const char *end, double *value)
+{
+    const char *p = *pp;
+    char *q;
+    double v;
+
+    while (p < end && isspace((unsigned char) *p))
+        p++;
+
+    v = strtod(p, &q);
+    if (q == p || q > end)
+        return -1;
+    p = q;
+
+    while (p < end && isspace((unsigned char) *p))
+        p++;
+    if (end - p >= 4 && !strncmp(p, "true", 4))
+        p += 4;
+
+    if (end - p >= 2) {
+        if (!strncmp(p, "pt", 2))
+            v *= 72.0 / 72.27;
+        else if (!strncmp(p, "in", 2))
+            v *= 72.0;
+        else if (!strncmp(p, "cm", 2))
+            v *= 72.0 / 2.54;
+        else if (!strncmp(p, "mm", 2))
+            v *= 72.0 / 25.4;
+        else if (!strncmp(p, "pc", 2))
+            v *= 12.0 * 72.0 / 72.27;
+        else if (strncmp(p, "bp", 2))
+            return -1;
+        p += 2;
+    }
+
+    *pp = p;
+    *value = v;
+    return 0;
+}
+
+static int
+select_paper (const char *spec, const char *end)
+{
+    const struct paper *pi;
+    double width, height;
+    char name[64];
+    size_t len = end - spec;
+
+    if (len < sizeof(name)) {
+        memcpy(name, spec, len);
+        name[len] = '\0';
+        pi = paperinfo(name);
+
+        if (pi && papername(pi)) {
+            svg.paper_width = paperpswidth(pi);
+            svg.paper_height = paperpsheight(pi);
+            return 0;
+        }
+    }
+
+    if (read_length(&spec, end, &width) < 0)
+        return -1;
+    while (spec < end && isspace((unsigned char) *spec))
+        spec++;
+    if (spec >= end || *spec != ',')
+        return -1;
+    spec++;
+    if (read_length(&spec, end, &height) < 0 || width <= 0.0 || height <= 0.0)
+        return -1;
+
+    svg.paper_width = width;
+    svg.paper_height = height;
+    return 0;
+}
+
+/* Handle `pdf:pagesize width 10cm height 20cm`. */
+static void
+select_pagesize (const char *p, const char *end)
+{
+    double width = svg.paper_width, height = svg.paper_height;
+
+    for (;;) {
+        while (p < end && isspace((unsigned char) *p))
+            p++;
+
+        if (end - p >= 5 && !strncmp(p, "width", 5)) {
+            p += 5;
+            if (read_length(&p, end, &width) < 0)
+                return;
+        } else if (end - p >= 6 && !strncmp(p, "height", 6)) {
+            p += 6;
+            if (read_length(&p, end, &height) < 0)
+                return;
+        } else {
+            break;
+        }
+    }
+
+    if (width > 0.0 && height > 0.0) {
+        svg.paper_width = width;
+        svg.paper_height = height;
+    }
+}
+
+
+/* Specials */
+
+static int
+starts_with (const char **pp, const char *end, const char *prefix)
+{
+    size_t len = strlen(prefix);
+
+    if ((size_t) (end - *pp) < len || strncmp(*pp, prefix, len))
+        return 0;
+
+    *pp += len;
+    return 1;
+}
+
+static void
+do_special (const char *p, size_t len)
+{
+    const char *end = p + len;
+    char color[COLOR_LEN];
+
+    while (p < end && isspace((unsigned char) *p))
+        p++;
+
+    if (svg.skimming) {
+        if (starts_with(&p, end, "pdf:pagesize"))
+            select_pagesize(p, end);
+        else if (starts_with(&p, end, "papersize="))
+            select_paper(p, end);
+        return;
+    }
+
+    if (starts_with(&p, end, "color ")) {
+        while (p < end && isspace((unsigned char) *p))
+            p++;
+
+        if (starts_with(&p, end, "pop")) {
+            pop_color();
+        } else if (starts_with(&p, end, "push")) {
+            if (parse_color_named(p, end, color) == 0)
+                push_color(color);
+            else
+                push_color(cur_color());
+        } else if (parse_color_named(p, end, color) == 0) {
+            svg.color_depth = 0;
+            push_color(color);
+        }
+    } else if (starts_with(&p, end, "pdf:bcolor") || starts_with(&p, end, "pdf:bc")) {
+        if (parse_color_numbers(p, end, color) == 0)
+            push_color(color);
+        else
+            push_color(cur_color());
+    } else if (starts_with(&p, end, "pdf:ecolor") || starts_with(&p, end, "pdf:ec")) {
+        pop_color();
+    } else if (starts_with(&p, end, "pdf:pagesize")) {
+        select_pagesize(p, end);
+    } else if (starts_with(&p, end, "papersize=")) {
+        if (select_paper(p, end) < 0)
+            dpx_warning("Ignoring unrecognized paper size: %.*s", (int) (end - p), p);
+    }
+}
+
+
+/* Drawing */
+
+static double
+svg_x (int32_t h)
+{
+    return 72.0 + h * svg.conv;
+}
+
+static double
+svg_y (int32_t v)
+{
+    return 72.0 + v * svg.conv;
+}
+
+/* Rasterization */
+
+static void
+cur_color_rgb (unsigned char *rgb)
+{
+    unsigned int r = 0, g = 0, b = 0;
+
+    sscanf(cur_color(), "#%02x%02x%02x", &r, &g, &b);
+    rgb[0] = r;
+    rgb[1] = g;
+    rgb[2] = b;
+}
+
+/* Paint the current color onto pixel (x, y) of the page, with the given
+ * coverage out of 255. */
+static void
+blend_pixel (int x, int y, const unsigned char *rgb, unsigned int coverage)
+{
+    unsigned char *p;
+    int i;
+
+    if (x < 0 || y < 0 || x >= svg.raster_width || y >= svg.raster_height || coverage == 0)
+        return;
+
+    p = svg.raster + 3 * ((size_t) y * svg.raster_width + x);
+
+    for (i = 0; i < 3; i++)
+        p[i] = (unsigned char) ((p[i] * (255 - coverage) + rgb[i] * coverage + 127) / 255);
+}
+
+static void
+raster_glyph (svg_font *font, FT_UInt gid, double x, double y)
+{
+    FT_Outline *outline;
+    FT_Matrix matrix;
+    FT_BBox cbox;
+    FT_Bitmap bitmap;
+    unsigned char rgb[3];
+    double scale, px, py;
+    int x0, y0, bx0, by0, bx1, by1, r, c;
+
+    if (FT_Load_Glyph(font->face, gid, FT_LOAD_NO_SCALE) != 0 ||
+        font->face->glyph->format != FT_GLYPH_FORMAT_OUTLINE)
+        return;
+
+    /* Scale the outline from font units to 26.6 pixels, and shift it by the
+     * fractional part of its position. FreeType's y axis points up. */
+    outline = &font->face->glyph->outline;
+    scale = font->size * svg.conv * svg.png_dpi / 72.0 * 64.0 /
+        (font->face->units_per_EM ? font->face->units_per_EM : 1000);
+    matrix.xx = (FT_Fixed) (scale * font->extend * 65536.0);
+    matrix.xy = (FT_Fixed) (scale * font->slant * 65536.0);
+    matrix.yx = 0;
+    matrix.yy = (FT_Fixed) (scale * 65536.0);
+    FT_Outline_Transform(outline, &matrix);
+
+    px = x * svg.png_dpi / 72.0;
+    py = y * svg.png_dpi / 72.0;
+    x0 = (int) floor(px);
+    y0 = (int) floor(py);
+    FT_Outline_Translate(outline, (FT_Pos) ((px - x0) * 64.0), (FT_Pos) (-(py - y0) * 64.0));
+
+    FT_Outline_Get_CBox(outline, &cbox);
+    bx0 = (int) floor(cbox.xMin / 64.0);
+    by0 = (int) floor(cbox.yMin / 64.0);
+    bx1 = (int) ceil(cbox.xMax / 64.0);
+    by1 = (int) ceil(cbox.yMax / 64.0);
+
+    if (bx1 <= bx0 || by1 <= by0)
+        return;
+
+    FT_Outline_Translate(outline, -bx0 * 64, -by0 * 64);
+
+    memset(&bitmap, 0, sizeof(bitmap));
+    bitmap.rows = by1 - by0;
+    bitmap.width = bx1 - bx0;
+    bitmap.pitch = bx1 - bx0;
+    bitmap.num_grays = 256;
+    bitmap.pixel_mode = FT_PIXEL_MODE_GRAY;
+    bitmap.buffer = NEW(bitmap.rows * bitmap.width, unsigned char);
+    memset(bitmap.buffer, 0, bitmap.rows * bitmap.width);
+
+    if (FT_Outline_Get_Bitmap(svg.ft, outline, &bitmap) == 0) {
+        cur_color_rgb(rgb);
+
+        /* The first row of the bitmap is its top. */
+        for (r = 0; r < (int) bitmap.rows; r++) {
+            for (c = 0; c < (int) bitmap.width; c++)
+                blend_pixel(x0 + bx0 + c, y0 - by1 + r, rgb, bitmap.buffer[r * bitmap.pitch + c]);
+        }
+    }
+
+    free(bitmap.buffer);
+}
+
+static void
+raster_rect (double x, double y, double width, double height)
+{
+    unsigned char rgb[3];
+    double k = svg.png_dpi / 72.0;
+    int x0, y0, x1, y1, i, j;
+
+    /* Rules snap to whole pixels, but never vanish. */
+    x0 = (int) floor(x * k + 0.5);
+    y0 = (int) floor(y * k + 0.5);
+    x1 = (int) floor((x + width) * k + 0.5);
+    y1 = (int) floor((y + height) * k + 0.5);
+    if (x1 <= x0)
+        x1 = x0 + 1;
+    if (y1 <= y0)
+        y1 = y0 + 1;
+
+    cur_color_rgb(rgb);
+
+    for (j = y0; j < y1; j++) {
+        for (i = x0; i < x1; i++)
+            blend_pixel(i, j, rgb, 255);
+    }
+}
+
+static void
+put_png_chunk (rust_output_handle_t handle, const char *type, const unsigned char *data, size_t len)
+{
+    unsigned char tmp[4];
+    uLong crc;
+
+    put_ulong(tmp, (uint32_t) len);
+    ttstub_output_write(handle, (char *) tmp, 4);
+    ttstub_output_write(handle, type, 4);
+    if (len)
+        ttstub_output_write(handle, (const char *) data, len);
+
+    crc = crc32(0, (const Bytef *) type, 4);
+    if (len)
+        crc = crc32(crc, data, len);
+    put_ulong(tmp, (uint32_t) crc);
+    ttstub_output_write(handle, (char *) tmp, 4);
+}
+
+static void
+write_png (rust_output_handle_t handle)
+{
+    static const unsigned char signature[8] = { 0x89, 'P', 'N', 'G', '\r', '\n', 0x1a, '\n' };
+    size_t row_len = 3 * (size_t) svg.raster_width, raw_len, i;
+    unsigned char ihdr[13], phys[9], *raw, *comp;
+    uLongf comp_len;
+    uint32_t ppm;
+
+    /* Each row gets a filter-type byte of zero: no filtering. */
+    raw_len = (row_len + 1) * svg.raster_height;
+    raw = NEW(raw_len, unsigned char);
+    for (i = 0; i < (size_t) svg.raster_height; i++) {
+        raw[i * (row_len + 1)] = 0;
+        memcpy(raw + i * (row_len + 1) + 1, svg.raster + i * row_len, row_len);
+    }
+
+    comp_len = compressBound(raw_len);
+    comp = NEW(comp_len, unsigned char);
+    if (compress2(comp, &comp_len, raw, raw_len, Z_DEFAULT_COMPRESSION) != Z_OK)
+        _tt_abort("Failed to compress PNG image data");
+
+    put_ulong(ihdr, svg.raster_width);
+    put_ulong(ihdr + 4, svg.raster_height);
+    ihdr[8] = 8; /* bit depth */
+    ihdr[9] = 2; /* truecolor */
+    ihdr[10] = ihdr[11] = ihdr[12] = 0;
+
+    ppm = (uint32_t) (svg.png_dpi / 0.0254 + 0.5);
+    put_ulong(phys, ppm);
+    put_ulong(phys + 4, ppm);
+    phys[8] = 1; /* meters */
+
+    ttstub_output_write(handle, (const char *) signature, 8);
+    put_png_chunk(handle, "IHDR", ihdr, sizeof(ihdr));
+    put_png_chunk(handle, "pHYs", phys, sizeof(phys));
+    put_png_chunk(handle, "IDAT", comp, comp_len);
+    put_png_chunk(handle, "IEND", NULL, 0);
+
+    free(raw);
+    free(comp);
+}
+
+
+/* Text extraction. We take the text in the order that it appears in the
+ * XDV file, which is the order that TeX typeset it in, and guess where the
+ * spaces and line breaks go from the positions of the glyphs. */
+
+#define MAX_GLYPH_UNICODES 16
+
+/* Work out the characters that a glyph name stands for, like "f_i" or
+ * "uni00E9". */
+static int
+name_unicodes (const char *name, int32_t *unicodes)
+{
+    int n;
+
+    if (!name || !*name || !strcmp(name, ".notdef"))
+        return 0;
+
+    n = agl_get_unicodes(name, unicodes, MAX_GLYPH_UNICODES);
+    return n > 0 ? n : 0;
+}
+
+static int
+char_unicodes (svg_font *font, uint32_t ch, int32_t *unicodes)
+{
+    char name[128];
+    int n = 0;
+
+    if (font->enc_id >= 0) {
+        char **enc = pdf_encoding_get_encoding(font->enc_id);
+
+        if (ch < 256 && enc)
+            n = name_unicodes(enc[ch], unicodes);
+    } else if (font->face && FT_HAS_GLYPH_NAMES(font->face)) {
+        FT_UInt gid = FT_Get_Char_Index(font->face, ch);
+
+        if (gid != 0 && FT_Get_Glyph_Name(font->face, gid, name, sizeof(name)) == 0)
+            n = name_unicodes(name, unicodes);
+    }
+
+    /* Without anything better to go on, most TeX encodings agree with
+     * ASCII on the letters and digits. */
+    if (n == 0 && ch >= 0x21 && ch < 0x7f) {
+        unicodes[0] = (int32_t) ch;
+        n = 1;
+    }
+
+    return n;
+}
+
+static int
+glyph_unicodes (svg_font *font, FT_UInt gid, int32_t *unicodes)
+{
+    char name[128];
+
+    if (!font->face || gid == 0 || gid >= (FT_UInt) font->face->num_glyphs)
+        return 0;
+
+    if (font->unicodes && font->unicodes[gid] != 0) {
+        unicodes[0] = (int32_t) font->unicodes[gid];
+        return 1;
+    }
+
+    /* Ligatures often have no character of their own, but their names
+     * tell us what they're made of. */
+    if (FT_HAS_GLYPH_NAMES(font->face) && FT_Get_Glyph_Name(font->face, gid, name, sizeof(name)) == 0)
+        return name_unicodes(name, unicodes);
+
+    return 0;
+}
+
+/* Add some characters that were typeset at (h, v) and take up `width`,
+ * preceded by a space or line break if they're far enough from the
+ * previous ones. Superscripts and subscripts move the baseline by less
+ * than half of an em, so they don't count as new lines. */
+static void
+put_text (const int32_t *unicodes, int n, int32_t h, int32_t v, int32_t width, int32_t size)
+{
+    int i;
+
+    if (n <= 0)
+        return;
+
+    if (svg.text_on_page) {
+        double em = size > svg.text_size ? size : svg.text_size;
+        double dv = (double) v - svg.text_v;
+
+        if (dv > 0.6 * em || dv < -0.6 * em)
+            buf_puts(&svg.text, "\n");
+        else if ((double) h - svg.text_h > 0.15 * size)
+            buf_puts(&svg.text, " ");
+    }
+
+    for (i = 0; i < n; i++) {
+        if (unicodes[i] >= 0x20 && unicodes[i] != 0x7f)
+            buf_utf8(&svg.text, (uint32_t) unicodes[i]);
+    }
+
+    svg.text_on_page = 1;
+    svg.text_h = h + width;
+    svg.text_v = v;
+    svg.text_size = size;
+}
+
+
+static void
+draw_glyph (svg_font *font, FT_UInt gid, int32_t h, int32_t v)
+{
+    if (!font->face || gid >= (FT_UInt) font->face->num_glyphs || svg.skimming || svg.text_output)
+        return;
+
+    if (svg.png_dpi) {
+        raster_glyph(font, gid, svg_x(h), svg_y(v));
+        return;
+    }
+
+    glyph_path(font, gid);
+    font->used[gid] = 1;
+
+    buf_printf(&svg.body, "<use xlink:href=\"#g%d-%u\" x=\"", (int) (font - svg.fonts), gid);
+    buf_num(&svg.body, svg_x(h));
+    buf_puts(&svg.body, "\" y=\"");
+    buf_num(&svg.body, svg_y(v));
+    buf_puts(&svg.body, "\"");
+    put_fill();
+    buf_puts(&svg.body, "/>\n");
+}
+
+static void
+draw_rule (int32_t h, int32_t v, int32_t height, int32_t width)
+{
+    if (height <= 0 || width <= 0 || svg.skimming || svg.text_output)
+        return;
+
+    if (svg.png_dpi) {
+        raster_rect(svg_x(h), svg_y(v - height), width * svg.conv, height * svg.conv);
+        return;
+    }
+
+    buf_puts(&svg.body, "<rect x=\"");
+    buf_num(&svg.body, svg_x(h));
+    buf_puts(&svg.body, "\" y=\"");
+    buf_num(&svg.body, svg_y(v - height));
+    buf_puts(&svg.body, "\" width=\"");
+    buf_num(&svg.body, width * svg.conv);
+    buf_puts(&svg.body, "\" height=\"");
+    buf_num(&svg.body, height * svg.conv);
+    buf_puts(&svg.body, "\"");
+    put_fill();
+    buf_puts(&svg.body, "/>\n");
+}
+
+/* Typeset a character of a TFM font, returning its width. */
+static int32_t
+set_char (uint32_t ch)
+{
+    svg_font *font;
+    FT_UInt gid = 0;
+    int32_t width;
+
+    if (svg.cur_font < 0)
+        _tt_abort("No font selected!");
+
+    font = &svg.fonts[svg.cur_font];
+
+    if (font->native)
+        _tt_abort("Characters can't be typeset with native font \"%s\".", font->name);
+
+    width = sqxfw(font->size, tfm_get_fw_width(font->tfm_id, ch));
+
+    if (svg.text_output) {
+        int32_t unicodes[MAX_GLYPH_UNICODES];
+
+        put_text(unicodes, char_unicodes(font, ch, unicodes), svg.h, svg.v, width, font->size);
+        return width;
+    }
+
+    if (font->face) {
+        if (font->enc_id >= 0) {
+            char **enc = pdf_encoding_get_encoding(font->enc_id);
+
+            if (ch < 256 && enc && enc[ch])
+                gid = FT_Get_Name_Index(font->face, enc[ch]);
+        } else {
+            gid = FT_Get_Char_Index(font->face, ch);
+        }
+
+        if (gid != 0)
+            draw_glyph(font, gid, svg.h, svg.v);
+    }
+
+    return width;
+}
+
+/* Extract the text of a run of glyphs. If XeTeX recorded the characters
+ * behind the glyphs, as UTF-16, they're better than anything we could work
+ * out from the glyphs themselves. */
+static void
+extract_glyphs (svg_font *font, const unsigned char *text, unsigned int text_len, int32_t width,
+                unsigned int n, const int32_t *xloc, const int32_t *yloc, const FT_UInt *gids)
+{
+    int32_t unicodes[MAX_GLYPH_UNICODES];
+    unsigned int i;
+
+    if (text_len > 0) {
+        int32_t *chars = NEW(text_len, int32_t);
+        int num_chars = 0;
+
+        for (i = 0; i < text_len; i++) {
+            uint32_t c = (text[2 * i] << 8) | text[2 * i + 1];
+
+            if (c >= 0xd800 && c < 0xdc00 && i + 1 < text_len) {
+                uint32_t d = (text[2 * i + 2] << 8) | text[2 * i + 3];
+
+                if (d >= 0xdc00 && d < 0xe000) {
+                    c = 0x10000 + ((c - 0xd800) << 10) + (d - 0xdc00);
+                    i++;
+                }
+            }
+
+            chars[num_chars++] = (int32_t) c;
+        }
+
+        put_text(chars, num_chars, svg.h + (n ? xloc[0] : 0), svg.v + (n ? yloc[0] : 0),
+                 width - (n ? xloc[0] : 0), font->size);
+        free(chars);
+        return;
+    }
+
+    for (i = 0; i < n; i++) {
+        int32_t end = (i + 1 < n) ? xloc[i + 1] : width;
+
+        put_text(unicodes, glyph_unicodes(font, gids[i], unicodes), svg.h + xloc[i], svg.v + yloc[i],
+                 end - xloc[i], font->size);
+    }
+}
+
+static void
+do_glyphs (int with_text)
+{
+    svg_font *font;
+    int32_t width, *xloc, *yloc;
+    FT_UInt *gids;
+    unsigned int i, n, text_len = 0;
+    const unsigned char *text = NULL;
+    int as_text, colored = 0;
+
+    if (svg.cur_font < 0)
+        _tt_abort("No font selected!");
+
+    font = &svg.fonts[svg.cur_font];
+
+    if (with_text) {
+        text_len = dvi_unsigned(2);
+        text = svg.dvi + svg.pos;
+        dvi_skip(2 * text_len);
+    }
+
+    width = dvi_signed(4);
+    n = dvi_unsigned(2);
+    xloc = NEW(n, int32_t);
+    yloc = NEW(n, int32_t);
+    gids = NEW(n, FT_UInt);
+
+    for (i = 0; i < n; i++) {
+        xloc[i] = dvi_signed(4);
+        yloc[i] = dvi_signed(4);
+    }
+
+    for (i = 0; i < n; i++)
+        gids[i] = dvi_unsigned(2);
+
+    if (svg.text_output) {
+        extract_glyphs(font, text, text_len, width, n, xloc, yloc, gids);
+        svg.h += width;
+        free(xloc);
+        free(yloc);
+        free(gids);
+        return;
+    }
+
+    if (font->rgba != 0xffffffff) {
+        char color[COLOR_LEN];
+
+        format_color(color, ((font->rgba >> 24) & 0xff) / 255.0, ((font->rgba >> 16) & 0xff) / 255.0,
+                     ((font->rgba >> 8) & 0xff) / 255.0);
+        push_color(color);
+        colored = 1;
+    }
+
+    as_text = svg.woff_fonts && font_uses_text(font);
+
+    if (as_text) {
+        unsigned int num_text = 0;
+
+        for (i = 0; i < n; i++) {
+            if (gids[i] < (FT_UInt) font->face->num_glyphs && font->unicodes[gids[i]] >= 0x20)
+                num_text++;
+        }
+
+        if (num_text > 0) {
+            font->text_used = 1;
+
+            buf_puts(&svg.body, "<text x=\"");
+            for (i = 0; i < n; i++) {
+                if (gids[i] < (FT_UInt) font->face->num_glyphs && font->unicodes[gids[i]] >= 0x20) {
+                    buf_num(&svg.body, svg_x(svg.h + xloc[i]));
+                    buf_puts(&svg.body, " ");
+                }
+            }
+            svg.body.data[--svg.body.len] = '\0';
+
+            buf_puts(&svg.body, "\" y=\"");
+            for (i = 0; i < n; i++) {
+                if (gids[i] < (FT_UInt) font->face->num_glyphs && font->unicodes[gids[i]] >= 0x20) {
+                    buf_num(&svg.body, svg_y(svg.v + yloc[i]));
+                    buf_puts(&svg.body, " ");
+                }
+            }
+            svg.body.data[--svg.body.len] = '\0';
+
+            buf_printf(&svg.body, "\" font-family=\"f%d\" font-size=\"", (int) (font - svg.fonts));
+            buf_num(&svg.body, font->size * svg.conv);
+            buf_puts(&svg.body, "\"");
+            put_fill();
+            buf_puts(&svg.body, ">");
+
+            for (i = 0; i < n; i++) {
+                if (gids[i] < (FT_UInt) font->face->num_glyphs && font->unicodes[gids[i]] >= 0x20)
+                    buf_utf8(&svg.body, (uint32_t) font->unicodes[gids[i]]);
+            }
+
+            buf_puts(&svg.body, "</text>\n");
+        }
+    }
+
+    for (i = 0; i < n; i++) {
+        if (as_text && gids[i] < (FT_UInt) font->face->num_glyphs && font->unicodes[gids[i]] >= 0x20)
+            continue;
+        draw_glyph(font, gids[i], svg.h + xloc[i], svg.v + yloc[i]);
+    }
+
+    if (colored)
+        pop_color();
+
+    svg.h += width;
+    free(xloc);
+    free(yloc);
+    free(gids);
+}
+
+
+/* Pages */
+
+static void
+begin_page (void)
+{
+    int i;
+
+    svg.h = svg.v = svg.w = svg.x = svg.y = svg.z = 0;
+    svg.stack_depth = 0;
+    svg.cur_font = -1;
+    svg.body.len = 0;
+    buf_puts(&svg.body, "");
+    svg.text_on_page = 0;
+
+    if (svg.png_dpi && !svg.skimming) {
+        double width = ceil(svg.paper_width * svg.png_dpi / 72.0);
+        double height = ceil(svg.paper_height * svg.png_dpi / 72.0);
+
+        if (width < 1 || height < 1 || width > MAX_RASTER_SIZE || height > MAX_RASTER_SIZE)
+            _tt_abort("Page images of %.0fx%.0f pixels are too big; use a lower resolution", width, height);
+
+        svg.raster_width = (int) width;
+        svg.raster_height = (int) height;
+        free(svg.raster);
+        svg.raster = NEW(3 * (size_t) svg.raster_width * svg.raster_height, unsigned char);
+        memset(svg.raster, 0xff, 3 * (size_t) svg.raster_width * svg.raster_height);
+    }
+
+    for (i = 0; i < svg.num_fonts; i++) {
+        if (svg.fonts[i].face)
+            memset(svg.fonts[i].used, 0, svg.fonts[i].face->num_glyphs);
+        svg.fonts[i].text_used = 0;
+    }
+}
+
+static void
+end_page (void)
+{
+    rust_output_handle_t handle;
+    svg_buf head = { NULL, 0, 0 };
+    char name[1024];
+    int i, any_text = 0;
+    FT_Long j;
+
+    svg.page_no++;
+
+    /* Like pdftotext, we end each page with a form feed. */
+    if (svg.text_output) {
+        if (svg.text_on_page)
+            buf_puts(&svg.text, "\n");
+        buf_puts(&svg.text, "\f");
+        return;
+    }
+
+    if (svg.png_dpi) {
+        snprintf(name, sizeof(name), "%s-%d.png", svg.stem, svg.page_no);
+        handle = ttstub_output_open(name, 0);
+        if (handle == NULL)
+            _tt_abort("Unable to open \"%s\".", name);
+
+        write_png(handle);
+        ttstub_output_close(handle);
+        return;
+    }
+
+    buf_puts(&head, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    buf_puts(&head, "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" "
+             "version=\"1.1\" width=\"");
+    buf_num(&head, svg.paper_width);
+    buf_puts(&head, "pt\" height=\"");
+    buf_num(&head, svg.paper_height);
+    buf_puts(&head, "pt\" viewBox=\"0 0 ");
+    buf_num(&head, svg.paper_width);
+    buf_puts(&head, " ");
+    buf_num(&head, svg.paper_height);
+    buf_puts(&head, "\">\n<defs>\n");
+
+    for (i = 0; i < svg.num_fonts; i++) {
+        if (svg.fonts[i].text_used)
+            any_text = 1;
+    }
+
+    if (any_text) {
+        buf_puts(&head, "<style type=\"text/css\"><![CDATA[\n");
+
+        for (i = 0; i < svg.num_fonts; i++) {
+            if (svg.fonts[i].text_used) {
+                font_woff_name(&svg.fonts[i], name, sizeof(name), 1);
+                buf_printf(&head, "@font-face{font-family:f%d;src:url(\"%s\") format(\"woff\");}\n", i, name);
+            }
+        }
+
+        buf_puts(&head, "text{white-space:pre;font-kerning:none;font-variant-ligatures:none;}\n");
+        buf_puts(&head, "]]></style>\n");
+    }
+
+    for (i = 0; i < svg.num_fonts; i++) {
+        svg_font *font = &svg.fonts[i];
+
+        if (!font->face)
+            continue;
+
+        for (j = 0; j < font->face->num_glyphs; j++) {
+            if (font->used[j])
+                buf_printf(&head, "<path id=\"g%d-%ld\" d=\"%s\"/>\n", i, (long) j, font->paths[j]);
+        }
+    }
+
+    buf_puts(&head, "</defs>\n");
+
+    snprintf(name, sizeof(name), "%s-%d.svg", svg.stem, svg.page_no);
+    handle = ttstub_output_open(name, 0);
+    if (handle == NULL)
+        _tt_abort("Unable to open \"%s\".", name);
+
+    ttstub_output_write(handle, head.data, head.len);
+    ttstub_output_write(handle, svg.body.data, svg.body.len);
+    ttstub_output_write(handle, "</svg>\n", 7);
+    ttstub_output_close(handle);
+    free(head.data);
+}
+
+static void
+interpret_page (void)
+{
+    for (;;) {
+        unsigned char op = dvi_byte();
+        int32_t a, b;
+
+        if (op <= SET_CHAR_127) {
+            svg.h += set_char(op);
+            continue;
+        }
+
+        if (op >= FNT_NUM_0 && op <= FNT_NUM_63) {
+            svg_font *font = find_font(op - FNT_NUM_0);
+
+            if (!font)
+                _tt_abort("Font %d used before it was defined", op - FNT_NUM_0);
+            svg.cur_font = font - svg.fonts;
+            continue;
+        }
+
+        switch (op) {
+        case SET1: case SET2: case SET3: case SET4:
+            svg.h += set_char(dvi_unsigned(op - SET1 + 1));
+            break;
+        case PUT1: case PUT2: case PUT3: case PUT4:
+            set_char(dvi_unsigned(op - PUT1 + 1));
+            break;
+        case SET_RULE:
+            a = dvi_signed(4);
+            b = dvi_signed(4);
+            draw_rule(svg.h, svg.v, a, b);
+            svg.h += b;
+            break;
+        case PUT_RULE:
+            a = dvi_signed(4);
+            b = dvi_signed(4);
+            draw_rule(svg.h, svg.v, a, b);
+            break;
+        case NOP:
+            break;
+        case EOP:
+            return;
+        case PUSH:
+            if (svg.stack_depth >= DVI_STACK_SIZE)
+                _tt_abort("DVI stack exceeded limit.");
+            svg.stack[svg.stack_depth][0] = svg.h;
+            svg.stack[svg.stack_depth][1] = svg.v;
+            svg.stack[svg.stack_depth][2] = svg.w;
+            svg.stack[svg.stack_depth][3] = svg.x;
+            svg.stack[svg.stack_depth][4] = svg.y;
+            svg.stack[svg.stack_depth][5] = svg.z;
+            svg.stack_depth++;
+            break;
+        case POP:
+            if (svg.stack_depth <= 0)
+                _tt_abort("Tried to pop an empty stack.");
+            svg.stack_depth--;
+            svg.h = svg.stack[svg.stack_depth][0];
+            svg.v = svg.stack[svg.stack_depth][1];
+            svg.w = svg.stack[svg.stack_depth][2];
+            svg.x = svg.stack[svg.stack_depth][3];
+            svg.y = svg.stack[svg.stack_depth][4];
+            svg.z = svg.stack[svg.stack_depth][5];
+            break;
+        case RIGHT1: case RIGHT2: case RIGHT3: case RIGHT4:
+            svg.h += dvi_signed(op - RIGHT1 + 1);
+            break;
+        case W0:
+            svg.h += svg.w;
+            break;
+        case W1: case W2: case W3: case W4:
+            svg.w = dvi_signed(op - W1 + 1);
+            svg.h += svg.w;
+            break;
+        case X0:
+            svg.h += svg.x;
+            break;
+        case X1: case X2: case X3: case X4:
+            svg.x = dvi_signed(op - X1 + 1);
+            svg.h += svg.x;
+            break;
+        case DOWN1: case DOWN2: case DOWN3: case DOWN4:
+            svg.v += dvi_signed(op - DOWN1 + 1);
+            break;
+        case Y0:
+            svg.v += svg.y;
+            break;
+        case Y1: case Y2: case Y3: case Y4:
+            svg.y = dvi_signed(op - Y1 + 1);
+            svg.v += svg.y;
+            break;
+        case Z0:
+            svg.v += svg.z;
+            break;
+        case Z1: case Z2: case Z3: case Z4:
+            svg.z = dvi_signed(op - Z1 + 1);
+            svg.v += svg.z;
+            break;
+        case FNT1: case FNT2: case FNT3: case FNT4: {
+            int32_t id = dvi_signed(op - FNT1 + 1);
+            svg_font *font = find_font(id);
+
+            if (!font)
+                _tt_abort("Font %d used before it was defined", id);
+            svg.cur_font = font - svg.fonts;
+            break;
+        }
+        case XXX1: case XXX2: case XXX3: case XXX4: {
+            uint32_t len = dvi_unsigned(op - XXX1 + 1);
+            size_t start = svg.pos;
+
+            dvi_skip(len);
+            do_special((const char *) svg.dvi + start, len);
+            break;
+        }
+        case FNT_DEF1: case FNT_DEF2: case FNT_DEF3: case FNT_DEF4:
+            define_tfm_font(dvi_signed(op - FNT_DEF1 + 1));
+            break;
+        case XDV_NATIVE_FONT_DEF:
+            define_native_font(dvi_signed(4));
+            break;
+        case XDV_GLYPHS:
+            do_glyphs(0);
+            break;
+        case XDV_TEXT_AND_GLYPHS:
+            do_glyphs(1);
+            break;
+        default:
+            _tt_abort("Unexpected opcode %d in XDV file", op);
+        }
+    }
+}
+
+static void
+do_page (void)
+{
+    size_t start;
+
+    dvi_skip(44); /* \count0 to \count9, and the pointer to the previous page */
+    ttstub_check_cancelled();
+    start = svg.pos;
+
+    /* A PNG's size has to be known before we can draw anything, but the
+     * paper size is often set by a special in the middle of the first page.
+     * So we go over each page once just to find such specials. */
+    if (svg.png_dpi) {
+        int cur_font = svg.cur_font;
+
+        svg.skimming = 1;
+        begin_page();
+        interpret_page();
+        svg.skimming = 0;
+        svg.pos = start;
+        svg.cur_font = cur_font;
+    }
+
+    begin_page();
+    interpret_page();
+    end_page();
+}
+
+
+int
+xdvisvg_main (const char *dvi_filename, const char *svg_stem, const tt_xdvisvg_config_t *config)
+{
+    rust_input_handle_t handle;
+    unsigned char *data;
+    uint32_t num, den, mag;
+    size_t len;
+    unsigned char id;
+
+    handle = ttstub_input_open(dvi_filename, kpse_program_binary_format, 0);
+    if (handle == NULL)
+        _tt_abort("DVI file can't be opened: %s", dvi_filename);
+
+    len = ttstub_input_get_size(handle);
+    data = NEW(len, unsigned char);
+    if (ttstub_input_read(handle, (char *) data, len) != (ssize_t) len)
+        _tt_abort("Failed to read the DVI file: %s", dvi_filename);
+    ttstub_input_close(handle);
+
+    memset(&svg, 0, sizeof(svg));
+    svg.dvi = data;
+    svg.dvi_len = len;
+    svg.stem = svg_stem;
+    svg.text_output = config && config->text_output;
+    svg.png_dpi = (config && !svg.text_output) ? config->png_dpi : 0;
+    svg.woff_fonts = config && config->woff_fonts && !svg.png_dpi && !svg.text_output;
+    svg.cur_font = -1;
+    svg.paper_width = 612.0;
+    svg.paper_height = 792.0;
+
+    if (config && config->paperspec &&
+        select_paper(config->paperspec, config->paperspec + strlen(config->paperspec)) < 0)
+        _tt_abort("Unrecognized paper format: %s", config->paperspec);
+
+    if (dvi_byte() != PRE)
+        _tt_abort("Not a DVI file: %s", dvi_filename);
+
+    id = dvi_byte();
+    if (id != DVI_ID && id != XDV_ID && id != XDV_ID_OLD)
+        _tt_abort("Unsupported DVI or XDV version %d", id);
+
+    num = dvi_unsigned(4);
+    den = dvi_unsigned(4);
+    mag = dvi_unsigned(4);
+    dvi_skip(dvi_byte());
+
+    if (num == 0 || den == 0 || mag == 0)
+        _tt_abort("Invalid DVI preamble");
+
+    svg.conv = ((double) num / den) * (mag / 1000.0) * (72.0 / 254000.0);
+
+    if (FT_Init_FreeType(&svg.ft))
+        _tt_abort("Failed to initialize FreeType");
+
+    pdf_init_fontmaps();
+    pdf_load_fontmap_file("pdftex.map", FONTMAP_RMODE_APPEND);
+    pdf_init_encodings();
+    if (svg.text_output)
+        agl_init_map();
+
+    buf_puts(&svg.text, "");
+
+    for (;;) {
+        unsigned char op = dvi_byte();
+
+        if (op == POST)
+            break;
+
+        switch (op) {
+        case NOP:
+            break;
+        case BOP:
+            do_page();
+            break;
+        case FNT_DEF1: case FNT_DEF2: case FNT_DEF3: case FNT_DEF4:
+            define_tfm_font(dvi_signed(op - FNT_DEF1 + 1));
+            break;
+        case XDV_NATIVE_FONT_DEF:
+            define_native_font(dvi_signed(4));
+            break;
+        default:
+            _tt_abort("Unexpected opcode %d between pages of XDV file", op);
+        }
+    }
+
+    if (svg.text_output) {
+        char name[1024];
+
+        snprintf(name, sizeof(name), "%s.txt", svg.stem);
+        handle = ttstub_output_open(name, 0);
+        if (handle == NULL)
+            _tt_abort("Unable to open \"%s\".", name);
+
+        ttstub_output_write(handle, svg.text.data, svg.text.len);
+        ttstub_output_close(handle);
+        agl_close_map();
+    }
+
+    free_fonts();
+    FT_Done_FreeType(svg.ft);
+    pdf_close_encodings();
+    pdf_close_fontmaps();
+    tfm_close_all();
+    free(svg.body.data);
+    free(svg.text.data);
+    free(svg.raster);
+    free(data);
+
+    return 0;
+}
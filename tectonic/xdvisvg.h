@@ -0,0 +1,13 @@
+/* tectonic/xdvisvg.h: converting XDV files to SVG
+   Copyright 2017 the Tectonic Project
+   Licensed under the MIT License.
+*/
+
+#ifndef TECTONIC_XDVISVG_H
+#define TECTONIC_XDVISVG_H
+
+#include <tectonic/core-bridge.h>
+
+int xdvisvg_main (const char *dvi_filename, const char *svg_stem, const tt_xdvisvg_config_t *config);
+
+#endif /* not TECTONIC_XDVISVG_H */
@@ -2937,6 +2937,9 @@ print_cmd_chr(uint16_t cmd, int32_t chr_code)
         case PDF_MDFIVE_SUM_CODE:
             print_esc(S(mdfivesum));
             break;
+        case PDF_FILE_MOD_DATE_CODE:
+            print_esc(S(filemoddate));
+            break;
         case LEFT_MARGIN_KERN_CODE:
             print_esc(S(leftmarginkern));
             break;
@@ -5207,6 +5210,13 @@ get_next(void)
     UTF16_code lower;
     small_number d;
     small_number sup_count;
+    static unsigned int cancel_poll_count = 0;
+
+    /* Every token passes through here, so this is where we check whether
+     * we've been cancelled, albeit not every time. */
+
+    if ((++cancel_poll_count & 0xFFFF) == 0)
+        ttstub_check_cancelled();
 
 restart:
     cur_cs = 0;
@@ -9377,6 +9387,45 @@ conv_toks(void)
         return;
         break;
 
+    case PDF_FILE_MOD_DATE_CODE:
+        save_scanner_status = scanner_status;
+        save_warning_index = warning_index;
+        save_def_ref = def_ref;
+
+        if (str_start[(str_ptr) - 65536L] < pool_ptr)
+            u = make_string();
+        else
+            u = 0;
+
+        scan_pdf_ext_toks();
+
+        if (selector == SELECTOR_NEW_STRING)
+            pdf_error(S(tokens), S(tokens_to_string___called_wh/*ile selector = new_string*/));
+
+        old_setting = selector;
+        selector = SELECTOR_NEW_STRING ;
+        show_token_list(mem[def_ref].hh.v.RH, MIN_HALFWORD, pool_size - pool_ptr);
+        selector = old_setting;
+        s = make_string();
+        delete_token_ref(def_ref);
+        def_ref = save_def_ref;
+        warning_index = save_warning_index;
+        scanner_status = save_scanner_status;
+        b = pool_ptr;
+        getfilemoddate(s);
+        mem[mem_top - 12].hh.v.RH = str_toks(b);
+
+        if (s == str_ptr - 1) {
+            str_ptr--;
+            pool_ptr = str_start[(str_ptr) - 65536L];
+        }
+
+        begin_token_list(mem[mem_top - 3].hh.v.RH, INSERTED);
+        if (u != 0)
+            str_ptr--;
+        return;
+        break;
+
     case XETEX_REVISION_CODE:
         break;
 
@@ -10835,10 +10884,44 @@ effective_char_info(internal_font_number f, uint16_t c)
 }
 
 
+/* Find the input file that we're reading and the line that we're on, for
+ * reporting font problems to the driver. This follows print_file_line. The
+ * returned name should be freed; it is NULL if we're not reading a file. */
+static char *
+current_source_location(int *line_no)
+{
+    integer level = in_open;
+
+    while ((level > 0) && (full_source_filename_stack[level] == 0))
+        level--;
+
+    if (level == 0) {
+        *line_no = 0;
+        return NULL;
+    }
+
+    if (level == in_open)
+        *line_no = line;
+    else
+        *line_no = line_stack[level + 1];
+
+    return gettexstring(full_source_filename_stack[level]);
+}
+
+
 void char_warning(internal_font_number f, integer c)
 {
     CACHE_THE_EQTB;
     integer old_setting;
+    char *font, *file;
+    int line_no;
+
+    /* The driver always hears about this, regardless of \tracinglostchars. */
+    font = gettexstring(font_name[f]);
+    file = current_source_location(&line_no);
+    ttstub_missing_char(font, c, file, line_no);
+    free(font);
+    free(file);
 
     if (INTPAR(tracing_lost_chars) > 0) {
         old_setting = INTPAR(tracing_online);
@@ -10860,6 +10943,16 @@ void char_warning(internal_font_number f, integer c)
     }
 }
 
+void font_substitution_warning(const char *requested, const char *substitute)
+{
+    char *file;
+    int line_no;
+
+    file = current_source_location(&line_no);
+    ttstub_font_substituted(requested, substitute, file, line_no);
+    free(file);
+}
+
 int32_t new_native_word_node(internal_font_number f, integer n)
 {
     CACHE_THE_EQTB;
@@ -12281,6 +12374,26 @@ void special_out(int32_t p)
         if (pool_ptr + 1 > pool_size)
             overflow(S(pool_size), pool_size - init_pool_ptr);
     }
+    {
+        /* Give the driver a chance to handle the special itself. The text
+         * was printed with doing_special set, so it's UTF-8 bytes. */
+        size_t len = pool_ptr - str_start[(str_ptr) - 65536L];
+        char *text = xmalloc(len + 1);
+        int handled;
+
+        for (k = 0; k < (pool_pointer) len; k++)
+            text[k] = str_pool[str_start[(str_ptr) - 65536L] + k];
+        text[len] = '\0';
+
+        handled = ttstub_special(text, len);
+        free(text);
+
+        if (handled) {
+            pool_ptr = str_start[(str_ptr) - 65536L];
+            doing_special = false;
+            return;
+        }
+    }
     if ((pool_ptr - str_start[(str_ptr) - 65536L]) < 256) {
         {
             dvi_buf[dvi_ptr] = XXX1;
@@ -14190,6 +14303,14 @@ void ship_out(int32_t p)
                 dvi_swap();
         }
         total_pages++;
+        {
+            int32_t counts[10];
+            int i;
+
+            for (i = 0; i < 10; i++)
+                counts[i] = COUNT_REG(i);
+            ttstub_page_shipped(counts);
+        }
         cur_s = -1; /*:662 */
 
     done:
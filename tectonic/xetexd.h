@@ -250,6 +250,7 @@ typedef struct {
 /* Functions originating in texmfmp.c */
 
 void getmd5sum(integer s, bool file);
+void getfilemoddate(integer s);
 void get_date_and_time (integer *, integer *, integer *, integer *);
 
 str_number make_full_name_string(void);
@@ -274,6 +275,7 @@ integer first;
 integer last;
 integer max_buf_stack;
 bool in_initex_mode;
+bool initex_preload_format;
 bool dump_line;
 const char *dump_name;
 integer bound_default;
@@ -302,6 +304,7 @@ integer dvi_buf_size;
 integer expand_depth;
 int file_line_error_style_p;
 int halt_on_error_p;
+int default_interaction_mode;
 bool quoted_filename;
 bool src_specials_p;
 bool insert_src_special_auto;
@@ -902,6 +905,7 @@ void open_log_file(void);
 void start_input(const char *primary_input_name);
 four_quarters effective_char_info(internal_font_number f, uint16_t c);
 void char_warning(internal_font_number f, integer c);
+void font_substitution_warning(const char *requested, const char *substitute);
 int32_t new_native_word_node(internal_font_number f, integer n);
 int32_t new_native_character(internal_font_number f, UnicodeScalar c);
 void font_feature_warning(const void *featureNameP, integer featLen, const void *settingNameP, integer setLen);
@@ -2868,7 +2868,7 @@ initialize_more_variables(void)
     native_text_size = 128;
     native_text = xmalloc(native_text_size * sizeof(UTF16_code));
 
-    interaction = ERROR_STOP_MODE;
+    interaction = default_interaction_mode;
 
     deletions_allowed = true;
     set_box_allowed = true;
@@ -3917,6 +3917,8 @@ tt_run_engine(char *dump_name, char *input_file_name)
         primitive(S(strcmp), CONVERT, PDF_STRCMP_CODE);
         primitive(S(mdfivesum), CONVERT, PDF_MDFIVE_SUM_CODE);
         primitive(S(pdfmdfivesum), CONVERT, PDF_MDFIVE_SUM_CODE);
+        primitive(S(filemoddate), CONVERT, PDF_FILE_MOD_DATE_CODE);
+        primitive(S(pdffilemoddate), CONVERT, PDF_FILE_MOD_DATE_CODE);
 
         primitive(S(shellescape), LAST_ITEM, PDF_SHELL_ESCAPE_CODE);
         primitive(S(XeTeXpdfpagecount), LAST_ITEM, XETEX_PDF_PAGE_COUNT_CODE);
@@ -4022,7 +4024,10 @@ tt_run_engine(char *dump_name, char *input_file_name)
 
     no_new_control_sequence = true;
 
-    if (!in_initex_mode) {
+    /* In initex mode we can still start from an existing format, so that the
+     * format we dump extends it. */
+
+    if (!in_initex_mode || initex_preload_format) {
         if (!load_fmt_file())
             return history;
     }
@@ -40,7 +40,7 @@ lazy_static! {
 }
 
 
-/// A stunted version of cli_driver:FileSummary for examining the format file
+/// A stunted version of driver::FileSummary for examining the format file
 /// SHA256 sum.
 #[derive(Clone,Debug,Eq,PartialEq)]
 struct FileSummary {
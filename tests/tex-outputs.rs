@@ -13,7 +13,7 @@ use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
-use tectonic::errors::{DefinitelySame, ErrorKind, Result};
+use tectonic::errors::{ErrorKind, Result};
 use tectonic::engines::NoopIoEventBackend;
 use tectonic::engines::tex::TexResult;
 use tectonic::io::{FilesystemIo, FilesystemPrimaryInputIo, IoStack, MemoryIo, try_open_file};
@@ -172,7 +172,7 @@ impl TestCase {
                          &mut NoopStatusBackend::new(), "plain.fmt.gz", &texname)
         };
 
-        if !res.definitely_same(&self.expected_result) {
+        if res != self.expected_result {
             panic!(format!("expected TeX result {:?}, got {:?}", self.expected_result, res));
         }
 